@@ -0,0 +1,70 @@
+// Offline echo canceller evaluation.
+//
+//   cargo run --release --example aec_eval -- [far_end.wav [near_end.wav [room_ir.wav]]] [--out dir]
+//
+// Without WAVs both talkers are synthetic. With a room IR the scenario uses it instead of the
+// built-in small/medium/large rooms. `--out` writes the mic and each backend's output for listening.
+
+use std::error::Error;
+use std::path::PathBuf;
+
+use melodeus_browser::aec_eval::{
+    AecConfig, EchoCancellerKind, Scenario, read_wav_mono, run_suite, synthetic_rooms, synthetic_speech,
+    write_wav_mono,
+};
+
+const SAMPLE_RATE: u32 = 16_000;
+const FRAME_SIZE_MS: usize = 10;
+const FILTER_LENGTH_MS: usize = 100;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut paths = Vec::new();
+    let mut out_dir = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--out" {
+            out_dir = Some(PathBuf::from(args.next().ok_or("--out needs a directory")?));
+        } else {
+            paths.push(PathBuf::from(arg));
+        }
+    }
+
+    let far_end = match paths.first() {
+        Some(path) => read_wav_mono(path, SAMPLE_RATE)?,
+        None => synthetic_speech(12_000, SAMPLE_RATE, 1),
+    };
+    let near_end = match paths.get(1) {
+        Some(path) => read_wav_mono(path, SAMPLE_RATE)?,
+        None => synthetic_speech(12_000, SAMPLE_RATE, 2),
+    };
+    let scenarios = match paths.get(2) {
+        Some(path) => vec![
+            Scenario::new("recorded room", SAMPLE_RATE, far_end)
+                .with_room_ir(read_wav_mono(path, SAMPLE_RATE)?)
+                .with_near_end(near_end, 4000)
+                .with_noise(-60.0),
+        ],
+        None => synthetic_rooms(SAMPLE_RATE, &far_end, &near_end),
+    };
+
+    let frame_size = SAMPLE_RATE as usize * FRAME_SIZE_MS / 1000;
+    let filter_len = SAMPLE_RATE as usize * FILTER_LENGTH_MS / 1000;
+    let aec_config = AecConfig::new(SAMPLE_RATE, frame_size, filter_len);
+    let reports = run_suite(&scenarios, &[EchoCancellerKind::Aec3, EchoCancellerKind::Speex], &aec_config)?;
+    for report in &reports {
+        println!("{report}");
+    }
+
+    if let Some(dir) = out_dir {
+        std::fs::create_dir_all(&dir)?;
+        for scenario in &scenarios {
+            let file = format!("{}_mic.wav", scenario.name.replace(' ', "_")).to_lowercase();
+            write_wav_mono(dir.join(file), &scenario.render().mic, SAMPLE_RATE)?;
+        }
+        for report in &reports {
+            let file = format!("{}_{:?}.wav", report.scenario.replace(' ', "_"), report.backend).to_lowercase();
+            write_wav_mono(dir.join(file), &report.output, SAMPLE_RATE)?;
+        }
+    }
+    Ok(())
+}
@@ -3,10 +3,13 @@ import("./pkg").then(({ list_devices, enable_aec }) => {
   const outputSelect = document.getElementById("output-devices");
   const refreshButton = document.getElementById("refresh-devices");
   const enableButton = document.getElementById("enable-aec");
+  const cancelButton = document.getElementById("cancel-calibration");
   const statusEl = document.getElementById("status");
   const inputWaveContainer = document.getElementById("input-waves");
   const outputWaveContainer = document.getElementById("output-waves");
   const aecCanvas = document.getElementById("aec-waveform");
+  const echoPathCanvas = document.getElementById("echo-path");
+  const echoDelayEl = document.getElementById("echo-delay");
   const logEl = document.getElementById("log");
 
   const log = (...args) => {
@@ -171,6 +174,128 @@ import("./pkg").then(({ list_devices, enable_aec }) => {
     }
 
     drawWaveform(aecCanvas, collapseChannels(aec, inputChannels || 1));
+
+    // echo path of the first input/output channel pair, scaled to fit
+    const responses = Array.isArray(frame.impulseResponses) ? frame.impulseResponses : [];
+    const delays = Array.isArray(frame.echoDelaysMs) ? frame.echoDelaysMs : [];
+    if (responses.length) {
+      const response = responses[0];
+      const peak = response.reduce((acc, v) => Math.max(acc, Math.abs(v)), 0) || 1;
+      drawWaveform(echoPathCanvas, response.map((v) => v / peak));
+    } else {
+      drawWaveform(echoPathCanvas, new Float32Array());
+    }
+    if (echoDelayEl) {
+      echoDelayEl.textContent = delays
+        .map((d, idx) => `pair ${idx}: ${d == null ? "-" : `${d.toFixed(1)} ms`}`)
+        .join(", ");
+    }
+  };
+
+  // converged echo canceller state per device pair, so a reload doesn't start from zero
+  const AEC_STATE_SAVE_MS = 10000;
+  let lastStateSave = 0;
+
+  const toBase64 = (bytes) => {
+    let binary = "";
+    for (let i = 0; i < bytes.length; i += 0x8000) {
+      binary += String.fromCharCode.apply(null, bytes.subarray(i, i + 0x8000));
+    }
+    return btoa(binary);
+  };
+
+  const fromBase64 = (text) => Uint8Array.from(atob(text), (c) => c.charCodeAt(0));
+
+  const saveAecState = () => {
+    if (!handle || !handle.aec_state_supported()) return;
+    try {
+      const state = handle.aec_state();
+      if (state) localStorage.setItem(handle.aec_state_key(), toBase64(state));
+    } catch (err) {
+      console.warn("Failed to save AEC state", err);
+    }
+  };
+
+  // measured echo delays per device pair, so a reload can skip or shorten calibration.
+  // enable_aec asks for them by the key of the devices it actually picked
+  const loadCalibration = (key) => {
+    try {
+      const saved = localStorage.getItem(key);
+      return saved ? fromBase64(saved) : null;
+    } catch (err) {
+      console.warn("Ignoring saved calibration", err);
+      return null;
+    }
+  };
+
+  const saveCalibration = () => {
+    try {
+      const state = handle.calibration_state();
+      if (state) localStorage.setItem(handle.calibration_key(), toBase64(state));
+    } catch (err) {
+      console.warn("Failed to save calibration", err);
+    }
+  };
+
+  const restoreAecState = () => {
+    if (!handle.aec_state_supported()) {
+      console.info("This echo canceller can't save its state, it starts unconverged every session");
+      return;
+    }
+    try {
+      const saved = localStorage.getItem(handle.aec_state_key());
+      if (saved) handle.restore_aec_state(fromBase64(saved));
+    } catch (err) {
+      console.warn("Ignoring saved AEC state", err);
+    }
+  };
+
+  // pairs calibration couldn't measure, and what the user can do about it
+  const ISOLATED_NOTE = "No speaker echo heard (headphones?), echo cancellation bypassed";
+
+  const calibrationWarning = (report) => {
+    if (report && report.isolated) return ISOLATED_NOTE;
+    const failed = (report && Array.isArray(report.pairs) ? report.pairs : []).filter((p) => p.failure);
+    if (!failed.length) return "";
+    if (failed.some((p) => p.failure === "notHeard")) {
+      const outputs = [...new Set(failed.map((p) => p.outputDevice))].join(", ");
+      const retried = report.attempts > 1 ? " even with louder probes" : "";
+      return `Calibration couldn't hear ${outputs}${retried}, turn up your speakers and restart AEC`;
+    }
+    return "Calibration probe didn't play, restart AEC to try again";
+  };
+
+  let calibrationNote = "";
+  let calibrationAbort = null;
+
+  const calibrationProgress = ({ stage, fraction }) => {
+    const percent = stage === "capturing" ? ` ${Math.round(fraction * 100)}%` : "";
+    setStatus(`Calibrating: ${stage}${percent}`);
+  };
+
+  // ?debugWav keeps what each device captured during calibration, offered as downloads in the log
+  const debugWav = new URLSearchParams(window.location.search).has("debugWav");
+  // ?chirpProbe calibrates with an audible chirp, for rooms too noisy to hear the quiet default probe
+  const chirpProbe = new URLSearchParams(window.location.search).has("chirpProbe");
+  // ?echoPathAction=notify|resetAec|recalibrate picks what happens when the echo path changes (recalibrate by default)
+  const echoPathAction = new URLSearchParams(window.location.search).get("echoPathAction");
+  let debugWavUrls = [];
+
+  const logDebugWavs = (report) => {
+    debugWavUrls.forEach((url) => URL.revokeObjectURL(url));
+    debugWavUrls = [];
+    if (!logEl) return;
+    for (const { name, blob } of (report && report.debugWavs) || []) {
+      const url = URL.createObjectURL(blob);
+      debugWavUrls.push(url);
+      const link = document.createElement("a");
+      link.href = url;
+      link.download = name;
+      link.textContent = name;
+      const line = document.createElement("div");
+      line.appendChild(link);
+      logEl.appendChild(line);
+    }
   };
 
   const step = async () => {
@@ -178,7 +303,25 @@ import("./pkg").then(({ list_devices, enable_aec }) => {
     try {
       const frame = await handle.update();
       render(frame);
-      setStatus("");
+      for (const change of frame.echoPathChanges || []) {
+        log("Echo path changed", change);
+        if (change.reason === "isolated") calibrationNote = ISOLATED_NOTE;
+        if (change.reason === "coupled") calibrationNote = "";
+      }
+      if (frame.recalibrationError) {
+        console.warn("Recalibration failed", frame.recalibrationError);
+      }
+      if (frame.recalibrated) {
+        saveCalibration();
+        const report = handle.calibration_report();
+        log("Recalibrated", report);
+        calibrationNote = calibrationWarning(report);
+      }
+      if (performance.now() - lastStateSave > AEC_STATE_SAVE_MS) {
+        lastStateSave = performance.now();
+        saveAecState();
+      }
+      setStatus(calibrationNote);
     } catch (err) {
       console.error(err);
       setStatus("AEC update failed");
@@ -195,18 +338,40 @@ import("./pkg").then(({ list_devices, enable_aec }) => {
       if (raf) cancelAnimationFrame(raf);
       const inName = inputSelect ? inputSelect.value : null;
       const outName = outputSelect ? outputSelect.value : null;
-      handle = await enable_aec(inName || null, outName || null);
+      calibrationAbort = new AbortController();
+      if (cancelButton) cancelButton.disabled = false;
+      handle = await enable_aec(
+        inName || null,
+        outName || null,
+        loadCalibration,
+        debugWav,
+        calibrationProgress,
+        calibrationAbort.signal,
+        chirpProbe,
+        echoPathAction
+      );
+      saveCalibration();
+      restoreAecState();
+      lastStateSave = performance.now();
+      const report = handle.calibration_report();
+      log("Calibration", report);
+      logDebugWavs(report);
+      calibrationNote = calibrationWarning(report);
       step();
-      setStatus("AEC running");
+      setStatus(calibrationNote || "AEC running");
     } catch (err) {
       console.error(err);
-      setStatus("Failed to start AEC");
+      setStatus(calibrationAbort && calibrationAbort.signal.aborted ? "Calibration cancelled" : "Failed to start AEC");
     } finally {
+      calibrationAbort = null;
+      if (cancelButton) cancelButton.disabled = true;
       enableButton.disabled = false;
     }
   };
 
   refreshButton && refreshButton.addEventListener("click", refreshDevices);
   enableButton && enableButton.addEventListener("click", startAec);
+  cancelButton && cancelButton.addEventListener("click", () => calibrationAbort && calibrationAbort.abort());
+  window.addEventListener("beforeunload", saveAecState);
   refreshDevices();
 }); 
@@ -26,11 +26,12 @@ use futures::channel::mpsc;
 use futures::StreamExt;
 
 use std::{
-    collections::{HashMap},
+    collections::{HashMap, VecDeque},
     error::Error,
+    io::Cursor,
     mem::{MaybeUninit},
     sync::{
-        atomic::{AtomicU64, Ordering},
+        atomic::{AtomicBool, AtomicU64, Ordering},
         Arc,
     },
 };
@@ -40,7 +41,7 @@ use aec3::voip::VoipAec3;
 
 use std::backtrace::Backtrace;
 
-use rustfft::{FftPlanner, num_complex::Complex};
+use rustfft::{FftDirection, num_complex::Complex};
 
 use cpal::{
     traits::{DeviceTrait, HostTrait, StreamTrait},
@@ -62,7 +63,7 @@ use ringbuf::{
     HeapCons, HeapProd, HeapRb, LocalRb,
 };
 use ringbuf::storage::Heap;
-use crate::speex::{Resampler};
+use crate::speex::{plan_fft, EchoCanceller, EchoCancellerSnapshot, Preprocessor, Resampler};
 use crate::cpal_webaudio_inputs::WasmStream;
 use std::f32::consts::PI;
 use rustfft::num_complex::Complex32;
@@ -238,42 +239,699 @@ fn hann(n: usize, i: usize) -> f32 {
     0.5 * (1.0 - (2.0 * std::f32::consts::PI * i as f32 / (n as f32)).cos())
 }
 
+// exponential sweep from f0 to f1 with 5 ms fades, so (unlike a Hann window) both ends of the band keep their level
 fn chirp(f0: f32, f1: f32, sr: f32, dur_s: f32) -> Vec<f32> {
     let n = (dur_s * sr) as usize;
-    let k = (f1 / f0).ln() / dur_s; // log sweep rate
+    let k = (f1 as f64 / f0 as f64).ln() / dur_s as f64; // log sweep rate
+    let fade = ((sr / 200.0) as usize).min(n / 2).max(1);
     (0..n)
         .map(|i| {
-            let t = i as f32 / sr;
-            let phase = 2.0 * std::f32::consts::PI * f0 * ( (k * t).exp() - 1.0 ) / k;
-            (phase).sin() * hann(n, i)
+            let t = i as f64 / sr as f64;
+            // the phase runs into the tens of thousands of radians, too many for f32
+            let phase = 2.0 * std::f64::consts::PI * f0 as f64 * ( (k * t).exp() - 1.0 ) / k;
+            let edge = i.min(n - 1 - i);
+            let ramp = if edge < fade { 0.5 * (1.0 - (PI * edge as f32 / fade as f32).cos()) } else { 1.0 };
+            phase.sin() as f32 * ramp
         })
         .collect()
 }
 
+// Farina inverse filter for a `chirp` from f0 to f1: the sweep backwards, falling 6 dB per octave to make up
+// for the sweep spending longer on the low frequencies. Convolving the sweep with it gives a band-limited impulse.
+fn inverse_chirp(sweep: &[f32], f0: f32, f1: f32, sr: f32) -> Vec<f32> {
+    let k = (f1 / f0).ln() / (sweep.len() as f32 / sr);
+    sweep
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &s)| s * (-k * i as f32 / sr).exp())
+        .collect()
+}
+
+// Linear convolution through one big FFT, plenty fast for a few seconds of audio.
+pub(crate) fn convolve(x: &[f32], h: &[f32]) -> Vec<f32> {
+    if x.is_empty() || h.is_empty() {
+        return Vec::new();
+    }
+    let len = x.len() + h.len() - 1;
+    let n = len.next_power_of_two();
+    let mut xs: Vec<Complex32> = x.iter().map(|&v| Complex32::new(v, 0.0)).collect();
+    xs.resize(n, Complex32::new(0.0, 0.0));
+    let mut hs: Vec<Complex32> = h.iter().map(|&v| Complex32::new(v, 0.0)).collect();
+    hs.resize(n, Complex32::new(0.0, 0.0));
+    plan_fft(n, FftDirection::Forward).process(&mut xs);
+    plan_fft(n, FftDirection::Forward).process(&mut hs);
+    for (a, b) in xs.iter_mut().zip(&hs) {
+        *a *= b;
+    }
+    plan_fft(n, FftDirection::Inverse).process(&mut xs);
+    let scale = 1.0 / n as f32;
+    xs[..len].iter().map(|v| v.re * scale).collect()
+}
+
+
+/// Maximum length sequence (±1) from a Galois LFSR, repeated or truncated to `len`.
+/// `taps` has to be a primitive polynomial (see `primitive_lfsr_taps`).
+fn mls_sequence(taps: u32, len: usize) -> Vec<f32> {
+    let mut state = 1u32;
+    (0..len)
+        .map(|_| {
+            let bit = state & 1;
+            state >>= 1;
+            if bit != 0 {
+                state ^= taps;
+            }
+            if bit != 0 { 1.0 } else { -1.0 }
+        })
+        .collect()
+}
+
+thread_local! {
+    // primitive polynomials found so far per order, and the next tap mask to try
+    static LFSR_TAPS: std::cell::RefCell<HashMap<u32, (Vec<u32>, u32)>> = std::cell::RefCell::new(HashMap::new());
+}
+
+/// The `index`-th primitive feedback polynomial of the given order, found by searching tap masks in order
+/// and keeping the ones whose LFSR has the full 2^order - 1 period. None once the order has no more of them.
+/// Different polynomials give sequences with low cross-correlation, so each output device gets its own.
+/// The search is cached, as every probe detection needs the taps again
+fn primitive_lfsr_taps(order: u32, index: usize) -> Option<u32> {
+    let top = 1u32 << (order - 1);
+    let full_period = (1u64 << order) - 1;
+    let is_primitive = |taps: u32| {
+        let mut state = 1u32;
+        let mut period = 0u64;
+        loop {
+            let bit = state & 1;
+            state >>= 1;
+            if bit != 0 {
+                state ^= taps;
+            }
+            period += 1;
+            if state == 1 || period > full_period {
+                break;
+            }
+        }
+        period == full_period
+    };
+    LFSR_TAPS.with_borrow_mut(|cache| {
+        let (found, next) = cache.entry(order).or_insert_with(|| (Vec::new(), 1));
+        while found.len() <= index && *next < top {
+            if is_primitive(top | *next) {
+                found.push(top | *next);
+            }
+            *next += 1;
+        }
+        found.get(index).copied()
+    })
+}
+
+/// Low-level pseudo-noise probe for one output device: an MLS at `level_db` dBFS RMS with 5 ms fades.
+/// White and ~45 dB under speech it is barely audible, but correlating over the whole probe pulls it out of the noise.
+fn pseudo_noise_probe(device_index: usize, duration_ms: f32, sample_rate: u32, level_db: f32) -> Vec<f32> {
+    let samples = (duration_ms * sample_rate as f32 / 1000.0).round() as usize;
+    if samples == 0 {
+        return Vec::new();
+    }
+    // shortest sequence that doesn't repeat within the probe, so the correlation has one peak
+    // (or a longer one if that order has run out of polynomials for this many devices)
+    let order = (usize::BITS - samples.leading_zeros()).clamp(10, 20);
+    let Some(taps) = (order..32).find_map(|order| primitive_lfsr_taps(order, device_index)) else {
+        return Vec::new();
+    };
+    let gain = 10.0f32.powf(level_db / 20.0);
+    let fade = ((sample_rate / 200) as usize).min(samples / 2).max(1);
+    let mut probe = mls_sequence(taps, samples);
+    for (i, sample) in probe.iter_mut().enumerate() {
+        let edge = i.min(samples - 1 - i);
+        let ramp = if edge < fade { 0.5 * (1.0 - (PI * edge as f32 / fade as f32).cos()) } else { 1.0 };
+        *sample *= gain * ramp;
+    }
+    probe
+}
+
+/// Probe played on every output device by `AecStream::calibrate`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CalibrationProbe {
+    // 100 ms 150–800 Hz sweep at 0.3 gain, clearly audible
+    Chirp,
+    // 1 s maximum length sequence per device at `level_db` dBFS, found by correlating over the whole probe
+    PseudoNoise { level_db: f32 },
+}
+
+impl Default for CalibrationProbe {
+    fn default() -> Self {
+        CalibrationProbe::PseudoNoise { level_db: -45.0 }
+    }
+}
+
+impl CalibrationProbe {
+    fn duration_ms(&self) -> f32 {
+        match self {
+            CalibrationProbe::Chirp => 100.0,
+            CalibrationProbe::PseudoNoise { .. } => 1000.0,
+        }
+    }
+
+    fn generate(&self, device_index: usize, duration_ms: f32, sample_rate: u32) -> Vec<f32> {
+        match *self {
+            CalibrationProbe::Chirp => generate_probe_tone_for_device(device_index, duration_ms, sample_rate),
+            CalibrationProbe::PseudoNoise { level_db } => pseudo_noise_probe(device_index, duration_ms, sample_rate, level_db),
+        }
+    }
+}
+
+// Settings for AecStream::calibrate
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CalibrationConfig {
+    pub probe: CalibrationProbe,
+    // probe length, None for the probe's own (100 ms chirp, 1 s pseudo-noise)
+    pub probe_ms: Option<f32>,
+    // how long to listen, has to cover the probe plus the longest echo delay
+    pub capture_secs: f32,
+    // buffer of the stream each probe plays through
+    pub probe_buffer_secs: u32,
+    // resampler quality (0-10) of those streams
+    pub resampler_quality: i32,
+    // play the probe again this many seconds later and estimate each input's clock drift against each output
+    // from how far the delay moved (None, the default, plays it once)
+    pub drift_interval_secs: Option<f32>,
+    // more attempts at outputs some input didn't hear, each with a louder and twice as long probe
+    pub retries: u32,
+    // how much louder each retry plays a pseudo-noise probe, and the loudest it gets (dBFS RMS)
+    pub retry_step_db: f32,
+    pub max_level_db: f32,
+    // longest probe a retry plays
+    pub max_probe_ms: f32,
+}
+
+impl Default for CalibrationConfig {
+    fn default() -> Self {
+        Self {
+            probe: CalibrationProbe::default(),
+            probe_ms: None,
+            capture_secs: 3.0,
+            probe_buffer_secs: 2,
+            resampler_quality: 5,
+            drift_interval_secs: None,
+            retries: 3,
+            retry_step_db: 10.0,
+            max_level_db: -20.0,
+            max_probe_ms: 4000.0,
+        }
+    }
+}
+
+impl CalibrationConfig {
+    fn probe_duration_ms(&self) -> f32 {
+        self.probe_ms.unwrap_or_else(|| self.probe.duration_ms())
+    }
+
+    fn generate_probe(&self, device_index: usize, sample_rate: u32) -> Vec<f32> {
+        self.probe.generate(device_index, self.probe_duration_ms(), sample_rate)
+    }
+
+    // settings for the next retry: louder (pseudo-noise only, the chirp is already loud) and longer,
+    // up to the ceilings. None once it's at both
+    fn louder(&self) -> Option<Self> {
+        let mut next = *self;
+        if let CalibrationProbe::PseudoNoise { level_db } = &mut next.probe {
+            *level_db = (*level_db + self.retry_step_db).min(self.max_level_db.max(*level_db));
+        }
+        let duration_ms = self.probe_duration_ms();
+        next.probe_ms = Some((duration_ms * 2.0).min(self.max_probe_ms.max(duration_ms)));
+        (next.probe != self.probe || next.probe_duration_ms() != duration_ms).then_some(next)
+    }
+}
+
+/// Where a calibration is, passed to the `AecStream::on_calibration_progress` callback.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CalibrationProgress {
+    // probes queued on the outputs
+    Probing,
+    // listening, with the fraction of the capture done so far
+    Capturing(f32),
+    // looking for the probes in what was captured
+    Analysing,
+    // some outputs weren't heard, probing them again louder, with the attempt number from 1
+    Retrying(u32),
+}
 
-/// Probe detection using GCC-PHAT to estimate lag between the captured stream and each probe.
-/// Returns best (device_index, start_sample, score) per device that produced a valid match.
-fn detect_probe_tones(input_mono: &[f32], num_devices: usize, duration_ms: f32, sample_rate: u32) -> Vec<(usize, i64, f32)> {
-    let mut results = Vec::new();
-    if num_devices == 0 || input_mono.is_empty() {
-        return results;
+/// Aborts the calibration running on the `AecStream` it came from (see `AecStream::calibration_cancel`),
+/// which then returns an error and leaves the alignment as it was.
+#[derive(Debug, Clone, Default)]
+pub struct CalibrationCancel(Arc<AtomicBool>);
+
+impl CalibrationCancel {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Why one output→input pair couldn't be calibrated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CalibrationFailure {
+    // the probe never showed up in the output's own stream (no producer for it, or it didn't play)
+    OutputNotPlayed,
+    // the probe played but the input didn't pick it up, even after retrying louder and longer.
+    // Usually the speakers are too quiet or muted
+    NotHeard,
+}
+
+/// Calibration result for one output device as heard by one input device.
+#[derive(Debug, Clone)]
+pub struct CalibrationPair {
+    pub output_device: String,
+    pub input_device: String,
+    // echo delay from the output stream to the input stream, in samples at the target rate
+    pub delay_samples: Option<i64>,
+    // sub-sample part of the delay on top of delay_samples, -0.5..0.5
+    pub delay_fraction: f32,
+    pub delay_ms: Option<f32>,
+    // normalized correlation of the input with the probe at the detected offset, 0..1
+    pub confidence: f32,
+    // probe energy against everything else the input heard meanwhile
+    pub snr_db: f32,
+    pub failure: Option<CalibrationFailure>,
+    // how much faster the input's stream runs than the output's, when measuring drift
+    pub drift_ppm: Option<f32>,
+}
+
+/// What `AecStream::calibrate` measured and did.
+#[derive(Debug, Clone, Default)]
+pub struct CalibrationReport {
+    pub sample_rate: u32,
+    // one entry per output device per input device, inputs outermost
+    pub pairs: Vec<CalibrationPair>,
+    // samples skipped on each input device to line it up with the outputs
+    pub input_shifts: Vec<(String, i64)>,
+    // samples skipped on every output's reference, for inputs that heard the echo too early to fix by skipping input
+    pub reference_shift: i64,
+    // samples each output's reference is held back by, so outputs further away line up too.
+    // Includes the sub-sample part, which only delays of FRACTIONAL_DELAY_LATENCY samples or more get
+    pub output_delays: Vec<(String, f32)>,
+    // the delays came from a saved calibration that this run only verified
+    pub from_saved: bool,
+    // measurements it took, more than one when outputs that weren't heard got retried
+    pub attempts: u32,
+    // no input heard any output, so echo cancellation is bypassed
+    pub acoustically_isolated: bool,
+    // ppm each input's resampling rate was moved by to cancel its measured drift
+    pub drift_corrections: Vec<(String, f32)>,
+    // (file name, mono WAV) of what each device captured, when calibrating with debug_wav
+    pub debug_wavs: Vec<(String, Vec<u8>)>,
+}
+
+impl CalibrationReport {
+    pub fn failed_pairs(&self) -> impl Iterator<Item = &CalibrationPair> {
+        self.pairs.iter().filter(|pair| pair.failure.is_some())
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.failed_pairs().next().is_none()
+    }
+
+    // takes over what `retry` measured for the pairs of `outputs` that were still failing, and its debug captures
+    fn merge_retry(&mut self, retry: CalibrationReport, outputs: &[String], attempt: u32) {
+        for pair in retry.pairs.into_iter().filter(|pair| outputs.contains(&pair.output_device)) {
+            if let Some(old) = self.pairs.iter_mut().find(|old| {
+                old.failure.is_some() && old.output_device == pair.output_device && old.input_device == pair.input_device
+            }) {
+                *old = pair;
+            }
+        }
+        self.debug_wavs.extend(
+            retry.debug_wavs.into_iter().map(|(name, wav)| (name.replace(".wav", &format!("_retry{attempt}.wav")), wav)),
+        );
+        self.attempts += retry.attempts;
+    }
+}
+
+const SAVED_CALIBRATION_MAGIC: &[u8; 4] = b"MCAL";
+const SAVED_CALIBRATION_VERSION: u32 = 1;
+// how far a quick verification may measure a pair's delay from the saved one and still reuse it
+const SAVED_CALIBRATION_TOLERANCE_MS: f32 = 2.0;
+// a saved calibration younger than this is applied without playing a probe at all. If the delays did
+// move since, the delay tracker and echo path monitor pick that up like any other change
+const SAVED_CALIBRATION_TRUSTED_SECS: u64 = 60 * 60;
+
+/// Calibration kept across sessions for the same devices, see `AecStream::calibrate_from_saved`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SavedCalibration {
+    // target sample rate the delays are in
+    pub sample_rate: u32,
+    // device sample rates at the time, a different rate means different buffering and so different delays
+    pub input_sample_rates: Vec<(String, u32)>,
+    pub output_sample_rates: Vec<(String, u32)>,
+    // (output device, input device, echo delay in samples) for every pair that was heard.
+    // this is the input offset minus the output offset, the offsets on their own only mean something
+    // within one capture. Counted from before any shifts were applied, so a later calibration of an
+    // already aligned stream saves the same delays
+    pub delays: Vec<(String, String, i64)>,
+    // unix time in microseconds when it was measured
+    pub created_micros: u64,
+}
+
+struct SavedCalibrationReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> SavedCalibrationReader<'a> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], Box<dyn Error>> {
+        if self.bytes.len() < N {
+            return Err("saved calibration is truncated".into());
+        }
+        let (head, tail) = self.bytes.split_at(N);
+        self.bytes = tail;
+        Ok(head.try_into()?)
+    }
+
+    fn u32(&mut self) -> Result<u32, Box<dyn Error>> {
+        Ok(u32::from_le_bytes(self.take()?))
     }
 
-    for device in 0..num_devices {
-        let probe = generate_probe_tone_for_device(device, duration_ms, sample_rate);
-        if probe.is_empty() || probe.len() > input_mono.len() {
-            continue;
+    fn string(&mut self) -> Result<String, Box<dyn Error>> {
+        let len = self.u32()? as usize;
+        if self.bytes.len() < len {
+            return Err("saved calibration is truncated".into());
         }
-        // Pad probe to match captured length for GCC-PHAT.
-        let mut probe_padded = vec![0.0f32; input_mono.len()];
-        probe_padded[..probe.len()].copy_from_slice(&probe);
-        let _margin = input_mono.len().saturating_sub(1);
-        let lag = gcc_phat_delay(&input_mono, &probe_padded);
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(String::from_utf8(head.to_vec())?)
+    }
+
+    fn sample_rates(&mut self) -> Result<Vec<(String, u32)>, Box<dyn Error>> {
+        (0..self.u32()?).map(|_| Ok((self.string()?, self.u32()?))).collect()
+    }
+}
+
+impl SavedCalibration {
+    pub fn delay(&self, output_device: &str, input_device: &str) -> Option<i64> {
+        self.delays
+            .iter()
+            .find(|(output, input, _)| output == output_device && input == input_device)
+            .map(|(_, _, delay)| *delay)
+    }
+
+    /// Serialize into a compact little-endian byte blob.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        fn put_u32(out: &mut Vec<u8>, v: u32) {
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+        fn put_str(out: &mut Vec<u8>, v: &str) {
+            put_u32(out, v.len() as u32);
+            out.extend_from_slice(v.as_bytes());
+        }
+        let mut out = Vec::new();
+        out.extend_from_slice(SAVED_CALIBRATION_MAGIC);
+        put_u32(&mut out, SAVED_CALIBRATION_VERSION);
+        put_u32(&mut out, self.sample_rate);
+        out.extend_from_slice(&self.created_micros.to_le_bytes());
+        for rates in [&self.input_sample_rates, &self.output_sample_rates] {
+            put_u32(&mut out, rates.len() as u32);
+            for (name, rate) in rates {
+                put_str(&mut out, name);
+                put_u32(&mut out, *rate);
+            }
+        }
+        put_u32(&mut out, self.delays.len() as u32);
+        for (output, input, delay) in &self.delays {
+            put_str(&mut out, output);
+            put_str(&mut out, input);
+            out.extend_from_slice(&delay.to_le_bytes());
+        }
+        out
+    }
+
+    /// Parse a blob produced by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let mut reader = SavedCalibrationReader { bytes };
+        if &reader.take::<4>()? != SAVED_CALIBRATION_MAGIC {
+            return Err("not a saved calibration".into());
+        }
+        let version = reader.u32()?;
+        if version != SAVED_CALIBRATION_VERSION {
+            return Err(format!("unsupported saved calibration version {version}").into());
+        }
+        let sample_rate = reader.u32()?;
+        let created_micros = u64::from_le_bytes(reader.take()?);
+        let input_sample_rates = reader.sample_rates()?;
+        let output_sample_rates = reader.sample_rates()?;
+        let delays = (0..reader.u32()?)
+            .map(|_| Ok((reader.string()?, reader.string()?, i64::from_le_bytes(reader.take()?))))
+            .collect::<Result<_, Box<dyn Error>>>()?;
+        Ok(Self {
+            sample_rate,
+            input_sample_rates,
+            output_sample_rates,
+            delays,
+            created_micros,
+        })
+    }
+}
+
+// log sweep played by AecStream::measure_impulse_response
+const IR_SWEEP_SECS: f32 = 3.0;
+const IR_SWEEP_MIN_HZ: f32 = 50.0;
+const IR_SWEEP_MAX_HZ: f32 = 20_000.0;
+// loud enough to stay well above the room noise over the whole IR, not so loud the speaker distorts
+const IR_SWEEP_LEVEL_DB: f32 = -12.0;
+
+/// Room impulse response from one output device to one input device.
+#[derive(Debug, Clone, Default)]
+pub struct ImpulseResponse {
+    pub output_device: String,
+    pub input_device: String,
+    pub sample_rate: u32,
+    // relative to the reference the echo canceller gets, so the leading silence is the echo lag it sees
+    // and a gain of 1.0 means the input got the reference back at the level it was played
+    pub samples: Vec<f32>,
+}
+
+impl ImpulseResponse {
+    /// Mono 32-bit float WAV of `samples`, e.g. for `aec_eval::read_wav_mono`.
+    pub fn to_wav(&self) -> Result<Vec<u8>, Box<dyn Error>> {
+        wav_bytes(&self.samples, self.sample_rate)
+    }
+}
+
+// probe played on each channel by AecStream::identify_speaker_channels
+const CHANNEL_PROBE_SECS: f32 = 0.5;
+// how long to keep listening after a channel's probe, for the echo to come back
+const CHANNEL_LISTEN_SECS: f32 = 0.7;
+
+/// Whether one output channel was heard on one input device.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpeakerChannel {
+    pub output_device: String,
+    pub input_device: String,
+    // physical channel of the output device, as used in begin_audio_stream's channel_map
+    pub channel: usize,
+    pub audible: bool,
+    // normalized correlation of the probe with the input, 0..1
+    pub confidence: f32,
+    // level relative to the loudest audible channel on this input, None if not audible
+    pub relative_level_db: Option<f32>,
+    // relative to the reference the echo canceller gets, None if not audible
+    pub delay_ms: Option<f32>,
+}
+
+fn wav_bytes(samples: &[f32], sample_rate: u32) -> Result<Vec<u8>, Box<dyn Error>> {
+    let spec = WavSpec {
+        channels: 1,
+        sample_rate,
+        bits_per_sample: 32,
+        sample_format: HoundSampleFormat::Float,
+    };
+    let mut wav = Cursor::new(Vec::new());
+    let mut writer = WavWriter::new(&mut wav, spec)?;
+    for &sample in samples {
+        writer.write_sample(sample)?;
+    }
+    writer.finalize()?;
+    Ok(wav.into_inner())
+}
+
+// correlation peak to rms ratio a pseudo-noise probe needs before it counts as heard
+const MIN_PROBE_SCORE: f32 = 10.0;
+// normalized correlation a chirp needs at the gcc-phat lag, gcc-phat's own score is unreliable on
+// narrowband chirps in a reverberant room (heard chirps land around 0.4-0.7, silence below 0.05)
+const MIN_CHIRP_CONFIDENCE: f32 = 0.3;
+
+// what the probe detector found for one device's probe in one capture
+#[derive(Debug, Clone, Copy)]
+struct ProbeDetection {
+    // start of the probe in the capture, None if it wasn't found
+    start: Option<i64>,
+    // where between samples the best match is, -0.5..0.5 on top of start
+    fraction: f32,
+    // normalized correlation with the capture at the best match, 0..1
+    confidence: f32,
+    // probe energy against everything else in the capture window, derived from the confidence
+    snr_db: f32,
+}
+
+/// Probe detection for every device's probe in `input_mono`, indexed by device.
+fn detect_probe_tones(input_mono: &[f32], num_devices: usize, config: &CalibrationConfig, sample_rate: u32) -> Vec<ProbeDetection> {
+    (0..num_devices)
+        .map(|device| detect_probe_tone(input_mono, device, config, sample_rate))
+        .collect()
+}
+
+/// Probe detection: GCC-PHAT for chirps, a matched filter over the whole probe for pseudo-noise.
+/// The best match is rated by the normalized correlation at that offset either way.
+fn detect_probe_tone(input_mono: &[f32], device: usize, config: &CalibrationConfig, sample_rate: u32) -> ProbeDetection {
+    let probe_kind = &config.probe;
+    let probe = config.generate_probe(device, sample_rate);
+    let miss = ProbeDetection { start: None, fraction: 0.0, confidence: 0.0, snr_db: f32::NEG_INFINITY };
+    let best = match probe_kind {
+        CalibrationProbe::PseudoNoise { .. } => detect_probe_correlation(input_mono, &probe),
+        CalibrationProbe::Chirp => {
+            if probe.is_empty() || probe.len() > input_mono.len() {
+                return miss;
+            }
+            // Pad probe to match captured length for GCC-PHAT.
+            let mut probe_padded = vec![0.0f32; input_mono.len()];
+            probe_padded[..probe.len()].copy_from_slice(&probe);
             // positive lag means probe leads capture; lag is the start index in the capture
-        println!("Got lag {lag}");
-        results.push((device, lag as i64, 0.0));
+            let lag = gcc_phat_delay(input_mono, &probe_padded);
+            let start = lag.round();
+            (start >= 0.0).then_some((start as usize, lag - start, 0.0))
+        }
+    };
+    let Some((start, fraction, score)) = best else {
+        return miss;
+    };
+    let (confidence, snr_db) = probe_match_quality(input_mono, &probe, start);
+    let heard = match probe_kind {
+        CalibrationProbe::PseudoNoise { .. } => score >= MIN_PROBE_SCORE,
+        CalibrationProbe::Chirp => confidence >= MIN_CHIRP_CONFIDENCE,
+    };
+    ProbeDetection { start: heard.then_some(start as i64), fraction, confidence, snr_db }
+}
+
+/// Sub-sample position of `probe` in `input` near `start`: the strongest raw correlation within `radius` samples,
+/// moved by the vertex of a parabola through it and its neighbours.
+fn refine_probe_start(input: &[f32], probe: &[f32], start: usize, radius: usize) -> f64 {
+    let corr = |offset: usize| -> f64 {
+        input.get(offset..).map_or(0.0, |window| window.iter().zip(probe).map(|(&a, &b)| a as f64 * b as f64).sum())
+    };
+    let peak = (start.saturating_sub(radius)..=start + radius)
+        .max_by(|&a, &b| corr(a).abs().total_cmp(&corr(b).abs()))
+        .unwrap_or(start);
+    if peak == 0 {
+        return 0.0;
+    }
+    // the echo can come back inverted
+    let sign = corr(peak).signum();
+    peak as f64 + parabolic_peak_offset(sign * corr(peak - 1), sign * corr(peak), sign * corr(peak + 1))
+}
+
+/// Where the vertex of a parabola through three neighbouring values lies relative to the middle one, -0.5..0.5.
+/// 0 unless the middle one is a maximum.
+fn parabolic_peak_offset(left: f64, mid: f64, right: f64) -> f64 {
+    let curvature = left - 2.0 * mid + right;
+    if curvature >= 0.0 {
+        return 0.0;
+    }
+    (0.5 * (left - right) / curvature).clamp(-0.5, 0.5)
+}
+
+/// Drift of `input` against `output` in ppm from output `device`'s probe, found at `first` (output start,
+/// input start) and again `split` samples later. Positive means the input's stream gains samples on the output's.
+fn probe_drift_ppm(output: &[f32], input: &[f32], device: usize, first: (i64, i64), split: usize, config: &CalibrationConfig, sample_rate: u32) -> Option<f32> {
+    let (second_output, second_input) = (output.get(split..)?, input.get(split..)?);
+    let second = (
+        detect_probe_tone(second_output, device, config, sample_rate).start?,
+        detect_probe_tone(second_input, device, config, sample_rate).start?,
+    );
+    // a whole sample over a few seconds is tens of ppm, so this needs the sub-sample positions. The detected
+    // start can sit a lobe off the peak of an inverted echo, so look a couple of milliseconds around it
+    let probe = config.generate_probe(device, sample_rate);
+    let radius = sample_rate as usize / 500;
+    let refine = |buf: &[f32], start: i64| refine_probe_start(buf, &probe, start as usize, radius);
+    let output_first = refine(output, first.0);
+    let input_first = refine(input, first.1);
+    let output_second = split as f64 + refine(second_output, second.0);
+    let input_second = split as f64 + refine(second_input, second.1);
+    let elapsed = output_second - output_first;
+    if elapsed <= 0.0 {
+        return None;
+    }
+    let moved = (input_second - output_second) - (input_first - output_first);
+    Some((moved / elapsed * 1e6) as f32)
+}
+
+/// Normalized correlation of `probe` with `input` starting at `start` (the `detect_probe_fft` score at a
+/// single offset), and the SNR it implies: rho^2 of the window's energy is probe, the rest isn't.
+/// Reverb the echo path adds to the probe counts as noise, so this reads low in a live room.
+fn probe_match_quality(input: &[f32], probe: &[f32], start: usize) -> (f32, f32) {
+    let window = input.get(start..).unwrap_or(&[]);
+    let (mut corr, mut win_energy, mut probe_energy) = (0.0f32, 0.0f32, 0.0f32);
+    for (&x, &p) in window.iter().zip(probe) {
+        corr += x * p;
+        win_energy += x * x;
+        probe_energy += p * p;
+    }
+    let denom = (win_energy * probe_energy).sqrt();
+    if denom == 0.0 {
+        return (0.0, f32::NEG_INFINITY);
+    }
+    // the echo path can flip polarity
+    let confidence = (corr.abs() / denom).min(1.0);
+    let rho2 = confidence * confidence;
+    let snr_db = 10.0 * (rho2.max(1e-12) / (1.0 - rho2).max(1e-6)).log10();
+    (confidence, snr_db)
+}
+
+/// Matched filter: correlates `input` with `probe` at every start offset (the probe may run past the end)
+/// and returns (start_index, sub-sample offset of the peak, peak to rms ratio of the correlation).
+/// Integrating over the whole probe is what lets a probe far below the noise floor still stand out.
+/// The input spectrum is whitened first (the probe already is), otherwise speech and hum in the
+/// capture dominate the correlation.
+fn detect_probe_correlation(input: &[f32], probe: &[f32]) -> Option<(usize, f32, f32)> {
+    if probe.is_empty() || input.is_empty() {
+        return None;
+    }
+    let n_fft = (input.len() + probe.len() - 1).next_power_of_two();
+    let mut a: Vec<Complex32> = input.iter().map(|&v| Complex32::new(v, 0.0)).collect();
+    let mut b: Vec<Complex32> = probe.iter().map(|&v| Complex32::new(v, 0.0)).collect();
+    a.resize(n_fft, Complex32::ZERO);
+    b.resize(n_fft, Complex32::ZERO);
+
+    plan_fft(n_fft, FftDirection::Forward).process(&mut a);
+    plan_fft(n_fft, FftDirection::Forward).process(&mut b);
+    let mean_mag = a.iter().map(|v| v.norm()).sum::<f32>() / n_fft as f32;
+    for (ai, bi) in a.iter_mut().zip(b.iter()) {
+        let mag = ai.norm().max(1e-3 * mean_mag);
+        *ai = *ai * bi.conj() / mag;
+    }
+    plan_fft(n_fft, FftDirection::Inverse).process(&mut a);
+
+    // index k holds sum_i input[i + k] * probe[i], only non-negative starts are meaningful
+    let corr = &a[..input.len()];
+    let rms = (corr.iter().map(|c| c.re * c.re).sum::<f32>() / corr.len() as f32).sqrt();
+    if rms == 0.0 {
+        return None;
     }
-    results
+    // the echo path can flip polarity, so take the strongest peak either way
+    let (start, peak) = corr
+        .iter()
+        .enumerate()
+        .map(|(i, c)| (i, c.re.abs()))
+        .fold((0usize, 0.0f32), |best, cur| if cur.1 > best.1 { cur } else { best });
+    let sign = corr[start].re.signum();
+    let at = |i: Option<usize>| i.and_then(|i| corr.get(i)).map_or(0.0, |c| (sign * c.re) as f64);
+    let fraction = parabolic_peak_offset(at(start.checked_sub(1)), peak as f64, at(Some(start + 1)));
+    Some((start, fraction as f32, peak / rms))
 }
 
 /// Cross-correlate `input` with `probe` using FFT convolution.
@@ -301,9 +959,8 @@ fn detect_probe_fft(input: &[f32], probe: &[f32]) -> Option<(usize, f32)> {
     a.resize(n_fft, Complex32::ZERO);
     b.resize(n_fft, Complex32::ZERO);
 
-    let mut planner = FftPlanner::<f32>::new();
-    let fft = planner.plan_fft_forward(n_fft);
-    let ifft = planner.plan_fft_inverse(n_fft);
+    let fft = plan_fft(n_fft, FftDirection::Forward);
+    let ifft = plan_fft(n_fft, FftDirection::Inverse);
 
     fft.process(&mut a);
     fft.process(&mut b);
@@ -337,15 +994,31 @@ fn detect_probe_fft(input: &[f32], probe: &[f32]) -> Option<(usize, f32)> {
     }
     best
 }
+// index of the strongest tap of an impulse response, None if it hasn't learned anything yet
+pub fn dominant_delay(response: &[f32]) -> Option<usize> {
+    let (idx, peak) = response
+        .iter()
+        .enumerate()
+        .map(|(i, v)| (i, v.abs()))
+        .fold((0usize, 0.0f32), |best, cur| if cur.1 > best.1 { cur } else { best });
+    if peak > 0.0 { Some(idx) } else { None }
+}
+
 fn normalize(x: &[f32]) -> Vec<f32> {
     let peak = x.iter().fold(0.0f32, |m, &v| m.max(v.abs()));
     if peak == 0.0 { return x.to_vec(); }
     let gain = 1.0 / peak;
     x.iter().map(|&v| v * gain).collect()
 }
-/// Estimates delay (in samples) between x and y using GCC‑PHAT.
+/// Estimates delay (in samples) between x and y using GCC‑PHAT, to a fraction of a sample.
 /// Assumes x.len() == y.len() and power-of-two length for simplicity.
-fn gcc_phat_delay(x_in: &[f32], y_in: &[f32]) -> isize {
+fn gcc_phat_delay(x_in: &[f32], y_in: &[f32]) -> f32 {
+    gcc_phat_delay_with_score(x_in, y_in).0
+}
+
+/// Same as `gcc_phat_delay`, also returning the peak to RMS ratio of the correlation as a confidence score.
+/// Uncorrelated speech-like signals stay around 10 or below, an echo path scores in the tens or more.
+fn gcc_phat_delay_with_score(x_in: &[f32], y_in: &[f32]) -> (f32, f32) {
     let x = normalize(x_in);
     let y = normalize(y_in);
     let n = x.len();
@@ -354,8 +1027,7 @@ fn gcc_phat_delay(x_in: &[f32], y_in: &[f32]) -> isize {
     let mut y_vec: Vec<Complex<f32>> = y.iter().map(|&v| Complex::new(v, 0.0)).collect();
 
     // Forward FFT
-    let mut planner = FftPlanner::<f32>::new();
-    let fft = planner.plan_fft_forward(n);
+    let fft = plan_fft(n, FftDirection::Forward);
     fft.process(&mut x_vec);
     fft.process(&mut y_vec);
 
@@ -367,7 +1039,7 @@ fn gcc_phat_delay(x_in: &[f32], y_in: &[f32]) -> isize {
     }).collect();
 
     // Inverse FFT to get correlation-like function
-    let ifft = planner.plan_fft_inverse(n);
+    let ifft = plan_fft(n, FftDirection::Inverse);
     ifft.process(&mut psi);
 
     // Take the peak: unwrap indices so delays near end map to negative lags
@@ -379,12 +1051,17 @@ fn gcc_phat_delay(x_in: &[f32], y_in: &[f32]) -> isize {
             max_idx = i;
         }
     }
+    let rms = (psi.iter().map(|v| v.re * v.re).sum::<f32>() / n as f32).sqrt();
+    let score = if rms > 0.0 { max_val / rms } else { 0.0 };
     let half = n / 2;
-    if max_idx > half {
+    let lag = if max_idx > half {
         (max_idx as isize) - n as isize // negative lag
     } else {
         max_idx as isize                 // positive/zero lag
-    }
+    };
+    // the true peak falls between bins, the correlation wraps around at the ends
+    let fraction = parabolic_peak_offset(psi[(max_idx + n - 1) % n].re as f64, max_val as f64, psi[(max_idx + 1) % n].re as f64);
+    (lag as f32 + fraction as f32, score)
 }
 
 /// GCC-PHAT delay estimator between two real signals.
@@ -410,9 +1087,8 @@ fn gcc_phat_delay_old(siga: &[f32], sigb: &[f32], margin: usize) -> Option<(i64,
         b[i].re = v;
     }
 
-    let mut planner = FftPlanner::<f32>::new();
-    let fft_fwd = planner.plan_fft_forward(n_fft);
-    let fft_inv = planner.plan_fft_inverse(n_fft);
+    let fft_fwd = plan_fft(n_fft, FftDirection::Forward);
+    let fft_inv = plan_fft(n_fft, FftDirection::Inverse);
 
     fft_fwd.process(&mut a);
     fft_fwd.process(&mut b);
@@ -662,6 +1338,8 @@ impl ResampledBufferedCircularProducer {
 
 enum AudioBufferMetadata {
     Arrive(u64, u128, u128, bool),
+    // shift the resampling rate by this many ppm on top of what it already is (clock drift from calibration)
+    AdjustDrift(f64),
     Teardown(),
 }
 
@@ -804,6 +1482,12 @@ struct StreamAlignerResampler {
     input_sample_rate: u32,
     output_sample_rate: u32,
     dynamic_output_sample_rate: u32,
+    // clock drift against the other devices measured by calibration. It stretches the target frame count,
+    // which the dynamic rate then follows
+    drift_ppm: f64,
+    // frames the drift has added to the target so far, and the undrifted target they were added up to
+    drift_frames: f64,
+    drift_target_frames: u128,
     input_audio_buffer_consumer: ResampledBufferedCircularProducer,
     input_audio_buffer_metadata_consumer: mpsc::Receiver<AudioBufferMetadata>,
     total_emitted_frames: u128,
@@ -831,6 +1515,9 @@ impl StreamAlignerResampler {
             input_sample_rate: input_sample_rate,
             output_sample_rate: output_sample_rate,
             dynamic_output_sample_rate: output_sample_rate,
+            drift_ppm: 0.0,
+            drift_frames: 0.0,
+            drift_target_frames: 0,
             // we need buffered because this interfaces with speex which expects continuous buffers
             input_audio_buffer_consumer: ResampledBufferedCircularProducer::new(
                 channels,
@@ -851,29 +1538,43 @@ impl StreamAlignerResampler {
 
     // do it very slowly
     fn decrease_dynamic_sample_rate(&mut self)  -> Result<(), Box<dyn std::error::Error>>  {
-        if self.dynamic_output_sample_rate >= self.output_sample_rate {
+        if self.dynamic_output_sample_rate >= self.drifted_output_sample_rate() {
             self.dynamic_output_sample_rate -= 1;
             println!("Decrease dynamic sample");
         }
         //self.dynamic_output_sample_rate = (((self.output_sample_rate as f32) * 0.95) as i128).max((self.dynamic_output_sample_rate-1) as i128) as u32;
-        self.input_audio_buffer_consumer.set_sample_rate(self.input_sample_rate, self.dynamic_output_sample_rate)?;
-        Ok(())
+        self.apply_dynamic_sample_rate()
     }
 
     fn increase_dynamic_sample_rate(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        if self.dynamic_output_sample_rate <= self.output_sample_rate {
+        if self.dynamic_output_sample_rate <= self.drifted_output_sample_rate() {
             self.dynamic_output_sample_rate += 1;
             println!("Increase dynamic sample");
         }
         //self.dynamic_output_sample_rate = (((self.output_sample_rate as f32) * 1.05) as i128).min((self.dynamic_output_sample_rate+1) as i128) as u32;
-        self.input_audio_buffer_consumer.set_sample_rate(self.input_sample_rate, self.dynamic_output_sample_rate)?;
-        Ok(())
+        self.apply_dynamic_sample_rate()
+    }
+
+    // whole Hz the dynamic rate steps around, so it can follow a drift of more than 1 Hz
+    fn drifted_output_sample_rate(&self) -> u32 {
+        (self.output_sample_rate as f64 * (1.0 + self.drift_ppm * 1e-6)).round() as u32
+    }
+
+    fn apply_dynamic_sample_rate(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        // speex's fractional ratios overflow once the denominator gets past 2^16, and whole Hz are 60 ppm steps
+        // at 16 kHz, so the rate stays whole and the drift goes into the target instead (see handle_metadata)
+        self.input_audio_buffer_consumer.set_sample_rate(self.input_sample_rate, self.dynamic_output_sample_rate)
     }
 
     fn handle_metadata(&mut self, num_available_frames : u64, target_emitted_input_frames : u128, calibrated: bool) -> Result<(usize, usize), Box<dyn std::error::Error>> {
         let estimated_emitted_frames = input_to_output_frames(num_available_frames as u128, self.input_sample_rate, self.dynamic_output_sample_rate);
         let updated_total_frames_emitted = self.total_emitted_frames + estimated_emitted_frames;
         let target_emitted_output_frames = input_to_output_frames(target_emitted_input_frames, self.input_sample_rate, self.output_sample_rate);
+        // only what the target grew by since the drift was set gets stretched, so setting it doesn't move what's past
+        let target_growth = target_emitted_output_frames.saturating_sub(self.drift_target_frames);
+        self.drift_frames += target_growth as f64 * self.drift_ppm * 1e-6;
+        self.drift_target_frames = self.drift_target_frames.max(target_emitted_output_frames);
+        let target_emitted_output_frames = (target_emitted_output_frames as i128 + self.drift_frames.round() as i128).max(0) as u128;
         // dynamic adjustment to synchronize input devices to global clock:
         // don't do dynamic adjustment until after calibration, bc it's not gonna drift too much over the course of just a few seconds of calibration data
         // and that simplifies logic/prevents accumulated error during calibration
@@ -931,6 +1632,14 @@ impl StreamAlignerResampler {
                     self.finished_resampling_producer.try_send(ResamplingMetadata::Arrive(produced / self.channels, system_micros_at_start_of_packet, system_micros_after_resampled_packet_finishes as u128, calibrated))?;
                     Ok(true)
                 },
+                AudioBufferMetadata::AdjustDrift(ppm) => {
+                    self.drift_ppm += ppm;
+                    aec_log(format!("Resampling drift now {} ppm", self.drift_ppm));
+                    // start from the drifted rate rather than stepping there a Hz at a time
+                    self.dynamic_output_sample_rate = self.drifted_output_sample_rate();
+                    self.apply_dynamic_sample_rate()?;
+                    Ok(true)
+                }
                 AudioBufferMetadata::Teardown() => {
                     Ok(false)
                 }
@@ -949,6 +1658,10 @@ struct StreamAlignerConsumer {
     initial_metadata: Vec<ResamplingMetadata>,
     frames_recieved: u128,
     calibrated: bool,
+    // frames to move this stream by on the next read, positive drops input, negative holds it back behind silence
+    pending_shift: i64,
+    // every shift this stream was given since it was added, pending_shift included
+    shifted_frames: i64,
 }
 
 impl StreamAlignerConsumer {
@@ -962,9 +1675,17 @@ impl StreamAlignerConsumer {
             initial_metadata: Vec::new(),
             frames_recieved: 0,
             calibrated: false,
+            pending_shift: 0,
+            shifted_frames: 0,
         }
     }
 
+    // moves the stream by `frames` on the next read, see pending_shift
+    fn shift(&mut self, frames: i64) {
+        self.pending_shift += frames;
+        self.shifted_frames += frames;
+    }
+
     // used to poll for when an input stream is actually ready to output data
     // we allow some initial calibration time to synchronize the clocks
     // (it needs some extra time because packets can be delayed sometimes 
@@ -1092,6 +1813,12 @@ impl StreamAlignerConsumer {
     fn finish_read(&mut self, size: usize) -> usize {
         self.final_audio_buffer_consumer.finish_read(size)
     }
+
+    // emit ppm more samples per second (negative: fewer) than this stream does now, to make up for clock drift
+    fn adjust_drift(&mut self, ppm: f64) -> Result<(), Box<dyn Error>> {
+        self.thread_message_sender.try_send(AudioBufferMetadata::AdjustDrift(ppm))?;
+        Ok(())
+    }
 }
 
 impl Drop for StreamAlignerConsumer {
@@ -1168,7 +1895,7 @@ fn spawn_resampler_loop(mut resampler: StreamAlignerResampler) {
         } else {
             thread::spawn(move || {
                 loop {
-                    match resampler.resample() {
+                    match futures::executor::block_on(resampler.resample()) {
                         Ok(true) => continue,
                         Ok(false) => break,
                         Err(err) => {
@@ -1528,73 +2255,715 @@ impl OutputDeviceConfig {
 pub struct AecConfig {
     target_sample_rate: u32,
     frame_size: usize,
-    filter_length: usize
+    filter_length: usize,
+    backend: EchoCancellerKind,
+    fallback_backend: Option<EchoCancellerKind>,
+    preprocess: Option<PreprocessConfig>,
+    delay_tracking: Option<DelayTrackingConfig>,
+    echo_path_monitor: Option<EchoPathMonitorConfig>,
+    isolation: Option<IsolationConfig>,
+    calibration: CalibrationConfig,
 }
 
 impl AecConfig {
     pub fn new(target_sample_rate: u32, frame_size: usize, filter_length: usize) -> Self {
-        Self { target_sample_rate, frame_size, filter_length }
+        Self {
+            target_sample_rate,
+            frame_size,
+            filter_length,
+            backend: EchoCancellerKind::Aec3,
+            fallback_backend: Some(EchoCancellerKind::Speex),
+            preprocess: None,
+            delay_tracking: Some(DelayTrackingConfig::default()),
+            echo_path_monitor: Some(EchoPathMonitorConfig::default()),
+            isolation: Some(IsolationConfig::default()),
+            calibration: CalibrationConfig::default(),
+        }
     }
-}
 
-async fn get_input_stream_aligners(device_config: &InputDeviceConfig, aec_config: &AecConfig) -> Result<(InputStream, StreamAlignerConsumer), Box<dyn std::error::Error>>  {
+    /// Echo canceller to build whenever the set of devices changes.
+    pub fn with_backend(mut self, backend: EchoCancellerKind) -> Self {
+        self.backend = backend;
+        self
+    }
 
-    aec_log("Input stream aligners 1");
-    // we need to use these methods instead of the more generic select_device because of wasm wrapping to workaround cpal not having webaudio input device support
-    let device = select_input_device(
-        &device_config.host_id,
-        &device_config.device_name
-    ).await?;
+    /// Echo canceller to use if `backend` fails to build (`None` makes that an error).
+    pub fn with_fallback_backend(mut self, fallback_backend: Option<EchoCancellerKind>) -> Self {
+        self.fallback_backend = fallback_backend;
+        self
+    }
 
-    aec_log("Input stream aligners 2");
-    let supported_config = find_matching_input_device_config(
-        &device,
-        &device_config.device_name,
-        device_config.channels,
-        device_config.sample_rate,
-        device_config.sample_format
-    ).await?;
-    
+    /// Speex noise suppression / AGC / dereverb run on every input channel after echo cancellation
+    /// (`None`, the default, skips it).
+    pub fn with_preprocess(mut self, preprocess: Option<PreprocessConfig>) -> Self {
+        self.preprocess = preprocess;
+        self
+    }
 
-    aec_log("Input stream aligners 3");
-    let (producer, resampler, consumer) = create_stream_aligner(
-        device_config.channels,
-        device_config.sample_rate,
-        aec_config.target_sample_rate,
-        device_config.history_len,
-        device_config.calibration_packets,
-        device_config.audio_buffer_seconds,
-        device_config.resampler_quality)?;
+    /// Keep following the echo delay after calibration and re-shift inputs when it changes
+    /// (`None` turns it off, leaving only the one-shot `AecStream::calibrate`).
+    pub fn with_delay_tracking(mut self, delay_tracking: Option<DelayTrackingConfig>) -> Self {
+        self.delay_tracking = delay_tracking;
+        self
+    }
 
-    aec_log("Input stream aligners 4");
-    spawn_resampler_loop(resampler);
-    aec_log("Input stream aligners 5");
+    /// Flag echo path changes after calibration and optionally reset or recalibrate on them
+    /// (`None` turns it off, the default only reports them).
+    pub fn with_echo_path_monitor(mut self, echo_path_monitor: Option<EchoPathMonitorConfig>) -> Self {
+        self.echo_path_monitor = echo_path_monitor;
+        self
+    }
 
-    let stream = build_input_alignment_stream(
-        &device,
-        device_config,
-        supported_config,
-        producer,
-    ).await?;
-    aec_log("Input stream aligners 6");
+    /// Bypass echo cancellation while the inputs can't hear the outputs, e.g. on headphones
+    /// (`None` always runs it). By default only calibration turns the bypass on, an echo showing up turns it off.
+    pub fn with_isolation_bypass(mut self, isolation: Option<IsolationConfig>) -> Self {
+        self.isolation = isolation;
+        self
+    }
 
-    // start input stream
-    cfg_if::cfg_if! {
-        if #[cfg(all(target_arch = "wasm32"))] {
-            stream.play().await?; // wasm is async
-        } else {
-            stream.play()?;
-        }
+    /// Probe `AecStream::calibrate` plays, defaults to quiet pseudo-noise.
+    pub fn with_calibration_probe(mut self, calibration_probe: CalibrationProbe) -> Self {
+        self.calibration.probe = calibration_probe;
+        self
+    }
+
+    /// Probe, capture length and probe streams `AecStream::calibrate` uses.
+    pub fn with_calibration(mut self, calibration: CalibrationConfig) -> Self {
+        self.calibration = calibration;
+        self
     }
-    aec_log("Input stream aligners 7");
 
+    pub fn target_sample_rate(&self) -> u32 {
+        self.target_sample_rate
+    }
 
-    Ok((stream, consumer))
+    pub fn frame_size(&self) -> usize {
+        self.frame_size
+    }
 }
 
-fn get_output_stream_aligners(device_config: &OutputDeviceConfig, aec_config: &AecConfig) -> Result<(Stream, OutputStreamAlignerProducer, StreamAlignerConsumer), Box<dyn std::error::Error>> {
-
-    let host = cpal::host_from_id(device_config.host_id)?;
+// Settings for the speex preprocessor stage, defaults match speex
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PreprocessConfig {
+    pub denoise: bool,
+    // max noise attenuation in dB (negative)
+    pub noise_suppress_db: i32,
+    pub agc: bool,
+    // agc target level in i16 sample units
+    pub agc_level: f32,
+    pub agc_max_gain_db: i32,
+    pub dereverb: bool,
+    pub dereverb_level: f32,
+    pub dereverb_decay: f32,
+    // max residual echo attenuation in dB (negative), only used with the speex echo canceller
+    pub echo_suppress_db: i32,
+    // same, while near-end speech is active
+    pub echo_suppress_active_db: i32,
+}
+
+impl Default for PreprocessConfig {
+    fn default() -> Self {
+        Self {
+            denoise: true,
+            noise_suppress_db: -15,
+            agc: false,
+            agc_level: 8000.0,
+            agc_max_gain_db: 30,
+            dereverb: false,
+            dereverb_level: 0.0,
+            dereverb_decay: 0.0,
+            echo_suppress_db: -40,
+            echo_suppress_active_db: -15,
+        }
+    }
+}
+
+fn create_preprocessor(config: &PreprocessConfig, aec_config: &AecConfig) -> Result<Preprocessor, Box<dyn Error>> {
+    let Some(mut preprocessor) = Preprocessor::new(aec_config.frame_size, aec_config.target_sample_rate) else {
+        return Err("failed to allocate speex preprocessor".into());
+    };
+    preprocessor.set_denoise(config.denoise);
+    preprocessor.set_noise_suppress(config.noise_suppress_db);
+    preprocessor.set_agc(config.agc);
+    preprocessor.set_agc_level(config.agc_level);
+    preprocessor.set_agc_max_gain(config.agc_max_gain_db);
+    preprocessor.set_dereverb(config.dereverb);
+    preprocessor.set_dereverb_level(config.dereverb_level);
+    preprocessor.set_dereverb_decay(config.dereverb_decay);
+    preprocessor.set_echo_suppress(config.echo_suppress_db);
+    preprocessor.set_echo_suppress_active(config.echo_suppress_active_db);
+    Ok(preprocessor)
+}
+
+// taps of the windowed-sinc filter that does the sub-sample part of a reference delay. It delays by
+// FRACTIONAL_DELAY_LATENCY whole frames on top, which come out of the delay it's doing the fraction of
+const FRACTIONAL_DELAY_TAPS: usize = 17;
+const FRACTIONAL_DELAY_LATENCY: usize = FRACTIONAL_DELAY_TAPS / 2;
+
+// Delays one device's channels of an interleaved buffer, used to hold back the reference of outputs
+// that are further from the microphones. Whole frames go through a plain buffer, any fraction of a
+// frame through a short windowed-sinc filter after it
+struct DelayLine {
+    channels: usize,
+    // always exactly `whole` frames between calls to process
+    buffer: VecDeque<f32>,
+    // sub-sample part of the delay, -0.5..0.5, and the filter taps for it (empty when it's 0)
+    fraction: f32,
+    taps: Vec<f32>,
+    // last FRACTIONAL_DELAY_TAPS frames out of `buffer`, oldest first
+    history: VecDeque<f32>,
+}
+
+impl DelayLine {
+    fn new(channels: usize, delay: f32) -> Self {
+        let mut line = Self {
+            channels,
+            buffer: VecDeque::new(),
+            fraction: 0.0,
+            taps: Vec::new(),
+            history: VecDeque::new(),
+        };
+        line.set_delay(delay);
+        line
+    }
+
+    // total delay in frames, including the filter's latency
+    fn delay(&self) -> f32 {
+        let whole = self.buffer.len() / self.channels.max(1);
+        if self.taps.is_empty() {
+            whole as f32
+        } else {
+            (whole + FRACTIONAL_DELAY_LATENCY) as f32 + self.fraction
+        }
+    }
+
+    // a shorter delay drops the oldest frames, a longer one plays silence first.
+    // A fraction needs at least FRACTIONAL_DELAY_LATENCY frames of delay to come out of, shorter delays are
+    // rounded to whole frames (the target echo lag leaves plenty of room for the half frame that can cost)
+    fn set_delay(&mut self, delay: f32) {
+        let delay = delay.max(0.0);
+        let rounded = delay.round();
+        let fraction = delay - rounded;
+        let whole = if fraction.abs() < 1e-3 || (rounded as usize) < FRACTIONAL_DELAY_LATENCY {
+            self.fraction = 0.0;
+            self.taps.clear();
+            self.history.clear();
+            rounded as usize
+        } else {
+            if self.taps.is_empty() {
+                self.history = vec![0.0; FRACTIONAL_DELAY_TAPS * self.channels].into();
+            }
+            self.fraction = fraction;
+            self.taps = fractional_delay_taps(FRACTIONAL_DELAY_LATENCY as f32 + fraction);
+            (rounded as usize).saturating_sub(FRACTIONAL_DELAY_LATENCY)
+        };
+        let len = whole * self.channels;
+        if len < self.buffer.len() {
+            let excess = self.buffer.len() - len;
+            self.buffer.drain(..excess);
+        } else {
+            for _ in self.buffer.len()..len {
+                self.buffer.push_front(0.0);
+            }
+        }
+    }
+
+    // delays channels offset..offset + self.channels of every `stride` long frame in buf
+    fn process(&mut self, buf: &mut [f32], stride: usize, offset: usize) {
+        if self.buffer.is_empty() && self.taps.is_empty() {
+            return;
+        }
+        for frame in buf.chunks_exact_mut(stride) {
+            let samples = &mut frame[offset..offset + self.channels];
+            self.buffer.extend(samples.iter().copied());
+            for sample in samples.iter_mut() {
+                *sample = self.buffer.pop_front().unwrap_or(0.0);
+            }
+            if self.taps.is_empty() {
+                continue;
+            }
+            self.history.drain(..self.channels);
+            self.history.extend(samples.iter().copied());
+            for (channel, sample) in samples.iter_mut().enumerate() {
+                // taps[k] weighs the frame k back from the newest
+                *sample = self
+                    .taps
+                    .iter()
+                    .zip(self.history.iter().skip(channel).step_by(self.channels).rev())
+                    .map(|(tap, x)| tap * x)
+                    .sum();
+            }
+        }
+    }
+}
+
+// Hann-windowed sinc taps that delay by `delay` frames, which has to sit in the middle of the filter.
+// Normalized to unity gain at DC
+fn fractional_delay_taps(delay: f32) -> Vec<f32> {
+    let half_width = (FRACTIONAL_DELAY_TAPS / 2 + 1) as f32;
+    let taps: Vec<f32> = (0..FRACTIONAL_DELAY_TAPS)
+        .map(|k| {
+            let x = k as f32 - delay;
+            let sinc = if x.abs() < 1e-6 { 1.0 } else { (PI * x).sin() / (PI * x) };
+            let window = 0.5 + 0.5 * (PI * x / half_width).cos();
+            sinc * window
+        })
+        .collect();
+    let sum: f32 = taps.iter().sum();
+    taps.iter().map(|tap| tap / sum).collect()
+}
+
+// Settings for the background echo delay tracker, which re-runs gcc-phat on the live
+// far-end/near-end audio so a delay change after calibration (bluetooth reconnect,
+// OS buffer change) doesn't silently break cancellation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DelayTrackingConfig {
+    // gcc-phat peak to rms ratio an estimate needs to count
+    pub min_confidence: f32,
+    // confident estimates in a row (one every ~0.5s of far-end audio) that have to agree before inputs are shifted
+    pub persist_estimates: u32,
+    // estimates this close (ms) agree, and smaller delay changes are left to the echo canceller
+    pub tolerance_ms: f32,
+}
+
+impl Default for DelayTrackingConfig {
+    fn default() -> Self {
+        Self {
+            min_confidence: 12.0,
+            persist_estimates: 3,
+            tolerance_ms: 2.0,
+        }
+    }
+}
+
+// Echo delay estimator for one input device, fed one frame at a time.
+// Runs gcc-phat over a ~1s window every half window, and reports a delay once
+// enough confident estimates in a row agree on it.
+struct DelayTracker {
+    window: usize,
+    input: Vec<f32>,
+    output: Vec<f32>,
+    candidate: Option<isize>,
+    agreeing: u32,
+    // windows in a row where the far end played but the input had no confident echo of it
+    uncoupled: u32,
+}
+
+impl DelayTracker {
+    // below this rms (about -50 dBFS) a window is treated as silence and skipped
+    const MIN_RMS: f32 = 0.003;
+
+    fn new(sample_rate: u32) -> Self {
+        let window = (sample_rate as usize).next_power_of_two();
+        Self {
+            window,
+            input: Vec::with_capacity(window),
+            output: Vec::with_capacity(window),
+            candidate: None,
+            agreeing: 0,
+            uncoupled: 0,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.input.clear();
+        self.output.clear();
+        self.candidate = None;
+        self.agreeing = 0;
+        self.uncoupled = 0;
+    }
+
+    // seconds of far-end audio in a row the input hasn't echoed
+    fn uncoupled_secs(&self, sample_rate: u32) -> f32 {
+        (self.uncoupled as usize * self.window / 2) as f32 / sample_rate as f32
+    }
+
+    // appends one mono frame of near-end (input) and far-end (output) audio,
+    // returns the lag of the input behind the output (in samples) once it has persisted
+    fn push(&mut self, input: impl Iterator<Item = f32>, output: impl Iterator<Item = f32>, config: &DelayTrackingConfig, sample_rate: u32) -> Option<isize> {
+        self.input.extend(input);
+        self.output.extend(output);
+        if self.input.len() < self.window || self.output.len() < self.window {
+            return None;
+        }
+        let rms = |x: &[f32]| (x.iter().map(|v| v * v).sum::<f32>() / x.len() as f32).sqrt();
+        let far_end_active = rms(&self.output[..self.window]) > Self::MIN_RMS;
+        let estimate = if far_end_active && rms(&self.input[..self.window]) > Self::MIN_RMS {
+            let (lag, score) = gcc_phat_delay_with_score(&self.input[..self.window], &self.output[..self.window]);
+            (score >= config.min_confidence).then_some(lag.round() as isize)
+        } else {
+            None
+        };
+        if far_end_active {
+            self.uncoupled = if estimate.is_some() { 0 } else { self.uncoupled + 1 };
+        }
+        let hop = self.window / 2;
+        self.input.drain(..hop);
+        self.output.drain(..hop);
+
+        // quiet or unclear windows don't break a streak, they just don't add to it
+        let lag = estimate?;
+        let tolerance = (config.tolerance_ms * sample_rate as f32 / 1000.0).round() as isize;
+        match self.candidate {
+            Some(candidate) if (lag - candidate).abs() <= tolerance => self.agreeing += 1,
+            _ => {
+                self.candidate = Some(lag);
+                self.agreeing = 1;
+            }
+        }
+        if self.agreeing >= config.persist_estimates { self.candidate } else { None }
+    }
+}
+
+// Settings for bypassing echo cancellation while no output can be heard on any input (headphones),
+// where it would only add distortion and cost CPU
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct IsolationConfig {
+    // also count a running stream as isolated after this many seconds of far-end audio with no echo of it
+    // on any input. None, the default, leaves that to calibration hearing none of the outputs, since quiet
+    // speakers or a noisy room can look uncoupled for a long time too
+    pub uncoupled_secs: Option<f32>,
+}
+
+// Settings for the echo path monitor, which notices when the echo path changes after calibration
+// (switching to bluetooth, the OS adding buffering) from the delay tracker having to move an input,
+// from cancellation falling apart after it had converged, or from the echo canceller's own delay estimate moving
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EchoPathMonitorConfig {
+    // echo return loss enhancement (mic energy over echo canceller output energy, smoothed over about
+    // a second of far-end audio) cancellation has to reach before a drop counts
+    pub converged_erle_db: f32,
+    // erle staying below this for hold_secs of far-end audio means the echo path changed.
+    // Long enough that both sides talking at once doesn't set it off
+    pub lost_erle_db: f32,
+    pub hold_secs: f32,
+    // the echo canceller's own delay estimate (aec3 has one) staying this far (ms) from where it was once
+    // cancellation converged, for hold_secs, means the echo path changed too
+    pub delay_moved_ms: f32,
+    // what AecStream does about a change besides reporting it
+    pub action: EchoPathAction,
+    // EchoPathAction::Recalibrate asks for another calibration no sooner than this after the last one started
+    pub recalibrate_interval_secs: f32,
+}
+
+impl Default for EchoPathMonitorConfig {
+    fn default() -> Self {
+        Self {
+            converged_erle_db: 10.0,
+            lost_erle_db: 3.0,
+            hold_secs: 8.0,
+            delay_moved_ms: 20.0,
+            action: EchoPathAction::Notify,
+            recalibrate_interval_secs: 120.0,
+        }
+    }
+}
+
+impl EchoPathMonitorConfig {
+    // whether to ask for a calibration now, given when the last one started
+    fn recalibration_due(&self, last_calibration_micros: Option<u128>, now_micros: u128) -> bool {
+        let interval_micros = (self.recalibrate_interval_secs.max(0.0) as f64 * 1_000_000.0) as u128;
+        self.action == EchoPathAction::Recalibrate
+            && last_calibration_micros.is_none_or(|last| now_micros.saturating_sub(last) >= interval_micros)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EchoPathAction {
+    // only report it, see AecStream::take_echo_path_changes
+    Notify,
+    // also start the echo canceller over
+    ResetAec,
+    // also ask for a calibration when cancellation is lost, see AecStream::needs_recalibration
+    // (update can't play probes itself). A moved delay is already fixed by the tracker
+    Recalibrate,
+}
+
+/// Why the echo path was flagged as changed, from `AecStream::take_echo_path_changes`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EchoPathChange {
+    // the delay tracker found the echo of this input had moved and shifted it by this many samples
+    DelayMoved { input_device: String, shift_samples: i64 },
+    // cancellation had converged and then stayed down at this erle
+    CancellationLost { erle_db: f32 },
+    // the echo canceller's own delay estimate moved from and settled at these (ms) after cancellation converged
+    EchoCancellerDelayMoved { from_ms: i32, to_ms: i32 },
+    // no input hears any output, echo cancellation is bypassed until one does
+    Isolated,
+    // an echo showed up again while isolated, echo cancellation is back on
+    Coupled,
+}
+
+// Follows the erle of the frames the echo canceller ran on, for the echo path monitor
+#[derive(Default)]
+struct EchoPathMonitor {
+    input_energy: f64,
+    output_energy: f64,
+    converged: bool,
+    low_secs: f32,
+    // the echo canceller's delay estimate once it had converged, and how long it's been somewhere else
+    converged_delay_ms: Option<i32>,
+    moved_secs: f32,
+}
+
+impl EchoPathMonitor {
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    // feeds one frame of mic input and echo canceller output, with what the echo canceller reported about it if
+    // it reports anything, and returns the change once one counts. Cancellation has to converge again before
+    // the next lost one
+    fn push(&mut self, input: &[f32], aec_out: &[f32], metrics: Option<EchoCancellerMetrics>, config: &EchoPathMonitorConfig, frame_secs: f32) -> Option<EchoPathChange> {
+        let energy = |buf: &[f32]| buf.iter().map(|s| (s * s) as f64).sum::<f64>() / buf.len().max(1) as f64;
+        // smoothed over about a second
+        let alpha = frame_secs.min(1.0) as f64;
+        if self.input_energy == 0.0 && self.output_energy == 0.0 {
+            self.input_energy = energy(input);
+            self.output_energy = energy(aec_out);
+        } else {
+            self.input_energy += alpha * (energy(input) - self.input_energy);
+            self.output_energy += alpha * (energy(aec_out) - self.output_energy);
+        }
+        // the echo canceller's own erle knows which part of the input was echo, so it goes by that when it has one
+        let erle_db = metrics.map_or_else(
+            || (10.0 * (self.input_energy.max(1e-12) / self.output_energy.max(1e-12)).log10()) as f32,
+            |metrics| metrics.erle_db,
+        );
+        if erle_db >= config.converged_erle_db {
+            self.converged = true;
+        }
+        if !self.converged {
+            return None;
+        }
+        if let Some(delay_ms) = metrics.map(|metrics| metrics.delay_ms) {
+            let from_ms = *self.converged_delay_ms.get_or_insert(delay_ms);
+            if (delay_ms - from_ms).abs() as f32 > config.delay_moved_ms {
+                self.moved_secs += frame_secs;
+            } else {
+                self.moved_secs = 0.0;
+            }
+            if self.moved_secs >= config.hold_secs {
+                self.converged_delay_ms = Some(delay_ms);
+                self.moved_secs = 0.0;
+                return Some(EchoPathChange::EchoCancellerDelayMoved { from_ms, to_ms: delay_ms });
+            }
+        }
+        if erle_db < config.lost_erle_db {
+            self.low_secs += frame_secs;
+        } else {
+            self.low_secs = 0.0;
+        }
+        if self.low_secs < config.hold_secs {
+            return None;
+        }
+        self.reset();
+        Some(EchoPathChange::CancellationLost { erle_db })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EchoCancellerKind {
+    // webrtc aec3, best quality but the most expensive
+    Aec3,
+    // speex mdf, much cheaper so better for weak devices
+    Speex,
+}
+
+impl EchoCancellerKind {
+    // whether this backend can export its converged state, see EchoCancellerBackend::snapshot
+    pub fn supports_snapshot(self) -> bool {
+        matches!(self, EchoCancellerKind::Speex)
+    }
+}
+
+/// What an echo canceller reported about the last frame it ran on, see `EchoCancellerBackend::metrics`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EchoCancellerMetrics {
+    // echo return loss enhancement, how much echo it took out
+    pub erle_db: f32,
+    // the echo delay it estimated
+    pub delay_ms: i32,
+}
+
+// Anything that can cancel echo from one aligned frame.
+// input and aec_out are interleaved (frame_size * input_channels),
+// output is the interleaved reference (frame_size * output_channels)
+pub trait EchoCancellerBackend {
+    fn kind(&self) -> EchoCancellerKind;
+    fn process(&mut self, input: &[f32], output: &[f32], aec_out: &mut [f32]) -> Result<(), Box<dyn Error>>;
+    // learned echo path per (input channel, output channel) pair, indexed [input * output_channels + output]
+    // None if the backend doesn't expose its filter
+    fn impulse_response(&self) -> Option<Vec<Vec<f32>>> {
+        None
+    }
+    // serialized converged state so a later session doesn't start from zero
+    // None if the backend can't export it, which aec3 can't (the crate has no state api)
+    fn snapshot(&self) -> Option<Vec<u8>> {
+        None
+    }
+    fn restore(&mut self, _state: &[u8]) -> Result<(), Box<dyn Error>> {
+        Err(format!("{:?} echo canceller can't restore saved state", self.kind()).into())
+    }
+    // speex echo state, lets the preprocessor suppress the residual echo too
+    fn speex_echo_state(&mut self) -> Option<&mut EchoCanceller> {
+        None
+    }
+    // what it reported about the last frame, None if it doesn't report anything
+    fn metrics(&self) -> Option<EchoCancellerMetrics> {
+        None
+    }
+}
+
+// aec3 doesn't expose its filter or delay estimate, so it has no impulse response or snapshot
+// and reconverges from scratch every session
+struct Aec3Backend {
+    aec: VoipAec3,
+    metrics: Option<EchoCancellerMetrics>,
+}
+
+impl Aec3Backend {
+    fn new(aec_config: &AecConfig, input_channels: usize, output_channels: usize) -> Result<Self, Box<dyn Error>> {
+        let aec = VoipAec3::builder(aec_config.target_sample_rate as i32, input_channels, output_channels)
+            .initial_delay_ms((aec_config.frame_size/3) as i32)
+            .enable_high_pass(true)
+            .build()?;
+        Ok(Self { aec, metrics: None })
+    }
+}
+
+impl EchoCancellerBackend for Aec3Backend {
+    fn kind(&self) -> EchoCancellerKind {
+        EchoCancellerKind::Aec3
+    }
+
+    fn process(&mut self, input: &[f32], output: &[f32], aec_out: &mut [f32]) -> Result<(), Box<dyn Error>> {
+        let metrics = self.aec.process(input, Some(output), false, aec_out)?;
+        self.metrics = Some(EchoCancellerMetrics {
+            erle_db: metrics.echo_return_loss_enhancement as f32,
+            delay_ms: metrics.delay_ms,
+        });
+        Ok(())
+    }
+
+    fn metrics(&self) -> Option<EchoCancellerMetrics> {
+        self.metrics
+    }
+}
+
+struct SpeexBackend {
+    aec: EchoCanceller,
+}
+
+impl SpeexBackend {
+    fn new(aec_config: &AecConfig, input_channels: usize, output_channels: usize) -> Result<Self, Box<dyn Error>> {
+        let Some(mut aec) = EchoCanceller::new_multichannel(
+            aec_config.frame_size,
+            aec_config.filter_length,
+            input_channels,
+            output_channels,
+        ) else {
+            return Err("failed to allocate speex echo canceller".into());
+        };
+        aec.set_sampling_rate(aec_config.target_sample_rate);
+        Ok(Self { aec })
+    }
+}
+
+impl EchoCancellerBackend for SpeexBackend {
+    fn kind(&self) -> EchoCancellerKind {
+        EchoCancellerKind::Speex
+    }
+
+    fn process(&mut self, input: &[f32], output: &[f32], aec_out: &mut [f32]) -> Result<(), Box<dyn Error>> {
+        self.aec.cancel_frame_f32(input, output, aec_out);
+        Ok(())
+    }
+
+    fn impulse_response(&self) -> Option<Vec<Vec<f32>>> {
+        Some(self.aec.impulse_response())
+    }
+
+    fn snapshot(&self) -> Option<Vec<u8>> {
+        Some(self.aec.snapshot().to_bytes())
+    }
+
+    fn restore(&mut self, state: &[u8]) -> Result<(), Box<dyn Error>> {
+        let snapshot = EchoCancellerSnapshot::from_bytes(state)?;
+        self.aec.restore(&snapshot)?;
+        Ok(())
+    }
+
+    fn speex_echo_state(&mut self) -> Option<&mut EchoCanceller> {
+        Some(&mut self.aec)
+    }
+}
+
+pub(crate) fn create_echo_canceller(kind: EchoCancellerKind, aec_config: &AecConfig, input_channels: usize, output_channels: usize) -> Result<Box<dyn EchoCancellerBackend>, Box<dyn Error>> {
+    Ok(match kind {
+        EchoCancellerKind::Aec3 => Box::new(Aec3Backend::new(aec_config, input_channels, output_channels)?),
+        EchoCancellerKind::Speex => Box::new(SpeexBackend::new(aec_config, input_channels, output_channels)?),
+    })
+}
+
+async fn get_input_stream_aligners(device_config: &InputDeviceConfig, aec_config: &AecConfig) -> Result<(InputStream, StreamAlignerConsumer), Box<dyn std::error::Error>>  {
+
+    aec_log("Input stream aligners 1");
+    // we need to use these methods instead of the more generic select_device because of wasm wrapping to workaround cpal not having webaudio input device support
+    let device = select_input_device(
+        &device_config.host_id,
+        &device_config.device_name
+    ).await?;
+
+    aec_log("Input stream aligners 2");
+    let supported_config = find_matching_input_device_config(
+        &device,
+        &device_config.device_name,
+        device_config.channels,
+        device_config.sample_rate,
+        device_config.sample_format
+    ).await?;
+    
+
+    aec_log("Input stream aligners 3");
+    let (producer, resampler, consumer) = create_stream_aligner(
+        device_config.channels,
+        device_config.sample_rate,
+        aec_config.target_sample_rate,
+        device_config.history_len,
+        device_config.calibration_packets,
+        device_config.audio_buffer_seconds,
+        device_config.resampler_quality)?;
+
+    aec_log("Input stream aligners 4");
+    spawn_resampler_loop(resampler);
+    aec_log("Input stream aligners 5");
+
+    let stream = build_input_alignment_stream(
+        &device,
+        device_config,
+        supported_config,
+        producer,
+    ).await?;
+    aec_log("Input stream aligners 6");
+
+    // start input stream
+    cfg_if::cfg_if! {
+        if #[cfg(all(target_arch = "wasm32"))] {
+            stream.play().await?; // wasm is async
+        } else {
+            stream.play()?;
+        }
+    }
+    aec_log("Input stream aligners 7");
+
+
+    Ok((stream, consumer))
+}
+
+fn get_output_stream_aligners(device_config: &OutputDeviceConfig, aec_config: &AecConfig) -> Result<(Stream, OutputStreamAlignerProducer, StreamAlignerConsumer), Box<dyn std::error::Error>> {
+
+    let host = cpal::host_from_id(device_config.host_id)?;
 
     let device = select_device(
         host.output_devices(),
@@ -1667,7 +3036,6 @@ enum DeviceUpdateMessage {
 }
 
 pub struct AecStream {
-    //aec: Option<EchoCanceller>,
     aec_config: AecConfig,
     device_update_sender: mpsc::Sender<DeviceUpdateMessage>,
     device_update_receiver: mpsc::Receiver<DeviceUpdateMessage>,
@@ -1683,11 +3051,38 @@ pub struct AecStream {
     output_channels: usize,
     start_micros: Option<u128>,
     total_frames_emitted: u128,
-    input_audio_buffer: Vec<i16>,
-    output_audio_buffer: Vec<i16>,
-    aec_audio_buffer: Vec<i16>,
+    input_audio_buffer: Vec<f32>,
+    output_audio_buffer: Vec<f32>,
+    aec_audio_buffer: Vec<f32>,
     aec_out_audio_buffer: Vec<f32>,
-    aec3: Option<VoipAec3>,
+    aec: Option<Box<dyn EchoCancellerBackend>>,
+    // saved state to load once the echo canceller is built
+    pending_aec_state: Option<Vec<u8>>,
+    // one per input channel, empty if preprocessing is off
+    preprocessors: Vec<Preprocessor>,
+    preprocess_buffer: Vec<f32>,
+    // one per input device, empty if delay tracking is off
+    delay_trackers: HashMap<String, DelayTracker>,
+    // calibration measures offsets itself, so the tracker mustn't shift anything underneath it
+    calibrating: bool,
+    echo_path_monitor: EchoPathMonitor,
+    // flagged since the last take_echo_path_changes
+    echo_path_changes: Vec<EchoPathChange>,
+    // an echo path change asked for a calibration that hasn't run yet
+    recalibration_needed: bool,
+    // when the last calibration started, automatic ones wait recalibrate_interval_secs after it
+    last_calibration_micros: Option<u128>,
+    // no output reaches any input, so echo cancellation is bypassed
+    acoustically_isolated: bool,
+    // one per output device, holds its reference back so every speaker lines up with the nearest one
+    reference_delays: HashMap<String, DelayLine>,
+    // device sample rates by device name, saved with the calibration
+    input_sample_rates: HashMap<String, u32>,
+    output_sample_rates: HashMap<String, u32>,
+    // what the last calibrate measured, in a form that can be stored for next time
+    last_calibration: Option<SavedCalibration>,
+    calibration_progress: Option<Box<dyn FnMut(CalibrationProgress)>>,
+    calibration_cancel: CalibrationCancel,
 }
 
 impl AecStream {
@@ -1699,7 +3094,6 @@ impl AecStream {
         }
         let (device_update_sender, device_update_receiver) = mpsc::channel::<DeviceUpdateMessage>(CHANNEL_SIZE);
         Ok(Self {
-           //aec: None,
            aec_config: aec_config,
            device_update_sender: device_update_sender,
            device_update_receiver: device_update_receiver,
@@ -1719,7 +3113,23 @@ impl AecStream {
            output_audio_buffer: Vec::new(),
            aec_audio_buffer: Vec::new(),
            aec_out_audio_buffer: Vec::new(),
-           aec3: None,
+           aec: None,
+           pending_aec_state: None,
+           preprocessors: Vec::new(),
+           preprocess_buffer: Vec::new(),
+           delay_trackers: HashMap::new(),
+           calibrating: false,
+           echo_path_monitor: EchoPathMonitor::default(),
+           echo_path_changes: Vec::new(),
+           recalibration_needed: false,
+           last_calibration_micros: None,
+           acoustically_isolated: false,
+           reference_delays: HashMap::new(),
+           input_sample_rates: HashMap::new(),
+           output_sample_rates: HashMap::new(),
+           last_calibration: None,
+           calibration_progress: None,
+           calibration_cancel: CalibrationCancel::default(),
         })
     }
     
@@ -1737,6 +3147,36 @@ impl AecStream {
             .sum()
     }
 
+    // see EchoCancellerBackend::impulse_response
+    pub fn echo_impulse_response(&self) -> Option<Vec<Vec<f32>>> {
+        self.aec.as_ref().and_then(|aec| aec.impulse_response())
+    }
+
+    // see EchoCancellerBackend::snapshot
+    pub fn aec_snapshot(&self) -> Option<Vec<u8>> {
+        self.aec.as_ref().and_then(|aec| aec.snapshot())
+    }
+
+    // whether aec_snapshot can return anything; only speex exports its state, so on the
+    // default aec3 backend saving and restoring is a no-op
+    pub fn aec_snapshot_supported(&self) -> bool {
+        match self.aec.as_ref() {
+            Some(aec) => aec.kind().supports_snapshot(),
+            None => self.aec_config.backend.supports_snapshot(),
+        }
+    }
+
+    // restores right away if the echo canceller exists, otherwise once the devices are ready
+    pub fn restore_aec_snapshot(&mut self, state: Vec<u8>) -> Result<(), Box<dyn Error>> {
+        match self.aec.as_mut() {
+            Some(aec) => aec.restore(&state),
+            None => {
+                self.pending_aec_state = Some(state);
+                Ok(())
+            }
+        }
+    }
+
     fn reinitialize_aec(&mut self) -> Result<(), Box<dyn std::error::Error>> {
         self.input_channels = self.num_input_channels();
         self.output_channels = self.num_output_channels();
@@ -1748,52 +3188,262 @@ impl AecStream {
         self.sorted_output_aligners = self.output_aligners.keys().cloned().collect();
         self.sorted_output_aligners.sort();
 
-        //self.aec2 = Some(FdafAec::new(1024, 0.02));
+        self.aec = self.create_aec()?;
+        self.echo_path_monitor.reset();
 
-        
-        /*(self.aec, self.aec3) = if self.input_channels > 0 && self.output_channels > 0 {
-            (EchoCanceller::new_multichannel(
-                self.aec_config.frame_size,
-                self.aec_config.filter_length,
-                self.input_channels,
-                self.output_channels,
-            ), Some(VoipAec3::builder(self.aec_config.target_sample_rate as i32, self.input_channels, self.output_channels)
-            .initial_delay_ms((self.aec_config.frame_size/3) as i32)
-            .enable_high_pass(true)
-            .build()
-            .expect("failed to create AEC pipeline")))
-
-            //EchoCanceller::new(
-            //    self.aec_config.frame_size,
-            //    self.aec_config.filter_length
-            //)
-
-        */
-        self.aec3 = if self.input_channels > 0 && self.output_channels > 0 {
-            Some(VoipAec3::builder(self.aec_config.target_sample_rate as i32, self.input_channels, self.output_channels)
-            .initial_delay_ms((self.aec_config.frame_size/3) as i32)
-            .enable_high_pass(true)
-            .build()
-            .expect("failed to create AEC pipeline"))
+        if let Some(aec) = self.aec.as_mut()
+            && let Some(state) = self.pending_aec_state.take()
+            && let Err(err) = aec.restore(&state)
+        {
+            aec_log(format!("Discarding saved echo canceller state: {err}"));
+        }
+
+        self.input_audio_buffer.clear();
+        self.input_audio_buffer.resize(self.aec_config.frame_size * self.input_channels, 0 as f32);
+        self.output_audio_buffer.clear();
+        self.output_audio_buffer.resize(self.aec_config.frame_size * self.output_channels, 0 as f32);
+        self.aec_audio_buffer.clear();
+        self.aec_audio_buffer.resize(self.aec_config.frame_size * self.input_channels, 0 as f32);
+        self.aec_out_audio_buffer.clear();
+        self.aec_out_audio_buffer.resize(self.aec_config.frame_size * self.input_channels, 0 as f32);
+
+        self.preprocessors.clear();
+        if let Some(preprocess) = self.aec_config.preprocess.as_ref() {
+            for _ in 0..self.input_channels {
+                self.preprocessors.push(create_preprocessor(preprocess, &self.aec_config)?);
+            }
+        }
+        self.preprocess_buffer.clear();
+        self.preprocess_buffer.resize(self.aec_config.frame_size, 0 as f32);
+
+        // the isolation bypass listens for coupling through the trackers too, see track_echo_delay
+        self.delay_trackers.clear();
+        if self.aec_config.delay_tracking.is_some() || self.aec_config.isolation.is_some() {
+            for name in &self.sorted_input_aligners {
+                self.delay_trackers.insert(name.clone(), DelayTracker::new(self.aec_config.target_sample_rate));
+            }
+        }
+
+        // outputs that are still around keep their calibrated delay
+        let mut reference_delays = HashMap::new();
+        for name in &self.sorted_output_aligners {
+            let Some(aligner) = self.output_aligners.get(name) else {
+                continue;
+            };
+            let delay = self.reference_delays.get(name).map_or(0.0, |line| line.delay());
+            reference_delays.insert(name.clone(), DelayLine::new(aligner.channels, delay));
+        }
+        self.reference_delays = reference_delays;
+        Ok(())
+    }
+
+    // echo canceller for the current channels (None without inputs or outputs), on the fallback backend if
+    // the configured one can't be built
+    fn create_aec(&self) -> Result<Option<Box<dyn EchoCancellerBackend>>, Box<dyn std::error::Error>> {
+        if self.input_channels == 0 || self.output_channels == 0 {
+            return Ok(None);
+        }
+        match create_echo_canceller(self.aec_config.backend, &self.aec_config, self.input_channels, self.output_channels) {
+            Ok(aec) => Ok(Some(aec)),
+            Err(err) => {
+                let Some(fallback_backend) = self.aec_config.fallback_backend else {
+                    return Err(format!("failed to create {:?} echo canceller: {err}", self.aec_config.backend).into());
+                };
+                aec_log(format!("Failed to create {:?} echo canceller ({err}), falling back to {:?}", self.aec_config.backend, fallback_backend));
+                Ok(Some(create_echo_canceller(fallback_backend, &self.aec_config, self.input_channels, self.output_channels)?))
+            }
+        }
+    }
+
+    /// Echo path changes flagged since the last call, oldest first. Only isolation changes if the monitor is off.
+    pub fn take_echo_path_changes(&mut self) -> Vec<EchoPathChange> {
+        std::mem::take(&mut self.echo_path_changes)
+    }
+
+    /// An echo path change asked for a calibration (`EchoPathAction::Recalibrate`), cleared once the next one
+    /// starts, whether or not it succeeds.
+    pub fn needs_recalibration(&self) -> bool {
+        self.recalibration_needed
+    }
+
+    // records a change and does what the monitor is configured to about it
+    fn flag_echo_path_change(&mut self, change: EchoPathChange) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(config) = self.aec_config.echo_path_monitor else {
+            return Ok(());
+        };
+        aec_log(format!("Echo path changed: {change:?}"));
+        let cancellation_lost = matches!(change, EchoPathChange::CancellationLost { .. });
+        self.echo_path_changes.push(change);
+        match config.action {
+            EchoPathAction::Notify => {}
+            EchoPathAction::ResetAec => {
+                self.aec = self.create_aec()?;
+                self.echo_path_monitor.reset();
+            }
+            EchoPathAction::Recalibrate if cancellation_lost => self.request_recalibration(),
+            EchoPathAction::Recalibrate => {}
+        }
+        Ok(())
+    }
+
+    // asks for a calibration if the monitor recalibrates and the last one wasn't too recent
+    fn request_recalibration(&mut self) {
+        let Some(config) = self.aec_config.echo_path_monitor else {
+            return;
+        };
+        if config.action != EchoPathAction::Recalibrate || self.recalibration_needed {
+            return;
+        }
+        if !config.recalibration_due(self.last_calibration_micros, now_micros()) {
+            aec_log("Not recalibrating, the last calibration was too recent");
+            return;
+        }
+        self.recalibration_needed = true;
+    }
+
+    // feeds the frame the echo canceller just ran on to the echo path monitor
+    fn monitor_echo_path(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(config) = self.aec_config.echo_path_monitor else {
+            return Ok(());
+        };
+        if self.calibrating {
+            return Ok(());
+        }
+        let frame_secs = self.aec_config.frame_size as f32 / self.aec_config.target_sample_rate as f32;
+        let metrics = self.aec.as_ref().and_then(|aec| aec.metrics());
+        if let Some(change) = self.echo_path_monitor.push(&self.input_audio_buffer, &self.aec_audio_buffer, metrics, &config, frame_secs) {
+            self.flag_echo_path_change(change)?;
+        }
+        Ok(())
+    }
+
+    // how far the echo should lag the reference after alignment, leaves the echo canceller a little causal slack
+    fn target_echo_lag(&self) -> i64 {
+        (self.aec_config.frame_size / 3) as i64
+    }
+
+    // feeds the current frame to the delay trackers, and schedules a shift on any input
+    // whose echo delay has moved away from the target for long enough (with delay tracking on).
+    // Also what decides when the isolation bypass starts and ends, so with delay tracking off
+    // they only run while isolated or when isolation can start at runtime
+    fn track_echo_delay(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.delay_trackers.is_empty() || self.calibrating || self.input_channels == 0 || self.output_channels == 0 {
+            return Ok(());
+        }
+        let shifting = self.aec_config.delay_tracking.is_some();
+        let isolation_listening = self
+            .aec_config
+            .isolation
+            .is_some_and(|isolation| self.acoustically_isolated || isolation.uncoupled_secs.is_some());
+        if !shifting && !isolation_listening {
+            return Ok(());
+        }
+        let config = self.aec_config.delay_tracking.unwrap_or_default();
+        let mut moved = Vec::new();
+        let mut coupled = false;
+        let frames = self.aec_config.frame_size;
+        let sample_rate = self.aec_config.target_sample_rate;
+        let target = self.target_echo_lag();
+        let tolerance = (config.tolerance_ms * sample_rate as f32 / 1000.0).round() as i64;
+        let output_channels = self.output_channels;
+        let input_channels = self.input_channels;
+        let output = &self.output_audio_buffer;
+        let input = &self.input_audio_buffer;
+        let mut start_channel = 0;
+        for name in &self.sorted_input_aligners {
+            let Some(aligner) = self.input_aligners.get_mut(name) else {
+                continue;
+            };
+            let channels = aligner.channels;
+            let Some(tracker) = self.delay_trackers.get_mut(name) else {
+                start_channel += channels;
+                continue;
+            };
+            let input_mono = (0..frames).map(|frame| {
+                let base = frame * input_channels + start_channel;
+                input[base..base + channels].iter().sum::<f32>() / channels as f32
+            });
+            let output_mono = (0..frames).map(|frame| {
+                let base = frame * output_channels;
+                output[base..base + output_channels].iter().sum::<f32>() / output_channels as f32
+            });
+            if let Some(lag) = tracker.push(input_mono, output_mono, &config, sample_rate) {
+                coupled = true;
+                let shift = lag as i64 - target;
+                if shifting && shift.abs() > tolerance {
+                    aec_log(format!("Echo delay of '{name}' moved by {shift} samples, shifting input"));
+                    aligner.shift(shift);
+                    tracker.reset();
+                    moved.push(EchoPathChange::DelayMoved { input_device: name.clone(), shift_samples: shift });
+                }
+            }
+            start_channel += channels;
+        }
+        for change in moved {
+            self.flag_echo_path_change(change)?;
+        }
+
+        if let Some(isolation) = self.aec_config.isolation {
+            if self.acoustically_isolated && coupled {
+                self.set_acoustically_isolated(false)?;
+            } else if !self.acoustically_isolated
+                && let Some(uncoupled_secs) = isolation.uncoupled_secs
+                && self.delay_trackers.values().all(|tracker| tracker.uncoupled_secs(sample_rate) >= uncoupled_secs)
+            {
+                self.set_acoustically_isolated(true)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// No output reaches any input (by calibration or by listening since), so echo cancellation is bypassed.
+    pub fn is_acoustically_isolated(&self) -> bool {
+        self.acoustically_isolated
+    }
+
+    // switches the bypass and reports it as an echo path change, whether or not the monitor is on
+    fn set_acoustically_isolated(&mut self, isolated: bool) -> Result<(), Box<dyn std::error::Error>> {
+        if isolated == self.acoustically_isolated {
+            return Ok(());
+        }
+        self.acoustically_isolated = isolated;
+        if isolated {
+            aec_log("No output reaches any input, bypassing echo cancellation");
+            self.echo_path_changes.push(EchoPathChange::Isolated);
+            return Ok(());
+        }
+        aec_log("Echo showed up again, resuming echo cancellation");
+        // the echo canceller sat the isolation out, start it fresh on the echo that just showed up
+        self.aec = self.create_aec()?;
+        self.echo_path_monitor.reset();
+        self.echo_path_changes.push(EchoPathChange::Coupled);
+        // nothing was lined up for an echo that calibration couldn't hear
+        self.request_recalibration();
+        Ok(())
+    }
+
+    // runs the preprocessors in place on aec_out_audio_buffer, one channel at a time
+    fn preprocess(&mut self, aec_ran: bool) {
+        let channels = self.input_channels;
+        let frames = self.aec_config.frame_size;
+        // speex only keeps the residual echo of one mic channel, so only link it when there is just one
+        let mut echo = if aec_ran && channels == 1 {
+            self.aec.as_mut().and_then(|aec| aec.speex_echo_state())
         } else {
             None
         };
-
-        //if let Some(aec) = self.aec.as_mut() {
-        //    aec.set_sampling_rate(self.aec_config.target_sample_rate);
-        //    let sampling_rate = aec.sampling_rate();
-        //    println!("Set sampling rate to {sampling_rate}");
-        //}
-
-        self.input_audio_buffer.clear();
-        self.input_audio_buffer.resize(self.aec_config.frame_size * self.input_channels, 0 as i16);
-        self.output_audio_buffer.clear();
-        self.output_audio_buffer.resize(self.aec_config.frame_size * self.output_channels, 0 as i16);
-        self.aec_audio_buffer.clear();
-        self.aec_audio_buffer.resize(self.aec_config.frame_size * self.input_channels, 0 as i16);
-        self.aec_out_audio_buffer.clear();
-        self.aec_out_audio_buffer.resize(self.aec_config.frame_size * self.input_channels, 0 as f32);
-        Ok(())
+        for (channel, preprocessor) in self.preprocessors.iter_mut().enumerate() {
+            for frame in 0..frames {
+                self.preprocess_buffer[frame] = self.aec_out_audio_buffer[frame * channels + channel];
+            }
+            match echo.as_deref_mut() {
+                Some(echo) => preprocessor.run_with_echo(&mut self.preprocess_buffer, echo),
+                None => preprocessor.run_f32(&mut self.preprocess_buffer),
+            };
+            for frame in 0..frames {
+                self.aec_out_audio_buffer[frame * channels + channel] = self.preprocess_buffer[frame];
+            }
+        }
     }
 
     pub async fn add_input_device(&mut self, config: &InputDeviceConfig) -> Result<(), Box<dyn std::error::Error>> {
@@ -1801,6 +3451,7 @@ impl AecStream {
         let (stream, aligners) = get_input_stream_aligners(config, &self.aec_config).await?;
         aec_log("Add input device 2");
         self.device_update_sender.try_send(DeviceUpdateMessage::AddInputDevice(config.device_name.clone(), stream, aligners))?;
+        self.input_sample_rates.insert(config.device_name.clone(), config.sample_rate);
         aec_log("Add input device done");
         Ok(())
     }
@@ -1809,58 +3460,452 @@ impl AecStream {
         aec_log("Add output device");
         let (stream, producer, consumer) = get_output_stream_aligners(config, &self.aec_config)?;
         self.device_update_sender.try_send(DeviceUpdateMessage::AddOutputDevice(config.device_name.clone(), stream, consumer))?;
+        self.output_sample_rates.insert(config.device_name.clone(), config.sample_rate);
         aec_log("Add output device done");
         Ok(producer)
     }
 
     pub fn remove_input_device(&mut self, config: &InputDeviceConfig) -> Result<(), Box<dyn std::error::Error>> {
         self.device_update_sender.try_send(DeviceUpdateMessage::RemoveInputDevice(config.device_name.clone()))?;
+        self.input_sample_rates.remove(&config.device_name);
         Ok(())
     }
 
     pub fn remove_output_device(&mut self, config: &OutputDeviceConfig) -> Result<(), Box<dyn std::error::Error>> {
         self.device_update_sender.try_send(DeviceUpdateMessage::RemoveOutputDevice(config.device_name.clone()))?;
+        self.output_sample_rates.remove(&config.device_name);
         Ok(())
     }
 
-    pub async fn calibrate(&mut self, output_producers: &mut [OutputStreamAlignerProducer], debug_wav: bool) -> Result<(), Box<dyn std::error::Error>> {
-        let (output_offsets, input_offsets) = self.get_calibration_offsets(output_producers, debug_wav).await?;
+    pub async fn calibrate(&mut self, output_producers: &mut [OutputStreamAlignerProducer], debug_wav: bool) -> Result<CalibrationReport, Box<dyn std::error::Error>> {
+        // this is the calibration an echo path change asked for, even if it fails
+        self.recalibration_needed = false;
+        self.last_calibration_micros = Some(now_micros());
+        let base = self.aec_config.calibration;
+        let mut report = self.measure_calibration(output_producers, base, base.capture_secs, None, debug_wav).await?;
+        // outputs an input couldn't pick out of the noise get probed again on their own, louder and longer
+        // each time, so a pair only ends up NotHeard once the probe is as loud and long as it gets
+        let mut config = base;
+        for attempt in 1..=base.retries {
+            let mut quiet: Vec<String> = report
+                .pairs
+                .iter()
+                .filter(|pair| pair.failure == Some(CalibrationFailure::NotHeard))
+                .map(|pair| pair.output_device.clone())
+                .collect();
+            quiet.sort();
+            quiet.dedup();
+            if quiet.is_empty() {
+                break;
+            }
+            let Some(louder) = config.louder() else {
+                break;
+            };
+            config = louder;
+            aec_log(format!("Not heard: {quiet:?}, retrying with {:?} for {} ms", config.probe, config.probe_duration_ms()));
+            self.report_calibration_progress(CalibrationProgress::Retrying(attempt));
+            let capture_secs = base.capture_secs + (config.probe_duration_ms() - base.probe_duration_ms()) / 1000.0;
+            let retry = self.measure_calibration(output_producers, config, capture_secs, Some(&quiet), debug_wav).await?;
+            report.merge_retry(retry, &quiet, attempt);
+        }
+        self.apply_calibration(report).await
+    }
+
+    /// Called as calibration (and `measure_impulse_response`) moves along, e.g. to drive a progress bar.
+    pub fn on_calibration_progress(&mut self, callback: impl FnMut(CalibrationProgress) + 'static) {
+        self.calibration_progress = Some(Box::new(callback));
+    }
+
+    /// Handle to abort the running (or next) calibration from elsewhere, e.g. when someone starts talking.
+    pub fn calibration_cancel(&self) -> CalibrationCancel {
+        self.calibration_cancel.clone()
+    }
+
+    fn report_calibration_progress(&mut self, progress: CalibrationProgress) {
+        if let Some(callback) = self.calibration_progress.as_mut() {
+            callback(progress);
+        }
+    }
+
+    // calibration to store and pass to calibrate_from_saved next time, None until calibrate has run
+    pub fn saved_calibration(&self) -> Option<&SavedCalibration> {
+        self.last_calibration.as_ref()
+    }
+
+    /// Reuses a calibration saved from an earlier session with the same devices. One measured within the
+    /// last hour is applied as is, an older one after checking it with a shorter capture (just long enough
+    /// for the probe and the saved delays). Falls back to a full `calibrate` if the devices or their sample
+    /// rates changed, or any pair moved by more than a couple ms.
+    pub async fn calibrate_from_saved(&mut self, output_producers: &mut [OutputStreamAlignerProducer], saved: &SavedCalibration, debug_wav: bool) -> Result<CalibrationReport, Box<dyn std::error::Error>> {
+        let sample_rate = self.aec_config.target_sample_rate;
+        let same_devices = |saved_rates: &[(String, u32)], rates: &HashMap<String, u32>| {
+            saved_rates.len() == rates.len() && saved_rates.iter().all(|(name, rate)| rates.get(name) == Some(rate))
+        };
+        if saved.sample_rate != sample_rate
+            || !same_devices(&saved.input_sample_rates, &self.input_sample_rates)
+            || !same_devices(&saved.output_sample_rates, &self.output_sample_rates)
+        {
+            aec_log("Saved calibration is for other devices, calibrating");
+            return self.calibrate(output_producers, debug_wav).await;
+        }
+        // one that heard nothing can't tell a muted speaker from a probe that didn't play, so it's rechecked
+        let age_micros = (now_micros() as u64).saturating_sub(saved.created_micros);
+        if age_micros < SAVED_CALIBRATION_TRUSTED_SECS * 1_000_000 && !saved.delays.is_empty() {
+            aec_log("Saved calibration is recent, applying it without probing");
+            let report = self.apply_calibration(self.report_from_saved(saved)).await?;
+            if let Some(calibration) = self.last_calibration.as_mut() {
+                calibration.created_micros = saved.created_micros;
+            }
+            return Ok(report);
+        }
+
+        let longest_delay = saved.delays.iter().map(|(_, _, delay)| *delay).max().unwrap_or(0).max(0);
+        let capture_secs = (self.aec_config.calibration.probe_duration_ms() / 1000.0) + (longest_delay as f32 / sample_rate as f32) + 0.5;
+        let mut report = self.measure_calibration(output_producers, self.aec_config.calibration, capture_secs, None, debug_wav).await?;
+        let tolerance = (SAVED_CALIBRATION_TOLERANCE_MS * sample_rate as f32 / 1000.0).round() as i64;
+        let verified = report.pairs.iter().all(|pair| {
+            match (pair.delay_samples, self.saved_delay(saved, &pair.output_device, &pair.input_device)) {
+                (Some(measured), Some(saved_delay)) => (measured - saved_delay).abs() <= tolerance,
+                (None, None) => true,
+                _ => false,
+            }
+        });
+        if !verified {
+            aec_log("Saved calibration no longer matches, calibrating");
+            return self.calibrate(output_producers, debug_wav).await;
+        }
+        // only whole samples are saved, the fraction just measured still applies
+        for pair in report.pairs.iter_mut() {
+            pair.delay_samples = self.saved_delay(saved, &pair.output_device, &pair.input_device);
+            pair.delay_ms = pair.delay_samples.map(|delay| (delay as f32 + pair.delay_fraction) * 1000.0 / sample_rate as f32);
+        }
+        report.from_saved = true;
+        let report = self.apply_calibration(report).await?;
+        // still the original measurement
+        if let Some(calibration) = self.last_calibration.as_mut() {
+            calibration.created_micros = saved.created_micros;
+        }
+        Ok(report)
+    }
+
+    // how much further the input of this pair has been shifted than its output, which is how much shorter the
+    // delay calibration measures now is than the saved one, counted from before any shifts
+    fn shift_offset(&self, output_device: &str, input_device: &str) -> i64 {
+        let input_shift = self.input_aligners.get(input_device).map_or(0, |aligner| aligner.shifted_frames);
+        let output_shift = self.output_aligners.get(output_device).map_or(0, |aligner| aligner.shifted_frames);
+        input_shift - output_shift
+    }
+
+    // a saved delay as calibration would measure it on the stream as it's shifted now
+    fn saved_delay(&self, saved: &SavedCalibration, output_device: &str, input_device: &str) -> Option<i64> {
+        Some(saved.delay(output_device, input_device)? - self.shift_offset(output_device, input_device))
+    }
+
+    // the report calibrate would have given for the saved delays, without the measurement details
+    fn report_from_saved(&self, saved: &SavedCalibration) -> CalibrationReport {
+        let sample_rate = self.aec_config.target_sample_rate;
+        let pairs = self
+            .sorted_input_aligners
+            .iter()
+            .flat_map(|input_name| {
+                self.sorted_output_aligners.iter().map(move |output_name| {
+                    let delay_samples = self.saved_delay(saved, output_name, input_name);
+                    CalibrationPair {
+                        output_device: output_name.clone(),
+                        input_device: input_name.clone(),
+                        delay_samples,
+                        delay_fraction: 0.0,
+                        delay_ms: delay_samples.map(|delay| delay as f32 * 1000.0 / sample_rate as f32),
+                        confidence: 0.0,
+                        snr_db: f32::NEG_INFINITY,
+                        failure: delay_samples.is_none().then_some(CalibrationFailure::NotHeard),
+                        drift_ppm: None,
+                    }
+                })
+            })
+            .collect();
+        CalibrationReport { sample_rate, pairs, from_saved: true, ..Default::default() }
+    }
+
+    /// Measures the room impulse response from `output_device` to every input with Farina's method: plays a
+    /// log sweep on that output alone and convolves what each input heard with the inverse sweep. Responses are
+    /// `length_ms` long and relative to the aligned reference, so run it after `calibrate`.
+    pub async fn measure_impulse_response(&mut self, output_producers: &mut [OutputStreamAlignerProducer], output_device: &str, length_ms: f32) -> Result<Vec<ImpulseResponse>, Box<dyn std::error::Error>> {
+        let sample_rate = self.aec_config.target_sample_rate;
+        let sr = sample_rate as f32;
+        let producer = output_producers
+            .iter_mut()
+            .find(|p| p.device_name == output_device)
+            .ok_or_else(|| format!("no output producer found for '{output_device}'"))?;
+        let f1 = IR_SWEEP_MAX_HZ.min(sr * 0.45);
+        let gain = 10.0f32.powf(IR_SWEEP_LEVEL_DB / 20.0);
+        let sweep = chirp(IR_SWEEP_MIN_HZ, f1, sr, IR_SWEEP_SECS);
+        let inverse = inverse_chirp(&sweep, IR_SWEEP_MIN_HZ, f1, sr);
+
+        let channel_map = HashMap::from([(0, (0..producer.channels).collect())]);
+        let (stream_id, mut stream) = producer.begin_audio_stream(1, channel_map, IR_SWEEP_SECS.ceil() as u32 + 1, sample_rate, 5)?;
+        stream.queue_audio(&sweep.iter().map(|s| s * gain).collect::<Vec<f32>>());
+        // keep delay tracking from moving the alignment while we listen
+        self.calibrating = true;
+        let captured = self.capture_per_device(IR_SWEEP_SECS + length_ms / 1000.0 + 0.5).await;
+        self.calibrating = false;
+        self.calibration_cancel.reset();
+        producer.end_audio_stream(stream_id)?;
+        let (captured_inputs, captured_outputs) = captured?;
+
+        // the deconvolved reference is a single impulse where the reference had the sweep, which is where the
+        // responses start, and its height is what a gain of 1.0 comes out as
+        let reference = captured_outputs
+            .iter()
+            .find(|(name, _)| name == output_device)
+            .map(|(_, samples)| convolve(samples, &inverse))
+            .ok_or_else(|| format!("'{output_device}' is not an output of this stream"))?;
+        let start = reference
+            .iter()
+            .enumerate()
+            .fold(0, |best, (i, v)| if v.abs() > reference[best].abs() { i } else { best });
+        let peak = reference.get(start).copied().unwrap_or(0.0);
+        if peak.abs() < 1e-3 {
+            return Err(format!("the sweep never showed up in the reference of '{output_device}'").into());
+        }
+        let len = (length_ms * sr / 1000.0).round() as usize;
+        let responses = captured_inputs
+            .iter()
+            .map(|(input_name, samples)| {
+                let mut response: Vec<f32> = convolve(samples, &inverse).into_iter().skip(start).take(len).map(|v| v / peak).collect();
+                response.resize(len, 0.0);
+                ImpulseResponse {
+                    output_device: output_device.to_string(),
+                    input_device: input_name.clone(),
+                    sample_rate,
+                    samples: response,
+                }
+            })
+            .collect();
+        Ok(responses)
+    }
+
+    /// Play a distinct probe on each physical channel of `output_device` in turn and report, for every input,
+    /// which channels it heard and at what level and delay, to check a channel_map against the actual speakers.
+    pub async fn identify_speaker_channels(&mut self, output_producers: &mut [OutputStreamAlignerProducer], output_device: &str) -> Result<Vec<SpeakerChannel>, Box<dyn std::error::Error>> {
+        let sample_rate = self.aec_config.target_sample_rate;
+        let sr = sample_rate as f32;
+        let producer = output_producers
+            .iter_mut()
+            .find(|p| p.device_name == output_device)
+            .ok_or_else(|| format!("no output producer found for '{output_device}'"))?;
+
+        // per channel, per input: (input, confidence, gain, delay in samples)
+        let mut heard: Vec<Vec<(String, f32, f32, i64)>> = Vec::with_capacity(producer.channels);
+        for channel in 0..producer.channels {
+            let probe = indexed_chirp(channel as u32, sample_rate, CHANNEL_PROBE_SECS);
+            let channel_map = HashMap::from([(0, vec![channel])]);
+            let (stream_id, mut stream) = producer.begin_audio_stream(1, channel_map, CHANNEL_PROBE_SECS.ceil() as u32 + 1, sample_rate, 5)?;
+            stream.queue_audio(&probe);
+            // keep delay tracking from moving the alignment while we listen
+            self.calibrating = true;
+            let captured = self.capture_per_device(CHANNEL_PROBE_SECS + CHANNEL_LISTEN_SECS).await;
+            self.calibrating = false;
+            self.calibration_cancel.reset();
+            producer.end_audio_stream(stream_id)?;
+            let (captured_inputs, captured_outputs) = captured?;
+
+            // the reference is the channel average, which still has the probe at the right time
+            let reference_start = captured_outputs
+                .iter()
+                .find(|(name, _)| name == output_device)
+                .and_then(|(_, samples)| detect_probe_fft(samples, &probe))
+                .filter(|&(_, score)| score >= MIN_CHIRP_CONFIDENCE)
+                .map(|(start, _)| start as i64)
+                .ok_or_else(|| format!("the probe for channel {channel} never showed up in the reference of '{output_device}'"))?;
+            let probe_energy = probe.iter().map(|v| v * v).sum::<f32>();
+            heard.push(
+                captured_inputs
+                    .iter()
+                    .map(|(input_name, samples)| match detect_probe_fft(samples, &probe) {
+                        Some((start, score)) => {
+                            // least squares gain of the probe at the match, only compared between channels
+                            let gain = samples[start..].iter().zip(&probe).map(|(x, p)| x * p).sum::<f32>() / probe_energy;
+                            (input_name.clone(), score.max(0.0), gain.abs(), start as i64 - reference_start)
+                        }
+                        None => (input_name.clone(), 0.0, 0.0, 0),
+                    })
+                    .collect(),
+            );
+        }
+
+        let inputs = heard.first().map_or(0, |h| h.len());
+        let mut channels = Vec::with_capacity(heard.len() * inputs);
+        for input in 0..inputs {
+            let loudest = heard
+                .iter()
+                .map(|h| &h[input])
+                .filter(|(_, confidence, _, _)| *confidence >= MIN_CHIRP_CONFIDENCE)
+                .fold(0.0f32, |m, &(_, _, gain, _)| m.max(gain));
+            for (channel, h) in heard.iter().enumerate() {
+                let (input_name, confidence, gain, delay) = &h[input];
+                let audible = *confidence >= MIN_CHIRP_CONFIDENCE && *gain > 0.0;
+                channels.push(SpeakerChannel {
+                    output_device: output_device.to_string(),
+                    input_device: input_name.clone(),
+                    channel,
+                    audible,
+                    confidence: *confidence,
+                    relative_level_db: audible.then(|| 20.0 * (gain / loudest).log10()),
+                    delay_ms: audible.then(|| *delay as f32 * 1000.0 / sr),
+                });
+            }
+        }
+        Ok(channels)
+    }
+
+    // one calibration measurement with `config`, probing only `outputs` if given
+    async fn measure_calibration(&mut self, output_producers: &mut [OutputStreamAlignerProducer], config: CalibrationConfig, capture_secs: f32, outputs: Option<&[String]>, debug_wav: bool) -> Result<CalibrationReport, Box<dyn std::error::Error>> {
+        self.calibrating = true;
+        let report = self.get_calibration_offsets(output_producers, config, capture_secs, outputs, debug_wav).await;
+        self.calibrating = false;
+        // a cancel only ever aborts one calibration
+        self.calibration_cancel.reset();
+        report
+    }
+
+    // lines the devices up according to the measured delays and remembers them for saved_calibration
+    async fn apply_calibration(&mut self, mut report: CalibrationReport) -> Result<CalibrationReport, Box<dyn std::error::Error>> {
+        self.last_calibration = Some(SavedCalibration {
+            sample_rate: report.sample_rate,
+            input_sample_rates: self.input_sample_rates.iter().map(|(name, rate)| (name.clone(), *rate)).collect(),
+            output_sample_rates: self.output_sample_rates.iter().map(|(name, rate)| (name.clone(), *rate)).collect(),
+            delays: report
+                .pairs
+                .iter()
+                .filter_map(|pair| {
+                    let delay = pair.delay_samples? + self.shift_offset(&pair.output_device, &pair.input_device);
+                    Some((pair.output_device.clone(), pair.input_device.clone(), delay))
+                })
+                .collect(),
+            created_micros: now_micros() as u64,
+        });
+        // calibration starts the delay tracking and echo path monitoring over from the new alignment
+        for tracker in self.delay_trackers.values_mut() {
+            tracker.reset();
+        }
+        self.echo_path_monitor.reset();
+        self.recalibration_needed = false;
+        // an output no input could hear, even probed as loud as calibration goes, is a headset or similar.
+        // Outputs whose probe didn't play don't say anything either way
+        if self.aec_config.isolation.is_some() {
+            let heard = report.pairs.iter().any(|pair| pair.failure.is_none());
+            let not_heard = report.pairs.iter().any(|pair| pair.failure == Some(CalibrationFailure::NotHeard));
+            self.set_acoustically_isolated(not_heard && !heard)?;
+            // this is the calibration coupling would ask for
+            self.recalibration_needed = false;
+        }
+        report.acoustically_isolated = self.acoustically_isolated;
         // we need to throw away some samples for each device until we are calibrated
         // each device will have an offset (could be negative)
-        for input_index in 0..input_offsets.len() {
-            let mut shifts_needed = Vec::new();
-            for output_index in 0..output_offsets.len() {
-                if let Some(output_offset) = output_offsets[output_index] {
-                    if let Some(input_offset) = input_offsets[input_index][output_index] {
-                        let shift_needed = input_offset - output_offset;
-                        shifts_needed.push(shift_needed);
-                    }
+        let shifts_needed: Vec<(String, i64)> = self
+            .sorted_input_aligners
+            .iter()
+            .map(|input_name| {
+                // take the min of the shift needed for each device (we don't ever want it to occur before the device).
+                let shift_needed = report
+                    .pairs
+                    .iter()
+                    .filter(|pair| &pair.input_device == input_name)
+                    .filter_map(|pair| pair.delay_samples)
+                    .min()
+                    .map_or(0, |min_val| min_val - self.target_echo_lag());
+                (input_name.clone(), shift_needed)
+            })
+            .collect();
+        // a negative shift means the input hears the echo before (or not long enough after) the reference has it.
+        // throwing away reference instead fixes that, it's shared so every input gets shifted by as much on top
+        let reference_shift = shifts_needed.iter().map(|(_, shift)| -shift).max().unwrap_or(0).max(0);
+        if reference_shift > 0 {
+            aec_log(format!("Shifting reference {reference_shift}"));
+            for output_name in &self.sorted_output_aligners {
+                if let Some(aligner) = self.output_aligners.get_mut(output_name) {
+                    let (_ok, chunk) = aligner.get_chunk_to_read((reference_shift as usize) * aligner.channels).await;
+                    let chunk_len = chunk.len();
+                    aligner.finish_read(chunk_len);
+                    aligner.shifted_frames += (chunk_len / aligner.channels) as i64;
                 }
             }
-            // take the min of the shift needed for each device (we don't ever want it to occur before the device)
-            let shift_needed = if let Some(min_val) = shifts_needed.iter().min() {
-                (*min_val as i64) - ((self.aec_config.frame_size/3) as i64)
-            } else {
-                0
-            };
-            println!("Shifting {shift_needed}");
-            if let Some(aligner) = self.input_aligners.get_mut(&self.sorted_input_aligners[input_index].clone()) {
+        }
+        report.reference_shift = reference_shift;
+
+        // each input is lined up with its nearest output, so hold the reference of the others back by how much
+        // further away they are, to a fraction of a sample. An output heard by several inputs gets the smallest
+        // such delay, any more would put its echo ahead of its reference on one of them
+        for output_name in &self.sorted_output_aligners {
+            let delay = report
+                .pairs
+                .iter()
+                .filter(|pair| &pair.output_device == output_name)
+                .filter_map(|pair| {
+                    let (_, shift_needed) = shifts_needed.iter().find(|(input_name, _)| *input_name == pair.input_device)?;
+                    Some((pair.delay_samples? - self.target_echo_lag() - shift_needed) as f32 + pair.delay_fraction)
+                })
+                .min_by(f32::total_cmp)
+                .unwrap_or(0.0)
+                .max(0.0);
+            if let Some(line) = self.reference_delays.get_mut(output_name) {
+                line.set_delay(delay);
+            }
+            aec_log(format!("Delaying reference of '{output_name}' by {delay}"));
+            report.output_delays.push((output_name.clone(), delay));
+        }
+
+        // start each input's resampler off against the drift it showed, averaged over the outputs it heard.
+        // The dynamic rate keeps following the system clock from there
+        for input_name in &self.sorted_input_aligners {
+            let drifts: Vec<f32> = report
+                .pairs
+                .iter()
+                .filter(|pair| &pair.input_device == input_name)
+                .filter_map(|pair| pair.drift_ppm)
+                .collect();
+            if drifts.is_empty() {
+                continue;
+            }
+            let correction = -drifts.iter().sum::<f32>() / drifts.len() as f32;
+            aec_log(format!("Correcting drift of '{input_name}' by {correction} ppm"));
+            if let Some(aligner) = self.input_aligners.get_mut(input_name) {
+                aligner.adjust_drift(correction as f64)?;
+            }
+            report.drift_corrections.push((input_name.clone(), correction));
+        }
+
+        for (input_name, shift_needed) in shifts_needed {
+            let shift_needed = shift_needed + reference_shift;
+            aec_log(format!("Shifting input '{input_name}' by {shift_needed}"));
+            if let Some(aligner) = self.input_aligners.get_mut(&input_name) {
+                // this replaces whatever shift was still pending
+                aligner.shifted_frames -= aligner.pending_shift;
+                aligner.pending_shift = 0;
                 // skip ahead that many samples (* num channels bc it is multi channel)
                 if shift_needed > 0 {
+                    aligner.shifted_frames += shift_needed;
                     let (_ok, chunk) = aligner.get_chunk_to_read((shift_needed as usize) * aligner.channels).await;
                     let chunk_len = chunk.len();
                     aligner.finish_read(chunk_len);
+                    // if the stream closed early, drop the rest once it's back
+                    aligner.pending_shift = shift_needed - (chunk_len / aligner.channels) as i64;
                 }
             }
+            report.input_shifts.push((input_name, shift_needed));
         }
-        Ok(())
+        Ok(report)
     }
 
-    async fn get_calibration_offsets(&mut self, output_producers: &mut [OutputStreamAlignerProducer], debug_wav: bool) -> Result<(Vec<Option<i64>>, Vec<Vec<Option<i64>>>), Box<dyn std::error::Error>> {
+    async fn get_calibration_offsets(&mut self, output_producers: &mut [OutputStreamAlignerProducer], config: CalibrationConfig, capture_secs: f32, outputs: Option<&[String]>, debug_wav: bool) -> Result<CalibrationReport, Box<dyn std::error::Error>> {
         let sample_rate = self.aec_config.target_sample_rate as u32;
-        // Probe length (~0.1s) to stay quick but audible.
-        let tone_ms = 100.0;
-        let capture_secs = 3.0;
+        // measuring drift plays every probe a second time, this many samples after the first,
+        // so each half of the capture has one of them
+        let drift_split = config.drift_interval_secs.map(|secs| (secs * sample_rate as f32).round() as usize);
+        let capture_secs = capture_secs + config.drift_interval_secs.unwrap_or(0.0);
+        let probe_secs = config.probe_duration_ms() / 1000.0 + config.drift_interval_secs.unwrap_or(0.0);
+        let probe_buffer_secs = config.probe_buffer_secs.max(probe_secs.ceil() as u32 + 1);
 
         // 1) Emit a distinct probe on each output device (all channels), in sorted output order.
         let mut active_streams: Vec<(usize, usize, StreamId)> = Vec::new();
@@ -1868,13 +3913,16 @@ impl AecStream {
         let mut streams = Vec::new();
 
         for (idx, dev_name) in self.sorted_output_aligners.clone().iter().enumerate() {
+            if outputs.is_some_and(|outputs| !outputs.contains(dev_name)) {
+                continue;
+            }
             let Some(producer) = output_producers
                 .iter_mut()
                 .find(|p| p.device_name == *dev_name) else {
                 eprintln!("calibrate: no output producer found for '{dev_name}'");
                 continue;
             };
-            let tone_mono = generate_probe_tone_for_device(idx, tone_ms, sample_rate);
+            let tone_mono = config.generate_probe(idx, sample_rate);
             if tone_mono.is_empty() {
                 continue;
             }
@@ -1888,9 +3936,9 @@ impl AecStream {
             let (stream_id, stream) = producer.begin_audio_stream(
                 1, // 1 channel
                 channel_map,
-                2, // seconds of buffer for this probe
+                probe_buffer_secs,
                 self.aec_config.target_sample_rate,
-                5, // resampler quality
+                config.resampler_quality,
             )?;
             tones.push(tone_mono);
             active_streams.push((idx, channels, stream_id));
@@ -1899,8 +3947,93 @@ impl AecStream {
 
         for (mut stream, tone) in streams.into_iter().zip(tones.into_iter()) {
             stream.queue_audio(tone.as_slice());
+            if let Some(split) = drift_split {
+                stream.queue_audio(&vec![0.0; split.saturating_sub(tone.len())]);
+                stream.queue_audio(tone.as_slice());
+            }
+        }
+        self.report_calibration_progress(CalibrationProgress::Probing);
+
+        // 2) Capture capture_secs of aligned input/output data, averaged per device.
+        let captured = self.capture_per_device(capture_secs).await;
+
+        // 3) Stop probe streams now that capture is done.
+        for (idx, _channels, stream_id) in active_streams {
+            if let Some(producer) = output_producers.get_mut(idx) {
+                producer.end_audio_stream(stream_id)?;
+            }
+        }
+        let (captured_inputs, captured_outputs) = captured?;
+
+        // keep what every device got, in memory so it works without a filesystem too
+        let mut debug_wavs = Vec::new();
+        if debug_wav {
+            for (prefix, captures) in [("calib_input", &captured_inputs), ("calib_output", &captured_outputs)] {
+                for (name, samples) in captures {
+                    let file_name = format!("{prefix}_{}.wav", Self::sanitize_filename(name));
+                    debug_wavs.push((file_name, wav_bytes(samples, sample_rate)?));
+                }
+            }
+        }
+
+        // 4) Detect probes on outputs to compute offsets relative to output device 0.
+        self.report_calibration_progress(CalibrationProgress::Analysing);
+        // the delays come from the first probe alone
+        let first_probe = |buf: &[f32]| buf.len().min(drift_split.unwrap_or(usize::MAX));
+        // mapping of output device -> offset
+        let mut output_offsets: Vec<Option<i64>> = vec![None; captured_outputs.len()];
+        let mut output_fractions = vec![0.0f32; captured_outputs.len()];
+        for (dev_idx, (_name, buf)) in captured_outputs.iter().enumerate() {
+            // just detect this device, no others should show up since it just forwards the data
+            let detection = detect_probe_tone(&buf[..first_probe(buf)], dev_idx, &config, sample_rate);
+            if let Some(start) = detection.start {
+                let in_seconds = (start as f32) / (sample_rate as f32);
+                aec_log(format!("Output {dev_idx} has offset {start} ({in_seconds} s)"));
+                output_offsets[dev_idx] = Some(start);
+                output_fractions[dev_idx] = detection.fraction;
+            }
         }
 
+        // 5) Detect probes per input device, the echo delay is how much later the input heard it than the output sent it.
+        let mut report = CalibrationReport { sample_rate, attempts: 1, debug_wavs, ..Default::default() };
+        for (input_name, buf) in &captured_inputs {
+            let detections = detect_probe_tones(&buf[..first_probe(buf)], captured_outputs.len(), &config, sample_rate);
+            for (output_idx, detection) in detections.into_iter().enumerate() {
+                let output_offset = output_offsets[output_idx];
+                let failure = match (output_offset, detection.start) {
+                    (None, _) => Some(CalibrationFailure::OutputNotPlayed),
+                    (Some(_), None) => Some(CalibrationFailure::NotHeard),
+                    (Some(_), Some(_)) => None,
+                };
+                // the captured outputs already went through their reference delay, which isn't part of the echo path
+                let output_name = &captured_outputs[output_idx].0;
+                let reference_delay = self.reference_delays.get(output_name).map_or(0.0, |line| line.delay());
+                let delay = output_offset.zip(detection.start).map(|(output_start, input_start)| {
+                    (input_start - output_start) as f32 + detection.fraction - output_fractions[output_idx] + reference_delay
+                });
+                let delay_samples = delay.map(|delay| delay.round() as i64);
+                let drift_ppm = drift_split.zip(output_offset.zip(detection.start)).and_then(|(split, first)| {
+                    probe_drift_ppm(&captured_outputs[output_idx].1, buf, output_idx, first, split, &config, sample_rate)
+                });
+                report.pairs.push(CalibrationPair {
+                    output_device: output_name.clone(),
+                    input_device: input_name.clone(),
+                    delay_samples,
+                    delay_fraction: delay.map_or(0.0, |delay| delay - delay.round()),
+                    delay_ms: delay.map(|delay| delay * 1000.0 / sample_rate as f32),
+                    confidence: detection.confidence,
+                    snr_db: detection.snr_db,
+                    failure,
+                    drift_ppm,
+                });
+            }
+        }
+        Ok(report)
+    }
+
+    // runs the stream for capture_secs and returns what each input and output device got (channels averaged),
+    // in sorted device order
+    async fn capture_per_device(&mut self, capture_secs: f32) -> Result<(Vec<(String, Vec<f32>)>, Vec<(String, Vec<f32>)>), Box<dyn std::error::Error>> {
         // Build channel ranges for inputs and outputs (interleaved order).
         let mut input_channel_ranges: Vec<(String, usize, usize)> = Vec::new();
         let mut in_ch_start = 0usize;
@@ -1918,15 +4051,16 @@ impl AecStream {
                 out_ch_start += aligner.channels;
             }
         }
-
-        // 2) Capture ~3s of aligned input/output data, averaged per device.
         let mut captured_inputs: Vec<Vec<f32>> = vec![Vec::new(); input_channel_ranges.len()];
         let mut captured_outputs: Vec<Vec<f32>> = vec![Vec::new(); output_channel_ranges.len()];
         let mut captured_micros: u128 = 0;
-        let target_micros: u128 = ((capture_secs as u128) * 1_000_000) as u128;
+        let target_micros: u128 = (capture_secs * 1_000_000.0) as u128;
         let total_in_ch = self.input_channels.max(1);
         let total_out_ch = self.output_channels.max(1);
         while captured_micros < target_micros {
+            if self.calibration_cancel.is_cancelled() {
+                return Err("calibration cancelled".into());
+            }
             let (input_slices, output_slices, _aec_out, start_time, end_time) = self.update_debug().await?;
             let chunk_micros = end_time.saturating_sub(start_time);
             if !input_slices.is_empty() && total_in_ch > 0 {
@@ -1937,7 +4071,7 @@ impl AecStream {
                         let mut acc = 0.0f32;
                         for ch in 0..*ch_count {
                             let sample = input_slices[base + start_ch + ch];
-                            acc += f32::from_sample(sample);
+                            acc += sample;
                         }
                         captured_inputs[dev_idx].push(acc / (*ch_count as f32));
                     }
@@ -1951,103 +4085,21 @@ impl AecStream {
                         let mut acc = 0.0f32;
                         for ch in 0..*ch_count {
                             let sample = output_slices[base + start_ch + ch];
-                            acc += f32::from_sample(sample);
+                            acc += sample;
                         }
                         captured_outputs[dev_idx].push(acc / (*ch_count as f32));
                     }
                 }
             }
             captured_micros += chunk_micros;
+            self.report_calibration_progress(CalibrationProgress::Capturing((captured_micros as f32 / target_micros.max(1) as f32).min(1.0)));
         }
 
-        // 3) Stop probe streams now that capture is done.
-        for (idx, _channels, stream_id) in active_streams {
-            if let Some(producer) = output_producers.get_mut(idx) {
-                producer.end_audio_stream(stream_id)?;
-            }
-        }
-
-        if debug_wav {
-            // write captured inputs
-            for (i, (name, _, _)) in input_channel_ranges.iter().enumerate() {
-                let sanitized = Self::sanitize_filename(name);
-                let path = format!("calib_input_{sanitized}.wav");
-                let mut writer = WavWriter::create(
-                    path,
-                    WavSpec {
-                        channels: 1,
-                        sample_rate: self.aec_config.target_sample_rate,
-                        bits_per_sample: 16,
-                        sample_format: HoundSampleFormat::Int,
-                    },
-                )?;
-                for &s in &captured_inputs[i] {
-                    writer.write_sample(Self::f32_to_i16(s))?;
-                }
-                writer.finalize()?;
-            }
-            // write captured outputs
-            for (i, (name, _, _)) in output_channel_ranges.iter().enumerate() {
-                let sanitized = Self::sanitize_filename(name);
-                let path = format!("calib_output_{sanitized}.wav");
-                let mut writer = WavWriter::create(
-                    path,
-                    WavSpec {
-                        channels: 1,
-                        sample_rate: self.aec_config.target_sample_rate,
-                        bits_per_sample: 16,
-                        sample_format: HoundSampleFormat::Int,
-                    },
-                )?;
-                for &s in &captured_outputs[i] {
-                    writer.write_sample(Self::f32_to_i16(s))?;
-                }
-                writer.finalize()?;
-            }
-        }
-
-        // 4) Detect probes on outputs to compute offsets relative to output device 0.
-        // mapping of output device -> offset
-        let mut output_offsets: Vec<Option<i64>> = vec![None; output_channel_ranges.len()];
-        // mapping of input device -> (output device index, output device index offset)
-        let mut input_offsets: Vec<Vec<Option<i64>>> = vec![vec![None; output_channel_ranges.len()]; input_channel_ranges.len()];
-
-        if !captured_outputs.is_empty() {
-            for (dev_idx, buf) in captured_outputs.iter().enumerate() {
-                let detections = detect_probe_tones(buf, output_producers.len(), tone_ms, sample_rate);
-                let mut start_for_dev: Option<(i64, f32)> = None;
-                for (d_idx, start, score) in detections {
-                    if d_idx == dev_idx { // just detect this device, no others should show up since it just forwards the data
-                        start_for_dev = Some((start, score));
-                        break;
-                    }
-                }
-                if let Some((start, score)) = start_for_dev {
-                    let in_seconds = (start as f32) / (sample_rate as f32);
-                    println!("Output {dev_idx} has offset {start} {in_seconds} with score {score}");
-                    output_offsets[dev_idx] = Some(start);
-                } else {
-                    eprintln!("No probe detected for output device index {dev_idx}");
-                }
-            }
-            for (input_idx, buf) in captured_inputs.iter().enumerate() {
-                let detections = detect_probe_tones(buf, output_producers.len(), tone_ms, sample_rate);
-                for (output_idx, start, score) in detections {
-                    let in_seconds = (start as f32) / (sample_rate as f32);
-                    println!("Output {output_idx} -> Input {input_idx} has offset {start} {in_seconds} with score {score}");
-                    input_offsets[input_idx][output_idx] = Some(start);
-                }
-                for output_idx in 0..output_channel_ranges.len() {
-                    if let None = input_offsets[input_idx][output_idx] {
-                        eprintln!("No probe detected for output {output_idx} -> input {input_idx} (that input could not hear that output, is the volume too low?)");
-                    }
-                }
-            }
-        }
 
-        // 5) Detect probes and compute offsets relative to output device 0, per input device.
-        
-        Ok((output_offsets, input_offsets))
+        let name_captures = |ranges: Vec<(String, usize, usize)>, captures: Vec<Vec<f32>>| -> Vec<(String, Vec<f32>)> {
+            ranges.into_iter().map(|(name, _, _)| name).zip(captures).collect()
+        };
+        Ok((name_captures(input_channel_ranges, captured_inputs), name_captures(output_channel_ranges, captured_outputs)))
     }
 
     fn sanitize_filename(name: &str) -> String {
@@ -2058,7 +4110,7 @@ impl AecStream {
 
     // calls update, but returns all involved audio buffers
     // (if needed for diagnostic reasons, usually .update() (which returns aec'd inputs) should be all you need)
-    pub async fn update_debug(&mut self) -> Result<(&[i16], &[i16], &[f32], u128, u128), Box<dyn std::error::Error>> {
+    pub async fn update_debug(&mut self) -> Result<(&[f32], &[f32], &[f32], u128, u128), Box<dyn std::error::Error>> {
         let (start_time, end_time) = {
             let (_, start_time, end_time) = self.update().await?;
             (start_time, end_time)
@@ -2166,21 +4218,32 @@ impl AecStream {
 
         // recieve audio data and interleave it into our buffers
         let mut input_channel = 0;
-        self.input_audio_buffer.fill(0 as i16);
+        self.input_audio_buffer.fill(0 as f32);
 
         for key in &self.sorted_input_aligners {
             if let Some(aligner) = self.input_aligners.get_mut(key) {
                 let channels = aligner.channels;
-                let needed = chunk_size * channels;
+                // apply a shift from the delay tracker: drop input to catch up,
+                // or read less (behind silence) to fall back
+                if aligner.pending_shift > 0 {
+                    let (_ok, chunk) = aligner.get_chunk_to_read(aligner.pending_shift as usize * channels).await;
+                    let chunk_len = chunk.len();
+                    aligner.finish_read(chunk_len);
+                    // a closed stream can hand back less than asked for, keep the rest for later
+                    aligner.pending_shift -= (chunk_len / channels) as i64;
+                }
+                let pad_frames = (-aligner.pending_shift).clamp(0, chunk_size as i64) as usize;
+                aligner.pending_shift += pad_frames as i64;
+                let needed = (chunk_size - pad_frames) * channels;
                 let (ok, chunk) = aligner.get_chunk_to_read(needed).await;
                 let frames = chunk.len() / channels;
 
                 if ok && frames > 0 {
                     for c in 0..channels {
                         let mut src_idx = c;
-                        let mut dst = input_channel + c;
+                        let mut dst = pad_frames * self.input_channels + input_channel + c;
                         for _ in 0..frames {
-                            self.input_audio_buffer[dst] = Self::f32_to_i16(chunk[src_idx]);
+                            self.input_audio_buffer[dst] = chunk[src_idx];
                             dst += self.input_channels;
                             src_idx += channels;
                         }
@@ -2193,6 +4256,7 @@ impl AecStream {
         }
 
         aec_log("Done update read aligners");
+        let mut aec_ran = false;
         let aec_output = if self.output_channels == 0 {
             // simply pass through input_channels, no need for aec
             &self.input_audio_buffer
@@ -2200,7 +4264,7 @@ impl AecStream {
         else {
                 
             let mut output_channel = 0;
-            self.output_audio_buffer.fill(0 as i16);
+            self.output_audio_buffer.fill(0 as f32);
             for key in &self.sorted_output_aligners {
                 if let Some(aligner) = self.output_aligners.get_mut(key) {
                     let channels = aligner.channels;
@@ -2213,28 +4277,29 @@ impl AecStream {
                             let mut src_idx = c;
                             let mut dst = output_channel + c;
                             for _ in 0..frames {
-                                self.output_audio_buffer[dst] = Self::f32_to_i16(chunk[src_idx]);
+                                self.output_audio_buffer[dst] = chunk[src_idx];
                                 dst += self.output_channels;
                                 src_idx += channels;
                             }
                         }
                         aligner.finish_read(frames * channels);
                     }
+                    if let Some(delay) = self.reference_delays.get_mut(key) {
+                        delay.process(&mut self.output_audio_buffer, self.output_channels, output_channel);
+                    }
 
                     output_channel += channels;
                 }
             }
 
-            self.aec_audio_buffer.fill(0 as i16);
+            self.track_echo_delay()?;
+
+            self.aec_audio_buffer.fill(0 as f32);
 
             if self.input_channels == 0 {
                 &self.aec_audio_buffer
             }
             else {
-                //let Some(aec) = self.aec.as_mut() else { 
-                //    return Err("no aec".into());
-                //};
-                
                 // skip ahead if no output, as there's nothing to cancel
                 // this helps avoid needing to recalibrate every time we recieve audio
                 let output_energy = Self::energy(&self.output_audio_buffer);
@@ -2243,25 +4308,16 @@ impl AecStream {
                     println!("skipping with energy {output_energy}");
                     self.aec_audio_buffer.copy_from_slice(&self.input_audio_buffer);
                 }
+                else if self.acoustically_isolated {
+                    self.aec_audio_buffer.copy_from_slice(&self.input_audio_buffer);
+                }
                 else {
-                    ////// aec3
-                    let Some(aec3_value) = self.aec3.as_mut() else {
-                        return Err("No aec3".into());
+                    let Some(aec) = self.aec.as_mut() else {
+                        return Err("No echo canceller".into());
                     };
-                    let mut input_audio_tmp = vec![0f32; self.input_audio_buffer.len()];
-                    let mut output_audio_tmp = vec![0f32; self.output_audio_buffer.len()];
-                    let mut aec_audio_tmp =  vec![0f32; self.input_audio_buffer.len()];
-                    for i in 0..input_audio_tmp.len() {
-                        input_audio_tmp[i] = f32::from_sample(self.input_audio_buffer[i]);
-                    }
-                    for i in 0..output_audio_tmp.len() {
-                        output_audio_tmp[i] = f32::from_sample(self.output_audio_buffer[i]);
-                    }
-                    let _metrics = aec3_value.process(&input_audio_tmp, Some(&output_audio_tmp), false, &mut aec_audio_tmp)?;
-
-                    for i in 0..aec_audio_tmp.len() {
-                        self.aec_audio_buffer[i] = i16::from_sample(aec_audio_tmp[i]);
-                    }
+                    aec.process(&self.input_audio_buffer, &self.output_audio_buffer, &mut self.aec_audio_buffer)?;
+                    aec_ran = true;
+                    self.monitor_echo_path()?;
                 }
                 
                 &self.aec_audio_buffer
@@ -2269,15 +4325,14 @@ impl AecStream {
         };
         
         aec_log("Done aec");
-        for (out, sample) in self.aec_out_audio_buffer.iter_mut().zip(aec_output) {
-            *out = f32::from_sample(*sample);
-        }
+        self.aec_out_audio_buffer.copy_from_slice(aec_output);
+        self.preprocess(aec_ran);
 
         Ok((self.aec_out_audio_buffer.as_slice(), chunk_start_micros, chunk_end_micros))
     }
     
-    fn energy(buf: &[i16]) -> f64 {
-        buf.iter().map(|s| (f32::from_sample(*s)*f32::from_sample(*s)) as f64).sum::<f64>() / buf.len() as f64
+    fn energy(buf: &[f32]) -> f64 {
+        buf.iter().map(|s| (s*s) as f64).sum::<f64>() / buf.len() as f64
     }
 
     fn write_channel_from_f32(
@@ -2285,22 +4340,18 @@ impl AecStream {
         channel: usize,
         total_channels: usize,
         frames: usize,
-        dst: &mut [i16],
+        dst: &mut [f32],
     ) {
         for frame in 0..frames {
             let value = src.get(frame).copied().unwrap_or(0.0);
-            dst[frame * total_channels + channel] = Self::f32_to_i16(value);
+            dst[frame * total_channels + channel] = value;
         }
     }
-    fn clear_channel(channel: usize, total_channels: usize, frames: usize, dst: &mut [i16]) {
+    fn clear_channel(channel: usize, total_channels: usize, frames: usize, dst: &mut [f32]) {
         for frame in 0..frames {
-            dst[frame * total_channels + channel] = 0;
+            dst[frame * total_channels + channel] = 0.0;
         }
     }
-    fn f32_to_i16(sample: f32) -> i16 {
-        let clamped = sample.clamp(-1.0, 1.0);
-        (clamped * i16::MAX as f32).round() as i16
-    }
 }
     
 
@@ -2351,12 +4402,12 @@ async fn select_input_device(
     device_name: &str
 ) -> Result<InputDevice, Box<dyn Error>>
 {
-    let host = cpal::host_from_id(host_id)?;
+    let host = cpal::host_from_id(*host_id)?;
     select_device(
         host.input_devices(),
         device_name,
         "Input",
-    )?;
+    )
 }
 
 #[cfg(target_arch = "wasm32")]
@@ -2536,9 +4587,10 @@ async fn get_supported_input_device_configs(
         resampler_quality // resampler_quality
     ).await?;
 
-    let result_configs = Vec::new();
-    result_configs.push(default_config);
+    let mut result_configs = Vec::new();
+    result_configs.push(default_config.clone());
 
+    let device = select_input_device(host_id, device_name).await?;
     let configs : Vec<_> = device.supported_input_configs().map(|configs| configs.collect())
             .map_err(|err| format!("Unable to enumerate input configs for '{device_name}': {err}"))?;
 
@@ -2549,7 +4601,7 @@ async fn get_supported_input_device_configs(
             let device_config = InputDeviceConfig::new(
                 host_id.clone(),
                 device_name.clone(),
-                cfg.channels(),
+                cfg.channels() as usize,
                 *sample_rate,
                 cfg.sample_format(),
                 history_len,
@@ -2678,7 +4730,7 @@ async fn get_default_input_device_config(host_id: &cpal::HostId, device_name: &S
         &device_name
     ).await?;
 
-    device.default_input_config()?
+    Ok(device.default_input_config()?)
 }
 
 #[cfg(target_arch = "wasm32")]
@@ -2718,7 +4770,7 @@ async fn find_matching_input_device_config(
     format: SampleFormat,
 ) -> Result<SupportedStreamConfig, Box<dyn Error>> {
     find_matching_device_config(
-        &(*device as Device),
+        device,
         device_name,
         channels,
         sample_rate,
@@ -2846,7 +4898,7 @@ where
     
     let device_name = config.device_name.clone();
     let device_name_inner = config.device_name.clone();
-    device.build_input_stream(
+    Ok(device.build_input_stream(
         &supported_config.config(),
         move |data: &[T], _info: &InputCallbackInfo| {
             if data.is_empty() {
@@ -2864,7 +4916,7 @@ where
         },
         move |err| eprintln!("Input stream '{device_name}' error: {err}",),
         None,
-    )
+    )?)
 }
 
 #[cfg(target_arch = "wasm32")]
@@ -3104,7 +5156,10 @@ fn main() -> Result<(), Box<dyn Error>> {
     }
 
     println!("Computing calibration");
-    stream.calibrate(std::slice::from_mut(&mut stream_output_creator), true)?;
+    let report = stream.calibrate(std::slice::from_mut(&mut stream_output_creator), true)?;
+    for (file_name, wav) in &report.debug_wavs {
+        std::fs::write(file_name, wav)?;
+    }
     println!("calibrated");
 
     let silence = vec![0.0f32; wav_samples.len()];
@@ -3148,3 +5203,468 @@ fn main() -> Result<(), Box<dyn Error>> {
 }
 
 */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // deterministic white noise in -amplitude..amplitude
+    fn noise(seed: &mut u32, len: usize, amplitude: f32) -> Vec<f32> {
+        (0..len)
+            .map(|_| {
+                *seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                ((*seed >> 8) as f32 / (1 << 24) as f32 * 2.0 - 1.0) * amplitude
+            })
+            .collect()
+    }
+
+    #[test]
+    fn lfsr_taps_are_distinct_and_run_out() {
+        // an order 4 LFSR has two primitive polynomials
+        assert_eq!(primitive_lfsr_taps(4, 0), Some(0b1001));
+        assert_eq!(primitive_lfsr_taps(4, 1), Some(0b1100));
+        assert_eq!(primitive_lfsr_taps(4, 2), None);
+        let taps: Vec<u32> = (0..60).map(|index| primitive_lfsr_taps(10, index).unwrap()).collect();
+        assert_eq!(taps.iter().collect::<HashSet<_>>().len(), taps.len());
+        // and one full period of each is balanced, as a maximum length sequence is
+        for &tap in &taps {
+            let ones = mls_sequence(tap, 1023).iter().filter(|&&v| v > 0.0).count();
+            assert_eq!(ones, 512, "taps {tap:#b}");
+        }
+        assert_eq!(primitive_lfsr_taps(10, 60), None);
+    }
+
+    #[test]
+    fn pseudo_noise_probe_outlasts_the_polynomials() {
+        // more devices than order 10 has polynomials moves on to a longer sequence
+        let probe = pseudo_noise_probe(100, 50.0, 16_000, -45.0);
+        assert_eq!(probe.len(), 800);
+        assert!(probe.iter().any(|&v| v != 0.0));
+        assert_ne!(probe, pseudo_noise_probe(0, 50.0, 16_000, -45.0));
+    }
+
+    #[test]
+    fn calibration_config_probe_lengths() {
+        let config = CalibrationConfig::default();
+        assert_eq!(config.probe_duration_ms(), 1000.0);
+        assert_eq!(config.generate_probe(0, 16_000).len(), 16_000);
+        let chirp = CalibrationConfig { probe: CalibrationProbe::Chirp, ..config };
+        assert_eq!(chirp.generate_probe(0, 48_000).len(), 4800);
+        let long = CalibrationConfig { probe_ms: Some(2500.0), ..config };
+        assert_eq!(long.generate_probe(1, 16_000).len(), 40_000);
+    }
+
+    #[test]
+    fn louder_retries_stop_at_the_ceilings() {
+        let config = CalibrationConfig::default();
+        let mut levels = Vec::new();
+        let mut lengths = Vec::new();
+        let mut next = config;
+        while let Some(louder) = next.louder() {
+            next = louder;
+            let CalibrationProbe::PseudoNoise { level_db } = next.probe else {
+                panic!("probe changed kind: {:?}", next.probe);
+            };
+            levels.push(level_db);
+            lengths.push(next.probe_duration_ms());
+        }
+        assert_eq!(levels, [-35.0, -25.0, -20.0]);
+        assert_eq!(lengths, [2000.0, 4000.0, 4000.0]);
+
+        // the chirp only gets longer
+        let chirp = CalibrationConfig { probe: CalibrationProbe::Chirp, max_probe_ms: 300.0, ..config };
+        let longer = chirp.louder().unwrap();
+        assert_eq!(longer.probe, CalibrationProbe::Chirp);
+        assert_eq!(longer.probe_duration_ms(), 200.0);
+        assert_eq!(longer.louder().unwrap().probe_duration_ms(), 300.0);
+        assert_eq!(longer.louder().unwrap().louder(), None);
+
+        // a probe already past a ceiling isn't turned down or shortened to it
+        let loud = CalibrationConfig { probe: CalibrationProbe::PseudoNoise { level_db: -10.0 }, probe_ms: Some(5000.0), ..config };
+        assert_eq!(loud.louder(), None);
+    }
+
+    #[test]
+    fn calibration_cancel_is_shared_until_reset() {
+        let cancel = CalibrationCancel::default();
+        let handle = cancel.clone();
+        assert!(!cancel.is_cancelled());
+        handle.cancel();
+        assert!(cancel.is_cancelled());
+        // the stream clears it once the cancelled calibration has returned, so the handle works again
+        cancel.reset();
+        assert!(!handle.is_cancelled());
+        handle.cancel();
+        assert!(cancel.is_cancelled());
+    }
+
+    #[test]
+    fn saved_calibration_round_trips_through_bytes() {
+        let saved = SavedCalibration {
+            sample_rate: 16_000,
+            input_sample_rates: vec![("Built-in Microphone".into(), 48_000), ("USB mic ü".into(), 44_100)],
+            output_sample_rates: vec![("Speakers".into(), 48_000)],
+            delays: vec![
+                ("Speakers".into(), "Built-in Microphone".into(), 812),
+                ("Speakers".into(), "USB mic ü".into(), -40),
+            ],
+            created_micros: 1_760_000_000_000_000,
+        };
+        let bytes = saved.to_bytes();
+        let parsed = SavedCalibration::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed, saved);
+        assert_eq!(parsed.delay("Speakers", "USB mic ü"), Some(-40));
+        assert_eq!(parsed.delay("Speakers", "Headset"), None);
+
+        for len in 0..bytes.len() {
+            let err = SavedCalibration::from_bytes(&bytes[..len]).unwrap_err();
+            assert!(err.to_string().contains("truncated"), "{len}: {err}");
+        }
+        let mut bad_magic = bytes.clone();
+        bad_magic[..4].copy_from_slice(b"RIFF");
+        assert_eq!(SavedCalibration::from_bytes(&bad_magic).unwrap_err().to_string(), "not a saved calibration");
+        let mut bad_version = bytes.clone();
+        bad_version[4] = 7;
+        assert!(SavedCalibration::from_bytes(&bad_version).unwrap_err().to_string().contains("version 7"));
+    }
+
+    #[test]
+    fn resampler_follows_drift_correction_while_the_rate_steps() {
+        let (channels, in_rate, out_rate) = (1, 48_000, 16_000);
+        let (mut input, input_consumer) = HeapRb::<f32>::new(in_rate as usize * channels).split();
+        let (output, mut output_consumer) = HeapRb::<f32>::new(out_rate as usize * channels).split();
+        let (mut metadata, metadata_consumer) = mpsc::channel::<AudioBufferMetadata>(CHANNEL_SIZE);
+        let (finished, mut finished_consumer) = mpsc::channel::<ResamplingMetadata>(CHANNEL_SIZE);
+        let mut resampler =
+            StreamAlignerResampler::new(channels, in_rate, out_rate, 5, input_consumer, metadata_consumer, output, finished).unwrap();
+        let mut step = |resampler: &mut StreamAlignerResampler, message: AudioBufferMetadata| {
+            metadata.try_send(message).unwrap();
+            futures::executor::block_on(resampler.resample())
+        };
+
+        // 10 ms packets, right on time by the system clock, for 15 s. Every packet steps the dynamic rate
+        let frames = 480;
+        let (mut received, mut produced, mut produced_before_drift) = (0u128, 0u128, 0u128);
+        for i in 0..1500 {
+            if i == 100 {
+                assert!(step(&mut resampler, AudioBufferMetadata::AdjustDrift(300.0)).unwrap());
+                produced_before_drift = produced;
+            }
+            input.push_slice(&vec![0.25; frames * channels]);
+            received += frames as u128;
+            let micros = 1_000_000_000 + received * 1_000_000 / in_rate as u128;
+            let result = step(&mut resampler, AudioBufferMetadata::Arrive(frames as u64, micros, received, true));
+            assert!(matches!(result, Ok(true)), "packet {i}: {result:?}");
+            output_consumer.clear();
+            while let Ok(Some(ResamplingMetadata::Arrive(frames, ..))) = finished_consumer.try_next() {
+                produced += frames as u128;
+            }
+        }
+        let undrifted = (received - 100 * frames as u128) / 3;
+        let gained = (produced - produced_before_drift) as f64 - undrifted as f64;
+        // 300 ppm of 14 s at 16 kHz is 67 frames, give or take the loop's few frames of slack
+        assert!((gained - 67.2).abs() < 4.0, "gained {gained} frames");
+        assert!(resampler.dynamic_output_sample_rate.abs_diff(16_005) <= 1, "{}", resampler.dynamic_output_sample_rate);
+    }
+
+    // a direct path and exponentially decaying noise reverb, `decay_ms` to -60 dB, at 16 kHz
+    fn synthetic_room(seed: &mut u32, decay_ms: f32) -> Vec<f32> {
+        let len = (decay_ms * 16.0) as usize;
+        let mut response = noise(seed, len, 0.05);
+        for (i, tap) in response.iter_mut().enumerate() {
+            *tap *= 10.0f32.powf(-3.0 * i as f32 / len as f32);
+        }
+        response[0] = 0.5;
+        response
+    }
+
+    // device 0's probe played into the room at `offset` (if any), over background noise
+    fn probe_capture(config: &CalibrationConfig, decay_ms: f32, offset: Option<usize>, noise_level: f32) -> Vec<f32> {
+        let mut seed = 11;
+        let room = synthetic_room(&mut seed, decay_ms);
+        let mut capture = noise(&mut seed, 3 * 16_000, noise_level);
+        if let Some(offset) = offset {
+            let echo = convolve(&config.generate_probe(0, 16_000), &room);
+            for (sample, &e) in capture[offset..].iter_mut().zip(&echo) {
+                *sample += e;
+            }
+        }
+        capture
+    }
+
+    #[test]
+    fn finds_played_probes_and_reports_missing_ones() {
+        // pseudo-noise probes are told apart by device, so device 1's is missing from the same capture.
+        // It sits 45 dB down, so its background noise does too
+        let config = CalibrationConfig::default();
+        for decay_ms in [50.0, 300.0] {
+            let capture = probe_capture(&config, decay_ms, Some(4000), 0.0003);
+            let detections = detect_probe_tones(&capture, 2, &config, 16_000);
+            let (heard, silent) = (detections[0], detections[1]);
+            assert!(heard.start.is_some_and(|start| (start - 4000).abs() <= 1), "{decay_ms}: {heard:?}");
+            assert!(heard.confidence > 0.25, "{decay_ms}: {heard:?}");
+            assert_eq!(silent.start, None, "{decay_ms}: {silent:?}");
+            assert!(silent.confidence < 0.1, "{decay_ms}: {silent:?}");
+        }
+
+        // chirps all sweep about the same band, so a missing one only shows in a capture without any
+        let chirp = CalibrationConfig { probe: CalibrationProbe::Chirp, ..Default::default() };
+        for decay_ms in [50.0, 300.0] {
+            let heard = detect_probe_tone(&probe_capture(&chirp, decay_ms, Some(4000), 0.01), 0, &chirp, 16_000);
+            assert!(heard.start.is_some_and(|start| (start - 4000).abs() <= 1), "{decay_ms}: {heard:?}");
+            assert!(heard.confidence > MIN_CHIRP_CONFIDENCE, "{decay_ms}: {heard:?}");
+            let silent = detect_probe_tone(&probe_capture(&chirp, decay_ms, None, 0.01), 0, &chirp, 16_000);
+            assert_eq!(silent.start, None, "{decay_ms}: {silent:?}");
+        }
+    }
+
+    #[test]
+    fn delay_line_delays_whole_frames_of_its_channels() {
+        // two channels at offset 1 of a three channel buffer, fed in uneven chunks
+        let mut line = DelayLine::new(2, 3.0);
+        assert_eq!(line.delay(), 3.0);
+        let input: Vec<f32> = (0..60).map(|i| i as f32).collect();
+        let mut output = Vec::new();
+        for chunk in input.chunks(12) {
+            let mut buf = chunk.to_vec();
+            line.process(&mut buf, 3, 1);
+            output.extend(buf);
+        }
+        for (i, (&got, &original)) in output.iter().zip(&input).enumerate() {
+            let (frame, channel) = (i / 3, i % 3);
+            let expected = if channel == 0 {
+                original
+            } else if frame < 3 {
+                0.0
+            } else {
+                input[i - 9]
+            };
+            assert_eq!(got, expected, "frame {frame} channel {channel}");
+        }
+    }
+
+    #[test]
+    fn delay_line_shorter_delay_drops_oldest_frames() {
+        let mut line = DelayLine::new(1, 4.0);
+        let mut buf: Vec<f32> = (1..=4).map(|i| i as f32).collect();
+        line.process(&mut buf, 1, 0);
+        assert_eq!(buf, [0.0; 4]);
+        line.set_delay(2.0);
+        let mut buf = vec![5.0, 6.0];
+        line.process(&mut buf, 1, 0);
+        assert_eq!(buf, [3.0, 4.0]);
+        // and a longer one plays silence first
+        line.set_delay(3.0);
+        let mut buf = vec![7.0, 8.0];
+        line.process(&mut buf, 1, 0);
+        assert_eq!(buf, [0.0, 5.0]);
+    }
+
+    #[test]
+    fn delay_line_fractional_delay_matches_sine() {
+        // a 500 Hz sine at 16 kHz is well inside the filter's passband
+        for delay in [10.4, 12.75, 9.5] {
+            let mut line = DelayLine::new(1, delay);
+            assert!((line.delay() - delay).abs() < 1e-5, "{delay}: {}", line.delay());
+            let w = 2.0 * PI * 500.0 / 16_000.0;
+            let mut buf: Vec<f32> = (0..2000).map(|i| (w * i as f32).sin()).collect();
+            line.process(&mut buf, 1, 0);
+            for (i, &got) in buf.iter().enumerate().skip(100) {
+                let expected = (w * (i as f32 - delay)).sin();
+                assert!((got - expected).abs() < 0.01, "{delay} at {i}: {got} vs {expected}");
+            }
+        }
+        // and whole delays drop the filter again
+        let mut line = DelayLine::new(1, 10.4);
+        line.set_delay(6.0);
+        assert!(line.taps.is_empty());
+        assert_eq!(line.delay(), 6.0);
+        // and so do delays too short for the filter's latency to come out of
+        line.set_delay(3.4);
+        assert!(line.taps.is_empty());
+        assert_eq!(line.delay(), 3.0);
+    }
+
+    // feeds `secs` of noise through a `lag` sample echo in 10ms frames, returning what the tracker reported
+    fn track(tracker: &mut DelayTracker, config: &DelayTrackingConfig, lag: usize, echo_gain: f32, secs: usize) -> Vec<isize> {
+        let sample_rate = 16_000;
+        let mut seed = 3;
+        let far = noise(&mut seed, sample_rate * secs, 0.3);
+        let near = noise(&mut seed, sample_rate * secs, 0.01);
+        let mut reported = Vec::new();
+        for start in (0..sample_rate * secs).step_by(160) {
+            let input = (start..start + 160).map(|i| if i >= lag { far[i - lag] * echo_gain } else { 0.0 } + near[i]);
+            let output = far[start..start + 160].iter().copied();
+            if let Some(found) = tracker.push(input, output, config, sample_rate as u32) {
+                reported.push(found);
+            }
+        }
+        reported
+    }
+
+    #[test]
+    fn delay_tracker_reports_a_persistent_lag() {
+        let config = DelayTrackingConfig::default();
+        let mut tracker = DelayTracker::new(16_000);
+        let reported = track(&mut tracker, &config, 37, 0.5, 4);
+        assert!(!reported.is_empty(), "no lag reported");
+        assert!(reported.iter().all(|&lag| (lag - 37).abs() <= 1), "{reported:?}");
+        assert_eq!(tracker.uncoupled, 0);
+    }
+
+    #[test]
+    fn delay_tracker_counts_uncoupled_windows() {
+        // the far end plays but nothing of it reaches the input
+        let config = DelayTrackingConfig::default();
+        let mut tracker = DelayTracker::new(16_000);
+        assert!(track(&mut tracker, &config, 37, 0.0, 4).is_empty());
+        assert!(tracker.uncoupled > 0);
+        assert!(tracker.uncoupled_secs(16_000) > 2.0, "{}", tracker.uncoupled_secs(16_000));
+        tracker.reset();
+        assert_eq!(tracker.uncoupled, 0);
+    }
+
+    // a mono aligner holding `len` frames of silence with a single click at `click`. Also returns the sender
+    // its reads listen on, which has to stay open
+    fn clicked_aligner(click: usize, len: usize) -> (StreamAlignerConsumer, mpsc::Sender<ResamplingMetadata>) {
+        let (mut producer, consumer) = HeapRb::<f32>::new(len).split();
+        let mut samples = vec![0.0; len];
+        samples[click] = 1.0;
+        producer.push_slice(&samples);
+        let (thread_message_sender, _) = mpsc::channel(CHANNEL_SIZE);
+        let (finished_sender, finished_receiver) = mpsc::channel(CHANNEL_SIZE);
+        let aligner = StreamAlignerConsumer::new(1, 16_000, BufferedCircularConsumer::new(consumer), thread_message_sender, finished_receiver);
+        (aligner, finished_sender)
+    }
+
+    // where the click is now, after any shift still pending was applied like the next read would
+    fn click_frame(aligner: &mut StreamAlignerConsumer) -> i64 {
+        futures::executor::block_on(async {
+            if aligner.pending_shift > 0 {
+                let (_, chunk) = aligner.get_chunk_to_read(aligner.pending_shift as usize).await;
+                let dropped = chunk.len();
+                aligner.finish_read(dropped);
+                aligner.pending_shift -= dropped as i64;
+            }
+            let available = aligner.final_audio_buffer_consumer.available();
+            let (_, chunk) = aligner.get_chunk_to_read(available).await;
+            chunk.iter().position(|&sample| sample > 0.5).unwrap() as i64 - aligner.pending_shift
+        })
+    }
+
+    // what calibration measures between the clicks, on the stream as it's shifted now
+    fn measure_clicks(stream: &mut AecStream) -> CalibrationReport {
+        let input = click_frame(stream.input_aligners.get_mut("mic").unwrap());
+        let output = click_frame(stream.output_aligners.get_mut("speaker").unwrap());
+        let pair = CalibrationPair {
+            output_device: "speaker".into(),
+            input_device: "mic".into(),
+            delay_samples: Some(input - output),
+            delay_fraction: 0.0,
+            delay_ms: None,
+            confidence: 1.0,
+            snr_db: 40.0,
+            failure: None,
+            drift_ppm: None,
+        };
+        CalibrationReport { sample_rate: 16_000, pairs: vec![pair], ..Default::default() }
+    }
+
+    #[test]
+    fn recalibrating_saves_the_same_delays() {
+        // an echo later than the target lag shifts the input, an earlier one drops reference
+        for echo_delay in [500, 20] {
+            let mut stream = AecStream::new(AecConfig::new(16_000, 160, 1600)).unwrap();
+            let (input, _input_sender) = clicked_aligner(1_000 + echo_delay as usize, 4_000);
+            let (output, _output_sender) = clicked_aligner(1_000, 4_000);
+            stream.input_aligners.insert("mic".into(), input);
+            stream.output_aligners.insert("speaker".into(), output);
+            stream.sorted_input_aligners.push("mic".into());
+            stream.sorted_output_aligners.push("speaker".into());
+            let lag = stream.target_echo_lag();
+
+            let report = measure_clicks(&mut stream);
+            futures::executor::block_on(stream.apply_calibration(report)).unwrap();
+            let saved = stream.saved_calibration().unwrap().clone();
+            assert_eq!(saved.delays, [("speaker".to_string(), "mic".to_string(), echo_delay)]);
+            assert_eq!(measure_clicks(&mut stream).pairs[0].delay_samples, Some(lag));
+
+            // the delay tracker moves the input on top, and calibration only measures what's left
+            stream.input_aligners.get_mut("mic").unwrap().shift(7);
+            let report = measure_clicks(&mut stream);
+            assert_eq!(report.pairs[0].delay_samples, Some(lag - 7));
+            futures::executor::block_on(stream.apply_calibration(report)).unwrap();
+            assert_eq!(stream.saved_calibration().unwrap().delays, saved.delays, "echo delay {echo_delay}");
+            assert_eq!(measure_clicks(&mut stream).pairs[0].delay_samples, Some(lag));
+            // and a saved calibration applied to the shifted stream asks for what it measures
+            assert_eq!(stream.report_from_saved(&saved).pairs[0].delay_samples, Some(lag));
+        }
+    }
+
+    #[test]
+    fn echo_path_monitor_goes_by_the_echo_cancellers_metrics() {
+        let config = EchoPathMonitorConfig::default();
+        let mut monitor = EchoPathMonitor::default();
+        let mut seed = 9;
+        let frame = noise(&mut seed, 160, 0.3);
+        let frame_secs = 0.01;
+        let metrics = |erle_db, delay_ms| Some(EchoCancellerMetrics { erle_db, delay_ms });
+        // the input and output energies alone would say it converged and then lost it, its erle says otherwise
+        let cancelled: Vec<f32> = frame.iter().map(|s| s * 0.01).collect();
+        for _ in 0..100 {
+            assert_eq!(monitor.push(&frame, &cancelled, metrics(1.0, 40), &config, frame_secs), None);
+        }
+        assert!(!monitor.converged);
+        for _ in 0..100 {
+            assert_eq!(monitor.push(&frame, &frame, metrics(20.0, 40), &config, frame_secs), None);
+        }
+        assert!(monitor.converged);
+        // a delay estimate that jumps for less than hold_secs is left alone, one that stays is a change
+        for _ in 0..400 {
+            assert_eq!(monitor.push(&frame, &frame, metrics(20.0, 120), &config, frame_secs), None);
+        }
+        let changes: Vec<_> = (0..1000).filter_map(|_| monitor.push(&frame, &frame, metrics(20.0, 120), &config, frame_secs)).collect();
+        assert_eq!(changes, [EchoPathChange::EchoCancellerDelayMoved { from_ms: 40, to_ms: 120 }]);
+        // its erle staying down is lost cancellation
+        let lost = (0..1000).filter_map(|_| monitor.push(&frame, &cancelled, metrics(1.0, 120), &config, frame_secs)).collect::<Vec<_>>();
+        assert_eq!(lost, [EchoPathChange::CancellationLost { erle_db: 1.0 }]);
+    }
+
+    #[test]
+    fn recalibration_waits_out_the_interval() {
+        let config = EchoPathMonitorConfig { action: EchoPathAction::Recalibrate, ..Default::default() };
+        let interval_micros = (config.recalibrate_interval_secs * 1_000_000.0) as u128;
+        let now = 1_000 * interval_micros;
+        assert!(config.recalibration_due(None, now));
+        // counted from when the last calibration started, whether or not it worked
+        assert!(!config.recalibration_due(Some(now - 1), now));
+        assert!(!config.recalibration_due(Some(now - interval_micros + 1), now));
+        assert!(config.recalibration_due(Some(now - interval_micros), now));
+        let notify = EchoPathMonitorConfig { action: EchoPathAction::Notify, ..config };
+        assert!(!notify.recalibration_due(None, now));
+    }
+
+    #[test]
+    fn echo_path_monitor_holds_out_double_talk() {
+        let config = EchoPathMonitorConfig::default();
+        let mut monitor = EchoPathMonitor::default();
+        let mut seed = 5;
+        let frame = noise(&mut seed, 160, 0.3);
+        let cancelled: Vec<f32> = frame.iter().map(|s| s * 0.01).collect();
+        let frame_secs = 0.01;
+        for _ in 0..300 {
+            assert_eq!(monitor.push(&frame, &cancelled, None, &config, frame_secs), None);
+        }
+        assert!(monitor.converged);
+        // five seconds of the near end talking over the echo doesn't count as lost
+        for _ in 0..500 {
+            assert_eq!(monitor.push(&frame, &frame, None, &config, frame_secs), None);
+        }
+        for _ in 0..300 {
+            assert_eq!(monitor.push(&frame, &cancelled, None, &config, frame_secs), None);
+        }
+        // cancellation staying gone does, once, after hold_secs
+        let lost = (0..1500).filter(|_| monitor.push(&frame, &frame, None, &config, frame_secs).is_some()).count();
+        assert_eq!(lost, 1);
+    }
+}
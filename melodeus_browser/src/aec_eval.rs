@@ -0,0 +1,501 @@
+// Offline echo canceller evaluation.
+//
+// A scenario plays far-end audio through a room impulse response into a simulated microphone,
+// optionally with near-end speech and noise on top, then runs the mic/reference pair through
+// the same `EchoCancellerBackend`s `AecStream` uses. Because every component of the mic signal
+// is known, the report can measure ERLE, near-end distortion and convergence time directly
+// instead of judging quality by ear in a browser.
+
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+
+use hound::{SampleFormat as HoundSampleFormat, WavReader, WavSpec, WavWriter};
+
+use crate::aec::{convolve, create_echo_canceller};
+pub use crate::aec::{AecConfig, EchoCancellerKind};
+use crate::speex::Resampler;
+
+const RESAMPLER_QUALITY: i32 = 10;
+// frames quieter than this (dBFS) don't count as echo or near-end activity
+const ACTIVITY_FLOOR_DB: f32 = -50.0;
+// far-end single talk: near-end energy at least this far (dB) below the echo
+const SINGLE_TALK_MARGIN_DB: f32 = 20.0;
+// window for the running ERLE used to find the convergence point
+const CONVERGENCE_WINDOW_MS: u32 = 200;
+// converged once the running ERLE gets within this many dB of the steady state
+const CONVERGENCE_TOLERANCE_DB: f32 = 3.0;
+
+/// Synthetic room: a direct path after `delay_ms`, then an exponentially decaying diffuse tail.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SyntheticRoom {
+    pub delay_ms: f32,
+    // time for the tail to decay by 60 dB
+    pub rt60_ms: f32,
+    // direct path gain (speaker to mic coupling)
+    pub gain: f32,
+    // tail level relative to the direct path
+    pub reverb_level: f32,
+    pub seed: u32,
+}
+
+impl SyntheticRoom {
+    pub fn new(delay_ms: f32, rt60_ms: f32) -> Self {
+        Self {
+            delay_ms,
+            rt60_ms,
+            gain: 0.5,
+            reverb_level: 0.3,
+            seed: 1,
+        }
+    }
+
+    pub fn with_gain(mut self, gain: f32) -> Self {
+        self.gain = gain;
+        self
+    }
+
+    pub fn with_reverb_level(mut self, reverb_level: f32) -> Self {
+        self.reverb_level = reverb_level;
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u32) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn impulse_response(&self, sample_rate: u32) -> Vec<f32> {
+        let delay = ms_to_samples(self.delay_ms, sample_rate);
+        let tail = ms_to_samples(self.rt60_ms, sample_rate).max(1);
+        let mut ir = vec![0.0f32; delay + tail];
+        ir[delay] = self.gain;
+        // 60 dB of amplitude decay over rt60
+        let decay = -6.908 / tail as f32;
+        let mut noise = Lcg::new(self.seed);
+        for i in 1..tail {
+            ir[delay + i] += self.gain * self.reverb_level * (decay * i as f32).exp() * noise.next_f32();
+        }
+        ir
+    }
+}
+
+/// One evaluation case. All signals are mono at `sample_rate`, which must match the
+/// `AecConfig` target rate it is run with.
+#[derive(Debug, Clone)]
+pub struct Scenario {
+    pub name: String,
+    sample_rate: u32,
+    far_end: Vec<f32>,
+    near_end: Vec<f32>,
+    room_ir: Vec<f32>,
+    noise_db: Option<f32>,
+    noise_seed: u32,
+}
+
+impl Scenario {
+    /// Far-end only, through a direct path with no delay or reverb.
+    pub fn new(name: impl Into<String>, sample_rate: u32, far_end: Vec<f32>) -> Self {
+        Self {
+            name: name.into(),
+            sample_rate,
+            far_end,
+            near_end: Vec::new(),
+            room_ir: vec![1.0],
+            noise_db: None,
+            noise_seed: 0x5eed,
+        }
+    }
+
+    /// Load the far end (and optionally the near end) from WAV files, resampled to `sample_rate`.
+    pub fn from_wavs(
+        name: impl Into<String>,
+        sample_rate: u32,
+        far_end: impl AsRef<Path>,
+        near_end: Option<(&Path, u32)>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let scenario = Self::new(name, sample_rate, read_wav_mono(far_end, sample_rate)?);
+        Ok(match near_end {
+            Some((path, start_ms)) => scenario.with_near_end(read_wav_mono(path, sample_rate)?, start_ms),
+            None => scenario,
+        })
+    }
+
+    /// Near-end talker starting `start_ms` into the scenario. Anything past the end of the far end is dropped.
+    pub fn with_near_end(mut self, near_end: Vec<f32>, start_ms: u32) -> Self {
+        let start = ms_to_samples(start_ms as f32, self.sample_rate);
+        self.near_end = vec![0.0; self.far_end.len()];
+        for (dst, src) in self.near_end.iter_mut().skip(start).zip(near_end) {
+            *dst = src;
+        }
+        self
+    }
+
+    /// Echo path from the speaker to the mic, at the scenario sample rate.
+    pub fn with_room_ir(mut self, room_ir: Vec<f32>) -> Self {
+        self.room_ir = room_ir;
+        self
+    }
+
+    pub fn with_room(self, room: SyntheticRoom) -> Self {
+        let ir = room.impulse_response(self.sample_rate);
+        self.with_room_ir(ir)
+    }
+
+    /// White noise at `level_db` dBFS (RMS) added to the mic.
+    pub fn with_noise(mut self, level_db: f32) -> Self {
+        self.noise_db = Some(level_db);
+        self
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn duration_ms(&self) -> f32 {
+        self.far_end.len() as f32 * 1000.0 / self.sample_rate as f32
+    }
+
+    /// Build the mic signal and keep its components around for scoring.
+    pub fn render(&self) -> RenderedScenario {
+        let len = self.far_end.len();
+        let mut echo = convolve(&self.far_end, &self.room_ir);
+        echo.truncate(len);
+        // anything that isn't echo, which a perfect canceller passes through untouched
+        let mut near = vec![0.0f32; len];
+        for (dst, &src) in near.iter_mut().zip(&self.near_end) {
+            *dst = src;
+        }
+        if let Some(level_db) = self.noise_db {
+            // uniform noise in [-1, 1) has an RMS of 1/sqrt(3)
+            let amplitude = db_to_amplitude(level_db) * 3.0f32.sqrt();
+            let mut noise = Lcg::new(self.noise_seed);
+            for sample in near.iter_mut() {
+                *sample += amplitude * noise.next_f32();
+            }
+        }
+        let mic = echo.iter().zip(&near).map(|(e, n)| e + n).collect();
+        RenderedScenario {
+            far_end: self.far_end.clone(),
+            echo,
+            near,
+            mic,
+        }
+    }
+}
+
+pub struct RenderedScenario {
+    pub far_end: Vec<f32>,
+    // far end after the room
+    pub echo: Vec<f32>,
+    // near-end speech plus noise
+    pub near: Vec<f32>,
+    // echo + near
+    pub mic: Vec<f32>,
+}
+
+/// Scores for one scenario run through one backend.
+pub struct EvalReport {
+    pub scenario: String,
+    pub backend: EchoCancellerKind,
+    // echo return loss enhancement over converged far-end single talk, None without any
+    pub erle_db: Option<f32>,
+    // energy of (output - near end) relative to the near end, over frames with near-end activity
+    // after convergence (so residual echo during double talk counts too); lower is better
+    pub near_end_distortion_db: Option<f32>,
+    // time until the running ERLE first gets within 3 dB of its steady state value, None if the
+    // steady state ERLE itself is under 3 dB
+    pub convergence_ms: Option<f32>,
+    pub output: Vec<f32>,
+}
+
+impl fmt::Display for EvalReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let db = |v: Option<f32>| v.map_or_else(|| "-".to_string(), |v| format!("{v:.1} dB"));
+        let ms = self.convergence_ms.map_or_else(|| "-".to_string(), |v| format!("{v:.0} ms"));
+        write!(
+            f,
+            "{} [{:?}]: ERLE {}, near-end distortion {}, convergence {}",
+            self.scenario,
+            self.backend,
+            db(self.erle_db),
+            db(self.near_end_distortion_db),
+            ms,
+        )
+    }
+}
+
+/// Run `scenario` through a fresh mono echo canceller of kind `kind`.
+pub fn run_scenario(scenario: &Scenario, kind: EchoCancellerKind, aec_config: &AecConfig) -> Result<EvalReport, Box<dyn Error>> {
+    if scenario.sample_rate != aec_config.target_sample_rate() {
+        return Err(format!(
+            "scenario '{}' is at {} Hz but the echo canceller runs at {} Hz",
+            scenario.name,
+            scenario.sample_rate,
+            aec_config.target_sample_rate()
+        )
+        .into());
+    }
+    let frame_size = aec_config.frame_size();
+    let rendered = scenario.render();
+    let mut aec = create_echo_canceller(kind, aec_config, 1, 1)?;
+    let frames = rendered.mic.len() / frame_size;
+    let mut output = vec![0.0f32; frames * frame_size];
+    for (i, out) in output.chunks_exact_mut(frame_size).enumerate() {
+        let range = i * frame_size..(i + 1) * frame_size;
+        aec.process(&rendered.mic[range.clone()], &rendered.far_end[range], out)?;
+    }
+    let scores = score(&rendered, &output, frame_size, scenario.sample_rate);
+    Ok(EvalReport {
+        scenario: scenario.name.clone(),
+        backend: kind,
+        erle_db: scores.erle_db,
+        near_end_distortion_db: scores.near_end_distortion_db,
+        convergence_ms: scores.convergence_ms,
+        output,
+    })
+}
+
+/// Run every scenario through every backend in `kinds`.
+pub fn run_suite(scenarios: &[Scenario], kinds: &[EchoCancellerKind], aec_config: &AecConfig) -> Result<Vec<EvalReport>, Box<dyn Error>> {
+    let mut reports = Vec::with_capacity(scenarios.len() * kinds.len());
+    for scenario in scenarios {
+        for &kind in kinds {
+            reports.push(run_scenario(scenario, kind, aec_config)?);
+        }
+    }
+    Ok(reports)
+}
+
+/// Default suite at `sample_rate`: `far_end` alone for a few seconds, then double talk with `near_end`,
+/// through a small, a medium and a large synthetic room.
+pub fn synthetic_rooms(sample_rate: u32, far_end: &[f32], near_end: &[f32]) -> Vec<Scenario> {
+    let near_start_ms = 4000;
+    [
+        ("small room", SyntheticRoom::new(5.0, 150.0)),
+        ("medium room", SyntheticRoom::new(15.0, 300.0).with_gain(0.4)),
+        ("large room", SyntheticRoom::new(30.0, 600.0).with_gain(0.3).with_reverb_level(0.5)),
+    ]
+    .into_iter()
+    .map(|(name, room)| {
+        Scenario::new(name, sample_rate, far_end.to_vec())
+            .with_room(room)
+            .with_near_end(near_end.to_vec(), near_start_ms)
+            .with_noise(-60.0)
+    })
+    .collect()
+}
+
+/// Speech-like test signal for running without recordings: voiced syllables (a buzz at a random pitch
+/// plus a little noise, lowpassed) of 100-300 ms separated by pauses.
+pub fn synthetic_speech(duration_ms: u32, sample_rate: u32, seed: u32) -> Vec<f32> {
+    let len = ms_to_samples(duration_ms as f32, sample_rate);
+    let sr = sample_rate as f32;
+    let mut rng = Lcg::new(seed);
+    let mut out = vec![0.0f32; len];
+    let mut pos = 0;
+    while pos < len {
+        let syllable = ms_to_samples(100.0 + 200.0 * rng.next_unit(), sample_rate);
+        let pause = ms_to_samples(50.0 + 350.0 * rng.next_unit(), sample_rate);
+        let pitch = 90.0 + 150.0 * rng.next_unit();
+        let level = 0.05 + 0.2 * rng.next_unit();
+        let mut lowpass = 0.0f32;
+        let mut phase = 0.0f32;
+        for i in 0..syllable.min(len - pos) {
+            phase = (phase + pitch / sr).fract();
+            let buzz = 2.0 * phase - 1.0;
+            lowpass += 0.3 * (buzz + 0.3 * rng.next_f32() - lowpass);
+            out[pos + i] = level * hann(syllable, i) * lowpass;
+        }
+        pos += syllable + pause;
+    }
+    out
+}
+
+/// Read a WAV file as mono f32 at `sample_rate`, averaging channels and resampling if needed.
+pub fn read_wav_mono(path: impl AsRef<Path>, sample_rate: u32) -> Result<Vec<f32>, Box<dyn Error>> {
+    let path = path.as_ref();
+    let mut reader = WavReader::open(path).map_err(|err| format!("failed to open {}: {err}", path.display()))?;
+    let spec = reader.spec();
+    let interleaved: Vec<f32> = match spec.sample_format {
+        HoundSampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>()?,
+        HoundSampleFormat::Int => {
+            let scale = 1.0 / (1u64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|s| s.map(|s| s as f32 * scale))
+                .collect::<Result<_, _>>()?
+        }
+    };
+    let channels = spec.channels.max(1) as usize;
+    let mono: Vec<f32> = interleaved
+        .chunks_exact(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect();
+    if spec.sample_rate == sample_rate {
+        return Ok(mono);
+    }
+    resample(&mono, spec.sample_rate, sample_rate)
+}
+
+pub fn write_wav_mono(path: impl AsRef<Path>, samples: &[f32], sample_rate: u32) -> Result<(), Box<dyn Error>> {
+    let spec = WavSpec {
+        channels: 1,
+        sample_rate,
+        bits_per_sample: 32,
+        sample_format: HoundSampleFormat::Float,
+    };
+    let mut writer = WavWriter::create(path, spec)?;
+    for &sample in samples {
+        writer.write_sample(sample)?;
+    }
+    writer.finalize()?;
+    Ok(())
+}
+
+fn resample(input: &[f32], in_rate: u32, out_rate: u32) -> Result<Vec<f32>, Box<dyn Error>> {
+    let mut resampler = Resampler::new(1, in_rate, out_rate, RESAMPLER_QUALITY)?;
+    resampler.skip_zeros()?;
+    let out_len = (input.len() as u64 * out_rate as u64).div_ceil(in_rate as u64) as usize;
+    // pad with the resampler latency so the tail comes out too
+    let mut padded = input.to_vec();
+    padded.resize(input.len() + resampler.input_latency(), 0.0);
+    let mut output = vec![0.0f32; out_len];
+    let (mut consumed, mut produced) = (0, 0);
+    while consumed < padded.len() && produced < out_len {
+        let (c, p) = resampler.process_interleaved_f32(&padded[consumed..], &mut output[produced..])?;
+        if c == 0 && p == 0 {
+            break;
+        }
+        consumed += c;
+        produced += p;
+    }
+    output.truncate(produced);
+    Ok(output)
+}
+
+struct Scores {
+    erle_db: Option<f32>,
+    near_end_distortion_db: Option<f32>,
+    convergence_ms: Option<f32>,
+}
+
+fn score(rendered: &RenderedScenario, output: &[f32], frame_size: usize, sample_rate: u32) -> Scores {
+    let energy = |x: &[f32]| x.iter().map(|v| v * v).sum::<f32>();
+    let floor = db_to_amplitude(ACTIVITY_FLOOR_DB).powi(2) * frame_size as f32;
+    let margin = db_to_amplitude(-SINGLE_TALK_MARGIN_DB).powi(2);
+
+    // per frame (mic energy, output energy) over far-end single talk
+    let mut single_talk = Vec::new();
+    // per frame (error energy, near energy) where the near end is active
+    let mut near_talk = Vec::new();
+    for (i, out) in output.chunks_exact(frame_size).enumerate() {
+        let range = i * frame_size..(i + 1) * frame_size;
+        let echo = energy(&rendered.echo[range.clone()]);
+        let near = energy(&rendered.near[range.clone()]);
+        if echo > floor && near < echo * margin {
+            single_talk.push((i, energy(&rendered.mic[range.clone()]), energy(out)));
+        }
+        if near > floor {
+            let error: f32 = out
+                .iter()
+                .zip(&rendered.near[range])
+                .map(|(o, n)| (o - n) * (o - n))
+                .sum();
+            near_talk.push((i, error, near));
+        }
+    }
+
+    let ratio_db = |num: f32, den: f32| 10.0 * (num / den.max(f32::MIN_POSITIVE)).log10();
+    // steady state over the second half of the single-talk frames
+    let steady = &single_talk[single_talk.len() / 2..];
+    let erle_db = (!steady.is_empty()).then(|| {
+        let (mic, out) = steady.iter().fold((0.0, 0.0), |(m, o), &(_, mic, out)| (m + mic, o + out));
+        ratio_db(mic, out)
+    });
+
+    let frame_ms = frame_size as f32 * 1000.0 / sample_rate as f32;
+    let window = ((CONVERGENCE_WINDOW_MS as f32 / frame_ms).round() as usize).max(1);
+    // a canceller that never gets past the tolerance hasn't converged to anything
+    let convergence_frame = erle_db.filter(|&db| db > CONVERGENCE_TOLERANCE_DB).and_then(|steady_db| {
+        single_talk.windows(window).find_map(|w| {
+            let (mic, out) = w.iter().fold((0.0, 0.0), |(m, o), &(_, mic, out)| (m + mic, o + out));
+            (ratio_db(mic, out) >= steady_db - CONVERGENCE_TOLERANCE_DB).then_some(w[w.len() - 1].0 + 1)
+        })
+    });
+
+    let settled = convergence_frame.unwrap_or(0);
+    let (error, near) = near_talk
+        .iter()
+        .filter(|&&(i, _, _)| i >= settled)
+        .fold((0.0, 0.0), |(e, n), &(_, error, near)| (e + error, n + near));
+    let near_end_distortion_db = (near > 0.0).then(|| ratio_db(error, near));
+
+    Scores {
+        erle_db,
+        near_end_distortion_db,
+        convergence_ms: convergence_frame.map(|frame| frame as f32 * frame_ms),
+    }
+}
+
+fn ms_to_samples(ms: f32, sample_rate: u32) -> usize {
+    (ms.max(0.0) * sample_rate as f32 / 1000.0).round() as usize
+}
+
+fn db_to_amplitude(db: f32) -> f32 {
+    10.0f32.powf(db / 20.0)
+}
+
+fn hann(n: usize, i: usize) -> f32 {
+    0.5 * (1.0 - (2.0 * std::f32::consts::PI * i as f32 / n as f32).cos())
+}
+
+// Small deterministic generator so scenarios are reproducible without pulling in `rand`.
+struct Lcg(u32);
+
+impl Lcg {
+    fn new(seed: u32) -> Self {
+        Self(seed)
+    }
+
+    // uniform in [0, 1)
+    fn next_unit(&mut self) -> f32 {
+        self.0 = self.0.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+        (self.0 >> 8) as f32 / (1u32 << 24) as f32
+    }
+
+    // uniform in [-1, 1)
+    fn next_f32(&mut self) -> f32 {
+        2.0 * self.next_unit() - 1.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 16_000;
+
+    fn aec_config() -> AecConfig {
+        // 10 ms frames, 100 ms filter, as in examples/aec_eval.rs
+        AecConfig::new(SAMPLE_RATE, 160, 1600)
+    }
+
+    #[test]
+    fn speex_cancels_small_room_echo() {
+        let far_end = synthetic_speech(8_000, SAMPLE_RATE, 1);
+        let scenario = Scenario::new("small room", SAMPLE_RATE, far_end)
+            .with_room(SyntheticRoom::new(5.0, 150.0))
+            .with_noise(-60.0);
+        let report = run_scenario(&scenario, EchoCancellerKind::Speex, &aec_config()).unwrap();
+        let erle_db = report.erle_db.expect("scenario has far-end single talk");
+        assert!(erle_db > 10.0, "{report}");
+        assert!(report.convergence_ms.is_some(), "{report}");
+    }
+
+    #[test]
+    fn rejects_mismatched_sample_rate() {
+        let scenario = Scenario::new("wrong rate", 48_000, synthetic_speech(100, 48_000, 1));
+        assert!(run_scenario(&scenario, EchoCancellerKind::Speex, &aec_config()).is_err());
+    }
+}
@@ -2,12 +2,19 @@
 
 mod cpal_webaudio_inputs;
 mod aec;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod aec_eval;
 #[path = "speex/lib.rs"]
 pub mod speex;
 
-use aec::{AecConfig, AecStream, InputDeviceConfig, OutputDeviceConfig, OutputStreamAlignerProducer};
-use js_sys::{Array, Float32Array, Object, Reflect};
+use aec::{
+    AecConfig, AecStream, CalibrationFailure, CalibrationProbe, CalibrationProgress, CalibrationReport, EchoPathAction,
+    EchoPathChange, EchoPathMonitorConfig, InputDeviceConfig, OutputDeviceConfig, OutputStreamAlignerProducer,
+    SavedCalibration,
+};
+use js_sys::{Array, Float32Array, Object, Reflect, Uint8Array};
 use wasm_bindgen::prelude::*;
+use web_sys::{AbortSignal, Blob, BlobPropertyBag};
 
 const HISTORY_LEN: usize = 120;
 const CALIBRATION_PACKETS: u32 = 15;
@@ -25,10 +32,25 @@ pub fn main_js() {
     console_error_panic_hook::set_once();
 }
 
-fn aec_config() -> AecConfig {
+fn aec_config(chirp_probe: bool, echo_path_action: EchoPathAction) -> AecConfig {
     let frame_size = TARGET_SAMPLE_RATE as usize * FRAME_SIZE_MS / 1000;
     let filter_len = TARGET_SAMPLE_RATE as usize * FILTER_LENGTH_MS / 1000;
-    AecConfig::new(TARGET_SAMPLE_RATE, frame_size, filter_len)
+    // preprocessing (noise suppression, AGC, dereverb) is left off, it's only reachable natively through
+    // AecConfig::with_preprocess
+    let echo_path_monitor = EchoPathMonitorConfig { action: echo_path_action, ..Default::default() };
+    let config = AecConfig::new(TARGET_SAMPLE_RATE, frame_size, filter_len).with_echo_path_monitor(Some(echo_path_monitor));
+    if chirp_probe { config.with_calibration_probe(CalibrationProbe::Chirp) } else { config }
+}
+
+// "notify" | "resetAec" | "recalibrate", what enable_aec's echo_path_action takes
+fn echo_path_action_from_js(action: Option<&str>) -> Result<EchoPathAction, JsValue> {
+    match action {
+        Some("notify") => Ok(EchoPathAction::Notify),
+        Some("resetAec") => Ok(EchoPathAction::ResetAec),
+        // a page can't tell when the user switches speakers, so calibrate again when cancellation falls apart
+        Some("recalibrate") | None => Ok(EchoPathAction::Recalibrate),
+        Some(other) => Err(JsValue::from_str(&format!("unknown echo path action '{other}'"))),
+    }
 }
 
 fn js_err(err: impl std::fmt::Display) -> JsValue {
@@ -63,6 +85,116 @@ fn outputs_to_js(configs: &[OutputDeviceConfig]) -> Result<Array, JsValue> {
     Ok(array)
 }
 
+fn calibration_report_to_js(report: &CalibrationReport) -> Result<Object, JsValue> {
+    let pairs = Array::new();
+    for pair in &report.pairs {
+        let obj = Object::new();
+        let failure = match pair.failure {
+            Some(CalibrationFailure::OutputNotPlayed) => JsValue::from_str("outputNotPlayed"),
+            Some(CalibrationFailure::NotHeard) => JsValue::from_str("notHeard"),
+            None => JsValue::NULL,
+        };
+        Reflect::set(&obj, &"outputDevice".into(), &pair.output_device.clone().into())?;
+        Reflect::set(&obj, &"inputDevice".into(), &pair.input_device.clone().into())?;
+        Reflect::set(&obj, &"delaySamples".into(), &pair.delay_samples.map_or(JsValue::NULL, |d| (d as f64).into()))?;
+        Reflect::set(&obj, &"delayMs".into(), &pair.delay_ms.map_or(JsValue::NULL, |d| (d as f64).into()))?;
+        Reflect::set(&obj, &"confidence".into(), &(pair.confidence as f64).into())?;
+        Reflect::set(&obj, &"snrDb".into(), &(pair.snr_db as f64).into())?;
+        Reflect::set(&obj, &"driftPpm".into(), &pair.drift_ppm.map_or(JsValue::NULL, |d| (d as f64).into()))?;
+        Reflect::set(&obj, &"failure".into(), &failure)?;
+        pairs.push(&obj);
+    }
+    let shifts = Array::new();
+    for (device_name, samples) in &report.input_shifts {
+        let obj = Object::new();
+        Reflect::set(&obj, &"inputDevice".into(), &device_name.clone().into())?;
+        Reflect::set(&obj, &"samples".into(), &(*samples as f64).into())?;
+        shifts.push(&obj);
+    }
+    let delays = Array::new();
+    for (device_name, samples) in &report.output_delays {
+        let obj = Object::new();
+        Reflect::set(&obj, &"outputDevice".into(), &device_name.clone().into())?;
+        Reflect::set(&obj, &"samples".into(), &(*samples as f64).into())?;
+        delays.push(&obj);
+    }
+    let drift_corrections = Array::new();
+    for (device_name, ppm) in &report.drift_corrections {
+        let obj = Object::new();
+        Reflect::set(&obj, &"inputDevice".into(), &device_name.clone().into())?;
+        Reflect::set(&obj, &"ppm".into(), &(*ppm as f64).into())?;
+        drift_corrections.push(&obj);
+    }
+    let result = Object::new();
+    Reflect::set(&result, &"sampleRate".into(), &(report.sample_rate as f64).into())?;
+    Reflect::set(&result, &"pairs".into(), &pairs)?;
+    Reflect::set(&result, &"inputShifts".into(), &shifts)?;
+    Reflect::set(&result, &"referenceShift".into(), &(report.reference_shift as f64).into())?;
+    Reflect::set(&result, &"outputDelays".into(), &delays)?;
+    Reflect::set(&result, &"driftCorrections".into(), &drift_corrections)?;
+    Reflect::set(&result, &"complete".into(), &report.is_complete().into())?;
+    Reflect::set(&result, &"fromSaved".into(), &report.from_saved.into())?;
+    Reflect::set(&result, &"attempts".into(), &(report.attempts as f64).into())?;
+    Reflect::set(&result, &"isolated".into(), &report.acoustically_isolated.into())?;
+    let debug_wavs = Array::new();
+    for (file_name, wav) in &report.debug_wavs {
+        let obj = Object::new();
+        let blob = wav_blob(wav)?;
+        Reflect::set(&obj, &"name".into(), &file_name.clone().into())?;
+        Reflect::set(&obj, &"blob".into(), &blob)?;
+        debug_wavs.push(&obj);
+    }
+    Reflect::set(&result, &"debugWavs".into(), &debug_wavs)?;
+    Ok(result)
+}
+
+fn calibration_progress_to_js(progress: CalibrationProgress) -> JsValue {
+    let (stage, fraction) = match progress {
+        CalibrationProgress::Probing => ("probing", 0.0),
+        CalibrationProgress::Capturing(fraction) => ("capturing", fraction),
+        CalibrationProgress::Analysing => ("analysing", 1.0),
+        CalibrationProgress::Retrying(_) => ("retrying", 0.0),
+    };
+    let obj = Object::new();
+    // setting plain properties on a fresh object can't fail
+    let _ = Reflect::set(&obj, &"stage".into(), &stage.into());
+    let _ = Reflect::set(&obj, &"fraction".into(), &(fraction as f64).into());
+    obj.into()
+}
+
+fn echo_path_change_to_js(change: &EchoPathChange) -> Result<Object, JsValue> {
+    let obj = Object::new();
+    match change {
+        EchoPathChange::DelayMoved { input_device, shift_samples } => {
+            Reflect::set(&obj, &"reason".into(), &"delayMoved".into())?;
+            Reflect::set(&obj, &"inputDevice".into(), &input_device.clone().into())?;
+            Reflect::set(&obj, &"shiftSamples".into(), &(*shift_samples as f64).into())?;
+        }
+        EchoPathChange::CancellationLost { erle_db } => {
+            Reflect::set(&obj, &"reason".into(), &"cancellationLost".into())?;
+            Reflect::set(&obj, &"erleDb".into(), &(*erle_db as f64).into())?;
+        }
+        EchoPathChange::EchoCancellerDelayMoved { from_ms, to_ms } => {
+            Reflect::set(&obj, &"reason".into(), &"echoCancellerDelayMoved".into())?;
+            Reflect::set(&obj, &"fromMs".into(), &(*from_ms as f64).into())?;
+            Reflect::set(&obj, &"toMs".into(), &(*to_ms as f64).into())?;
+        }
+        EchoPathChange::Isolated => {
+            Reflect::set(&obj, &"reason".into(), &"isolated".into())?;
+        }
+        EchoPathChange::Coupled => {
+            Reflect::set(&obj, &"reason".into(), &"coupled".into())?;
+        }
+    }
+    Ok(obj)
+}
+
+fn wav_blob(wav: &[u8]) -> Result<Blob, JsValue> {
+    let options = BlobPropertyBag::new();
+    options.set_type("audio/wav");
+    Blob::new_with_u8_array_sequence_and_options(&Array::of1(&Uint8Array::from(wav)), &options)
+}
+
 fn pick_input_config<'a>(
     configs: &'a [InputDeviceConfig],
     target_device: Option<&str>,
@@ -85,12 +217,10 @@ fn pick_output_config<'a>(
     }
 }
 
-fn normalize_i16(slice: &[i16]) -> Vec<f32> {
-    if slice.is_empty() {
-        return Vec::new();
-    }
-    let scale = i16::MAX as f32;
-    slice.iter().map(|s| *s as f32 / scale).collect()
+// key to store the calibration under, one per input/output device pair (hostId/deviceName as in list_devices)
+#[wasm_bindgen]
+pub fn calibration_key(input_host: &str, input_device: &str, output_host: &str, output_device: &str) -> String {
+    format!("melodeus-calibration:{input_host}/{input_device}:{output_host}/{output_device}")
 }
 
 #[wasm_bindgen]
@@ -161,13 +291,33 @@ pub struct AecHandle {
     output_producers: Vec<OutputStreamAlignerProducer>,
     inputs: Vec<InputDeviceConfig>,
     outputs: Vec<OutputDeviceConfig>,
+    calibration: CalibrationReport,
 }
 
 #[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
 pub async fn enable_aec(
     input_device: Option<String>,
     output_device: Option<String>,
+    // called with the calibration_key of the devices picked, returns what AecHandle::calibration_state gave for
+    // them in an earlier session (or null), which is reused instead of a full calibration
+    load_calibration: Option<js_sys::Function>,
+    // keep WAVs of what each device captured during calibration, see debugWavs in calibration_report
+    debug_wav: Option<bool>,
+    // called with { stage: "probing" | "capturing" | "analysing", fraction } as calibration goes along
+    on_progress: Option<js_sys::Function>,
+    // aborting it stops calibration and enable_aec rejects with "calibration cancelled"
+    signal: Option<AbortSignal>,
+    // calibrate with the audible chirp instead of the quiet pseudo-noise probe, for rooms too noisy for it
+    chirp_probe: Option<bool>,
+    // what to do when the echo path changes besides reporting it in echoPathChanges:
+    // "notify", "resetAec" (start the echo canceller over) or "recalibrate". Defaults to "recalibrate" here,
+    // unlike AecConfig on its own which only notifies: update() then runs a calibration when cancellation
+    // is lost (at most every couple of minutes) and sets recalibrated, and calibration_state has the new one
+    echo_path_action: Option<String>,
 ) -> Result<AecHandle, JsValue> {
+    let debug_wav = debug_wav.unwrap_or(false);
+    let echo_path_action = echo_path_action_from_js(echo_path_action.as_deref())?;
     let inputs = aec::get_supported_input_configs(
         HISTORY_LEN,
         CALIBRATION_PACKETS,
@@ -198,36 +348,185 @@ pub async fn enable_aec(
         .ok_or_else(|| js_err("no output device available"))?
         .clone();
 
-    let mut stream = AecStream::new(aec_config()).map_err(js_err)?;
+    let mut stream = AecStream::new(aec_config(chirp_probe.unwrap_or(false), echo_path_action)).map_err(js_err)?;
 
     let mut output_producers = Vec::new();
     let producer = stream.add_output_device(&output_cfg).await.map_err(js_err)?;
     output_producers.push(producer);
 
     stream.add_input_device(&input_cfg).await.map_err(js_err)?;
-    stream
-        .calibrate(output_producers.as_mut_slice(), false).await
-        .map_err(js_err)?;
+    let saved_calibration = match &load_calibration {
+        Some(load) => {
+            let key = calibration_key(
+                input_cfg.host_id.name(),
+                &input_cfg.device_name,
+                output_cfg.host_id.name(),
+                &output_cfg.device_name,
+            );
+            let saved = load.call1(&JsValue::NULL, &key.into())?;
+            (!saved.is_null() && !saved.is_undefined()).then(|| Uint8Array::new(&saved).to_vec())
+        }
+        None => None,
+    };
+    let saved_calibration = saved_calibration.and_then(|bytes| match SavedCalibration::from_bytes(&bytes) {
+        Ok(saved) => Some(saved),
+        Err(err) => {
+            web_sys::console::warn_1(&format!("Ignoring saved calibration: {err}").into());
+            None
+        }
+    });
+    if let Some(on_progress) = on_progress {
+        stream.on_calibration_progress(move |progress| {
+            if let Err(err) = on_progress.call1(&JsValue::NULL, &calibration_progress_to_js(progress)) {
+                web_sys::console::warn_1(&err);
+            }
+        });
+    }
+    let cancel = stream.calibration_cancel();
+    let on_abort = Closure::<dyn FnMut()>::new({
+        let cancel = cancel.clone();
+        move || cancel.cancel()
+    });
+    if let Some(signal) = &signal {
+        if signal.aborted() {
+            cancel.cancel();
+        }
+        signal.add_event_listener_with_callback("abort", on_abort.as_ref().unchecked_ref())?;
+    }
+    let calibration = match saved_calibration {
+        Some(saved) => stream.calibrate_from_saved(output_producers.as_mut_slice(), &saved, debug_wav).await,
+        None => stream.calibrate(output_producers.as_mut_slice(), debug_wav).await,
+    };
+    if let Some(signal) = &signal {
+        signal.remove_event_listener_with_callback("abort", on_abort.as_ref().unchecked_ref())?;
+    }
+    let calibration = calibration.map_err(js_err)?;
 
     Ok(AecHandle {
         stream,
         output_producers,
         inputs: vec![input_cfg],
         outputs: vec![output_cfg],
+        calibration,
     })
 }
 
+impl AecHandle {
+    // `name` if it's one of our outputs, the first output if it's None
+    fn output_device_name(&self, name: Option<String>) -> Result<String, JsValue> {
+        match name {
+            Some(name) if self.outputs.iter().any(|cfg| cfg.device_name == name) => Ok(name),
+            Some(name) => Err(js_err(format!("'{name}' is not an output of this AEC"))),
+            None => self.outputs.first().map(|cfg| cfg.device_name.clone()).ok_or_else(|| js_err("no output device")),
+        }
+    }
+}
+
 #[wasm_bindgen]
 impl AecHandle {
+    // key to store the echo canceller state under (one entry per input/output device pair)
+    pub fn aec_state_key(&self) -> String {
+        let devices = |names: Vec<String>| names.join("+");
+        format!(
+            "melodeus-aec-state:{}:{}",
+            devices(self.inputs.iter().map(|cfg| format!("{}/{}", cfg.host_id.name(), cfg.device_name)).collect()),
+            devices(self.outputs.iter().map(|cfg| format!("{}/{}", cfg.host_id.name(), cfg.device_name)).collect()),
+        )
+    }
+
+    pub fn calibration_key(&self) -> String {
+        calibration_key(
+            self.inputs[0].host_id.name(),
+            &self.inputs[0].device_name,
+            self.outputs[0].host_id.name(),
+            &self.outputs[0].device_name,
+        )
+    }
+
+    // calibration to pass to enable_aec next time, undefined if there is nothing worth keeping
+    pub fn calibration_state(&self) -> Option<Vec<u8>> {
+        self.stream.saved_calibration().map(|saved| saved.to_bytes())
+    }
+
+    // measured delay, confidence and snr per output/input pair from the last calibration. With debug_wav its
+    // debugWavs hold { name, blob } per device
+    pub fn calibration_report(&self) -> Result<JsValue, JsValue> {
+        Ok(calibration_report_to_js(&self.calibration)?.into())
+    }
+
+    // room impulse response from `output_device` (the enabled output if undefined) to each input, measured
+    // with a log sweep (loud, takes ~4 s), as samples and as WAV bytes
+    pub async fn measure_impulse_response(&mut self, length_ms: f32, output_device: Option<String>) -> Result<JsValue, JsValue> {
+        let output_device = self.output_device_name(output_device)?;
+        let responses = self
+            .stream
+            .measure_impulse_response(self.output_producers.as_mut_slice(), &output_device, length_ms)
+            .await
+            .map_err(js_err)?;
+        let array = Array::new();
+        for response in &responses {
+            let obj = Object::new();
+            Reflect::set(&obj, &"outputDevice".into(), &response.output_device.clone().into())?;
+            Reflect::set(&obj, &"inputDevice".into(), &response.input_device.clone().into())?;
+            Reflect::set(&obj, &"sampleRate".into(), &(response.sample_rate as f64).into())?;
+            Reflect::set(&obj, &"samples".into(), &Float32Array::from(response.samples.as_slice()))?;
+            let wav = response.to_wav().map_err(js_err)?;
+            Reflect::set(&obj, &"wav".into(), &Uint8Array::from(wav.as_slice()))?;
+            array.push(&obj);
+        }
+        Ok(array.into())
+    }
+
+    // plays a short probe on each channel of `output_device` (the enabled output if undefined) in turn (~1.2 s per
+    // channel) and reports per input and channel whether it was heard, with its level relative to the loudest
+    // channel and its delay
+    pub async fn identify_speaker_channels(&mut self, output_device: Option<String>) -> Result<JsValue, JsValue> {
+        let output_device = self.output_device_name(output_device)?;
+        let channels = self
+            .stream
+            .identify_speaker_channels(self.output_producers.as_mut_slice(), &output_device)
+            .await
+            .map_err(js_err)?;
+        let array = Array::new();
+        for channel in &channels {
+            let obj = Object::new();
+            Reflect::set(&obj, &"outputDevice".into(), &channel.output_device.clone().into())?;
+            Reflect::set(&obj, &"inputDevice".into(), &channel.input_device.clone().into())?;
+            Reflect::set(&obj, &"channel".into(), &(channel.channel as f64).into())?;
+            Reflect::set(&obj, &"audible".into(), &channel.audible.into())?;
+            Reflect::set(&obj, &"confidence".into(), &(channel.confidence as f64).into())?;
+            Reflect::set(&obj, &"relativeLevelDb".into(), &channel.relative_level_db.map_or(JsValue::NULL, |d| (d as f64).into()))?;
+            Reflect::set(&obj, &"delayMs".into(), &channel.delay_ms.map_or(JsValue::NULL, |d| (d as f64).into()))?;
+            array.push(&obj);
+        }
+        Ok(array.into())
+    }
+
+    // false on aec3, which can't export its state, so aec_state is always undefined there
+    pub fn aec_state_supported(&self) -> bool {
+        self.stream.aec_snapshot_supported()
+    }
+
+    // converged echo canceller state, undefined if the backend can't export it. Only the speex backend can:
+    // the aec3 crate has no way to get its state out or back in, so with the default aec3 backend this is
+    // always undefined and every session starts unconverged
+    pub fn aec_state(&self) -> Option<Vec<u8>> {
+        self.stream.aec_snapshot()
+    }
+
+    pub fn restore_aec_state(&mut self, state: &[u8]) -> Result<(), JsValue> {
+        self.stream.restore_aec_snapshot(state.to_vec()).map_err(js_err)
+    }
+
     pub async fn update(&mut self) -> Result<JsValue, JsValue> {
         let input_channels = self.stream.num_input_channels();
         let output_channels = self.stream.num_output_channels();
-        let (inputs_i16, outputs_i16, aec_out, start_micros, end_micros) =
+        let (inputs, outputs, aec, start_micros, end_micros) =
             self.stream.update_debug().await.map_err(js_err)?;
 
-        let inputs = normalize_i16(inputs_i16);
-        let outputs = normalize_i16(outputs_i16);
-        let aec = aec_out.to_vec();
+        let inputs = inputs.to_vec();
+        let outputs = outputs.to_vec();
+        let aec = aec.to_vec();
 
         let obj = Object::new();
         Reflect::set(&obj, &"inputs".into(), &Float32Array::from(inputs.as_slice()))?;
@@ -258,6 +557,21 @@ impl AecHandle {
             Reflect::set(&o, &"channels".into(), &(cfg.channels as f64).into())?;
             outputs_meta.push(&o);
         }
+        // learned echo path per input/output channel pair (empty if the backend doesn't expose it)
+        let impulse_responses = Array::new();
+        let echo_delays_ms = Array::new();
+        if let Some(responses) = self.stream.echo_impulse_response() {
+            for response in &responses {
+                impulse_responses.push(&Float32Array::from(response.as_slice()));
+                let delay_ms = aec::dominant_delay(response)
+                    .map(|idx| JsValue::from_f64(idx as f64 * 1000.0 / TARGET_SAMPLE_RATE as f64))
+                    .unwrap_or(JsValue::NULL);
+                echo_delays_ms.push(&delay_ms);
+            }
+        }
+        Reflect::set(&obj, &"impulseResponses".into(), &impulse_responses)?;
+        Reflect::set(&obj, &"echoDelaysMs".into(), &echo_delays_ms)?;
+
         Reflect::set(&obj, &"inputDevices".into(), &inputs_meta)?;
         Reflect::set(&obj, &"outputDevices".into(), &outputs_meta)?;
         Reflect::set(
@@ -267,6 +581,31 @@ impl AecHandle {
         )?;
         Reflect::set(&obj, &"endMicros".into(), &(end_micros as f64).into())?;
 
+        // echo path changes since the last frame, and whether they were recalibrated for (see calibration_report).
+        // A failed recalibration is reported in recalibrationError, the stream keeps the alignment it had
+        // and won't ask again until the monitor's recalibrate_interval_secs is up
+        let echo_path_changes = Array::new();
+        for change in self.stream.take_echo_path_changes() {
+            let change = echo_path_change_to_js(&change)?;
+            echo_path_changes.push(&change);
+        }
+        Reflect::set(&obj, &"echoPathChanges".into(), &echo_path_changes)?;
+        let mut recalibrated = false;
+        let mut recalibration_error = JsValue::NULL;
+        if self.stream.needs_recalibration() {
+            match self.stream.calibrate(self.output_producers.as_mut_slice(), false).await {
+                Ok(report) => {
+                    self.calibration = report;
+                    recalibrated = true;
+                }
+                Err(err) => recalibration_error = js_err(err),
+            }
+        }
+        Reflect::set(&obj, &"recalibrated".into(), &recalibrated.into())?;
+        Reflect::set(&obj, &"recalibrationError".into(), &recalibration_error)?;
+        // echo cancellation is bypassed because no input hears the outputs
+        Reflect::set(&obj, &"isolated".into(), &self.stream.is_acoustically_isolated().into())?;
+
         Ok(obj.into())
     }
 }
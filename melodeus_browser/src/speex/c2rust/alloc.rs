@@ -113,6 +113,10 @@ pub unsafe fn warn(msg: *const c_char) {
     log_error(&format!("speex warning: {}", text));
 }
 
+pub fn warn_str(msg: &str) {
+    log_error(&format!("speex warning: {}", msg));
+}
+
 pub unsafe fn warn_int(msg: *const c_char, val: c_int) {
     let text = cstr_to_string(msg);
     log_error(&format!("speex warning: {} {}", text, val));
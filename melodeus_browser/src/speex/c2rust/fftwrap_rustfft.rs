@@ -0,0 +1,129 @@
+// rustfft backend for the speex fftwrap API (enabled by the `rustfft-fftwrap` feature),
+// replacing smallft. The transform itself is speex::fft::RealFft, which keeps smallft's
+// packed layout and scaling so mdf / preprocess don't notice, and whose plans are shared
+// with the calibration code.
+
+use std::ffi::{c_float, c_int, c_void};
+
+use crate::speex::c2rust::alloc;
+use crate::speex::fft::RealFft;
+
+#[inline]
+unsafe extern "C" fn speex_warning(str: *const std::ffi::c_char) {
+    alloc::warn(str);
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn spx_fft_init(size: c_int) -> *mut c_void {
+    if size <= 0 || size % 2 != 0 {
+        speex_warning(b"FFT size must be even\0" as *const u8 as *const std::ffi::c_char);
+        return std::ptr::null_mut();
+    }
+    Box::into_raw(Box::new(RealFft::new(size as usize))) as *mut c_void
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn spx_fft_destroy(table: *mut c_void) {
+    if !table.is_null() {
+        drop(Box::from_raw(table as *mut RealFft));
+    }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn spx_fft(table: *mut c_void, in_0: *mut c_float, out: *mut c_float) {
+    let fft = &mut *(table as *mut RealFft);
+    let n = fft.size();
+    if in_0 == out {
+        speex_warning(b"FFT should not be done in-place\0" as *const u8 as *const std::ffi::c_char);
+        let input = std::slice::from_raw_parts(in_0 as *const f32, n).to_vec();
+        fft.forward(&input, std::slice::from_raw_parts_mut(out, n));
+    } else {
+        fft.forward(std::slice::from_raw_parts(in_0, n), std::slice::from_raw_parts_mut(out, n));
+    }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn spx_ifft(table: *mut c_void, in_0: *mut c_float, out: *mut c_float) {
+    let fft = &mut *(table as *mut RealFft);
+    let n = fft.size();
+    if in_0 == out {
+        speex_warning(b"FFT should not be done in-place\0" as *const u8 as *const std::ffi::c_char);
+        let input = std::slice::from_raw_parts(in_0 as *const f32, n).to_vec();
+        fft.inverse(&input, std::slice::from_raw_parts_mut(out, n));
+    } else {
+        fft.inverse(std::slice::from_raw_parts(in_0, n), std::slice::from_raw_parts_mut(out, n));
+    }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn spx_fft_float(table: *mut c_void, in_0: *mut c_float, out: *mut c_float) {
+    spx_fft(table, in_0, out);
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn spx_ifft_float(table: *mut c_void, in_0: *mut c_float, out: *mut c_float) {
+    spx_ifft(table, in_0, out);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::speex::c2rust::smallft::{
+        drft_lookup, spx_drft_backward, spx_drft_clear, spx_drft_forward, spx_drft_init,
+    };
+
+    // what the smallft fftwrap does: scale by 1/n, then the in-place drft
+    unsafe fn smallft(n: usize, input: &[f32], forward: bool) -> Vec<f32> {
+        let mut lookup = drft_lookup { n: 0, trigcache: std::ptr::null_mut(), splitcache: std::ptr::null_mut() };
+        spx_drft_init(&mut lookup, n as c_int);
+        let mut data = input.to_vec();
+        if forward {
+            data.iter_mut().for_each(|v| *v /= n as f32);
+            spx_drft_forward(&mut lookup, data.as_mut_ptr());
+        } else {
+            spx_drft_backward(&mut lookup, data.as_mut_ptr());
+        }
+        spx_drft_clear(&mut lookup);
+        data
+    }
+
+    fn signal(seed: u32, len: usize) -> Vec<f32> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (state >> 8) as f32 / (1u32 << 23) as f32 - 1.0
+            })
+            .collect()
+    }
+
+    fn assert_close(n: usize, actual: &[f32], expected: &[f32]) {
+        let peak = expected.iter().fold(0.0f32, |m, v| m.max(v.abs()));
+        // both round O(log n) times in f32, just in a different order
+        let tolerance = peak * 8.0 * f32::EPSILON * (n as f32).log2();
+        for (i, (a, e)) in actual.iter().zip(expected).enumerate() {
+            assert!((a - e).abs() <= tolerance, "n {n}, bin {i}: {a} vs {e}");
+        }
+    }
+
+    #[test]
+    fn matches_smallft() {
+        // 2 * frame_size for the frame sizes mdf and preprocess run with, plus odd factors
+        for n in [2, 8, 64, 128, 160, 256, 320, 480, 512, 640, 960, 1024, 2048, 4096] {
+            let table = unsafe { spx_fft_init(n as c_int) };
+            let input = signal(n as u32, n);
+
+            let mut spectrum = vec![0.0f32; n];
+            let mut samples = vec![0.0f32; n];
+            unsafe {
+                spx_fft(table, input.as_ptr() as *mut f32, spectrum.as_mut_ptr());
+                spx_ifft(table, spectrum.as_ptr() as *mut f32, samples.as_mut_ptr());
+            }
+            assert_close(n, &spectrum, &unsafe { smallft(n, &input, true) });
+            assert_close(n, &samples, &unsafe { smallft(n, &spectrum, false) });
+            // forward scales by 1/n and inverse doesn't, so the round trip is the identity
+            assert_close(n, &samples, &input);
+            unsafe { spx_fft_destroy(table) };
+        }
+    }
+}
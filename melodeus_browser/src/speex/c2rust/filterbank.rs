@@ -0,0 +1,193 @@
+#![allow(unused_assignments)]
+#![allow(unused_variables)]
+#![allow(unused_unsafe)]
+
+pub mod arch_h {
+    pub type spx_word16_t = std::ffi::c_float;
+    pub type spx_word32_t = std::ffi::c_float;
+}
+pub mod stddef_h {
+    pub type size_t = usize;
+}
+pub mod os_support_h {
+    use crate::speex::c2rust::alloc;
+    #[inline]
+    pub unsafe extern "C" fn speex_alloc(
+        size: std::ffi::c_int,
+    ) -> *mut std::ffi::c_void {
+        return alloc::calloc(size as size_t, 1 as size_t);
+    }
+    #[inline]
+    pub unsafe extern "C" fn speex_free(ptr: *mut std::ffi::c_void) {
+        alloc::free(ptr);
+    }
+    use super::stddef_h::size_t;
+}
+pub mod mathcalls_h {
+    unsafe extern "C" {
+        pub fn atan(__x: std::ffi::c_double) -> std::ffi::c_double;
+        pub fn floor(__x: std::ffi::c_double) -> std::ffi::c_double;
+    }
+}
+pub use self::arch_h::{spx_word16_t, spx_word32_t};
+pub use self::stddef_h::size_t;
+pub use self::os_support_h::{speex_alloc, speex_free};
+use self::mathcalls_h::{atan, floor};
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct FilterBank {
+    pub bank_left: *mut std::ffi::c_int,
+    pub bank_right: *mut std::ffi::c_int,
+    pub filter_left: *mut spx_word16_t,
+    pub filter_right: *mut spx_word16_t,
+    pub scaling: *mut std::ffi::c_float,
+    pub nb_banks: std::ffi::c_int,
+    pub len: std::ffi::c_int,
+}
+#[inline]
+unsafe extern "C" fn toBARK(n: std::ffi::c_float) -> std::ffi::c_float {
+    return (13.1f32 as std::ffi::c_double
+        * atan((0.00074f32 * n) as std::ffi::c_double)
+        + 2.24f32 as std::ffi::c_double
+            * atan((n * n * 1.85e-8f32) as std::ffi::c_double)
+        + (1e-4f32 * n) as std::ffi::c_double) as std::ffi::c_float;
+}
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn filterbank_new(
+    banks: std::ffi::c_int,
+    sampling: spx_word32_t,
+    len: std::ffi::c_int,
+    type_0: std::ffi::c_int,
+) -> *mut FilterBank {
+    let mut bank: *mut FilterBank = 0 as *mut FilterBank;
+    let mut df: spx_word32_t = 0.;
+    let mut max_mel: spx_word32_t = 0.;
+    let mut mel_interval: spx_word32_t = 0.;
+    let mut i: std::ffi::c_int = 0;
+    let mut id1: std::ffi::c_int = 0;
+    let mut id2: std::ffi::c_int = 0;
+    df = sampling / (2 as std::ffi::c_int * len) as spx_word32_t;
+    max_mel = toBARK(sampling / 2 as std::ffi::c_int as spx_word32_t);
+    mel_interval = max_mel / (banks - 1 as std::ffi::c_int) as spx_word32_t;
+    bank = speex_alloc(::core::mem::size_of::<FilterBank>() as std::ffi::c_int)
+        as *mut FilterBank;
+    (*bank).nb_banks = banks;
+    (*bank).len = len;
+    (*bank).bank_left = speex_alloc(
+        (len as usize).wrapping_mul(::core::mem::size_of::<std::ffi::c_int>())
+            as std::ffi::c_int,
+    ) as *mut std::ffi::c_int;
+    (*bank).bank_right = speex_alloc(
+        (len as usize).wrapping_mul(::core::mem::size_of::<std::ffi::c_int>())
+            as std::ffi::c_int,
+    ) as *mut std::ffi::c_int;
+    (*bank).filter_left = speex_alloc(
+        (len as usize).wrapping_mul(::core::mem::size_of::<spx_word16_t>())
+            as std::ffi::c_int,
+    ) as *mut spx_word16_t;
+    (*bank).filter_right = speex_alloc(
+        (len as usize).wrapping_mul(::core::mem::size_of::<spx_word16_t>())
+            as std::ffi::c_int,
+    ) as *mut spx_word16_t;
+    (*bank).scaling = speex_alloc(
+        (banks as usize).wrapping_mul(::core::mem::size_of::<std::ffi::c_float>())
+            as std::ffi::c_int,
+    ) as *mut std::ffi::c_float;
+    i = 0 as std::ffi::c_int;
+    while i < len {
+        let mut curr_freq: spx_word16_t = 0.;
+        let mut mel: spx_word32_t = 0.;
+        let mut val: spx_word16_t = 0.;
+        curr_freq = i as spx_word32_t * df;
+        mel = toBARK(curr_freq);
+        if mel > max_mel {
+            break;
+        }
+        id1 = floor((mel / mel_interval) as std::ffi::c_double) as std::ffi::c_int;
+        if id1 > banks - 2 as std::ffi::c_int {
+            id1 = banks - 2 as std::ffi::c_int;
+            val = 1.0f32;
+        } else {
+            val = (mel - id1 as spx_word32_t * mel_interval) / mel_interval;
+        }
+        id2 = id1 + 1 as std::ffi::c_int;
+        *((*bank).bank_left).offset(i as isize) = id1;
+        *((*bank).filter_left).offset(i as isize) = 1.0f32 - val;
+        *((*bank).bank_right).offset(i as isize) = id2;
+        *((*bank).filter_right).offset(i as isize) = val;
+        i += 1;
+    }
+    i = 0 as std::ffi::c_int;
+    while i < (*bank).nb_banks {
+        *((*bank).scaling).offset(i as isize) = 0 as std::ffi::c_int as std::ffi::c_float;
+        i += 1;
+    }
+    i = 0 as std::ffi::c_int;
+    while i < (*bank).len {
+        let mut id: std::ffi::c_int = *((*bank).bank_left).offset(i as isize);
+        *((*bank).scaling).offset(id as isize) += *((*bank).filter_left).offset(i as isize);
+        id = *((*bank).bank_right).offset(i as isize);
+        *((*bank).scaling).offset(id as isize) += *((*bank).filter_right).offset(i as isize);
+        i += 1;
+    }
+    i = 0 as std::ffi::c_int;
+    while i < (*bank).nb_banks {
+        *((*bank).scaling).offset(i as isize) = 1.0f32
+            / *((*bank).scaling).offset(i as isize);
+        i += 1;
+    }
+    return bank;
+}
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn filterbank_destroy(bank: *mut FilterBank) {
+    speex_free((*bank).bank_left as *mut std::ffi::c_void);
+    speex_free((*bank).bank_right as *mut std::ffi::c_void);
+    speex_free((*bank).filter_left as *mut std::ffi::c_void);
+    speex_free((*bank).filter_right as *mut std::ffi::c_void);
+    speex_free((*bank).scaling as *mut std::ffi::c_void);
+    speex_free(bank as *mut std::ffi::c_void);
+}
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn filterbank_compute_bank32(
+    bank: *mut FilterBank,
+    ps: *mut spx_word32_t,
+    mel: *mut spx_word32_t,
+) {
+    let mut i: std::ffi::c_int = 0;
+    i = 0 as std::ffi::c_int;
+    while i < (*bank).nb_banks {
+        *mel.offset(i as isize) = 0 as std::ffi::c_int as spx_word32_t;
+        i += 1;
+    }
+    i = 0 as std::ffi::c_int;
+    while i < (*bank).len {
+        let mut id: std::ffi::c_int = 0;
+        id = *((*bank).bank_left).offset(i as isize);
+        *mel.offset(id as isize)
+            += *((*bank).filter_left).offset(i as isize) * *ps.offset(i as isize);
+        id = *((*bank).bank_right).offset(i as isize);
+        *mel.offset(id as isize)
+            += *((*bank).filter_right).offset(i as isize) * *ps.offset(i as isize);
+        i += 1;
+    }
+}
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn filterbank_compute_psd16(
+    bank: *mut FilterBank,
+    mel: *mut spx_word16_t,
+    ps: *mut spx_word16_t,
+) {
+    let mut i: std::ffi::c_int = 0;
+    i = 0 as std::ffi::c_int;
+    while i < (*bank).len {
+        let mut tmp: spx_word32_t = 0.;
+        let mut id1: std::ffi::c_int = 0;
+        let mut id2: std::ffi::c_int = 0;
+        id1 = *((*bank).bank_left).offset(i as isize);
+        id2 = *((*bank).bank_right).offset(i as isize);
+        tmp = *mel.offset(id1 as isize) * *((*bank).filter_left).offset(i as isize);
+        tmp += *mel.offset(id2 as isize) * *((*bank).filter_right).offset(i as isize);
+        *ps.offset(i as isize) = tmp;
+        i += 1;
+    }
+}
@@ -20,11 +20,24 @@
 )]
 
 pub mod alloc;
+#[cfg(not(feature = "rustfft-fftwrap"))]
 pub mod fftwrap;
+#[cfg(feature = "rustfft-fftwrap")]
+#[path = "fftwrap_rustfft.rs"]
+pub mod fftwrap;
+pub mod filterbank;
+#[cfg(not(feature = "rustfft-fftwrap"))]
 pub mod kiss_fft;
+#[cfg(not(feature = "rustfft-fftwrap"))]
 pub mod kiss_fftr;
 pub mod mdf;
+pub mod preprocess;
 pub mod resample;
+#[cfg(not(feature = "fixed-point"))]
+pub mod resample_simd;
+// also built for tests, as the reference the rustfft fftwrap is checked against
+#[cfg(any(test, not(feature = "rustfft-fftwrap")))]
 pub mod smallft;
 
 pub use self::mdf::speex_echo_h;
+pub use self::preprocess::speex_preprocess_h;
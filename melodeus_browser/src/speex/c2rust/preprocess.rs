@@ -0,0 +1,1354 @@
+#![allow(unused_assignments)]
+#![allow(unused_variables)]
+#![allow(unused_unsafe)]
+
+pub mod types_h {
+    pub type __int16_t = i16;
+    pub type __int32_t = i32;
+}
+pub mod stdint_intn_h {
+    pub type int16_t = __int16_t;
+    pub type int32_t = __int32_t;
+    use super::types_h::{__int16_t, __int32_t};
+}
+pub mod speexdsp_config_types_h {
+    pub type spx_int16_t = int16_t;
+    pub type spx_int32_t = int32_t;
+    use super::stdint_intn_h::{int16_t, int32_t};
+}
+pub mod arch_h {
+    pub type spx_word16_t = std::ffi::c_float;
+    pub type spx_word32_t = std::ffi::c_float;
+}
+pub mod speex_preprocess_h {
+    pub type SpeexPreprocessState = SpeexPreprocessState_;
+    pub const SPEEX_PREPROCESS_SET_DENOISE: std::ffi::c_int = unsafe { 0 };
+    pub const SPEEX_PREPROCESS_GET_DENOISE: std::ffi::c_int = unsafe { 1 };
+    pub const SPEEX_PREPROCESS_SET_AGC: std::ffi::c_int = unsafe { 2 };
+    pub const SPEEX_PREPROCESS_GET_AGC: std::ffi::c_int = unsafe { 3 };
+    pub const SPEEX_PREPROCESS_SET_VAD: std::ffi::c_int = unsafe { 4 };
+    pub const SPEEX_PREPROCESS_GET_VAD: std::ffi::c_int = unsafe { 5 };
+    pub const SPEEX_PREPROCESS_SET_AGC_LEVEL: std::ffi::c_int = unsafe { 6 };
+    pub const SPEEX_PREPROCESS_GET_AGC_LEVEL: std::ffi::c_int = unsafe { 7 };
+    pub const SPEEX_PREPROCESS_SET_DEREVERB: std::ffi::c_int = unsafe { 8 };
+    pub const SPEEX_PREPROCESS_GET_DEREVERB: std::ffi::c_int = unsafe { 9 };
+    pub const SPEEX_PREPROCESS_SET_DEREVERB_LEVEL: std::ffi::c_int = unsafe { 10 };
+    pub const SPEEX_PREPROCESS_GET_DEREVERB_LEVEL: std::ffi::c_int = unsafe { 11 };
+    pub const SPEEX_PREPROCESS_SET_DEREVERB_DECAY: std::ffi::c_int = unsafe { 12 };
+    pub const SPEEX_PREPROCESS_GET_DEREVERB_DECAY: std::ffi::c_int = unsafe { 13 };
+    pub const SPEEX_PREPROCESS_SET_PROB_START: std::ffi::c_int = unsafe { 14 };
+    pub const SPEEX_PREPROCESS_GET_PROB_START: std::ffi::c_int = unsafe { 15 };
+    pub const SPEEX_PREPROCESS_SET_PROB_CONTINUE: std::ffi::c_int = unsafe { 16 };
+    pub const SPEEX_PREPROCESS_GET_PROB_CONTINUE: std::ffi::c_int = unsafe { 17 };
+    pub const SPEEX_PREPROCESS_SET_NOISE_SUPPRESS: std::ffi::c_int = unsafe { 18 };
+    pub const SPEEX_PREPROCESS_GET_NOISE_SUPPRESS: std::ffi::c_int = unsafe { 19 };
+    pub const SPEEX_PREPROCESS_SET_ECHO_SUPPRESS: std::ffi::c_int = unsafe { 20 };
+    pub const SPEEX_PREPROCESS_GET_ECHO_SUPPRESS: std::ffi::c_int = unsafe { 21 };
+    pub const SPEEX_PREPROCESS_SET_ECHO_SUPPRESS_ACTIVE: std::ffi::c_int = unsafe { 22 };
+    pub const SPEEX_PREPROCESS_GET_ECHO_SUPPRESS_ACTIVE: std::ffi::c_int = unsafe { 23 };
+    pub const SPEEX_PREPROCESS_SET_ECHO_STATE: std::ffi::c_int = unsafe { 24 };
+    pub const SPEEX_PREPROCESS_GET_ECHO_STATE: std::ffi::c_int = unsafe { 25 };
+    pub const SPEEX_PREPROCESS_SET_AGC_INCREMENT: std::ffi::c_int = unsafe { 26 };
+    pub const SPEEX_PREPROCESS_GET_AGC_INCREMENT: std::ffi::c_int = unsafe { 27 };
+    pub const SPEEX_PREPROCESS_SET_AGC_DECREMENT: std::ffi::c_int = unsafe { 28 };
+    pub const SPEEX_PREPROCESS_GET_AGC_DECREMENT: std::ffi::c_int = unsafe { 29 };
+    pub const SPEEX_PREPROCESS_SET_AGC_MAX_GAIN: std::ffi::c_int = unsafe { 30 };
+    pub const SPEEX_PREPROCESS_GET_AGC_MAX_GAIN: std::ffi::c_int = unsafe { 31 };
+    pub const SPEEX_PREPROCESS_GET_AGC_LOUDNESS: std::ffi::c_int = unsafe { 33 };
+    pub const SPEEX_PREPROCESS_GET_AGC_GAIN: std::ffi::c_int = unsafe { 35 };
+    pub const SPEEX_PREPROCESS_GET_PROB: std::ffi::c_int = unsafe { 45 };
+    pub const SPEEX_PREPROCESS_SET_AGC_TARGET: std::ffi::c_int = unsafe { 46 };
+    pub const SPEEX_PREPROCESS_GET_AGC_TARGET: std::ffi::c_int = unsafe { 47 };
+    // not in upstream speexdsp: hand over the residual echo power spectrum (ps_size floats)
+    // for the next speex_preprocess_run, for echo cancellers that aren't a SpeexEchoState
+    pub const SPEEX_PREPROCESS_SET_RESIDUAL_ECHO: std::ffi::c_int = unsafe { 48 };
+    use super::SpeexPreprocessState_;
+}
+pub mod speex_echo_h {
+    pub use crate::speex::c2rust::mdf::SpeexEchoState;
+    use super::arch_h::spx_word32_t;
+    unsafe extern "C" {
+        pub fn speex_echo_get_residual(
+            st: *mut SpeexEchoState,
+            Yout: *mut spx_word32_t,
+            len: std::ffi::c_int,
+        );
+    }
+}
+pub mod filterbank_h {
+    pub use crate::speex::c2rust::filterbank::FilterBank;
+    use super::arch_h::{spx_word16_t, spx_word32_t};
+    unsafe extern "C" {
+        pub fn filterbank_new(
+            banks: std::ffi::c_int,
+            sampling: spx_word32_t,
+            len: std::ffi::c_int,
+            type_0: std::ffi::c_int,
+        ) -> *mut FilterBank;
+        pub fn filterbank_destroy(bank: *mut FilterBank);
+        pub fn filterbank_compute_bank32(
+            bank: *mut FilterBank,
+            ps: *mut spx_word32_t,
+            mel: *mut spx_word32_t,
+        );
+        pub fn filterbank_compute_psd16(
+            bank: *mut FilterBank,
+            mel: *mut spx_word16_t,
+            psd: *mut spx_word16_t,
+        );
+    }
+}
+pub mod fftwrap_h {
+    use super::arch_h::spx_word16_t;
+    unsafe extern "C" {
+        pub fn spx_fft_init(size: std::ffi::c_int) -> *mut std::ffi::c_void;
+        pub fn spx_fft_destroy(table: *mut std::ffi::c_void);
+        pub fn spx_fft(
+            table: *mut std::ffi::c_void,
+            in_0: *mut spx_word16_t,
+            out: *mut spx_word16_t,
+        );
+        pub fn spx_ifft(
+            table: *mut std::ffi::c_void,
+            in_0: *mut spx_word16_t,
+            out: *mut spx_word16_t,
+        );
+    }
+}
+pub mod stddef_h {
+    pub type size_t = usize;
+}
+pub mod os_support_h {
+    use crate::speex::c2rust::alloc;
+    #[inline]
+    pub unsafe extern "C" fn speex_alloc(
+        size: std::ffi::c_int,
+    ) -> *mut std::ffi::c_void {
+        return alloc::calloc(size as size_t, 1 as size_t);
+    }
+    #[inline]
+    pub unsafe extern "C" fn speex_free(ptr: *mut std::ffi::c_void) {
+        alloc::free(ptr);
+    }
+    #[inline]
+    pub unsafe extern "C" fn speex_warning(str: *const std::ffi::c_char) {
+        alloc::warn(str);
+    }
+    #[inline]
+    pub unsafe extern "C" fn speex_warning_int(
+        str: *const std::ffi::c_char,
+        val: std::ffi::c_int,
+    ) {
+        alloc::warn_int(str, val);
+    }
+    use super::stddef_h::size_t;
+}
+pub mod math_h {
+    pub const M_PI: std::ffi::c_double = unsafe { 3.14159265358979323846f64 };
+}
+pub mod mathcalls_h {
+    unsafe extern "C" {
+        pub fn cos(__x: std::ffi::c_double) -> std::ffi::c_double;
+        pub fn exp(__x: std::ffi::c_double) -> std::ffi::c_double;
+        pub fn log(__x: std::ffi::c_double) -> std::ffi::c_double;
+        pub fn pow(__x: std::ffi::c_double, __y: std::ffi::c_double) -> std::ffi::c_double;
+        pub fn sqrt(__x: std::ffi::c_double) -> std::ffi::c_double;
+        pub fn floor(__x: std::ffi::c_double) -> std::ffi::c_double;
+        pub fn fabs(__x: std::ffi::c_double) -> std::ffi::c_double;
+    }
+}
+pub use self::types_h::{__int16_t, __int32_t};
+pub use self::stdint_intn_h::{int16_t, int32_t};
+pub use self::speexdsp_config_types_h::{spx_int16_t, spx_int32_t};
+pub use self::arch_h::{spx_word16_t, spx_word32_t};
+pub use self::speex_preprocess_h::*;
+use self::speex_echo_h::{speex_echo_get_residual, SpeexEchoState};
+use self::filterbank_h::{
+    filterbank_new, filterbank_destroy, filterbank_compute_bank32, filterbank_compute_psd16,
+    FilterBank,
+};
+use self::fftwrap_h::{spx_fft_init, spx_fft_destroy, spx_fft, spx_ifft};
+pub use self::stddef_h::size_t;
+pub use self::os_support_h::{speex_alloc, speex_free, speex_warning, speex_warning_int};
+pub use self::math_h::M_PI;
+use self::mathcalls_h::{cos, exp, log, pow, sqrt, floor, fabs};
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct SpeexPreprocessState_ {
+    pub frame_size: std::ffi::c_int,
+    pub ps_size: std::ffi::c_int,
+    pub sampling_rate: std::ffi::c_int,
+    pub nbands: std::ffi::c_int,
+    pub bank: *mut FilterBank,
+    pub denoise_enabled: std::ffi::c_int,
+    pub vad_enabled: std::ffi::c_int,
+    pub dereverb_enabled: std::ffi::c_int,
+    pub reverb_decay: spx_word16_t,
+    pub reverb_level: spx_word16_t,
+    pub speech_prob_start: spx_word16_t,
+    pub speech_prob_continue: spx_word16_t,
+    pub noise_suppress: std::ffi::c_int,
+    pub echo_suppress: std::ffi::c_int,
+    pub echo_suppress_active: std::ffi::c_int,
+    pub echo_state: *mut SpeexEchoState,
+    pub residual_echo_set: std::ffi::c_int,
+    pub speech_prob: spx_word16_t,
+    pub frame: *mut spx_word16_t,
+    pub ft: *mut spx_word16_t,
+    pub ps: *mut spx_word32_t,
+    pub gain2: *mut spx_word16_t,
+    pub gain_floor: *mut spx_word16_t,
+    pub window: *mut spx_word16_t,
+    pub noise: *mut spx_word32_t,
+    pub reverb_estimate: *mut spx_word32_t,
+    pub old_ps: *mut spx_word32_t,
+    pub gain: *mut spx_word16_t,
+    pub prior: *mut spx_word16_t,
+    pub post: *mut spx_word16_t,
+    pub S: *mut spx_word32_t,
+    pub Smin: *mut spx_word32_t,
+    pub Stmp: *mut spx_word32_t,
+    pub update_prob: *mut std::ffi::c_int,
+    pub zeta: *mut spx_word16_t,
+    pub echo_noise: *mut spx_word32_t,
+    pub residual_echo: *mut spx_word32_t,
+    pub inbuf: *mut spx_word16_t,
+    pub outbuf: *mut spx_word16_t,
+    pub agc_enabled: std::ffi::c_int,
+    pub agc_level: std::ffi::c_float,
+    pub loudness_accum: std::ffi::c_float,
+    pub loudness_weight: *mut std::ffi::c_float,
+    pub loudness: std::ffi::c_float,
+    pub agc_gain: std::ffi::c_float,
+    pub max_gain: std::ffi::c_float,
+    pub max_increase_step: std::ffi::c_float,
+    pub max_decrease_step: std::ffi::c_float,
+    pub prev_loudness: std::ffi::c_float,
+    pub init_max: std::ffi::c_float,
+    pub nb_adapt: std::ffi::c_int,
+    pub was_speech: std::ffi::c_int,
+    pub min_count: std::ffi::c_int,
+    pub fft_lookup: *mut std::ffi::c_void,
+}
+pub const LOUDNESS_EXP: std::ffi::c_float = unsafe { 5.0f32 };
+pub const AMP_SCALE: std::ffi::c_float = unsafe { 0.001f32 };
+pub const AMP_SCALE_1: std::ffi::c_float = unsafe { 1000.0f32 };
+pub const NB_BANDS: std::ffi::c_int = unsafe { 24 as std::ffi::c_int };
+pub const SPEECH_PROB_START_DEFAULT: spx_word16_t = unsafe { 0.35f32 };
+pub const SPEECH_PROB_CONTINUE_DEFAULT: spx_word16_t = unsafe { 0.20f32 };
+pub const NOISE_SUPPRESS_DEFAULT: std::ffi::c_int = unsafe { -(15 as std::ffi::c_int) };
+pub const ECHO_SUPPRESS_DEFAULT: std::ffi::c_int = unsafe { -(40 as std::ffi::c_int) };
+pub const ECHO_SUPPRESS_ACTIVE_DEFAULT: std::ffi::c_int = unsafe {
+    -(15 as std::ffi::c_int)
+};
+#[inline]
+unsafe extern "C" fn conj_window(w: *mut spx_word16_t, len: std::ffi::c_int) {
+    let mut i: std::ffi::c_int = 0;
+    i = 0 as std::ffi::c_int;
+    while i < len {
+        let mut tmp: spx_word16_t = 0.;
+        let mut x: spx_word16_t = 4.0f32 * i as spx_word16_t / len as spx_word16_t;
+        let mut inv: std::ffi::c_int = 0 as std::ffi::c_int;
+        if x < 1.0f32 {
+        } else if x < 2.0f32 {
+            x = 2.0f32 - x;
+            inv = 1 as std::ffi::c_int;
+        } else if x < 3.0f32 {
+            x = x - 2.0f32;
+            inv = 1 as std::ffi::c_int;
+        } else {
+            x = 4.0f32 - x;
+        }
+        x = 1.271903f32 * x;
+        tmp = 0.5f32
+            - 0.5f32
+                * cos(0.5f64 * M_PI * x as std::ffi::c_double) as spx_word16_t;
+        tmp = tmp * tmp;
+        if inv != 0 {
+            tmp = 1.0f32 - tmp;
+        }
+        *w.offset(i as isize) = sqrt(tmp as std::ffi::c_double) as spx_word16_t;
+        i += 1;
+    }
+}
+#[inline]
+unsafe extern "C" fn hypergeom_gain(xx: spx_word32_t) -> spx_word32_t {
+    let mut ind: std::ffi::c_int = 0;
+    let mut integer: std::ffi::c_float = 0.;
+    let mut frac: std::ffi::c_float = 0.;
+    let mut x: std::ffi::c_float = 0.;
+    static table: [std::ffi::c_float; 21] = [
+        0.82157f32, 1.02017f32, 1.20461f32, 1.37534f32, 1.53363f32, 1.68092f32,
+        1.81865f32, 1.94811f32, 2.07038f32, 2.18638f32, 2.29688f32, 2.40255f32,
+        2.50391f32, 2.60144f32, 2.69551f32, 2.78647f32, 2.87458f32, 2.96015f32,
+        3.04333f32, 3.12431f32, 3.20326f32,
+    ];
+    x = xx;
+    integer = floor((2 as std::ffi::c_int as std::ffi::c_float * x) as std::ffi::c_double)
+        as std::ffi::c_float;
+    ind = integer as std::ffi::c_int;
+    if ind < 0 as std::ffi::c_int {
+        return 1.0f32;
+    }
+    if ind > 19 as std::ffi::c_int {
+        return (1.0f64 * (1 as std::ffi::c_int as std::ffi::c_double
+            + 0.1296f64 / x as std::ffi::c_double)) as spx_word32_t;
+    }
+    frac = 2 as std::ffi::c_int as std::ffi::c_float * x - integer;
+    return (1.0f64
+        * ((1 as std::ffi::c_int as std::ffi::c_float - frac) * table[ind as usize]
+            + frac * table[(ind + 1 as std::ffi::c_int) as usize]) as std::ffi::c_double
+        / sqrt((x + 0.0001f32) as std::ffi::c_double)) as spx_word32_t;
+}
+#[inline]
+unsafe extern "C" fn qcurve(x: spx_word16_t) -> spx_word16_t {
+    return 1.0f32 / (1.0f32 + 0.15f32 / x);
+}
+unsafe extern "C" fn compute_gain_floor(
+    noise_suppress: std::ffi::c_int,
+    effective_echo_suppress: std::ffi::c_int,
+    noise: *mut spx_word32_t,
+    echo: *mut spx_word32_t,
+    gain_floor: *mut spx_word16_t,
+    len: std::ffi::c_int,
+) {
+    let mut i: std::ffi::c_int = 0;
+    let mut echo_floor: std::ffi::c_float = 0.;
+    let mut noise_floor: std::ffi::c_float = 0.;
+    noise_floor = exp((0.2302585f32 * noise_suppress as std::ffi::c_float)
+        as std::ffi::c_double) as std::ffi::c_float;
+    echo_floor = exp((0.2302585f32 * effective_echo_suppress as std::ffi::c_float)
+        as std::ffi::c_double) as std::ffi::c_float;
+    i = 0 as std::ffi::c_int;
+    while i < len {
+        *gain_floor.offset(i as isize) = (1.0f64
+            * sqrt(
+                (noise_floor * *noise.offset(i as isize)
+                    + echo_floor * *echo.offset(i as isize)) as std::ffi::c_double,
+            )
+            / sqrt(
+                (1 as std::ffi::c_int as spx_word32_t + *noise.offset(i as isize)
+                    + *echo.offset(i as isize)) as std::ffi::c_double,
+            )) as spx_word16_t;
+        i += 1;
+    }
+}
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn speex_preprocess_state_init(
+    frame_size: std::ffi::c_int,
+    sampling_rate: std::ffi::c_int,
+) -> *mut SpeexPreprocessState {
+    let mut i: std::ffi::c_int = 0;
+    let mut N: std::ffi::c_int = 0;
+    let mut N3: std::ffi::c_int = 0;
+    let mut N4: std::ffi::c_int = 0;
+    let mut M: std::ffi::c_int = 0;
+    let st: *mut SpeexPreprocessState = speex_alloc(
+        ::core::mem::size_of::<SpeexPreprocessState>() as std::ffi::c_int,
+    ) as *mut SpeexPreprocessState;
+    (*st).frame_size = frame_size;
+    (*st).ps_size = (*st).frame_size;
+    N = (*st).ps_size;
+    N3 = 2 as std::ffi::c_int * N - (*st).frame_size;
+    N4 = (*st).frame_size - N3;
+    (*st).sampling_rate = sampling_rate;
+    (*st).denoise_enabled = 1 as std::ffi::c_int;
+    (*st).vad_enabled = 0 as std::ffi::c_int;
+    (*st).dereverb_enabled = 0 as std::ffi::c_int;
+    (*st).reverb_decay = 0 as std::ffi::c_int as spx_word16_t;
+    (*st).reverb_level = 0 as std::ffi::c_int as spx_word16_t;
+    (*st).noise_suppress = NOISE_SUPPRESS_DEFAULT;
+    (*st).echo_suppress = ECHO_SUPPRESS_DEFAULT;
+    (*st).echo_suppress_active = ECHO_SUPPRESS_ACTIVE_DEFAULT;
+    (*st).speech_prob_start = SPEECH_PROB_START_DEFAULT;
+    (*st).speech_prob_continue = SPEECH_PROB_CONTINUE_DEFAULT;
+    (*st).echo_state = 0 as *mut SpeexEchoState;
+    (*st).residual_echo_set = 0 as std::ffi::c_int;
+    (*st).nbands = NB_BANDS;
+    M = (*st).nbands;
+    (*st).bank = filterbank_new(M, sampling_rate as spx_word32_t, N, 1 as std::ffi::c_int);
+    (*st).frame = speex_alloc(
+        ((2 as std::ffi::c_int * N) as usize)
+            .wrapping_mul(::core::mem::size_of::<spx_word16_t>()) as std::ffi::c_int,
+    ) as *mut spx_word16_t;
+    (*st).window = speex_alloc(
+        ((2 as std::ffi::c_int * N) as usize)
+            .wrapping_mul(::core::mem::size_of::<spx_word16_t>()) as std::ffi::c_int,
+    ) as *mut spx_word16_t;
+    (*st).ft = speex_alloc(
+        ((2 as std::ffi::c_int * N) as usize)
+            .wrapping_mul(::core::mem::size_of::<spx_word16_t>()) as std::ffi::c_int,
+    ) as *mut spx_word16_t;
+    (*st).ps = speex_alloc(
+        ((N + M) as usize).wrapping_mul(::core::mem::size_of::<spx_word32_t>())
+            as std::ffi::c_int,
+    ) as *mut spx_word32_t;
+    (*st).noise = speex_alloc(
+        ((N + M) as usize).wrapping_mul(::core::mem::size_of::<spx_word32_t>())
+            as std::ffi::c_int,
+    ) as *mut spx_word32_t;
+    (*st).echo_noise = speex_alloc(
+        ((N + M) as usize).wrapping_mul(::core::mem::size_of::<spx_word32_t>())
+            as std::ffi::c_int,
+    ) as *mut spx_word32_t;
+    (*st).residual_echo = speex_alloc(
+        ((N + M) as usize).wrapping_mul(::core::mem::size_of::<spx_word32_t>())
+            as std::ffi::c_int,
+    ) as *mut spx_word32_t;
+    (*st).reverb_estimate = speex_alloc(
+        ((N + M) as usize).wrapping_mul(::core::mem::size_of::<spx_word32_t>())
+            as std::ffi::c_int,
+    ) as *mut spx_word32_t;
+    (*st).old_ps = speex_alloc(
+        ((N + M) as usize).wrapping_mul(::core::mem::size_of::<spx_word32_t>())
+            as std::ffi::c_int,
+    ) as *mut spx_word32_t;
+    (*st).prior = speex_alloc(
+        ((N + M) as usize).wrapping_mul(::core::mem::size_of::<spx_word16_t>())
+            as std::ffi::c_int,
+    ) as *mut spx_word16_t;
+    (*st).post = speex_alloc(
+        ((N + M) as usize).wrapping_mul(::core::mem::size_of::<spx_word16_t>())
+            as std::ffi::c_int,
+    ) as *mut spx_word16_t;
+    (*st).gain = speex_alloc(
+        ((N + M) as usize).wrapping_mul(::core::mem::size_of::<spx_word16_t>())
+            as std::ffi::c_int,
+    ) as *mut spx_word16_t;
+    (*st).gain2 = speex_alloc(
+        ((N + M) as usize).wrapping_mul(::core::mem::size_of::<spx_word16_t>())
+            as std::ffi::c_int,
+    ) as *mut spx_word16_t;
+    (*st).gain_floor = speex_alloc(
+        ((N + M) as usize).wrapping_mul(::core::mem::size_of::<spx_word16_t>())
+            as std::ffi::c_int,
+    ) as *mut spx_word16_t;
+    (*st).zeta = speex_alloc(
+        ((N + M) as usize).wrapping_mul(::core::mem::size_of::<spx_word16_t>())
+            as std::ffi::c_int,
+    ) as *mut spx_word16_t;
+    (*st).S = speex_alloc(
+        (N as usize).wrapping_mul(::core::mem::size_of::<spx_word32_t>())
+            as std::ffi::c_int,
+    ) as *mut spx_word32_t;
+    (*st).Smin = speex_alloc(
+        (N as usize).wrapping_mul(::core::mem::size_of::<spx_word32_t>())
+            as std::ffi::c_int,
+    ) as *mut spx_word32_t;
+    (*st).Stmp = speex_alloc(
+        (N as usize).wrapping_mul(::core::mem::size_of::<spx_word32_t>())
+            as std::ffi::c_int,
+    ) as *mut spx_word32_t;
+    (*st).update_prob = speex_alloc(
+        (N as usize).wrapping_mul(::core::mem::size_of::<std::ffi::c_int>())
+            as std::ffi::c_int,
+    ) as *mut std::ffi::c_int;
+    (*st).inbuf = speex_alloc(
+        (N3 as usize).wrapping_mul(::core::mem::size_of::<spx_word16_t>())
+            as std::ffi::c_int,
+    ) as *mut spx_word16_t;
+    (*st).outbuf = speex_alloc(
+        (N3 as usize).wrapping_mul(::core::mem::size_of::<spx_word16_t>())
+            as std::ffi::c_int,
+    ) as *mut spx_word16_t;
+    conj_window((*st).window, 2 as std::ffi::c_int * N3);
+    i = 2 as std::ffi::c_int * N3;
+    while i < 2 as std::ffi::c_int * (*st).ps_size {
+        *((*st).window).offset(i as isize) = 1.0f32;
+        i += 1;
+    }
+    if N4 > 0 as std::ffi::c_int {
+        i = N3 - 1 as std::ffi::c_int;
+        while i >= 0 as std::ffi::c_int {
+            *((*st).window).offset((i + N3 + N4) as isize) = *((*st).window)
+                .offset((i + N3) as isize);
+            *((*st).window).offset((i + N3) as isize) = 1 as std::ffi::c_int
+                as spx_word16_t;
+            i -= 1;
+        }
+    }
+    i = 0 as std::ffi::c_int;
+    while i < N + M {
+        *((*st).noise).offset(i as isize) = 1.0f32;
+        *((*st).reverb_estimate).offset(i as isize) = 0 as std::ffi::c_int
+            as spx_word32_t;
+        *((*st).old_ps).offset(i as isize) = 1 as std::ffi::c_int as spx_word32_t;
+        *((*st).gain).offset(i as isize) = 1.0f32;
+        *((*st).post).offset(i as isize) = 1 as std::ffi::c_int as spx_word16_t;
+        *((*st).prior).offset(i as isize) = 1 as std::ffi::c_int as spx_word16_t;
+        i += 1;
+    }
+    i = 0 as std::ffi::c_int;
+    while i < N {
+        *((*st).update_prob).offset(i as isize) = 1 as std::ffi::c_int;
+        i += 1;
+    }
+    i = 0 as std::ffi::c_int;
+    while i < N3 {
+        *((*st).inbuf).offset(i as isize) = 0 as std::ffi::c_int as spx_word16_t;
+        *((*st).outbuf).offset(i as isize) = 0 as std::ffi::c_int as spx_word16_t;
+        i += 1;
+    }
+    (*st).agc_enabled = 0 as std::ffi::c_int;
+    (*st).agc_level = 8000 as std::ffi::c_int as std::ffi::c_float;
+    (*st).loudness_weight = speex_alloc(
+        (N as usize).wrapping_mul(::core::mem::size_of::<std::ffi::c_float>())
+            as std::ffi::c_int,
+    ) as *mut std::ffi::c_float;
+    i = 0 as std::ffi::c_int;
+    while i < N {
+        let ff: std::ffi::c_float = (i as std::ffi::c_float as std::ffi::c_double * 0.5f64
+            * sampling_rate as std::ffi::c_double / N as std::ffi::c_float as std::ffi::c_double)
+            as std::ffi::c_float;
+        *((*st).loudness_weight).offset(i as isize) = (0.35f32 as std::ffi::c_double
+            - (0.35f32 * ff / 16000.0f32) as std::ffi::c_double
+            + 0.73f32 as std::ffi::c_double
+                * exp(
+                    (-0.5f32 * (ff - 3800 as std::ffi::c_int as std::ffi::c_float)
+                        * (ff - 3800 as std::ffi::c_int as std::ffi::c_float) / 9e5f32)
+                        as std::ffi::c_double,
+                )) as std::ffi::c_float;
+        if *((*st).loudness_weight).offset(i as isize) < 0.01f32 {
+            *((*st).loudness_weight).offset(i as isize) = 0.01f32;
+        }
+        *((*st).loudness_weight).offset(i as isize)
+            *= *((*st).loudness_weight).offset(i as isize);
+        i += 1;
+    }
+    (*st).loudness = 1e-15f64 as std::ffi::c_float;
+    (*st).agc_gain = 1 as std::ffi::c_int as std::ffi::c_float;
+    (*st).max_gain = 30 as std::ffi::c_int as std::ffi::c_float;
+    (*st).max_increase_step = exp(
+        0.11513f32 as std::ffi::c_double * 12.0f64 * (*st).frame_size as std::ffi::c_double
+            / (*st).sampling_rate as std::ffi::c_double,
+    ) as std::ffi::c_float;
+    (*st).max_decrease_step = exp(
+        -0.11513f32 as std::ffi::c_double * 40.0f64 * (*st).frame_size as std::ffi::c_double
+            / (*st).sampling_rate as std::ffi::c_double,
+    ) as std::ffi::c_float;
+    (*st).prev_loudness = 1 as std::ffi::c_int as std::ffi::c_float;
+    (*st).init_max = 1 as std::ffi::c_int as std::ffi::c_float;
+    (*st).was_speech = 0 as std::ffi::c_int;
+    (*st).fft_lookup = spx_fft_init(2 as std::ffi::c_int * N);
+    (*st).nb_adapt = 0 as std::ffi::c_int;
+    (*st).min_count = 0 as std::ffi::c_int;
+    return st;
+}
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn speex_preprocess_state_destroy(st: *mut SpeexPreprocessState) {
+    speex_free((*st).frame as *mut std::ffi::c_void);
+    speex_free((*st).ft as *mut std::ffi::c_void);
+    speex_free((*st).ps as *mut std::ffi::c_void);
+    speex_free((*st).gain2 as *mut std::ffi::c_void);
+    speex_free((*st).gain_floor as *mut std::ffi::c_void);
+    speex_free((*st).window as *mut std::ffi::c_void);
+    speex_free((*st).noise as *mut std::ffi::c_void);
+    speex_free((*st).reverb_estimate as *mut std::ffi::c_void);
+    speex_free((*st).old_ps as *mut std::ffi::c_void);
+    speex_free((*st).gain as *mut std::ffi::c_void);
+    speex_free((*st).prior as *mut std::ffi::c_void);
+    speex_free((*st).post as *mut std::ffi::c_void);
+    speex_free((*st).loudness_weight as *mut std::ffi::c_void);
+    speex_free((*st).echo_noise as *mut std::ffi::c_void);
+    speex_free((*st).residual_echo as *mut std::ffi::c_void);
+    speex_free((*st).S as *mut std::ffi::c_void);
+    speex_free((*st).Smin as *mut std::ffi::c_void);
+    speex_free((*st).Stmp as *mut std::ffi::c_void);
+    speex_free((*st).update_prob as *mut std::ffi::c_void);
+    speex_free((*st).zeta as *mut std::ffi::c_void);
+    speex_free((*st).inbuf as *mut std::ffi::c_void);
+    speex_free((*st).outbuf as *mut std::ffi::c_void);
+    filterbank_destroy((*st).bank);
+    spx_fft_destroy((*st).fft_lookup);
+    speex_free(st as *mut std::ffi::c_void);
+}
+unsafe extern "C" fn speex_compute_agc(
+    st: *mut SpeexPreprocessState,
+    Pframe: spx_word16_t,
+    ft: *mut spx_word16_t,
+) {
+    let mut i: std::ffi::c_int = 0;
+    let N: std::ffi::c_int = (*st).ps_size;
+    let mut target_gain: std::ffi::c_float = 0.;
+    let mut loudness: std::ffi::c_float = 1.0f32;
+    let mut rate: std::ffi::c_float = 0.;
+    i = 2 as std::ffi::c_int;
+    while i < N {
+        loudness += 2.0f32 * N as std::ffi::c_float * *((*st).ps).offset(i as isize)
+            * *((*st).loudness_weight).offset(i as isize);
+        i += 1;
+    }
+    loudness = sqrt(loudness as std::ffi::c_double) as std::ffi::c_float;
+    if Pframe > 0.3f32 {
+        rate = (0.03f64 * Pframe as std::ffi::c_double * Pframe as std::ffi::c_double)
+            as std::ffi::c_float;
+        (*st).loudness = (1 as std::ffi::c_int as std::ffi::c_float - rate) * (*st).loudness
+            + rate
+                * pow(
+                    (AMP_SCALE * loudness) as std::ffi::c_double,
+                    LOUDNESS_EXP as std::ffi::c_double,
+                ) as std::ffi::c_float;
+        (*st).loudness_accum = (1 as std::ffi::c_int as std::ffi::c_float - rate)
+            * (*st).loudness_accum + rate;
+        if (*st).init_max < (*st).max_gain && (*st).nb_adapt > 20 as std::ffi::c_int {
+            (*st).init_max *= 1.0f32 + 0.1f32 * Pframe * Pframe;
+        }
+    }
+    target_gain = ((AMP_SCALE * (*st).agc_level) as std::ffi::c_double
+        * pow(
+            (*st).loudness as std::ffi::c_double
+                / (1e-4f64 + (*st).loudness_accum as std::ffi::c_double),
+            (-1.0f32 / LOUDNESS_EXP) as std::ffi::c_double,
+        )) as std::ffi::c_float;
+    if Pframe as std::ffi::c_double > 0.5f64 && (*st).nb_adapt > 20 as std::ffi::c_int
+        || target_gain < (*st).agc_gain
+    {
+        if target_gain > (*st).max_increase_step * (*st).agc_gain {
+            target_gain = (*st).max_increase_step * (*st).agc_gain;
+        }
+        if target_gain < (*st).max_decrease_step * (*st).agc_gain
+            && loudness < 10 as std::ffi::c_int as std::ffi::c_float * (*st).prev_loudness
+        {
+            target_gain = (*st).max_decrease_step * (*st).agc_gain;
+        }
+        if target_gain > (*st).max_gain {
+            target_gain = (*st).max_gain;
+        }
+        if target_gain > (*st).init_max {
+            target_gain = (*st).init_max;
+        }
+        (*st).agc_gain = target_gain;
+    }
+    i = 0 as std::ffi::c_int;
+    while i < 2 as std::ffi::c_int * N {
+        *ft.offset(i as isize) *= (*st).agc_gain;
+        i += 1;
+    }
+    (*st).prev_loudness = loudness;
+}
+unsafe extern "C" fn preprocess_analysis(
+    st: *mut SpeexPreprocessState,
+    x: *mut spx_int16_t,
+) {
+    let mut i: std::ffi::c_int = 0;
+    let N: std::ffi::c_int = (*st).ps_size;
+    let N3: std::ffi::c_int = 2 as std::ffi::c_int * N - (*st).frame_size;
+    let N4: std::ffi::c_int = (*st).frame_size - N3;
+    let ps: *mut spx_word32_t = (*st).ps;
+    i = 0 as std::ffi::c_int;
+    while i < N3 {
+        *((*st).frame).offset(i as isize) = *((*st).inbuf).offset(i as isize);
+        i += 1;
+    }
+    i = 0 as std::ffi::c_int;
+    while i < (*st).frame_size {
+        *((*st).frame).offset((N3 + i) as isize) = *x.offset(i as isize) as spx_word16_t;
+        i += 1;
+    }
+    i = 0 as std::ffi::c_int;
+    while i < N3 {
+        *((*st).inbuf).offset(i as isize) = *x.offset((N4 + i) as isize) as spx_word16_t;
+        i += 1;
+    }
+    i = 0 as std::ffi::c_int;
+    while i < 2 as std::ffi::c_int * N {
+        *((*st).frame).offset(i as isize) = *((*st).frame).offset(i as isize)
+            * *((*st).window).offset(i as isize);
+        i += 1;
+    }
+    spx_fft((*st).fft_lookup, (*st).frame, (*st).ft);
+    *ps.offset(0 as std::ffi::c_int as isize) = *((*st).ft)
+        .offset(0 as std::ffi::c_int as isize)
+        * *((*st).ft).offset(0 as std::ffi::c_int as isize);
+    i = 1 as std::ffi::c_int;
+    while i < N {
+        *ps.offset(i as isize) = *((*st).ft)
+            .offset((2 as std::ffi::c_int * i - 1 as std::ffi::c_int) as isize)
+            * *((*st).ft)
+                .offset((2 as std::ffi::c_int * i - 1 as std::ffi::c_int) as isize)
+            + *((*st).ft).offset((2 as std::ffi::c_int * i) as isize)
+                * *((*st).ft).offset((2 as std::ffi::c_int * i) as isize);
+        i += 1;
+    }
+    filterbank_compute_bank32((*st).bank, ps, ps.offset(N as isize));
+}
+unsafe extern "C" fn update_noise_prob(st: *mut SpeexPreprocessState) {
+    let mut i: std::ffi::c_int = 0;
+    let mut min_range: std::ffi::c_int = 0;
+    let N: std::ffi::c_int = (*st).ps_size;
+    i = 1 as std::ffi::c_int;
+    while i < N - 1 as std::ffi::c_int {
+        *((*st).S).offset(i as isize) = 0.8f32 * *((*st).S).offset(i as isize)
+            + 0.05f32 * *((*st).ps).offset((i - 1 as std::ffi::c_int) as isize)
+            + 0.1f32 * *((*st).ps).offset(i as isize)
+            + 0.05f32 * *((*st).ps).offset((i + 1 as std::ffi::c_int) as isize);
+        i += 1;
+    }
+    *((*st).S).offset(0 as std::ffi::c_int as isize) = 0.8f32
+        * *((*st).S).offset(0 as std::ffi::c_int as isize)
+        + 0.2f32 * *((*st).ps).offset(0 as std::ffi::c_int as isize);
+    *((*st).S).offset((N - 1 as std::ffi::c_int) as isize) = 0.8f32
+        * *((*st).S).offset((N - 1 as std::ffi::c_int) as isize)
+        + 0.2f32 * *((*st).ps).offset((N - 1 as std::ffi::c_int) as isize);
+    if (*st).nb_adapt == 1 as std::ffi::c_int {
+        i = 0 as std::ffi::c_int;
+        while i < N {
+            *((*st).Stmp).offset(i as isize) = 0 as std::ffi::c_int as spx_word32_t;
+            *((*st).Smin).offset(i as isize) = 0 as std::ffi::c_int as spx_word32_t;
+            i += 1;
+        }
+    }
+    if (*st).nb_adapt < 100 as std::ffi::c_int {
+        min_range = 15 as std::ffi::c_int;
+    } else if (*st).nb_adapt < 1000 as std::ffi::c_int {
+        min_range = 50 as std::ffi::c_int;
+    } else if (*st).nb_adapt < 10000 as std::ffi::c_int {
+        min_range = 150 as std::ffi::c_int;
+    } else {
+        min_range = 300 as std::ffi::c_int;
+    }
+    if (*st).min_count > min_range {
+        (*st).min_count = 0 as std::ffi::c_int;
+        i = 0 as std::ffi::c_int;
+        while i < N {
+            *((*st).Smin).offset(i as isize) = if *((*st).Stmp).offset(i as isize)
+                < *((*st).S).offset(i as isize)
+            {
+                *((*st).Stmp).offset(i as isize)
+            } else {
+                *((*st).S).offset(i as isize)
+            };
+            *((*st).Stmp).offset(i as isize) = *((*st).S).offset(i as isize);
+            i += 1;
+        }
+    } else {
+        i = 0 as std::ffi::c_int;
+        while i < N {
+            *((*st).Smin).offset(i as isize) = if *((*st).Smin).offset(i as isize)
+                < *((*st).S).offset(i as isize)
+            {
+                *((*st).Smin).offset(i as isize)
+            } else {
+                *((*st).S).offset(i as isize)
+            };
+            *((*st).Stmp).offset(i as isize) = if *((*st).Stmp).offset(i as isize)
+                < *((*st).S).offset(i as isize)
+            {
+                *((*st).Stmp).offset(i as isize)
+            } else {
+                *((*st).S).offset(i as isize)
+            };
+            i += 1;
+        }
+    }
+    i = 0 as std::ffi::c_int;
+    while i < N {
+        if 0.4f32 * *((*st).S).offset(i as isize) > *((*st).Smin).offset(i as isize) {
+            *((*st).update_prob).offset(i as isize) = 1 as std::ffi::c_int;
+        } else {
+            *((*st).update_prob).offset(i as isize) = 0 as std::ffi::c_int;
+        }
+        i += 1;
+    }
+}
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn speex_preprocess(
+    st: *mut SpeexPreprocessState,
+    x: *mut spx_int16_t,
+    echo: *mut spx_int32_t,
+) -> std::ffi::c_int {
+    return speex_preprocess_run(st, x);
+}
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn speex_preprocess_run(
+    st: *mut SpeexPreprocessState,
+    x: *mut spx_int16_t,
+) -> std::ffi::c_int {
+    let mut i: std::ffi::c_int = 0;
+    let mut M: std::ffi::c_int = 0;
+    let N: std::ffi::c_int = (*st).ps_size;
+    let N3: std::ffi::c_int = 2 as std::ffi::c_int * N - (*st).frame_size;
+    let N4: std::ffi::c_int = (*st).frame_size - N3;
+    let ps: *mut spx_word32_t = (*st).ps;
+    let mut Zframe: spx_word32_t = 0.;
+    let mut Pframe: spx_word16_t = 0.;
+    let mut beta: spx_word16_t = 0.;
+    let mut beta_1: spx_word16_t = 0.;
+    let mut effective_echo_suppress: spx_word16_t = 0.;
+    (*st).nb_adapt += 1;
+    if (*st).nb_adapt > 20000 as std::ffi::c_int {
+        (*st).nb_adapt = 20000 as std::ffi::c_int;
+    }
+    (*st).min_count += 1;
+    beta = if 0.03f32 > 1.0f32 / (*st).nb_adapt as spx_word16_t {
+        0.03f32
+    } else {
+        1.0f32 / (*st).nb_adapt as spx_word16_t
+    };
+    beta_1 = 1.0f32 - beta;
+    M = (*st).nbands;
+    if !((*st).echo_state).is_null() || (*st).residual_echo_set != 0 {
+        if !((*st).echo_state).is_null() {
+            speex_echo_get_residual((*st).echo_state, (*st).residual_echo, N);
+        }
+        (*st).residual_echo_set = 0 as std::ffi::c_int;
+        if !(*((*st).residual_echo).offset(0 as std::ffi::c_int as isize)
+            >= 0 as std::ffi::c_int as spx_word32_t
+            && *((*st).residual_echo).offset(0 as std::ffi::c_int as isize)
+                < N as std::ffi::c_float * 1e9f32)
+        {
+            i = 0 as std::ffi::c_int;
+            while i < N {
+                *((*st).residual_echo).offset(i as isize) = 0 as std::ffi::c_int
+                    as spx_word32_t;
+                i += 1;
+            }
+        }
+        i = 0 as std::ffi::c_int;
+        while i < N {
+            *((*st).echo_noise).offset(i as isize) = if 0.6f32
+                * *((*st).echo_noise).offset(i as isize)
+                > *((*st).residual_echo).offset(i as isize)
+            {
+                0.6f32 * *((*st).echo_noise).offset(i as isize)
+            } else {
+                *((*st).residual_echo).offset(i as isize)
+            };
+            i += 1;
+        }
+        filterbank_compute_bank32(
+            (*st).bank,
+            (*st).echo_noise,
+            ((*st).echo_noise).offset(N as isize),
+        );
+    } else {
+        i = 0 as std::ffi::c_int;
+        while i < N + M {
+            *((*st).echo_noise).offset(i as isize) = 0 as std::ffi::c_int as spx_word32_t;
+            i += 1;
+        }
+    }
+    preprocess_analysis(st, x);
+    update_noise_prob(st);
+    i = 0 as std::ffi::c_int;
+    while i < N {
+        if *((*st).update_prob).offset(i as isize) == 0
+            || *((*st).ps).offset(i as isize) < *((*st).noise).offset(i as isize)
+        {
+            let upd: spx_word32_t = beta_1 * *((*st).noise).offset(i as isize)
+                + beta * *((*st).ps).offset(i as isize);
+            *((*st).noise).offset(i as isize) = if 0 as std::ffi::c_int as spx_word32_t > upd {
+                0 as std::ffi::c_int as spx_word32_t
+            } else {
+                upd
+            };
+        }
+        i += 1;
+    }
+    filterbank_compute_bank32((*st).bank, (*st).noise, ((*st).noise).offset(N as isize));
+    if (*st).nb_adapt == 1 as std::ffi::c_int {
+        i = 0 as std::ffi::c_int;
+        while i < N + M {
+            *((*st).old_ps).offset(i as isize) = *ps.offset(i as isize);
+            i += 1;
+        }
+    }
+    i = 0 as std::ffi::c_int;
+    while i < N + M {
+        let mut gamma: spx_word16_t = 0.;
+        let tot_noise: spx_word32_t = 1 as std::ffi::c_int as spx_word32_t
+            + *((*st).noise).offset(i as isize)
+            + *((*st).echo_noise).offset(i as isize)
+            + *((*st).reverb_estimate).offset(i as isize);
+        *((*st).post).offset(i as isize) = *ps.offset(i as isize) / tot_noise - 1.0f32;
+        *((*st).post).offset(i as isize) = if *((*st).post).offset(i as isize) < 100.0f32 {
+            *((*st).post).offset(i as isize)
+        } else {
+            100.0f32
+        };
+        let ratio: spx_word16_t = *((*st).old_ps).offset(i as isize)
+            / (*((*st).old_ps).offset(i as isize) + tot_noise);
+        gamma = 0.1f32 + 0.89f32 * (ratio * ratio);
+        *((*st).prior).offset(i as isize) = gamma
+            * (if 0 as std::ffi::c_int as spx_word16_t > *((*st).post).offset(i as isize) {
+                0 as std::ffi::c_int as spx_word16_t
+            } else {
+                *((*st).post).offset(i as isize)
+            })
+            + (1.0f32 - gamma) * (*((*st).old_ps).offset(i as isize) / tot_noise);
+        *((*st).prior).offset(i as isize) = if *((*st).prior).offset(i as isize) < 100.0f32 {
+            *((*st).prior).offset(i as isize)
+        } else {
+            100.0f32
+        };
+        i += 1;
+    }
+    *((*st).zeta).offset(0 as std::ffi::c_int as isize) = 0.7f32
+        * *((*st).zeta).offset(0 as std::ffi::c_int as isize)
+        + 0.3f32 * *((*st).prior).offset(0 as std::ffi::c_int as isize);
+    i = 1 as std::ffi::c_int;
+    while i < N - 1 as std::ffi::c_int {
+        *((*st).zeta).offset(i as isize) = 0.7f32 * *((*st).zeta).offset(i as isize)
+            + 0.15f32 * *((*st).prior).offset(i as isize)
+            + 0.075f32 * *((*st).prior).offset((i - 1 as std::ffi::c_int) as isize)
+            + 0.075f32 * *((*st).prior).offset((i + 1 as std::ffi::c_int) as isize);
+        i += 1;
+    }
+    i = N - 1 as std::ffi::c_int;
+    while i < N + M {
+        *((*st).zeta).offset(i as isize) = 0.7f32 * *((*st).zeta).offset(i as isize)
+            + 0.3f32 * *((*st).prior).offset(i as isize);
+        i += 1;
+    }
+    Zframe = 0 as std::ffi::c_int as spx_word32_t;
+    i = N;
+    while i < N + M {
+        Zframe = Zframe + *((*st).zeta).offset(i as isize);
+        i += 1;
+    }
+    Pframe = 0.1f32 + 0.899f32 * qcurve(Zframe / (*st).nbands as spx_word32_t);
+    effective_echo_suppress = (1.0f32 - Pframe) * (*st).echo_suppress as spx_word16_t
+        + Pframe * (*st).echo_suppress_active as spx_word16_t;
+    compute_gain_floor(
+        (*st).noise_suppress,
+        effective_echo_suppress as std::ffi::c_int,
+        ((*st).noise).offset(N as isize),
+        ((*st).echo_noise).offset(N as isize),
+        ((*st).gain_floor).offset(N as isize),
+        M,
+    );
+    i = N;
+    while i < N + M {
+        let mut theta: spx_word32_t = 0.;
+        let mut MM: spx_word32_t = 0.;
+        let mut prior_ratio: spx_word16_t = 0.;
+        let mut P1: spx_word16_t = 0.;
+        let mut q: spx_word16_t = 0.;
+        prior_ratio = *((*st).prior).offset(i as isize)
+            / (*((*st).prior).offset(i as isize) + 1 as std::ffi::c_int as spx_word16_t);
+        theta = prior_ratio * (1.0f32 + *((*st).post).offset(i as isize));
+        MM = hypergeom_gain(theta);
+        *((*st).gain).offset(i as isize) = if 1.0f32 < prior_ratio * MM {
+            1.0f32
+        } else {
+            prior_ratio * MM
+        };
+        *((*st).old_ps).offset(i as isize) = 0.2f32 * *((*st).old_ps).offset(i as isize)
+            + 0.8f32 * (*((*st).gain).offset(i as isize) * *((*st).gain).offset(i as isize))
+                * *ps.offset(i as isize);
+        P1 = 0.199f32 + 0.8f32 * qcurve(*((*st).zeta).offset(i as isize));
+        q = 1.0f32 - Pframe * P1;
+        *((*st).gain2).offset(i as isize) = (1 as std::ffi::c_int as std::ffi::c_double
+            / (1.0f32 as std::ffi::c_double
+                + (q / (1.0f32 - q)) as std::ffi::c_double
+                    * (1 as std::ffi::c_int as spx_word16_t
+                        + *((*st).prior).offset(i as isize)) as std::ffi::c_double
+                    * exp(-theta as std::ffi::c_double))) as spx_word16_t;
+        i += 1;
+    }
+    filterbank_compute_psd16(
+        (*st).bank,
+        ((*st).gain2).offset(N as isize),
+        (*st).gain2,
+    );
+    filterbank_compute_psd16((*st).bank, ((*st).gain).offset(N as isize), (*st).gain);
+    filterbank_compute_psd16(
+        (*st).bank,
+        ((*st).gain_floor).offset(N as isize),
+        (*st).gain_floor,
+    );
+    i = 0 as std::ffi::c_int;
+    while i < N {
+        let mut MM_0: spx_word32_t = 0.;
+        let mut theta_0: spx_word32_t = 0.;
+        let mut prior_ratio_0: spx_word16_t = 0.;
+        let mut tmp: spx_word16_t = 0.;
+        let mut p: spx_word16_t = 0.;
+        let mut g: spx_word16_t = 0.;
+        prior_ratio_0 = *((*st).prior).offset(i as isize)
+            / (*((*st).prior).offset(i as isize) + 1 as std::ffi::c_int as spx_word16_t);
+        theta_0 = prior_ratio_0 * (1.0f32 + *((*st).post).offset(i as isize));
+        MM_0 = hypergeom_gain(theta_0);
+        g = if 1.0f32 < prior_ratio_0 * MM_0 { 1.0f32 } else { prior_ratio_0 * MM_0 };
+        p = *((*st).gain2).offset(i as isize);
+        if 0.333f32 * g > *((*st).gain).offset(i as isize) {
+            g = 3 as std::ffi::c_int as spx_word16_t * *((*st).gain).offset(i as isize);
+        }
+        *((*st).gain).offset(i as isize) = g;
+        *((*st).old_ps).offset(i as isize) = 0.2f32 * *((*st).old_ps).offset(i as isize)
+            + 0.8f32 * (*((*st).gain).offset(i as isize) * *((*st).gain).offset(i as isize))
+                * *ps.offset(i as isize);
+        if *((*st).gain).offset(i as isize) < *((*st).gain_floor).offset(i as isize) {
+            *((*st).gain).offset(i as isize) = *((*st).gain_floor).offset(i as isize);
+        }
+        tmp = p * sqrt(*((*st).gain).offset(i as isize) as std::ffi::c_double)
+            as spx_word16_t
+            + (1.0f32 - p)
+                * sqrt(*((*st).gain_floor).offset(i as isize) as std::ffi::c_double)
+                    as spx_word16_t;
+        *((*st).gain2).offset(i as isize) = tmp * tmp;
+        i += 1;
+    }
+    if (*st).denoise_enabled == 0 {
+        i = 0 as std::ffi::c_int;
+        while i < N + M {
+            *((*st).gain2).offset(i as isize) = 1.0f32;
+            i += 1;
+        }
+    }
+    i = 1 as std::ffi::c_int;
+    while i < N {
+        *((*st).ft).offset((2 as std::ffi::c_int * i - 1 as std::ffi::c_int) as isize) = *((*st)
+            .gain2)
+            .offset(i as isize)
+            * *((*st).ft).offset((2 as std::ffi::c_int * i - 1 as std::ffi::c_int) as isize);
+        *((*st).ft).offset((2 as std::ffi::c_int * i) as isize) = *((*st).gain2)
+            .offset(i as isize) * *((*st).ft).offset((2 as std::ffi::c_int * i) as isize);
+        i += 1;
+    }
+    *((*st).ft).offset(0 as std::ffi::c_int as isize) = *((*st).gain2)
+        .offset(0 as std::ffi::c_int as isize)
+        * *((*st).ft).offset(0 as std::ffi::c_int as isize);
+    *((*st).ft).offset((2 as std::ffi::c_int * N - 1 as std::ffi::c_int) as isize) = *((*st)
+        .gain2)
+        .offset((N - 1 as std::ffi::c_int) as isize)
+        * *((*st).ft).offset((2 as std::ffi::c_int * N - 1 as std::ffi::c_int) as isize);
+    if (*st).agc_enabled != 0 {
+        speex_compute_agc(st, Pframe, (*st).ft);
+    }
+    spx_ifft((*st).fft_lookup, (*st).ft, (*st).frame);
+    if (*st).agc_enabled != 0 {
+        let mut max_sample: std::ffi::c_float = 0 as std::ffi::c_int as std::ffi::c_float;
+        i = 0 as std::ffi::c_int;
+        while i < 2 as std::ffi::c_int * N {
+            if fabs(*((*st).frame).offset(i as isize) as std::ffi::c_double)
+                > max_sample as std::ffi::c_double
+            {
+                max_sample = fabs(*((*st).frame).offset(i as isize) as std::ffi::c_double)
+                    as std::ffi::c_float;
+            }
+            i += 1;
+        }
+        if max_sample > 28000.0f32 {
+            let damp: std::ffi::c_float = 28000.0f32 / max_sample;
+            i = 0 as std::ffi::c_int;
+            while i < 2 as std::ffi::c_int * N {
+                *((*st).frame).offset(i as isize) *= damp;
+                i += 1;
+            }
+        }
+    }
+    i = 0 as std::ffi::c_int;
+    while i < 2 as std::ffi::c_int * N {
+        *((*st).frame).offset(i as isize) = *((*st).frame).offset(i as isize)
+            * *((*st).window).offset(i as isize);
+        i += 1;
+    }
+    i = 0 as std::ffi::c_int;
+    while i < N3 {
+        *x.offset(i as isize) = WORD2INT(
+            *((*st).outbuf).offset(i as isize) + *((*st).frame).offset(i as isize),
+        );
+        i += 1;
+    }
+    i = 0 as std::ffi::c_int;
+    while i < N4 {
+        *x.offset((N3 + i) as isize) = WORD2INT(*((*st).frame).offset((N3 + i) as isize));
+        i += 1;
+    }
+    i = 0 as std::ffi::c_int;
+    while i < N3 {
+        *((*st).outbuf).offset(i as isize) = *((*st).frame)
+            .offset(((*st).frame_size + i) as isize);
+        i += 1;
+    }
+    (*st).speech_prob = Pframe;
+    if (*st).vad_enabled != 0 {
+        if (*st).speech_prob > (*st).speech_prob_start
+            || (*st).was_speech != 0 && (*st).speech_prob > (*st).speech_prob_continue
+        {
+            (*st).was_speech = 1 as std::ffi::c_int;
+            return 1 as std::ffi::c_int;
+        } else {
+            (*st).was_speech = 0 as std::ffi::c_int;
+            return 0 as std::ffi::c_int;
+        }
+    } else {
+        return 1 as std::ffi::c_int;
+    };
+}
+#[inline]
+unsafe extern "C" fn WORD2INT(x: spx_word32_t) -> spx_int16_t {
+    return (if (x as std::ffi::c_double) < -32767.5f64 {
+        -(32768 as std::ffi::c_int)
+    } else if x as std::ffi::c_double > 32766.5f64 {
+        32767 as std::ffi::c_int
+    } else {
+        floor(0.5f64 + x as std::ffi::c_double) as spx_int16_t as std::ffi::c_int
+    }) as spx_int16_t;
+}
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn speex_preprocess_estimate_update(
+    st: *mut SpeexPreprocessState,
+    x: *mut spx_int16_t,
+) {
+    let mut i: std::ffi::c_int = 0;
+    let N: std::ffi::c_int = (*st).ps_size;
+    let N3: std::ffi::c_int = 2 as std::ffi::c_int * N - (*st).frame_size;
+    let mut M: std::ffi::c_int = 0;
+    let ps: *mut spx_word32_t = (*st).ps;
+    M = (*st).nbands;
+    (*st).min_count += 1;
+    preprocess_analysis(st, x);
+    update_noise_prob(st);
+    i = 1 as std::ffi::c_int;
+    while i < N - 1 as std::ffi::c_int {
+        if *((*st).update_prob).offset(i as isize) == 0
+            || *((*st).ps).offset(i as isize) < *((*st).noise).offset(i as isize)
+        {
+            *((*st).noise).offset(i as isize) = 0.95f32 * *((*st).noise).offset(i as isize)
+                + 0.05f32 * *((*st).ps).offset(i as isize);
+        }
+        i += 1;
+    }
+    i = 0 as std::ffi::c_int;
+    while i < N3 {
+        *((*st).outbuf).offset(i as isize) = *x
+            .offset(((*st).frame_size - N3 + i) as isize) as spx_word16_t
+            * *((*st).window).offset(((*st).frame_size + i) as isize);
+        i += 1;
+    }
+    i = 0 as std::ffi::c_int;
+    while i < N + M {
+        *((*st).old_ps).offset(i as isize) = *ps.offset(i as isize);
+        i += 1;
+    }
+    i = 0 as std::ffi::c_int;
+    while i < N {
+        *((*st).reverb_estimate).offset(i as isize) = (*st).reverb_decay
+            * *((*st).reverb_estimate).offset(i as isize);
+        i += 1;
+    }
+}
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn speex_preprocess_ctl(
+    state: *mut SpeexPreprocessState,
+    request: std::ffi::c_int,
+    ptr: *mut std::ffi::c_void,
+) -> std::ffi::c_int {
+    let mut i: std::ffi::c_int = 0;
+    let st: *mut SpeexPreprocessState = state;
+    match request {
+        SPEEX_PREPROCESS_SET_DENOISE => {
+            (*st).denoise_enabled = *(ptr as *mut spx_int32_t) as std::ffi::c_int;
+        }
+        SPEEX_PREPROCESS_GET_DENOISE => {
+            *(ptr as *mut spx_int32_t) = (*st).denoise_enabled as spx_int32_t;
+        }
+        SPEEX_PREPROCESS_SET_AGC => {
+            (*st).agc_enabled = *(ptr as *mut spx_int32_t) as std::ffi::c_int;
+        }
+        SPEEX_PREPROCESS_GET_AGC => {
+            *(ptr as *mut spx_int32_t) = (*st).agc_enabled as spx_int32_t;
+        }
+        SPEEX_PREPROCESS_SET_AGC_LEVEL => {
+            (*st).agc_level = *(ptr as *mut std::ffi::c_float);
+            if (*st).agc_level < 1 as std::ffi::c_int as std::ffi::c_float {
+                (*st).agc_level = 1 as std::ffi::c_int as std::ffi::c_float;
+            }
+            if (*st).agc_level > 32768 as std::ffi::c_int as std::ffi::c_float {
+                (*st).agc_level = 32768 as std::ffi::c_int as std::ffi::c_float;
+            }
+        }
+        SPEEX_PREPROCESS_GET_AGC_LEVEL => {
+            *(ptr as *mut std::ffi::c_float) = (*st).agc_level;
+        }
+        SPEEX_PREPROCESS_SET_AGC_INCREMENT => {
+            (*st).max_increase_step = exp(
+                (0.11513f32 * *(ptr as *mut spx_int32_t) as std::ffi::c_float
+                    * (*st).frame_size as std::ffi::c_float
+                    / (*st).sampling_rate as std::ffi::c_float) as std::ffi::c_double,
+            ) as std::ffi::c_float;
+        }
+        SPEEX_PREPROCESS_GET_AGC_INCREMENT => {
+            *(ptr as *mut spx_int32_t) = floor(
+                0.5f64
+                    + 8.6858f64 * log((*st).max_increase_step as std::ffi::c_double)
+                        * (*st).sampling_rate as std::ffi::c_double
+                        / (*st).frame_size as std::ffi::c_double,
+            ) as spx_int32_t;
+        }
+        SPEEX_PREPROCESS_SET_AGC_DECREMENT => {
+            (*st).max_decrease_step = exp(
+                (0.11513f32 * *(ptr as *mut spx_int32_t) as std::ffi::c_float
+                    * (*st).frame_size as std::ffi::c_float
+                    / (*st).sampling_rate as std::ffi::c_float) as std::ffi::c_double,
+            ) as std::ffi::c_float;
+        }
+        SPEEX_PREPROCESS_GET_AGC_DECREMENT => {
+            *(ptr as *mut spx_int32_t) = floor(
+                0.5f64
+                    + 8.6858f64 * log((*st).max_decrease_step as std::ffi::c_double)
+                        * (*st).sampling_rate as std::ffi::c_double
+                        / (*st).frame_size as std::ffi::c_double,
+            ) as spx_int32_t;
+        }
+        SPEEX_PREPROCESS_SET_AGC_MAX_GAIN => {
+            (*st).max_gain = exp(
+                (0.11513f32 * *(ptr as *mut spx_int32_t) as std::ffi::c_float)
+                    as std::ffi::c_double,
+            ) as std::ffi::c_float;
+        }
+        SPEEX_PREPROCESS_GET_AGC_MAX_GAIN => {
+            *(ptr as *mut spx_int32_t) = floor(
+                0.5f64 + 8.6858f64 * log((*st).max_gain as std::ffi::c_double),
+            ) as spx_int32_t;
+        }
+        SPEEX_PREPROCESS_SET_VAD => {
+            speex_warning(
+                b"The VAD has been replaced by a hack pending a complete rewrite\0"
+                    as *const u8 as *const std::ffi::c_char,
+            );
+            (*st).vad_enabled = *(ptr as *mut spx_int32_t) as std::ffi::c_int;
+        }
+        SPEEX_PREPROCESS_GET_VAD => {
+            *(ptr as *mut spx_int32_t) = (*st).vad_enabled as spx_int32_t;
+        }
+        SPEEX_PREPROCESS_SET_DEREVERB => {
+            (*st).dereverb_enabled = *(ptr as *mut spx_int32_t) as std::ffi::c_int;
+            i = 0 as std::ffi::c_int;
+            while i < (*st).ps_size {
+                *((*st).reverb_estimate).offset(i as isize) = 0 as std::ffi::c_int
+                    as spx_word32_t;
+                i += 1;
+            }
+        }
+        SPEEX_PREPROCESS_GET_DEREVERB => {
+            *(ptr as *mut spx_int32_t) = (*st).dereverb_enabled as spx_int32_t;
+        }
+        SPEEX_PREPROCESS_SET_DEREVERB_LEVEL => {
+            (*st).reverb_level = *(ptr as *mut std::ffi::c_float);
+        }
+        SPEEX_PREPROCESS_GET_DEREVERB_LEVEL => {
+            *(ptr as *mut std::ffi::c_float) = (*st).reverb_level;
+        }
+        SPEEX_PREPROCESS_SET_DEREVERB_DECAY => {
+            (*st).reverb_decay = *(ptr as *mut std::ffi::c_float);
+        }
+        SPEEX_PREPROCESS_GET_DEREVERB_DECAY => {
+            *(ptr as *mut std::ffi::c_float) = (*st).reverb_decay;
+        }
+        SPEEX_PREPROCESS_SET_PROB_START => {
+            *(ptr as *mut spx_int32_t) = if (100 as std::ffi::c_int)
+                < (if 0 as std::ffi::c_int > *(ptr as *mut spx_int32_t) {
+                    0 as std::ffi::c_int
+                } else {
+                    *(ptr as *mut spx_int32_t)
+                })
+            {
+                100 as std::ffi::c_int
+            } else if 0 as std::ffi::c_int > *(ptr as *mut spx_int32_t) {
+                0 as std::ffi::c_int
+            } else {
+                *(ptr as *mut spx_int32_t)
+            };
+            (*st).speech_prob_start = 1.0f32 * *(ptr as *mut spx_int32_t) as spx_word16_t
+                / 100 as std::ffi::c_int as spx_word16_t;
+        }
+        SPEEX_PREPROCESS_GET_PROB_START => {
+            *(ptr as *mut spx_int32_t) = ((*st).speech_prob_start
+                * 100 as std::ffi::c_int as spx_word16_t) as spx_int32_t;
+        }
+        SPEEX_PREPROCESS_SET_PROB_CONTINUE => {
+            *(ptr as *mut spx_int32_t) = if (100 as std::ffi::c_int)
+                < (if 0 as std::ffi::c_int > *(ptr as *mut spx_int32_t) {
+                    0 as std::ffi::c_int
+                } else {
+                    *(ptr as *mut spx_int32_t)
+                })
+            {
+                100 as std::ffi::c_int
+            } else if 0 as std::ffi::c_int > *(ptr as *mut spx_int32_t) {
+                0 as std::ffi::c_int
+            } else {
+                *(ptr as *mut spx_int32_t)
+            };
+            (*st).speech_prob_continue = 1.0f32 * *(ptr as *mut spx_int32_t)
+                as spx_word16_t / 100 as std::ffi::c_int as spx_word16_t;
+        }
+        SPEEX_PREPROCESS_GET_PROB_CONTINUE => {
+            *(ptr as *mut spx_int32_t) = ((*st).speech_prob_continue
+                * 100 as std::ffi::c_int as spx_word16_t) as spx_int32_t;
+        }
+        SPEEX_PREPROCESS_SET_NOISE_SUPPRESS => {
+            (*st).noise_suppress = -(*(ptr as *mut spx_int32_t)).abs();
+        }
+        SPEEX_PREPROCESS_GET_NOISE_SUPPRESS => {
+            *(ptr as *mut spx_int32_t) = (*st).noise_suppress as spx_int32_t;
+        }
+        SPEEX_PREPROCESS_SET_ECHO_SUPPRESS => {
+            (*st).echo_suppress = -(*(ptr as *mut spx_int32_t)).abs();
+        }
+        SPEEX_PREPROCESS_GET_ECHO_SUPPRESS => {
+            *(ptr as *mut spx_int32_t) = (*st).echo_suppress as spx_int32_t;
+        }
+        SPEEX_PREPROCESS_SET_ECHO_SUPPRESS_ACTIVE => {
+            (*st).echo_suppress_active = -(*(ptr as *mut spx_int32_t)).abs();
+        }
+        SPEEX_PREPROCESS_GET_ECHO_SUPPRESS_ACTIVE => {
+            *(ptr as *mut spx_int32_t) = (*st).echo_suppress_active as spx_int32_t;
+        }
+        SPEEX_PREPROCESS_SET_ECHO_STATE => {
+            (*st).echo_state = ptr as *mut SpeexEchoState;
+        }
+        SPEEX_PREPROCESS_SET_RESIDUAL_ECHO => {
+            i = 0 as std::ffi::c_int;
+            while i < (*st).ps_size {
+                *((*st).residual_echo).offset(i as isize) = *(ptr as *mut spx_word32_t)
+                    .offset(i as isize);
+                i += 1;
+            }
+            (*st).residual_echo_set = 1 as std::ffi::c_int;
+        }
+        SPEEX_PREPROCESS_GET_ECHO_STATE => {
+            let ref mut fresh0 = *(ptr as *mut *mut SpeexEchoState);
+            *fresh0 = (*st).echo_state;
+        }
+        SPEEX_PREPROCESS_GET_AGC_LOUDNESS => {
+            *(ptr as *mut spx_int32_t) = pow(
+                (*st).loudness as std::ffi::c_double,
+                1.0f64 / LOUDNESS_EXP as std::ffi::c_double,
+            ) as spx_int32_t;
+        }
+        SPEEX_PREPROCESS_GET_AGC_GAIN => {
+            *(ptr as *mut spx_int32_t) = floor(
+                0.5f64 + 8.6858f64 * log((*st).agc_gain as std::ffi::c_double),
+            ) as spx_int32_t;
+        }
+        SPEEX_PREPROCESS_GET_PROB => {
+            *(ptr as *mut spx_int32_t) = ((*st).speech_prob
+                * 100 as std::ffi::c_int as spx_word16_t) as spx_int32_t;
+        }
+        SPEEX_PREPROCESS_SET_AGC_TARGET => {
+            (*st).agc_level = *(ptr as *mut spx_int32_t) as std::ffi::c_float;
+            if (*st).agc_level < 1 as std::ffi::c_int as std::ffi::c_float {
+                (*st).agc_level = 1 as std::ffi::c_int as std::ffi::c_float;
+            }
+            if (*st).agc_level > 32768 as std::ffi::c_int as std::ffi::c_float {
+                (*st).agc_level = 32768 as std::ffi::c_int as std::ffi::c_float;
+            }
+        }
+        SPEEX_PREPROCESS_GET_AGC_TARGET => {
+            *(ptr as *mut spx_int32_t) = (*st).agc_level as spx_int32_t;
+        }
+        _ => {
+            speex_warning_int(
+                b"Unknown speex_preprocess_ctl request: \0" as *const u8
+                    as *const std::ffi::c_char,
+                request,
+            );
+            return -(1 as std::ffi::c_int);
+        }
+    }
+    return 0 as std::ffi::c_int;
+}
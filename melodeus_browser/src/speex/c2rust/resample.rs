@@ -8,6 +8,16 @@
 #![allow(unsafe_op_in_unsafe_fn)]
 
 use crate::speex::c2rust::alloc;
+#[cfg(feature = "fixed-point")]
+use crate::speex::fixed::{
+    mult16_16, mult16_16_p15, mult16_32_q15, pdiv32, pshr32, qconst16, saturate32pshr, shl32,
+    shr32,
+};
+#[cfg(not(feature = "fixed-point"))]
+use crate::speex::c2rust::resample_simd::{
+    inner_product_single, interpolate_product_single, OVERRIDE_INNER_PRODUCT_SINGLE,
+    OVERRIDE_INTERPOLATE_PRODUCT_SINGLE,
+};
 
 #[inline]
 unsafe fn calloc(__nmemb: size_t, __size: size_t) -> *mut std::ffi::c_void {
@@ -93,10 +103,16 @@ pub type resampler_basic_func = Option<
         *mut spx_uint32_t,
     ) -> std::ffi::c_int,
 >;
+#[cfg(not(feature = "fixed-point"))]
 pub type spx_word16_t = std::ffi::c_float;
+#[cfg(feature = "fixed-point")]
+pub type spx_word16_t = spx_int16_t;
 pub type SpeexResamplerState = SpeexResamplerState_;
 pub type size_t = usize;
+#[cfg(not(feature = "fixed-point"))]
 pub type spx_word32_t = std::ffi::c_float;
+#[cfg(feature = "fixed-point")]
+pub type spx_word32_t = spx_int32_t;
 #[derive(Copy, Clone)]
 #[repr(C)]
 pub struct FuncDef {
@@ -498,6 +514,7 @@ unsafe extern "C" fn compute_func(
         + interp[3 as std::ffi::c_int as usize]
             * *((*func).table).offset((ind + 3 as std::ffi::c_int) as isize);
 }
+#[cfg(not(feature = "fixed-point"))]
 unsafe extern "C" fn sinc(
     mut cutoff: std::ffi::c_float,
     mut x: std::ffi::c_float,
@@ -517,6 +534,40 @@ unsafe extern "C" fn sinc(
             window_func,
         )) as spx_word16_t;
 }
+#[cfg(feature = "fixed-point")]
+fn WORD2INT(x: std::ffi::c_double) -> spx_word16_t {
+    return (if x < -32767.5f64 {
+        -(32768 as std::ffi::c_int)
+    } else if x > 32766.5f64 {
+        32767 as std::ffi::c_int
+    } else {
+        floor(0.5f64 + x) as spx_int16_t as std::ffi::c_int
+    }) as spx_word16_t;
+}
+#[cfg(feature = "fixed-point")]
+unsafe extern "C" fn sinc(
+    mut cutoff: std::ffi::c_float,
+    mut x: std::ffi::c_float,
+    mut N: std::ffi::c_int,
+    mut window_func: *const FuncDef,
+) -> spx_word16_t {
+    let mut xx: std::ffi::c_float = x * cutoff;
+    if fabs(x as std::ffi::c_double) < 1e-6f64 {
+        return WORD2INT(32768.0f64 * cutoff as std::ffi::c_double);
+    } else if fabs(x as std::ffi::c_double) > 0.5f64 * N as std::ffi::c_double {
+        return 0 as std::ffi::c_int as spx_word16_t;
+    }
+    return WORD2INT(
+        32768.0f64 * cutoff as std::ffi::c_double * sin(M_PI * xx as std::ffi::c_double)
+            / (M_PI * xx as std::ffi::c_double)
+            * compute_func(
+                fabs(2.0f64 * x as std::ffi::c_double / N as std::ffi::c_double)
+                    as std::ffi::c_float,
+                window_func,
+            ),
+    );
+}
+#[cfg(not(feature = "fixed-point"))]
 unsafe extern "C" fn cubic_coef(mut frac: spx_word16_t, mut interp: *mut spx_word16_t) {
     *interp.offset(0 as std::ffi::c_int as isize) =
         -0.16667f32 * frac + 0.16667f32 * frac * frac * frac;
@@ -530,6 +581,36 @@ unsafe extern "C" fn cubic_coef(mut frac: spx_word16_t, mut interp: *mut spx_wor
         - *interp.offset(3 as std::ffi::c_int as isize) as std::ffi::c_double)
         as spx_word16_t;
 }
+#[cfg(feature = "fixed-point")]
+unsafe extern "C" fn cubic_coef(mut x: spx_word16_t, mut interp: *mut spx_word16_t) {
+    let mut x2: spx_word16_t = 0;
+    let mut x3: spx_word16_t = 0;
+    x2 = mult16_16_p15(x, x) as spx_word16_t;
+    x3 = mult16_16_p15(x, x2) as spx_word16_t;
+    *interp.offset(0 as std::ffi::c_int as isize) = pshr32(
+        mult16_16(qconst16(-0.16667f64, 15), x)
+            .wrapping_add(mult16_16(qconst16(0.16667f64, 15), x3)),
+        15,
+    ) as spx_word16_t;
+    *interp.offset(1 as std::ffi::c_int as isize) =
+        (x as spx_word32_t + shr32(x2 as spx_word32_t - x3 as spx_word32_t, 1)) as spx_word16_t;
+    *interp.offset(3 as std::ffi::c_int as isize) = pshr32(
+        mult16_16(qconst16(-0.33333f64, 15), x)
+            .wrapping_add(mult16_16(qconst16(0.5f64, 15), x2))
+            .wrapping_sub(mult16_16(qconst16(0.16667f64, 15), x3)),
+        15,
+    ) as spx_word16_t;
+    *interp.offset(2 as std::ffi::c_int as isize) = (32767 as std::ffi::c_int
+        - *interp.offset(0 as std::ffi::c_int as isize) as std::ffi::c_int
+        - *interp.offset(1 as std::ffi::c_int as isize) as std::ffi::c_int
+        - *interp.offset(3 as std::ffi::c_int as isize) as std::ffi::c_int)
+        as spx_word16_t;
+    if (*interp.offset(2 as std::ffi::c_int as isize) as std::ffi::c_int) < 32767 {
+        let ref mut fresh3 = *interp.offset(2 as std::ffi::c_int as isize);
+        *fresh3 = (*fresh3 as std::ffi::c_int + 1 as std::ffi::c_int) as spx_word16_t;
+    }
+}
+#[cfg(not(feature = "fixed-point"))]
 unsafe extern "C" fn resampler_basic_direct_single(
     mut st: *mut SpeexResamplerState,
     mut channel_index: spx_uint32_t,
@@ -548,6 +629,59 @@ unsafe extern "C" fn resampler_basic_direct_single(
     let frac_advance: std::ffi::c_int = (*st).frac_advance;
     let den_rate: spx_uint32_t = (*st).den_rate;
     let mut sum: spx_word32_t = 0.;
+    while !(last_sample as spx_int32_t >= *in_len as spx_int32_t
+        || out_sample as spx_int32_t >= *out_len as spx_int32_t)
+    {
+        let mut sinct: *const spx_word16_t = &*sinc_table
+            .offset(samp_frac_num.wrapping_mul(N as spx_uint32_t) as isize)
+            as *const spx_word16_t;
+        let mut iptr: *const spx_word16_t =
+            &*in_0.offset(last_sample as isize) as *const spx_word16_t;
+        if !OVERRIDE_INNER_PRODUCT_SINGLE {
+            let mut j: std::ffi::c_int = 0;
+            sum = 0 as std::ffi::c_int as spx_word32_t;
+            j = 0 as std::ffi::c_int;
+            while j < N {
+                sum += *sinct.offset(j as isize) * *iptr.offset(j as isize);
+                j += 1;
+            }
+            sum = sum;
+        } else {
+            sum = inner_product_single(sinct, iptr, N as spx_uint32_t);
+        }
+        let fresh0 = out_sample;
+        out_sample = out_sample + 1;
+        *out.offset((out_stride * fresh0) as isize) = sum as spx_word16_t;
+        last_sample += int_advance;
+        samp_frac_num = samp_frac_num.wrapping_add(frac_advance as spx_uint32_t);
+        if samp_frac_num >= den_rate {
+            samp_frac_num = samp_frac_num.wrapping_sub(den_rate);
+            last_sample += 1;
+        }
+    }
+    *((*st).last_sample).offset(channel_index as isize) = last_sample as spx_int32_t;
+    *((*st).samp_frac_num).offset(channel_index as isize) = samp_frac_num;
+    return out_sample;
+}
+#[cfg(feature = "fixed-point")]
+unsafe extern "C" fn resampler_basic_direct_single(
+    mut st: *mut SpeexResamplerState,
+    mut channel_index: spx_uint32_t,
+    mut in_0: *const spx_word16_t,
+    mut in_len: *mut spx_uint32_t,
+    mut out: *mut spx_word16_t,
+    mut out_len: *mut spx_uint32_t,
+) -> std::ffi::c_int {
+    let N: std::ffi::c_int = (*st).filt_len as std::ffi::c_int;
+    let mut out_sample: std::ffi::c_int = 0 as std::ffi::c_int;
+    let mut last_sample: std::ffi::c_int = *((*st).last_sample).offset(channel_index as isize);
+    let mut samp_frac_num: spx_uint32_t = *((*st).samp_frac_num).offset(channel_index as isize);
+    let mut sinc_table: *const spx_word16_t = (*st).sinc_table;
+    let out_stride: std::ffi::c_int = (*st).out_stride;
+    let int_advance: std::ffi::c_int = (*st).int_advance;
+    let frac_advance: std::ffi::c_int = (*st).frac_advance;
+    let den_rate: spx_uint32_t = (*st).den_rate;
+    let mut sum: spx_word32_t = 0;
     while !(last_sample as spx_int32_t >= *in_len as spx_int32_t
         || out_sample as spx_int32_t >= *out_len as spx_int32_t)
     {
@@ -560,10 +694,13 @@ unsafe extern "C" fn resampler_basic_direct_single(
         sum = 0 as std::ffi::c_int as spx_word32_t;
         j = 0 as std::ffi::c_int;
         while j < N {
-            sum += *sinct.offset(j as isize) * *iptr.offset(j as isize);
+            sum = sum.wrapping_add(mult16_16(
+                *sinct.offset(j as isize),
+                *iptr.offset(j as isize),
+            ));
             j += 1;
         }
-        sum = sum;
+        sum = saturate32pshr(sum, 15, 32767);
         let fresh0 = out_sample;
         out_sample = out_sample + 1;
         *out.offset((out_stride * fresh0) as isize) = sum as spx_word16_t;
@@ -578,6 +715,7 @@ unsafe extern "C" fn resampler_basic_direct_single(
     *((*st).samp_frac_num).offset(channel_index as isize) = samp_frac_num;
     return out_sample;
 }
+#[cfg(not(feature = "fixed-point"))]
 unsafe extern "C" fn resampler_basic_direct_double(
     mut st: *mut SpeexResamplerState,
     mut channel_index: spx_uint32_t,
@@ -647,6 +785,7 @@ unsafe extern "C" fn resampler_basic_direct_double(
     *((*st).samp_frac_num).offset(channel_index as isize) = samp_frac_num;
     return out_sample;
 }
+#[cfg(not(feature = "fixed-point"))]
 unsafe extern "C" fn resampler_basic_interpolate_single(
     mut st: *mut SpeexResamplerState,
     mut channel_index: spx_uint32_t,
@@ -677,6 +816,125 @@ unsafe extern "C" fn resampler_basic_interpolate_single(
             .wrapping_rem((*st).den_rate) as spx_word16_t
             / (*st).den_rate as spx_word16_t;
         let mut interp: [spx_word16_t; 4] = [0.; 4];
+        if !OVERRIDE_INTERPOLATE_PRODUCT_SINGLE {
+            let mut j: std::ffi::c_int = 0;
+            let mut accum: [spx_word32_t; 4] = [
+                0 as std::ffi::c_int as spx_word32_t,
+                0 as std::ffi::c_int as spx_word32_t,
+                0 as std::ffi::c_int as spx_word32_t,
+                0 as std::ffi::c_int as spx_word32_t,
+            ];
+            j = 0 as std::ffi::c_int;
+            while j < N {
+                let curr_in: spx_word16_t = *iptr.offset(j as isize);
+                accum[0 as std::ffi::c_int as usize] += curr_in
+                    * *((*st).sinc_table).offset(
+                        (4 as spx_uint32_t)
+                            .wrapping_add(
+                                ((j + 1 as std::ffi::c_int) as spx_uint32_t)
+                                    .wrapping_mul((*st).oversample),
+                            )
+                            .wrapping_sub(offset as spx_uint32_t)
+                            .wrapping_sub(2 as spx_uint32_t) as isize,
+                    );
+                accum[1 as std::ffi::c_int as usize] += curr_in
+                    * *((*st).sinc_table).offset(
+                        (4 as spx_uint32_t)
+                            .wrapping_add(
+                                ((j + 1 as std::ffi::c_int) as spx_uint32_t)
+                                    .wrapping_mul((*st).oversample),
+                            )
+                            .wrapping_sub(offset as spx_uint32_t)
+                            .wrapping_sub(1 as spx_uint32_t) as isize,
+                    );
+                accum[2 as std::ffi::c_int as usize] += curr_in
+                    * *((*st).sinc_table).offset(
+                        (4 as spx_uint32_t)
+                            .wrapping_add(
+                                ((j + 1 as std::ffi::c_int) as spx_uint32_t)
+                                    .wrapping_mul((*st).oversample),
+                            )
+                            .wrapping_sub(offset as spx_uint32_t) as isize,
+                    );
+                accum[3 as std::ffi::c_int as usize] += curr_in
+                    * *((*st).sinc_table).offset(
+                        (4 as spx_uint32_t)
+                            .wrapping_add(
+                                ((j + 1 as std::ffi::c_int) as spx_uint32_t)
+                                    .wrapping_mul((*st).oversample),
+                            )
+                            .wrapping_sub(offset as spx_uint32_t)
+                            .wrapping_add(1 as spx_uint32_t) as isize,
+                    );
+                j += 1;
+            }
+            cubic_coef(frac, interp.as_mut_ptr());
+            sum = interp[0 as std::ffi::c_int as usize] * accum[0 as std::ffi::c_int as usize]
+                + interp[1 as std::ffi::c_int as usize] * accum[1 as std::ffi::c_int as usize]
+                + interp[2 as std::ffi::c_int as usize] * accum[2 as std::ffi::c_int as usize]
+                + interp[3 as std::ffi::c_int as usize] * accum[3 as std::ffi::c_int as usize];
+            sum = sum;
+        } else {
+            cubic_coef(frac, interp.as_mut_ptr());
+            sum = interpolate_product_single(
+                iptr,
+                ((*st).sinc_table)
+                    .offset(((*st).oversample as std::ffi::c_int + 4 - offset - 2) as isize),
+                N as spx_uint32_t,
+                (*st).oversample,
+                interp.as_ptr(),
+            );
+        }
+        let fresh2 = out_sample;
+        out_sample = out_sample + 1;
+        *out.offset((out_stride * fresh2) as isize) = sum as spx_word16_t;
+        last_sample += int_advance;
+        samp_frac_num = samp_frac_num.wrapping_add(frac_advance as spx_uint32_t);
+        if samp_frac_num >= den_rate {
+            samp_frac_num = samp_frac_num.wrapping_sub(den_rate);
+            last_sample += 1;
+        }
+    }
+    *((*st).last_sample).offset(channel_index as isize) = last_sample as spx_int32_t;
+    *((*st).samp_frac_num).offset(channel_index as isize) = samp_frac_num;
+    return out_sample;
+}
+#[cfg(feature = "fixed-point")]
+unsafe extern "C" fn resampler_basic_interpolate_single(
+    mut st: *mut SpeexResamplerState,
+    mut channel_index: spx_uint32_t,
+    mut in_0: *const spx_word16_t,
+    mut in_len: *mut spx_uint32_t,
+    mut out: *mut spx_word16_t,
+    mut out_len: *mut spx_uint32_t,
+) -> std::ffi::c_int {
+    let N: std::ffi::c_int = (*st).filt_len as std::ffi::c_int;
+    let mut out_sample: std::ffi::c_int = 0 as std::ffi::c_int;
+    let mut last_sample: std::ffi::c_int = *((*st).last_sample).offset(channel_index as isize);
+    let mut samp_frac_num: spx_uint32_t = *((*st).samp_frac_num).offset(channel_index as isize);
+    let out_stride: std::ffi::c_int = (*st).out_stride;
+    let int_advance: std::ffi::c_int = (*st).int_advance;
+    let frac_advance: std::ffi::c_int = (*st).frac_advance;
+    let den_rate: spx_uint32_t = (*st).den_rate;
+    let mut sum: spx_word32_t = 0;
+    while !(last_sample as spx_int32_t >= *in_len as spx_int32_t
+        || out_sample as spx_int32_t >= *out_len as spx_int32_t)
+    {
+        let mut iptr: *const spx_word16_t =
+            &*in_0.offset(last_sample as isize) as *const spx_word16_t;
+        let offset: std::ffi::c_int = samp_frac_num
+            .wrapping_mul((*st).oversample)
+            .wrapping_div((*st).den_rate) as std::ffi::c_int;
+        let frac: spx_word16_t = pdiv32(
+            shl32(
+                samp_frac_num
+                    .wrapping_mul((*st).oversample)
+                    .wrapping_rem((*st).den_rate) as spx_word32_t,
+                15,
+            ),
+            (*st).den_rate as spx_word32_t,
+        ) as spx_word16_t;
+        let mut interp: [spx_word16_t; 4] = [0; 4];
         let mut j: std::ffi::c_int = 0;
         let mut accum: [spx_word32_t; 4] = [
             0 as std::ffi::c_int as spx_word32_t,
@@ -687,53 +945,46 @@ unsafe extern "C" fn resampler_basic_interpolate_single(
         j = 0 as std::ffi::c_int;
         while j < N {
             let curr_in: spx_word16_t = *iptr.offset(j as isize);
-            accum[0 as std::ffi::c_int as usize] += curr_in
-                * *((*st).sinc_table).offset(
-                    (4 as spx_uint32_t)
-                        .wrapping_add(
-                            ((j + 1 as std::ffi::c_int) as spx_uint32_t)
-                                .wrapping_mul((*st).oversample),
-                        )
-                        .wrapping_sub(offset as spx_uint32_t)
-                        .wrapping_sub(2 as spx_uint32_t) as isize,
-                );
-            accum[1 as std::ffi::c_int as usize] += curr_in
-                * *((*st).sinc_table).offset(
-                    (4 as spx_uint32_t)
-                        .wrapping_add(
-                            ((j + 1 as std::ffi::c_int) as spx_uint32_t)
-                                .wrapping_mul((*st).oversample),
-                        )
-                        .wrapping_sub(offset as spx_uint32_t)
-                        .wrapping_sub(1 as spx_uint32_t) as isize,
-                );
-            accum[2 as std::ffi::c_int as usize] += curr_in
-                * *((*st).sinc_table).offset(
-                    (4 as spx_uint32_t)
-                        .wrapping_add(
-                            ((j + 1 as std::ffi::c_int) as spx_uint32_t)
-                                .wrapping_mul((*st).oversample),
-                        )
-                        .wrapping_sub(offset as spx_uint32_t) as isize,
-                );
-            accum[3 as std::ffi::c_int as usize] += curr_in
-                * *((*st).sinc_table).offset(
-                    (4 as spx_uint32_t)
-                        .wrapping_add(
-                            ((j + 1 as std::ffi::c_int) as spx_uint32_t)
-                                .wrapping_mul((*st).oversample),
-                        )
-                        .wrapping_sub(offset as spx_uint32_t)
-                        .wrapping_add(1 as spx_uint32_t) as isize,
-                );
+            let base: spx_uint32_t = (4 as spx_uint32_t)
+                .wrapping_add(((j + 1 as std::ffi::c_int) as spx_uint32_t).wrapping_mul((*st).oversample))
+                .wrapping_sub(offset as spx_uint32_t);
+            accum[0 as std::ffi::c_int as usize] = accum[0 as std::ffi::c_int as usize]
+                .wrapping_add(mult16_16(
+                    curr_in,
+                    *((*st).sinc_table).offset(base.wrapping_sub(2 as spx_uint32_t) as isize),
+                ));
+            accum[1 as std::ffi::c_int as usize] = accum[1 as std::ffi::c_int as usize]
+                .wrapping_add(mult16_16(
+                    curr_in,
+                    *((*st).sinc_table).offset(base.wrapping_sub(1 as spx_uint32_t) as isize),
+                ));
+            accum[2 as std::ffi::c_int as usize] = accum[2 as std::ffi::c_int as usize]
+                .wrapping_add(mult16_16(curr_in, *((*st).sinc_table).offset(base as isize)));
+            accum[3 as std::ffi::c_int as usize] = accum[3 as std::ffi::c_int as usize]
+                .wrapping_add(mult16_16(
+                    curr_in,
+                    *((*st).sinc_table).offset(base.wrapping_add(1 as spx_uint32_t) as isize),
+                ));
             j += 1;
         }
         cubic_coef(frac, interp.as_mut_ptr());
-        sum = interp[0 as std::ffi::c_int as usize] * accum[0 as std::ffi::c_int as usize]
-            + interp[1 as std::ffi::c_int as usize] * accum[1 as std::ffi::c_int as usize]
-            + interp[2 as std::ffi::c_int as usize] * accum[2 as std::ffi::c_int as usize]
-            + interp[3 as std::ffi::c_int as usize] * accum[3 as std::ffi::c_int as usize];
-        sum = sum;
+        sum = mult16_32_q15(
+            interp[0 as std::ffi::c_int as usize],
+            shr32(accum[0 as std::ffi::c_int as usize], 1),
+        )
+        .wrapping_add(mult16_32_q15(
+            interp[1 as std::ffi::c_int as usize],
+            shr32(accum[1 as std::ffi::c_int as usize], 1),
+        ))
+        .wrapping_add(mult16_32_q15(
+            interp[2 as std::ffi::c_int as usize],
+            shr32(accum[2 as std::ffi::c_int as usize], 1),
+        ))
+        .wrapping_add(mult16_32_q15(
+            interp[3 as std::ffi::c_int as usize],
+            shr32(accum[3 as std::ffi::c_int as usize], 1),
+        ));
+        sum = saturate32pshr(sum, 14, 32767);
         let fresh2 = out_sample;
         out_sample = out_sample + 1;
         *out.offset((out_stride * fresh2) as isize) = sum as spx_word16_t;
@@ -748,6 +999,7 @@ unsafe extern "C" fn resampler_basic_interpolate_single(
     *((*st).samp_frac_num).offset(channel_index as isize) = samp_frac_num;
     return out_sample;
 }
+#[cfg(not(feature = "fixed-point"))]
 unsafe extern "C" fn resampler_basic_interpolate_double(
     mut st: *mut SpeexResamplerState,
     mut channel_index: spx_uint32_t,
@@ -1029,6 +1281,7 @@ unsafe extern "C" fn update_filter(mut st: *mut SpeexResamplerState) -> std::ffi
                                     }
                                     i = i.wrapping_add(1);
                                 }
+                                #[cfg(not(feature = "fixed-point"))]
                                 if (*st).quality > 8 as std::ffi::c_int {
                                     (*st).resampler_ptr = Some(
                                         resampler_basic_direct_double
@@ -1058,6 +1311,22 @@ unsafe extern "C" fn update_filter(mut st: *mut SpeexResamplerState) -> std::ffi
                                     )
                                         as resampler_basic_func;
                                 }
+                                #[cfg(feature = "fixed-point")]
+                                {
+                                    (*st).resampler_ptr = Some(
+                                        resampler_basic_direct_single
+                                            as unsafe extern "C" fn(
+                                                *mut SpeexResamplerState,
+                                                spx_uint32_t,
+                                                *const spx_word16_t,
+                                                *mut spx_uint32_t,
+                                                *mut spx_word16_t,
+                                                *mut spx_uint32_t,
+                                            )
+                                                -> std::ffi::c_int,
+                                    )
+                                        as resampler_basic_func;
+                                }
                             } else {
                                 let mut i_0: spx_int32_t = 0;
                                 i_0 = -(4 as std::ffi::c_int) as spx_int32_t;
@@ -1079,6 +1348,7 @@ unsafe extern "C" fn update_filter(mut st: *mut SpeexResamplerState) -> std::ffi
                                         );
                                     i_0 += 1;
                                 }
+                                #[cfg(not(feature = "fixed-point"))]
                                 if (*st).quality > 8 as std::ffi::c_int {
                                     (*st).resampler_ptr = Some(
                                         resampler_basic_interpolate_double
@@ -1108,6 +1378,22 @@ unsafe extern "C" fn update_filter(mut st: *mut SpeexResamplerState) -> std::ffi
                                     )
                                         as resampler_basic_func;
                                 }
+                                #[cfg(feature = "fixed-point")]
+                                {
+                                    (*st).resampler_ptr = Some(
+                                        resampler_basic_interpolate_single
+                                            as unsafe extern "C" fn(
+                                                *mut SpeexResamplerState,
+                                                spx_uint32_t,
+                                                *const spx_word16_t,
+                                                *mut spx_uint32_t,
+                                                *mut spx_word16_t,
+                                                *mut spx_uint32_t,
+                                            )
+                                                -> std::ffi::c_int,
+                                    )
+                                        as resampler_basic_func;
+                                }
                             }
                             min_alloc_size = ((*st).filt_len)
                                 .wrapping_sub(1 as spx_uint32_t)
@@ -1514,6 +1800,7 @@ unsafe extern "C" fn speex_resampler_magic(
     *out = (*out).offset(out_len.wrapping_mul((*st).out_stride as spx_uint32_t) as isize);
     return out_len as std::ffi::c_int;
 }
+#[cfg(not(feature = "fixed-point"))]
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn speex_resampler_process_float(
     mut st: *mut SpeexResamplerState,
@@ -1588,6 +1875,7 @@ pub unsafe extern "C" fn speex_resampler_process_float(
         RESAMPLER_ERR_SUCCESS as std::ffi::c_int
     };
 }
+#[cfg(not(feature = "fixed-point"))]
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn speex_resampler_process_int(
     mut st: *mut SpeexResamplerState,
@@ -1693,6 +1981,180 @@ pub unsafe extern "C" fn speex_resampler_process_int(
         RESAMPLER_ERR_SUCCESS as std::ffi::c_int
     };
 }
+#[cfg(feature = "fixed-point")]
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn speex_resampler_process_float(
+    mut st: *mut SpeexResamplerState,
+    mut channel_index: spx_uint32_t,
+    mut in_0: *const std::ffi::c_float,
+    mut in_len: *mut spx_uint32_t,
+    mut out: *mut std::ffi::c_float,
+    mut out_len: *mut spx_uint32_t,
+) -> std::ffi::c_int {
+    let mut j: std::ffi::c_int = 0;
+    let istride_save: std::ffi::c_int = (*st).in_stride;
+    let ostride_save: std::ffi::c_int = (*st).out_stride;
+    let mut ilen: spx_uint32_t = *in_len;
+    let mut olen: spx_uint32_t = *out_len;
+    let mut x: *mut spx_word16_t =
+        ((*st).mem).offset(channel_index.wrapping_mul((*st).mem_alloc_size) as isize);
+    let xlen: spx_uint32_t =
+        ((*st).mem_alloc_size).wrapping_sub(((*st).filt_len).wrapping_sub(1 as spx_uint32_t));
+    let ylen: std::ffi::c_uint = FIXED_STACK_ALLOC as std::ffi::c_uint;
+    let mut ystack: [spx_word16_t; 1024] = [0; 1024];
+    (*st).out_stride = 1 as std::ffi::c_int;
+    while ilen != 0 && olen != 0 {
+        let mut y: *mut spx_word16_t = ystack.as_mut_ptr();
+        let mut ichunk: spx_uint32_t = if ilen > xlen { xlen } else { ilen };
+        let mut ochunk: spx_uint32_t = if olen > ylen as spx_uint32_t {
+            ylen as spx_uint32_t
+        } else {
+            olen
+        };
+        let mut omagic: spx_uint32_t = 0 as spx_uint32_t;
+        if *((*st).magic_samples).offset(channel_index as isize) != 0 {
+            omagic = speex_resampler_magic(st, channel_index, &mut y, ochunk) as spx_uint32_t;
+            ochunk = ochunk.wrapping_sub(omagic);
+            olen = olen.wrapping_sub(omagic);
+        }
+        if *((*st).magic_samples).offset(channel_index as isize) == 0 {
+            if !in_0.is_null() {
+                j = 0 as std::ffi::c_int;
+                while (j as spx_uint32_t) < ichunk {
+                    *x.offset(
+                        (j as spx_uint32_t)
+                            .wrapping_add((*st).filt_len)
+                            .wrapping_sub(1 as spx_uint32_t) as isize,
+                    ) = WORD2INT(*in_0.offset((j * istride_save) as isize) as std::ffi::c_double);
+                    j += 1;
+                }
+            } else {
+                j = 0 as std::ffi::c_int;
+                while (j as spx_uint32_t) < ichunk {
+                    *x.offset(
+                        (j as spx_uint32_t)
+                            .wrapping_add((*st).filt_len)
+                            .wrapping_sub(1 as spx_uint32_t) as isize,
+                    ) = 0 as std::ffi::c_int as spx_word16_t;
+                    j += 1;
+                }
+            }
+            speex_resampler_process_native(st, channel_index, &mut ichunk, y, &mut ochunk);
+        } else {
+            ichunk = 0 as spx_uint32_t;
+            ochunk = 0 as spx_uint32_t;
+        }
+        j = 0 as std::ffi::c_int;
+        while (j as spx_uint32_t) < ochunk.wrapping_add(omagic) {
+            *out.offset((j * ostride_save) as isize) = ystack[j as usize] as std::ffi::c_float;
+            j += 1;
+        }
+        ilen = ilen.wrapping_sub(ichunk);
+        olen = olen.wrapping_sub(ochunk);
+        out = out.offset(
+            ochunk
+                .wrapping_add(omagic)
+                .wrapping_mul(ostride_save as spx_uint32_t) as isize,
+        );
+        if !in_0.is_null() {
+            in_0 = in_0.offset(ichunk.wrapping_mul(istride_save as spx_uint32_t) as isize);
+        }
+    }
+    (*st).out_stride = ostride_save;
+    *in_len = (*in_len).wrapping_sub(ilen);
+    *out_len = (*out_len).wrapping_sub(olen);
+    return if (*st).resampler_ptr
+        == Some(
+            resampler_basic_zero
+                as unsafe extern "C" fn(
+                    *mut SpeexResamplerState,
+                    spx_uint32_t,
+                    *const spx_word16_t,
+                    *mut spx_uint32_t,
+                    *mut spx_word16_t,
+                    *mut spx_uint32_t,
+                ) -> std::ffi::c_int,
+        ) {
+        RESAMPLER_ERR_ALLOC_FAILED as std::ffi::c_int
+    } else {
+        RESAMPLER_ERR_SUCCESS as std::ffi::c_int
+    };
+}
+#[cfg(feature = "fixed-point")]
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn speex_resampler_process_int(
+    mut st: *mut SpeexResamplerState,
+    mut channel_index: spx_uint32_t,
+    mut in_0: *const spx_int16_t,
+    mut in_len: *mut spx_uint32_t,
+    mut out: *mut spx_int16_t,
+    mut out_len: *mut spx_uint32_t,
+) -> std::ffi::c_int {
+    let mut j: std::ffi::c_int = 0;
+    let mut ilen: spx_uint32_t = *in_len;
+    let mut olen: spx_uint32_t = *out_len;
+    let mut x: *mut spx_word16_t =
+        ((*st).mem).offset(channel_index.wrapping_mul((*st).mem_alloc_size) as isize);
+    let filt_offs: std::ffi::c_int =
+        ((*st).filt_len).wrapping_sub(1 as spx_uint32_t) as std::ffi::c_int;
+    let xlen: spx_uint32_t = ((*st).mem_alloc_size).wrapping_sub(filt_offs as spx_uint32_t);
+    let istride: std::ffi::c_int = (*st).in_stride;
+    if *((*st).magic_samples).offset(channel_index as isize) != 0 {
+        olen = olen
+            .wrapping_sub(speex_resampler_magic(st, channel_index, &mut out, olen) as spx_uint32_t);
+    }
+    if *((*st).magic_samples).offset(channel_index as isize) == 0 {
+        while ilen != 0 && olen != 0 {
+            let mut ichunk: spx_uint32_t = if ilen > xlen { xlen } else { ilen };
+            let mut ochunk: spx_uint32_t = olen;
+            if !in_0.is_null() {
+                j = 0 as std::ffi::c_int;
+                while (j as spx_uint32_t) < ichunk {
+                    *x.offset((j + filt_offs) as isize) =
+                        *in_0.offset((j * istride) as isize) as spx_word16_t;
+                    j += 1;
+                }
+            } else {
+                j = 0 as std::ffi::c_int;
+                while (j as spx_uint32_t) < ichunk {
+                    *x.offset((j + filt_offs) as isize) = 0 as std::ffi::c_int as spx_word16_t;
+                    j += 1;
+                }
+            }
+            speex_resampler_process_native(
+                st,
+                channel_index,
+                &mut ichunk,
+                out as *mut spx_word16_t,
+                &mut ochunk,
+            );
+            ilen = ilen.wrapping_sub(ichunk);
+            olen = olen.wrapping_sub(ochunk);
+            out = out.offset(ochunk.wrapping_mul((*st).out_stride as spx_uint32_t) as isize);
+            if !in_0.is_null() {
+                in_0 = in_0.offset(ichunk.wrapping_mul(istride as spx_uint32_t) as isize);
+            }
+        }
+    }
+    *in_len = (*in_len).wrapping_sub(ilen);
+    *out_len = (*out_len).wrapping_sub(olen);
+    return if (*st).resampler_ptr
+        == Some(
+            resampler_basic_zero
+                as unsafe extern "C" fn(
+                    *mut SpeexResamplerState,
+                    spx_uint32_t,
+                    *const spx_word16_t,
+                    *mut spx_uint32_t,
+                    *mut spx_word16_t,
+                    *mut spx_uint32_t,
+                ) -> std::ffi::c_int,
+        ) {
+        RESAMPLER_ERR_ALLOC_FAILED as std::ffi::c_int
+    } else {
+        RESAMPLER_ERR_SUCCESS as std::ffi::c_int
+    };
+}
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn speex_resampler_process_interleaved_float(
     mut st: *mut SpeexResamplerState,
@@ -0,0 +1,241 @@
+// Vectorized kernels for resampler_basic_direct_single / resampler_basic_interpolate_single,
+// the Rust counterpart of speexdsp's resample_sse.h / resample_neon.h.
+// Picked at compile time from the enabled target features; builds without any of them
+// keep the scalar loops in resample.rs (OVERRIDE_* is false).
+//
+// filt_len is always a multiple of 8 (see update_filter), which all kernels rely on.
+
+#[cfg(target_arch = "x86")]
+use std::arch::x86::*;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+#[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+use std::arch::wasm32::*;
+
+cfg_if::cfg_if! {
+    if #[cfg(any(
+        all(target_arch = "wasm32", target_feature = "simd128"),
+        all(any(target_arch = "x86", target_arch = "x86_64"), target_feature = "sse"),
+    ))] {
+        pub const OVERRIDE_INNER_PRODUCT_SINGLE: bool = true;
+        pub const OVERRIDE_INTERPOLATE_PRODUCT_SINGLE: bool = true;
+    } else {
+        pub const OVERRIDE_INNER_PRODUCT_SINGLE: bool = false;
+        pub const OVERRIDE_INTERPOLATE_PRODUCT_SINGLE: bool = false;
+    }
+}
+
+/// sum(a[i] * b[i]) for i in 0..len
+#[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), target_feature = "avx"))]
+#[inline]
+pub unsafe fn inner_product_single(a: *const f32, b: *const f32, len: u32) -> f32 {
+    let mut sum = _mm256_setzero_ps();
+    let mut i = 0usize;
+    while i < len as usize {
+        sum = _mm256_add_ps(sum, _mm256_mul_ps(_mm256_loadu_ps(a.add(i)), _mm256_loadu_ps(b.add(i))));
+        i += 8;
+    }
+    let sum = _mm_add_ps(_mm256_castps256_ps128(sum), _mm256_extractf128_ps(sum, 1));
+    horizontal_sum_sse(sum)
+}
+
+/// sum(a[i] * b[i]) for i in 0..len
+#[cfg(all(
+    any(target_arch = "x86", target_arch = "x86_64"),
+    target_feature = "sse",
+    not(target_feature = "avx"),
+))]
+#[inline]
+pub unsafe fn inner_product_single(a: *const f32, b: *const f32, len: u32) -> f32 {
+    let mut sum = _mm_setzero_ps();
+    let mut i = 0usize;
+    while i < len as usize {
+        sum = _mm_add_ps(sum, _mm_mul_ps(_mm_loadu_ps(a.add(i)), _mm_loadu_ps(b.add(i))));
+        sum = _mm_add_ps(sum, _mm_mul_ps(_mm_loadu_ps(a.add(i + 4)), _mm_loadu_ps(b.add(i + 4))));
+        i += 8;
+    }
+    horizontal_sum_sse(sum)
+}
+
+/// Interpolated sinc product: accumulates a[i] * b[i * oversample .. i * oversample + 4]
+/// over i in 0..len, then weights the four lanes with the cubic coefficients in `frac`.
+#[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), target_feature = "sse"))]
+#[inline]
+pub unsafe fn interpolate_product_single(
+    a: *const f32,
+    b: *const f32,
+    len: u32,
+    oversample: u32,
+    frac: *const f32,
+) -> f32 {
+    let oversample = oversample as usize;
+    let mut sum = _mm_setzero_ps();
+    let mut i = 0usize;
+    while i < len as usize {
+        sum = _mm_add_ps(sum, _mm_mul_ps(_mm_load1_ps(a.add(i)), _mm_loadu_ps(b.add(i * oversample))));
+        sum = _mm_add_ps(
+            sum,
+            _mm_mul_ps(_mm_load1_ps(a.add(i + 1)), _mm_loadu_ps(b.add((i + 1) * oversample))),
+        );
+        i += 2;
+    }
+    horizontal_sum_sse(_mm_mul_ps(_mm_loadu_ps(frac), sum))
+}
+
+#[cfg(all(any(target_arch = "x86", target_arch = "x86_64"), target_feature = "sse"))]
+#[inline]
+unsafe fn horizontal_sum_sse(sum: __m128) -> f32 {
+    let sum = _mm_add_ps(sum, _mm_movehl_ps(sum, sum));
+    let sum = _mm_add_ss(sum, _mm_shuffle_ps(sum, sum, 0x55));
+    _mm_cvtss_f32(sum)
+}
+
+/// sum(a[i] * b[i]) for i in 0..len
+#[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+#[inline]
+pub unsafe fn inner_product_single(a: *const f32, b: *const f32, len: u32) -> f32 {
+    let mut sum0 = f32x4_splat(0.0);
+    let mut sum1 = f32x4_splat(0.0);
+    let mut i = 0usize;
+    while i < len as usize {
+        sum0 = f32x4_add(sum0, f32x4_mul(v128_load(a.add(i) as *const v128), v128_load(b.add(i) as *const v128)));
+        sum1 = f32x4_add(
+            sum1,
+            f32x4_mul(v128_load(a.add(i + 4) as *const v128), v128_load(b.add(i + 4) as *const v128)),
+        );
+        i += 8;
+    }
+    horizontal_sum_simd128(f32x4_add(sum0, sum1))
+}
+
+/// Interpolated sinc product: accumulates a[i] * b[i * oversample .. i * oversample + 4]
+/// over i in 0..len, then weights the four lanes with the cubic coefficients in `frac`.
+#[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+#[inline]
+pub unsafe fn interpolate_product_single(
+    a: *const f32,
+    b: *const f32,
+    len: u32,
+    oversample: u32,
+    frac: *const f32,
+) -> f32 {
+    let oversample = oversample as usize;
+    let mut sum = f32x4_splat(0.0);
+    let mut i = 0usize;
+    while i < len as usize {
+        sum = f32x4_add(sum, f32x4_mul(f32x4_splat(*a.add(i)), v128_load(b.add(i * oversample) as *const v128)));
+        sum = f32x4_add(
+            sum,
+            f32x4_mul(f32x4_splat(*a.add(i + 1)), v128_load(b.add((i + 1) * oversample) as *const v128)),
+        );
+        i += 2;
+    }
+    horizontal_sum_simd128(f32x4_mul(v128_load(frac as *const v128), sum))
+}
+
+#[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+#[inline]
+unsafe fn horizontal_sum_simd128(sum: v128) -> f32 {
+    let sum = f32x4_add(sum, i32x4_shuffle::<2, 3, 0, 1>(sum, sum));
+    let sum = f32x4_add(sum, i32x4_shuffle::<1, 0, 3, 2>(sum, sum));
+    f32x4_extract_lane::<0>(sum)
+}
+
+// Never called (OVERRIDE_* is false), only keeps resample.rs compiling without SIMD.
+#[cfg(not(any(
+    all(target_arch = "wasm32", target_feature = "simd128"),
+    all(any(target_arch = "x86", target_arch = "x86_64"), target_feature = "sse"),
+)))]
+#[inline]
+pub unsafe fn inner_product_single(a: *const f32, b: *const f32, len: u32) -> f32 {
+    let mut sum = 0.0f32;
+    for i in 0..len as usize {
+        sum += *a.add(i) * *b.add(i);
+    }
+    sum
+}
+
+#[cfg(not(any(
+    all(target_arch = "wasm32", target_feature = "simd128"),
+    all(any(target_arch = "x86", target_arch = "x86_64"), target_feature = "sse"),
+)))]
+#[inline]
+pub unsafe fn interpolate_product_single(
+    a: *const f32,
+    b: *const f32,
+    len: u32,
+    oversample: u32,
+    frac: *const f32,
+) -> f32 {
+    let oversample = oversample as usize;
+    let mut accum = [0.0f32; 4];
+    for i in 0..len as usize {
+        for (k, acc) in accum.iter_mut().enumerate() {
+            *acc += *a.add(i) * *b.add(i * oversample + k);
+        }
+    }
+    (0..4).map(|k| *frac.add(k) * accum[k]).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the scalar loops in resample.rs, summed in f64 so only the kernel's rounding shows
+    fn scalar_inner_product(a: &[f32], b: &[f32]) -> (f64, f64) {
+        let exact = a.iter().zip(b).map(|(&x, &y)| x as f64 * y as f64).sum();
+        let magnitude = a.iter().zip(b).map(|(&x, &y)| (x as f64 * y as f64).abs()).sum();
+        (exact, magnitude)
+    }
+
+    fn signal(seed: u32, len: usize) -> Vec<f32> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (state >> 8) as f32 / (1u32 << 23) as f32 - 1.0
+            })
+            .collect()
+    }
+
+    #[test]
+    fn inner_product_matches_scalar() {
+        // filter lengths update_filter produces: multiples of 8
+        for len in (8..=256).step_by(8) {
+            let a = signal(len as u32, len);
+            let b = signal(len as u32 + 1000, len);
+            let simd = unsafe { inner_product_single(a.as_ptr(), b.as_ptr(), len as u32) };
+            let (exact, magnitude) = scalar_inner_product(&a, &b);
+            // only the summation order differs, so the error is a few ulps of the summed magnitude
+            let tolerance = magnitude * 4.0 * f32::EPSILON as f64;
+            assert!((simd as f64 - exact).abs() <= tolerance, "len {len}: {simd} vs {exact}");
+        }
+    }
+
+    #[test]
+    fn interpolate_product_matches_scalar() {
+        for oversample in [1usize, 2, 4, 8, 32] {
+            for len in (8..=128).step_by(8) {
+                let a = signal(len as u32, len);
+                let b = signal(oversample as u32, len * oversample + 4);
+                let frac = signal(7, 4);
+                let simd = unsafe {
+                    interpolate_product_single(a.as_ptr(), b.as_ptr(), len as u32, oversample as u32, frac.as_ptr())
+                };
+                let mut exact = 0.0f64;
+                let mut magnitude = 0.0f64;
+                for (k, &f) in frac.iter().enumerate() {
+                    let column: Vec<f32> = (0..len).map(|i| b[i * oversample + k]).collect();
+                    let (sum, mag) = scalar_inner_product(&a, &column);
+                    exact += f as f64 * sum;
+                    magnitude += (f as f64).abs() * mag;
+                }
+                let tolerance = magnitude * 4.0 * f32::EPSILON as f64;
+                assert!(
+                    (simd as f64 - exact).abs() <= tolerance,
+                    "oversample {oversample}, len {len}: {simd} vs {exact}"
+                );
+            }
+        }
+    }
+}
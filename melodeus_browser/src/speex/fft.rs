@@ -0,0 +1,110 @@
+//! Real FFT in the packed layout the speex DSP code expects.
+
+use std::sync::Arc;
+
+use rustfft::num_complex::Complex32;
+use rustfft::{Fft, FftDirection};
+
+use super::plan_fft;
+
+/// Real FFT of even length `n`, following smallft's conventions.
+///
+/// Spectra are packed as `[r0, r1, i1, r2, i2, ..., r(n/2)]`, [`forward`](Self::forward) scales
+/// by `1/n` and [`inverse`](Self::inverse) is unscaled. Internally this is a length `n/2` complex
+/// FFT of the even/odd interleaved samples plus one split pass.
+pub struct RealFft {
+    n: usize,
+    forward: Arc<dyn Fft<f32>>,
+    inverse: Arc<dyn Fft<f32>>,
+    twiddles: Vec<Complex32>,
+    buf: Vec<Complex32>,
+    scratch: Vec<Complex32>,
+}
+
+impl RealFft {
+    /// Plan a transform of `n` samples. Panics if `n` is zero or odd.
+    pub fn new(n: usize) -> Self {
+        assert!(n > 0 && n % 2 == 0, "real FFT size must be even, got {n}");
+        let half = n / 2;
+        let forward = plan_fft(half, FftDirection::Forward);
+        let inverse = plan_fft(half, FftDirection::Inverse);
+        let scratch_len = forward
+            .get_inplace_scratch_len()
+            .max(inverse.get_inplace_scratch_len());
+        // exp(-2*pi*i*k/n) for k in 0..n/2
+        let twiddles = (0..half)
+            .map(|k| Complex32::from_polar(1.0, -2.0 * std::f32::consts::PI * k as f32 / n as f32))
+            .collect();
+        Self {
+            n,
+            forward,
+            inverse,
+            twiddles,
+            buf: vec![Complex32::ZERO; half],
+            scratch: vec![Complex32::ZERO; scratch_len],
+        }
+    }
+
+    /// Transform length in real samples.
+    pub fn size(&self) -> usize {
+        self.n
+    }
+
+    /// Forward transform of `input` into `output`, scaled by `1/n`.
+    pub fn forward(&mut self, input: &[f32], output: &mut [f32]) {
+        let n = self.n;
+        let half = n / 2;
+        let input = &input[..n];
+        let output = &mut output[..n];
+        for (k, z) in self.buf.iter_mut().enumerate() {
+            *z = Complex32::new(input[2 * k], input[2 * k + 1]);
+        }
+        self.forward.process_with_scratch(&mut self.buf, &mut self.scratch);
+
+        let scale = 1.0 / n as f32;
+        // X[k] = E[k] + w^k O[k], with E/O the spectra of the even/odd samples
+        let z0 = self.buf[0];
+        output[0] = (z0.re + z0.im) * scale;
+        output[n - 1] = (z0.re - z0.im) * scale;
+        for k in 1..half {
+            let zk = self.buf[k];
+            let zm = self.buf[half - k].conj();
+            let even = (zk + zm) * 0.5;
+            let odd = (zk - zm) * Complex32::new(0.0, -0.5);
+            let x = (even + self.twiddles[k] * odd) * scale;
+            output[2 * k - 1] = x.re;
+            output[2 * k] = x.im;
+        }
+    }
+
+    /// Inverse transform of the packed spectrum `input` into `output`, unscaled.
+    pub fn inverse(&mut self, input: &[f32], output: &mut [f32]) {
+        let n = self.n;
+        let half = n / 2;
+        let input = &input[..n];
+        let output = &mut output[..n];
+        let bin = |k: usize| -> Complex32 {
+            if k == 0 {
+                Complex32::new(input[0], 0.0)
+            } else if k == half {
+                Complex32::new(input[n - 1], 0.0)
+            } else {
+                Complex32::new(input[2 * k - 1], input[2 * k])
+            }
+        };
+        // undo the split: Z[k] = 2 E[k] + 2i O[k], whose inverse is n/2 * 2 * (x_even + i x_odd)
+        for k in 0..half {
+            let xk = bin(k);
+            let xm = bin(half - k).conj();
+            let even = xk + xm;
+            let odd = (xk - xm) * self.twiddles[k].conj();
+            self.buf[k] = even + Complex32::new(0.0, 1.0) * odd;
+        }
+        self.inverse.process_with_scratch(&mut self.buf, &mut self.scratch);
+
+        for (k, z) in self.buf.iter().enumerate() {
+            output[2 * k] = z.re;
+            output[2 * k + 1] = z.im;
+        }
+    }
+}
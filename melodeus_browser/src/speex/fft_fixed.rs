@@ -0,0 +1,481 @@
+//! Fixed-point real FFT for the `fixed-point` build: a safe port of speex's `FIXED_POINT`
+//! kiss_fft / kiss_fftr and the kiss backend of `fftwrap.c`.
+//!
+//! Same packed layout and scaling as [`RealFft`](super::fft::RealFft): `forward` divides by
+//! `n` (spread over the butterfly stages, which keeps everything in 16 bits) and `inverse` is
+//! unscaled.
+
+use super::fixed::{mult16_16, pshr16, pshr32, shl32, shr32, spx_cos_norm};
+
+const SAMP_MAX: i32 = 32767;
+const MAX_RADIX: usize = 17;
+
+#[derive(Clone, Copy, Default)]
+struct Cpx {
+    r: i16,
+    i: i16,
+}
+
+#[inline]
+fn sround(x: i32) -> i16 {
+    (x.wrapping_add(1 << 14) >> 15) as i16
+}
+
+#[inline]
+fn s_mul(a: i16, b: i16) -> i16 {
+    sround(mult16_16(a, b))
+}
+
+#[inline]
+fn c_mul(a: Cpx, b: Cpx) -> Cpx {
+    Cpx {
+        r: sround(mult16_16(a.r, b.r).wrapping_sub(mult16_16(a.i, b.i))),
+        i: sround(mult16_16(a.r, b.i).wrapping_add(mult16_16(a.i, b.r))),
+    }
+}
+
+// C_MUL scaled down by 4, for the forward radix-4 butterfly
+#[inline]
+fn c_mul4(a: Cpx, b: Cpx) -> Cpx {
+    Cpx {
+        r: pshr32(mult16_16(a.r, b.r).wrapping_sub(mult16_16(a.i, b.i)), 17) as i16,
+        i: pshr32(mult16_16(a.r, b.i).wrapping_add(mult16_16(a.i, b.r)), 17) as i16,
+    }
+}
+
+#[inline]
+fn c_add(a: Cpx, b: Cpx) -> Cpx {
+    Cpx { r: a.r.wrapping_add(b.r), i: a.i.wrapping_add(b.i) }
+}
+
+#[inline]
+fn c_sub(a: Cpx, b: Cpx) -> Cpx {
+    Cpx { r: a.r.wrapping_sub(b.r), i: a.i.wrapping_sub(b.i) }
+}
+
+// C_FIXDIV: divide by the radix on the way in so the forward transform can't overflow
+#[inline]
+fn c_fixdiv(c: Cpx, div: i32) -> Cpx {
+    let k = (SAMP_MAX / div) as i16;
+    Cpx { r: s_mul(c.r, k), i: s_mul(c.i, k) }
+}
+
+// e^(i * phase * 2pi / 2^17)
+fn cexp2(phase: i32) -> Cpx {
+    Cpx { r: spx_cos_norm(phase), i: spx_cos_norm(phase - 32768) }
+}
+
+// complex FFT of nfft points (kiss_fft_state)
+struct KissFft {
+    nfft: usize,
+    inverse: bool,
+    // (radix, remaining length) per stage
+    factors: Vec<(usize, usize)>,
+    twiddles: Vec<Cpx>,
+}
+
+impl KissFft {
+    fn new(nfft: usize, inverse: bool) -> Self {
+        let twiddles = (0..nfft as i32)
+            .map(|i| {
+                let phase = if inverse { i } else { -i };
+                cexp2(shl32(phase, 17) / nfft as i32)
+            })
+            .collect();
+        Self { nfft, inverse, factors: factor(nfft), twiddles }
+    }
+
+    fn process(&self, fin: &[Cpx], fout: &mut [Cpx]) {
+        shuffle(fout, fin, 0, 1, &self.factors);
+        self.work(fout, 1, &self.factors, 1, 1);
+    }
+
+    fn work(&self, fout: &mut [Cpx], fstride: usize, factors: &[(usize, usize)], n: usize, m2: usize) {
+        let (p, m) = factors[0];
+        if m != 1 {
+            self.work(fout, fstride * p, &factors[1..], n * p, m);
+        }
+        match p {
+            2 => self.bfly2(fout, fstride, m, n, m2),
+            3 => (0..n).for_each(|i| self.bfly3(&mut fout[i * m2..], fstride, m)),
+            4 => self.bfly4(fout, fstride, m, n, m2),
+            5 => (0..n).for_each(|i| self.bfly5(&mut fout[i * m2..], fstride, m)),
+            _ => (0..n).for_each(|i| self.bfly_generic(&mut fout[i * m2..], fstride, m, p)),
+        }
+    }
+
+    fn bfly2(&self, fout: &mut [Cpx], fstride: usize, m: usize, n: usize, mm: usize) {
+        for i in 0..n {
+            let base = i * mm;
+            for j in 0..m {
+                let tw = self.twiddles[j * fstride];
+                let a = fout[base + j];
+                let b = fout[base + j + m];
+                if self.inverse {
+                    let t = c_mul(b, tw);
+                    fout[base + j + m] = c_sub(a, t);
+                    fout[base + j] = c_add(a, t);
+                } else {
+                    // the same butterfly with the inputs halved, keeping as much precision as possible
+                    let tr = shr32(mult16_16(b.r, tw.r).wrapping_sub(mult16_16(b.i, tw.i)), 1);
+                    let ti = shr32(mult16_16(b.i, tw.r).wrapping_add(mult16_16(b.r, tw.i)), 1);
+                    fout[base + j + m] = Cpx {
+                        r: pshr32(shl32(a.r as i32, 14) - tr, 15) as i16,
+                        i: pshr32(shl32(a.i as i32, 14) - ti, 15) as i16,
+                    };
+                    fout[base + j] = Cpx {
+                        r: pshr32(shl32(a.r as i32, 14) + tr, 15) as i16,
+                        i: pshr32(shl32(a.i as i32, 14) + ti, 15) as i16,
+                    };
+                }
+            }
+        }
+    }
+
+    fn bfly4(&self, fout: &mut [Cpx], fstride: usize, m: usize, n: usize, mm: usize) {
+        for i in 0..n {
+            let f = &mut fout[i * mm..];
+            for j in 0..m {
+                let tw1 = self.twiddles[j * fstride];
+                let tw2 = self.twiddles[j * fstride * 2];
+                let tw3 = self.twiddles[j * fstride * 3];
+                let (s0, s1, s2) = if self.inverse {
+                    (c_mul(f[j + m], tw1), c_mul(f[j + 2 * m], tw2), c_mul(f[j + 3 * m], tw3))
+                } else {
+                    f[j].r = pshr16(f[j].r, 2);
+                    f[j].i = pshr16(f[j].i, 2);
+                    (c_mul4(f[j + m], tw1), c_mul4(f[j + 2 * m], tw2), c_mul4(f[j + 3 * m], tw3))
+                };
+                let s5 = c_sub(f[j], s1);
+                f[j] = c_add(f[j], s1);
+                let s3 = c_add(s0, s2);
+                let s4 = c_sub(s0, s2);
+                f[j + 2 * m] = c_sub(f[j], s3);
+                f[j] = c_add(f[j], s3);
+                if self.inverse {
+                    f[j + m] = Cpx { r: s5.r.wrapping_sub(s4.i), i: s5.i.wrapping_add(s4.r) };
+                    f[j + 3 * m] = Cpx { r: s5.r.wrapping_add(s4.i), i: s5.i.wrapping_sub(s4.r) };
+                } else {
+                    f[j + m] = Cpx { r: s5.r.wrapping_add(s4.i), i: s5.i.wrapping_sub(s4.r) };
+                    f[j + 3 * m] = Cpx { r: s5.r.wrapping_sub(s4.i), i: s5.i.wrapping_add(s4.r) };
+                }
+            }
+        }
+    }
+
+    fn bfly3(&self, f: &mut [Cpx], fstride: usize, m: usize) {
+        let epi3 = self.twiddles[fstride * m];
+        for k in 0..m {
+            if !self.inverse {
+                f[k] = c_fixdiv(f[k], 3);
+                f[k + m] = c_fixdiv(f[k + m], 3);
+                f[k + 2 * m] = c_fixdiv(f[k + 2 * m], 3);
+            }
+            let s1 = c_mul(f[k + m], self.twiddles[k * fstride]);
+            let s2 = c_mul(f[k + 2 * m], self.twiddles[k * fstride * 2]);
+            let s3 = c_add(s1, s2);
+            let s0 = c_sub(s1, s2);
+            f[k + m] = Cpx {
+                r: f[k].r.wrapping_sub(s3.r >> 1),
+                i: f[k].i.wrapping_sub(s3.i >> 1),
+            };
+            let s0 = Cpx { r: s_mul(s0.r, epi3.i), i: s_mul(s0.i, epi3.i) };
+            f[k] = c_add(f[k], s3);
+            f[k + 2 * m] = Cpx {
+                r: f[k + m].r.wrapping_add(s0.i),
+                i: f[k + m].i.wrapping_sub(s0.r),
+            };
+            f[k + m].r = f[k + m].r.wrapping_sub(s0.i);
+            f[k + m].i = f[k + m].i.wrapping_add(s0.r);
+        }
+    }
+
+    fn bfly5(&self, f: &mut [Cpx], fstride: usize, m: usize) {
+        let ya = self.twiddles[fstride * m];
+        let yb = self.twiddles[fstride * 2 * m];
+        for u in 0..m {
+            if !self.inverse {
+                for q in 0..5 {
+                    f[u + q * m] = c_fixdiv(f[u + q * m], 5);
+                }
+            }
+            let s0 = f[u];
+            let s1 = c_mul(f[u + m], self.twiddles[u * fstride]);
+            let s2 = c_mul(f[u + 2 * m], self.twiddles[2 * u * fstride]);
+            let s3 = c_mul(f[u + 3 * m], self.twiddles[3 * u * fstride]);
+            let s4 = c_mul(f[u + 4 * m], self.twiddles[4 * u * fstride]);
+            let s7 = c_add(s1, s4);
+            let s10 = c_sub(s1, s4);
+            let s8 = c_add(s2, s3);
+            let s9 = c_sub(s2, s3);
+
+            f[u].r = f[u].r.wrapping_add(s7.r.wrapping_add(s8.r));
+            f[u].i = f[u].i.wrapping_add(s7.i.wrapping_add(s8.i));
+
+            let s5 = Cpx {
+                r: s0.r.wrapping_add(s_mul(s7.r, ya.r)).wrapping_add(s_mul(s8.r, yb.r)),
+                i: s0.i.wrapping_add(s_mul(s7.i, ya.r)).wrapping_add(s_mul(s8.i, yb.r)),
+            };
+            let s6 = Cpx {
+                r: s_mul(s10.i, ya.i).wrapping_add(s_mul(s9.i, yb.i)),
+                i: s_mul(s10.r, ya.i).wrapping_neg().wrapping_sub(s_mul(s9.r, yb.i)),
+            };
+            f[u + m] = c_sub(s5, s6);
+            f[u + 4 * m] = c_add(s5, s6);
+
+            let s11 = Cpx {
+                r: s0.r.wrapping_add(s_mul(s7.r, yb.r)).wrapping_add(s_mul(s8.r, ya.r)),
+                i: s0.i.wrapping_add(s_mul(s7.i, yb.r)).wrapping_add(s_mul(s8.i, ya.r)),
+            };
+            let s12 = Cpx {
+                r: s_mul(s10.i, yb.i).wrapping_neg().wrapping_add(s_mul(s9.i, ya.i)),
+                i: s_mul(s10.r, yb.i).wrapping_sub(s_mul(s9.r, ya.i)),
+            };
+            f[u + 2 * m] = c_add(s11, s12);
+            f[u + 3 * m] = c_sub(s11, s12);
+        }
+    }
+
+    fn bfly_generic(&self, f: &mut [Cpx], fstride: usize, m: usize, p: usize) {
+        assert!(p <= MAX_RADIX, "KissFFT: max radix supported is {MAX_RADIX}");
+        let mut scratch = [Cpx::default(); MAX_RADIX];
+        for u in 0..m {
+            for q1 in 0..p {
+                scratch[q1] = f[u + q1 * m];
+                if !self.inverse {
+                    scratch[q1] = c_fixdiv(scratch[q1], p as i32);
+                }
+            }
+            for q1 in 0..p {
+                let k = u + q1 * m;
+                let mut twidx = 0;
+                f[k] = scratch[0];
+                for s in &scratch[1..p] {
+                    twidx += fstride * k;
+                    if twidx >= self.nfft {
+                        twidx -= self.nfft;
+                    }
+                    f[k] = c_add(f[k], c_mul(*s, self.twiddles[twidx]));
+                }
+            }
+        }
+    }
+}
+
+// radix-4 first, then 2, 3 and odd numbers, like kf_factor
+fn factor(mut n: usize) -> Vec<(usize, usize)> {
+    let mut factors = Vec::new();
+    let mut p = 4;
+    loop {
+        while n % p != 0 {
+            p = match p {
+                4 => 2,
+                2 => 3,
+                _ => p + 2,
+            };
+            if p > 32000 || p * p > n {
+                p = n;
+            }
+        }
+        n /= p;
+        factors.push((p, n));
+        if n <= 1 {
+            return factors;
+        }
+    }
+}
+
+fn shuffle(fout: &mut [Cpx], fin: &[Cpx], mut f: usize, fstride: usize, factors: &[(usize, usize)]) {
+    let (p, m) = factors[0];
+    for j in 0..p {
+        if m == 1 {
+            fout[j] = fin[f];
+        } else {
+            shuffle(&mut fout[j * m..], fin, f, fstride * p, &factors[1..]);
+        }
+        f += fstride;
+    }
+}
+
+/// Fixed-point real FFT of even length `n`, see the module docs.
+pub struct FixedRealFft {
+    n: usize,
+    forward: KissFft,
+    inverse: KissFft,
+    super_twiddles_forward: Vec<Cpx>,
+    super_twiddles_inverse: Vec<Cpx>,
+    scaled: Vec<i16>,
+    cin: Vec<Cpx>,
+    tmpbuf: Vec<Cpx>,
+}
+
+impl FixedRealFft {
+    /// Plan a transform of `n` samples. Panics if `n` is zero or odd.
+    pub fn new(n: usize) -> Self {
+        assert!(n > 0 && n % 2 == 0, "real FFT size must be even, got {n}");
+        let ncfft = n / 2;
+        let super_twiddles = |inverse: bool| -> Vec<Cpx> {
+            (0..ncfft as i32)
+                .map(|i| {
+                    let phase = i + (ncfft as i32 >> 1);
+                    let phase = if inverse { phase } else { -phase };
+                    cexp2(shl32(phase, 16) / ncfft as i32)
+                })
+                .collect()
+        };
+        Self {
+            n,
+            forward: KissFft::new(ncfft, false),
+            inverse: KissFft::new(ncfft, true),
+            super_twiddles_forward: super_twiddles(false),
+            super_twiddles_inverse: super_twiddles(true),
+            scaled: vec![0; n],
+            cin: vec![Cpx::default(); ncfft],
+            tmpbuf: vec![Cpx::default(); ncfft],
+        }
+    }
+
+    /// Transform length in real samples.
+    pub fn size(&self) -> usize {
+        self.n
+    }
+
+    /// Forward transform of `input` into `output`, scaled by `1/n`.
+    pub fn forward(&mut self, input: &[i16], output: &mut [i16]) {
+        let n = self.n;
+        let ncfft = n / 2;
+        let input = &input[..n];
+        let output = &mut output[..n];
+
+        // scale the input up to use the whole 16 bits, and the output back down by as much
+        let max_val = input.iter().map(|v| (*v as i32).abs()).max().unwrap_or(0).min(32767);
+        let mut shift = 0;
+        let mut v = max_val;
+        while v <= 32000 >> 1 && v != 0 {
+            v <<= 1;
+            shift += 1;
+        }
+        for (dst, src) in self.scaled.iter_mut().zip(input) {
+            *dst = src.wrapping_shl(shift as u32);
+        }
+
+        for (k, c) in self.cin.iter_mut().enumerate() {
+            *c = Cpx { r: self.scaled[2 * k], i: self.scaled[2 * k + 1] };
+        }
+        self.forward.process(&self.cin, &mut self.tmpbuf);
+
+        let tdc = c_fixdiv(self.tmpbuf[0], 2);
+        output[0] = tdc.r.wrapping_add(tdc.i);
+        output[n - 1] = tdc.r.wrapping_sub(tdc.i);
+        for k in 1..=ncfft / 2 {
+            let a = self.tmpbuf[k];
+            let b = self.tmpbuf[ncfft - k];
+            let tw = self.super_twiddles_forward[k];
+            let f2k = Cpx {
+                r: shr32(a.r as i32 - b.r as i32, 1) as i16,
+                i: pshr32(a.i as i32 + b.i as i32, 1) as i16,
+            };
+            let f1kr = shl32(a.r as i32 + b.r as i32, 13);
+            let f1ki = shl32(a.i as i32 - b.i as i32, 13);
+            let twr = shr32(mult16_16(f2k.r, tw.r).wrapping_sub(mult16_16(f2k.i, tw.i)), 1);
+            let twi = shr32(mult16_16(f2k.i, tw.r).wrapping_add(mult16_16(f2k.r, tw.i)), 1);
+            output[2 * k - 1] = pshr32(f1kr + twr, 15) as i16;
+            output[2 * k] = pshr32(f1ki + twi, 15) as i16;
+            output[2 * (ncfft - k) - 1] = pshr32(f1kr - twr, 15) as i16;
+            output[2 * (ncfft - k)] = pshr32(twi - f1ki, 15) as i16;
+        }
+
+        for v in output.iter_mut() {
+            *v = pshr16(*v, shift);
+        }
+    }
+
+    /// Inverse transform of the packed spectrum `input` into `output`, unscaled.
+    pub fn inverse(&mut self, input: &[i16], output: &mut [i16]) {
+        let n = self.n;
+        let ncfft = n / 2;
+        let input = &input[..n];
+        let output = &mut output[..n];
+
+        self.tmpbuf[0] = Cpx {
+            r: input[0].wrapping_add(input[n - 1]),
+            i: input[0].wrapping_sub(input[n - 1]),
+        };
+        for k in 1..=ncfft / 2 {
+            let fk = Cpx { r: input[2 * k - 1], i: input[2 * k] };
+            let fnkc = Cpx {
+                r: input[2 * (ncfft - k) - 1],
+                i: input[2 * (ncfft - k)].wrapping_neg(),
+            };
+            let fek = c_add(fk, fnkc);
+            let fok = c_mul(c_sub(fk, fnkc), self.super_twiddles_inverse[k]);
+            self.tmpbuf[k] = c_add(fek, fok);
+            let mut back = c_sub(fek, fok);
+            back.i = back.i.wrapping_neg();
+            self.tmpbuf[ncfft - k] = back;
+        }
+        self.inverse.process(&self.tmpbuf, &mut self.cin);
+
+        for (k, c) in self.cin.iter().enumerate() {
+            output[2 * k] = c.r;
+            output[2 * k + 1] = c.i;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::speex::fft::RealFft;
+
+    fn signal(seed: u32, len: usize, amplitude: f32) -> Vec<i16> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (((state >> 16) as i16) as f32 * amplitude) as i16
+            })
+            .collect()
+    }
+
+    fn snr_db(actual: &[f32], expected: &[f32]) -> f32 {
+        let signal: f32 = expected.iter().map(|v| v * v).sum();
+        let noise: f32 = actual.iter().zip(expected).map(|(a, e)| (a - e) * (a - e)).sum();
+        10.0 * (signal / noise.max(f32::MIN_POSITIVE)).log10()
+    }
+
+    // Quantization noise is roughly fixed in absolute terms, so the SNR floor depends on the level:
+    // at least 35 dB for a -26 dBFS input and 50 dB at -6 dBFS. Full-scale inputs aren't
+    // covered, their round trip can wrap in 16 bits just like upstream's kiss_fft.
+    #[test]
+    fn matches_float_fft() {
+        for n in [64, 128, 160, 256, 320, 512, 640, 960] {
+            for (amplitude, min_snr_db) in [(0.05, 35.0), (0.5, 50.0)] {
+                let mut fixed = FixedRealFft::new(n);
+                let mut float = RealFft::new(n);
+                let input = signal(n as u32, n, amplitude);
+                let input_f32: Vec<f32> = input.iter().map(|&v| v as f32).collect();
+
+                let mut spectrum = vec![0i16; n];
+                let mut spectrum_f32 = vec![0.0f32; n];
+                fixed.forward(&input, &mut spectrum);
+                float.forward(&input_f32, &mut spectrum_f32);
+                let spectrum_fixed: Vec<f32> = spectrum.iter().map(|&v| v as f32).collect();
+                let snr = snr_db(&spectrum_fixed, &spectrum_f32);
+                assert!(snr >= min_snr_db, "forward n {n} amplitude {amplitude}: {snr:.1} dB");
+
+                // the inverse of the fixed spectrum against the float inverse of the same spectrum
+                let mut output = vec![0i16; n];
+                let mut output_f32 = vec![0.0f32; n];
+                fixed.inverse(&spectrum, &mut output);
+                float.inverse(&spectrum_fixed, &mut output_f32);
+                let output_fixed: Vec<f32> = output.iter().map(|&v| v as f32).collect();
+                let snr = snr_db(&output_fixed, &output_f32);
+                assert!(snr >= min_snr_db, "inverse n {n} amplitude {amplitude}: {snr:.1} dB");
+                let snr = snr_db(&output_fixed, &input_f32);
+                assert!(snr >= min_snr_db, "round trip n {n} amplitude {amplitude}: {snr:.1} dB");
+            }
+        }
+    }
+
+}
@@ -0,0 +1,604 @@
+//! Fixed-point arithmetic for the `fixed-point` build: the operators from speex's
+//! `fixed_generic.h`, the approximations from `math_approx.h` and the pseudo-float type from
+//! `pseudofloat.h`.
+//!
+//! Names follow the C macros (`mult16_32_q15` is `MULT16_32_Q15`). Additions and shifts wrap
+//! like the C code does on every platform we care about instead of panicking in debug builds,
+//! and shift counts past the word size saturate instead of being masked, so the results don't
+//! depend on the target.
+
+/// `QCONST16(x, bits)`: `x` in Q`bits`, rounded.
+pub const fn qconst16(x: f64, bits: u32) -> i16 {
+    (0.5 + x * (1u32 << bits) as f64) as i16
+}
+
+/// `QCONST32(x, bits)`
+pub const fn qconst32(x: f64, bits: u32) -> i32 {
+    (0.5 + x * (1u64 << bits) as f64) as i32
+}
+
+#[inline]
+pub fn add32(a: i32, b: i32) -> i32 {
+    a.wrapping_add(b)
+}
+
+#[inline]
+pub fn sub32(a: i32, b: i32) -> i32 {
+    a.wrapping_sub(b)
+}
+
+/// Arithmetic right shift; shifts of 32 or more leave only the sign.
+#[inline]
+pub fn shr32(a: i32, shift: i32) -> i32 {
+    if shift >= 32 { a >> 31 } else { a >> shift }
+}
+
+/// Left shift; shifts of 32 or more give 0.
+#[inline]
+pub fn shl32(a: i32, shift: i32) -> i32 {
+    if shift >= 32 { 0 } else { a.wrapping_shl(shift as u32) }
+}
+
+/// `PSHR32`: right shift with rounding.
+#[inline]
+pub fn pshr32(a: i32, shift: i32) -> i32 {
+    shr32(a.wrapping_add((1 << shift) >> 1), shift)
+}
+
+/// `VSHR32`: right shift for positive `shift`, left shift for negative.
+#[inline]
+pub fn vshr32(a: i32, shift: i32) -> i32 {
+    if shift > 0 { shr32(a, shift) } else { shl32(a, -shift) }
+}
+
+/// `PSHR16`
+#[inline]
+pub fn pshr16(a: i16, shift: i32) -> i16 {
+    pshr32(a as i32, shift) as i16
+}
+
+#[inline]
+pub fn saturate32(x: i32, a: i32) -> i32 {
+    x.clamp(-a, a)
+}
+
+/// `SATURATE32PSHR`: rounded right shift, saturated to `[-a, a]`.
+#[inline]
+pub fn saturate32pshr(x: i32, shift: i32, a: i32) -> i32 {
+    if x >= shl32(a, shift) {
+        a
+    } else if x <= -shl32(a, shift) {
+        -a
+    } else {
+        pshr32(x, shift)
+    }
+}
+
+#[inline]
+pub fn mult16_16(a: i16, b: i16) -> i32 {
+    a as i32 * b as i32
+}
+
+#[inline]
+pub fn mac16_16(c: i32, a: i16, b: i16) -> i32 {
+    c.wrapping_add(mult16_16(a, b))
+}
+
+#[inline]
+pub fn mult16_16_q13(a: i16, b: i16) -> i32 {
+    mult16_16(a, b) >> 13
+}
+
+#[inline]
+pub fn mult16_16_q14(a: i16, b: i16) -> i32 {
+    mult16_16(a, b) >> 14
+}
+
+#[inline]
+pub fn mult16_16_q15(a: i16, b: i16) -> i32 {
+    mult16_16(a, b) >> 15
+}
+
+#[inline]
+pub fn mult16_16_p13(a: i16, b: i16) -> i32 {
+    (4096 + mult16_16(a, b)) >> 13
+}
+
+#[inline]
+pub fn mult16_16_p14(a: i16, b: i16) -> i32 {
+    (8192 + mult16_16(a, b)) >> 14
+}
+
+#[inline]
+pub fn mult16_16_p15(a: i16, b: i16) -> i32 {
+    (16384 + mult16_16(a, b)) >> 15
+}
+
+/// `MULT16_32_Q15`: 16x32 multiply keeping the top bits, without a 64-bit product.
+#[inline]
+pub fn mult16_32_q15(a: i16, b: i32) -> i32 {
+    (a as i32)
+        .wrapping_mul(b >> 15)
+        .wrapping_add((a as i32 * (b & 0x7fff)) >> 15)
+}
+
+#[inline]
+pub fn mac16_32_q15(c: i32, a: i16, b: i32) -> i32 {
+    c.wrapping_add(mult16_32_q15(a, b))
+}
+
+/// `DIV32_16`: the quotient is truncated to 16 bits like in C.
+#[inline]
+pub fn div32_16(a: i32, b: i16) -> i16 {
+    a.wrapping_div(b as i32) as i16
+}
+
+#[inline]
+pub fn div32(a: i32, b: i32) -> i32 {
+    a.wrapping_div(b)
+}
+
+/// `PDIV32`: division rounded to nearest.
+#[inline]
+pub fn pdiv32(a: i32, b: i32) -> i32 {
+    a.wrapping_add(b >> 1).wrapping_div(b)
+}
+
+/// Fixed-point `WORD2INT`: saturate to the i16 range.
+#[inline]
+pub fn word2int(x: i32) -> i16 {
+    x.clamp(-32768, 32767) as i16
+}
+
+/// Index of the highest set bit (0 for 0 and 1).
+pub fn ilog2(mut x: u32) -> i32 {
+    let mut r = 0;
+    if x >= 65536 {
+        x >>= 16;
+        r += 16;
+    }
+    if x >= 256 {
+        x >>= 8;
+        r += 8;
+    }
+    if x >= 16 {
+        x >>= 4;
+        r += 4;
+    }
+    if x >= 4 {
+        x >>= 2;
+        r += 2;
+    }
+    if x >= 2 {
+        r += 1;
+    }
+    r
+}
+
+/// Integer base-4 logarithm.
+pub fn ilog4(mut x: u32) -> i32 {
+    let mut r = 0;
+    if x >= 65536 {
+        x >>= 16;
+        r += 8;
+    }
+    if x >= 256 {
+        x >>= 8;
+        r += 4;
+    }
+    if x >= 16 {
+        x >>= 4;
+        r += 2;
+    }
+    if x >= 4 {
+        r += 1;
+    }
+    r
+}
+
+/// Square root of a Q`2k` value in Q`k`.
+pub fn spx_sqrt(x: i32) -> i16 {
+    const C0: i16 = 3634;
+    const C1: i16 = 21173;
+    const C2: i16 = -12627;
+    const C3: i16 = 4215;
+    let k = ilog4(x as u32) - 6;
+    let x = vshr32(x, k << 1) as i16;
+    let rt = C0.wrapping_add(mult16_16_q14(
+        x,
+        C1.wrapping_add(mult16_16_q14(x, C2.wrapping_add(mult16_16_q14(x, C3) as i16)) as i16),
+    ) as i16);
+    vshr32(rt as i32, 7 - k) as i16
+}
+
+/// Cosine of a Q13 angle in `[0, pi]`, Q13 result.
+pub fn spx_cos(x: i16) -> i16 {
+    const K1: i32 = 8192;
+    const K2: i32 = -4096;
+    const K3: i32 = 340;
+    const K4: i16 = -10;
+    if x < 12868 {
+        let x2 = mult16_16_p13(x, x) as i16;
+        (K1 + mult16_16_p13(
+            x2,
+            (K2 + mult16_16_p13(x2, (K3 + mult16_16_p13(K4, x2)) as i16)) as i16,
+        )) as i16
+    } else {
+        let x = 25736i16.wrapping_sub(x);
+        let x2 = mult16_16_p13(x, x) as i16;
+        (-K1 - mult16_16_p13(
+            x2,
+            (K2 + mult16_16_p13(x2, (K3 + mult16_16_p13(K4, x2)) as i16)) as i16,
+        )) as i16
+    }
+}
+
+// cos(x * pi / 2) for x in Q15 [0, 1)
+fn cos_pi_2(x: i16) -> i16 {
+    const L1: i32 = 32767;
+    const L2: i32 = -7651;
+    const L3: i32 = 8277;
+    const L4: i16 = -626;
+    let x2 = mult16_16_p15(x, x) as i16;
+    let poly = (L1 - x2 as i32)
+        + mult16_16_p15(x2, (L2 + mult16_16_p15(x2, (L3 + mult16_16_p15(L4, x2)) as i16)) as i16);
+    1 + poly.min(32766) as i16
+}
+
+/// Cosine of `x * 2 * pi / 2^17`, Q15 result.
+pub fn spx_cos_norm(x: i32) -> i16 {
+    let mut x = x & 0x0001ffff;
+    if x > 1 << 16 {
+        x = (1 << 17) - x;
+    }
+    if x & 0x00007fff != 0 {
+        if x < 1 << 15 {
+            cos_pi_2(x as i16)
+        } else {
+            -cos_pi_2((65536 - x) as i16)
+        }
+    } else if x & 0x0000ffff != 0 {
+        0
+    } else if x & 0x0001ffff != 0 {
+        -32767
+    } else {
+        32767
+    }
+}
+
+// 2^x for x in Q11, Q16 result
+fn spx_exp2(x: i16) -> i32 {
+    const D0: i16 = 16384;
+    const D1: i16 = 11356;
+    const D2: i16 = 3726;
+    const D3: i16 = 1301;
+    let integer = (x >> 11) as i32;
+    if integer > 14 {
+        return 0x7fffffff;
+    } else if integer < -15 {
+        return 0;
+    }
+    let frac = ((x as i32 - (integer << 11)) << 3) as i16;
+    let frac = D0.wrapping_add(mult16_16_q14(
+        frac,
+        D1.wrapping_add(mult16_16_q14(frac, D2.wrapping_add(mult16_16_q14(D3, frac) as i16)) as i16),
+    ) as i16);
+    vshr32(frac as i32, -integer - 2)
+}
+
+/// e^x for x in Q11, Q16 result.
+pub fn spx_exp(x: i16) -> i32 {
+    if x > 21290 {
+        0x7fffffff
+    } else if x < -21290 {
+        0
+    } else {
+        spx_exp2(mult16_16_p14(23637, x) as i16)
+    }
+}
+
+/// Pseudo-float: a 16-bit mantissa normalized to `[16384, 32767]` in magnitude and a binary
+/// exponent, value `m * 2^e`. Used by the fixed-point echo canceller for quantities with too
+/// much dynamic range for a fixed Q format.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SpxFloat {
+    pub m: i16,
+    pub e: i16,
+}
+
+impl SpxFloat {
+    pub const ZERO: Self = Self { m: 0, e: 0 };
+    pub const ONE: Self = Self { m: 16384, e: -14 };
+    pub const HALF: Self = Self { m: 16384, e: -15 };
+
+    /// `PSEUDOFLOAT`
+    pub fn from_int(x: i32) -> Self {
+        if x == 0 {
+            return Self::ZERO;
+        }
+        let e = ilog2(x.unsigned_abs()) - 14;
+        let m = vshr32(x.wrapping_abs(), e);
+        let m = if x < 0 { m.wrapping_neg() } else { m };
+        Self { m: m as i16, e: e as i16 }
+    }
+
+    /// Nearest pseudo-float to `x` (used to read float-build snapshots).
+    pub fn from_f32(x: f32) -> Self {
+        if x == 0.0 || !x.is_finite() {
+            return Self::ZERO;
+        }
+        let mut e = x.abs().log2().floor() as i32 - 14;
+        let mut m = (x as f64 / 2f64.powi(e)).round() as i32;
+        if m.abs() > 32767 {
+            m /= 2;
+            e += 1;
+        }
+        Self { m: m as i16, e: e as i16 }
+    }
+
+    pub fn to_f32(self) -> f32 {
+        (self.m as f64 * 2f64.powi(self.e as i32)) as f32
+    }
+
+    fn normalize(mut self) -> Self {
+        if (self.m > 0 && self.m < 16384) || (self.m <= 0 && self.m > -16384) {
+            self.m = self.m.wrapping_shl(1);
+            self.e -= 1;
+        }
+        self
+    }
+
+    /// `FLOAT_ADD`
+    pub fn add(self, b: Self) -> Self {
+        if self.m == 0 {
+            return b;
+        } else if b.m == 0 {
+            return self;
+        }
+        let r = if self.e > b.e {
+            Self {
+                m: (self.m >> 1).wrapping_add(b.m >> (self.e - b.e + 1).min(15)),
+                e: self.e + 1,
+            }
+        } else {
+            Self {
+                m: (b.m >> 1).wrapping_add(self.m >> (b.e - self.e + 1).min(15)),
+                e: b.e + 1,
+            }
+        };
+        r.normalize()
+    }
+
+    /// `FLOAT_SUB`. Like upstream, returns `b` (not `-b`) when `self` is zero.
+    pub fn sub(self, b: Self) -> Self {
+        if self.m == 0 {
+            return b;
+        } else if b.m == 0 {
+            return self;
+        }
+        let r = if self.e > b.e {
+            Self {
+                m: (self.m >> 1).wrapping_sub(b.m >> (self.e - b.e + 1).min(15)),
+                e: self.e + 1,
+            }
+        } else {
+            Self {
+                m: (self.m >> (b.e - self.e + 1).min(15)).wrapping_sub(b.m >> 1),
+                e: b.e + 1,
+            }
+        };
+        r.normalize()
+    }
+
+    /// `FLOAT_LT`
+    pub fn lt(self, b: Self) -> bool {
+        if self.m == 0 {
+            b.m > 0
+        } else if b.m == 0 {
+            self.m < 0
+        } else if self.e > b.e {
+            (self.m >> 1) < (b.m >> (self.e - b.e + 1).min(15))
+        } else {
+            (b.m >> 1) > (self.m >> (b.e - self.e + 1).min(15))
+        }
+    }
+
+    /// `FLOAT_GT`
+    pub fn gt(self, b: Self) -> bool {
+        b.lt(self)
+    }
+
+    /// `FLOAT_MULT`
+    pub fn mult(self, b: Self) -> Self {
+        self.amult(b).normalize()
+    }
+
+    /// `FLOAT_AMULT`: multiply without renormalizing.
+    pub fn amult(self, b: Self) -> Self {
+        Self {
+            m: (mult16_16(self.m, b.m) >> 15) as i16,
+            e: self.e + b.e + 15,
+        }
+    }
+
+    /// `FLOAT_SHL`: multiply by `2^b`.
+    pub fn shl(self, b: i32) -> Self {
+        Self { m: self.m, e: self.e + b as i16 }
+    }
+
+    /// `FLOAT_EXTRACT16`: round to an integer, truncated to 16 bits.
+    pub fn extract16(self) -> i16 {
+        self.extract32() as i16
+    }
+
+    /// `FLOAT_EXTRACT32`: round to an integer.
+    pub fn extract32(self) -> i32 {
+        if self.e < 0 {
+            shr32((self.m as i32).wrapping_add(shl32(1, -self.e as i32 - 1)), -self.e as i32)
+        } else {
+            shl32(self.m as i32, self.e as i32)
+        }
+    }
+
+    /// `FLOAT_MUL32`: `self * b` as an integer.
+    pub fn mul32(self, b: i32) -> i32 {
+        vshr32(mult16_32_q15(self.m, b), -self.e as i32 - 15)
+    }
+
+    /// `FLOAT_MUL32U`: product of two integers as a pseudo-float.
+    pub fn mul32u(a: i32, b: i32) -> Self {
+        if a == 0 || b == 0 {
+            return Self::ZERO;
+        }
+        let e1 = ilog2(a.unsigned_abs());
+        let a = vshr32(a, e1 - 14) as i16;
+        let e2 = ilog2(b.unsigned_abs());
+        let b = vshr32(b, e2 - 14) as i16;
+        Self {
+            m: mult16_16_q15(a, b) as i16,
+            e: (e1 + e2 - 13) as i16,
+        }
+    }
+
+    /// `FLOAT_DIV32_FLOAT`: `a / b` for a positive `b`.
+    pub fn div32_float(a: i32, b: Self) -> Self {
+        if a == 0 {
+            return Self::ZERO;
+        }
+        let mut e = ilog2(a.unsigned_abs()) - ilog2((b.m as i32 - 1) as u32) - 15;
+        let mut a = vshr32(a, e);
+        if a.wrapping_abs() >= shl32(b.m as i32 - 1, 15) {
+            a >>= 1;
+            e += 1;
+        }
+        Self {
+            m: div32_16(a, b.m),
+            e: (e - b.e as i32) as i16,
+        }
+    }
+
+    /// `FLOAT_DIV32`: `a / b` for a positive `b`.
+    pub fn div32(a: i32, mut b: i32) -> Self {
+        if a == 0 {
+            return Self::ZERO;
+        }
+        let mut e0 = 0;
+        if b > 32767 {
+            e0 = ilog2(b as u32) - 14;
+            b = vshr32(b, e0);
+            e0 = -e0;
+        }
+        let mut e = ilog2(a.unsigned_abs()) - ilog2((b - 1) as u32) - 15;
+        let mut a = vshr32(a, e);
+        if a.wrapping_abs() >= shl32(b - 1, 15) {
+            a >>= 1;
+            e += 1;
+        }
+        e += e0;
+        Self {
+            m: div32_16(a, b as i16),
+            e: e as i16,
+        }
+    }
+
+    /// `FLOAT_DIVU`: `a / b` for a positive `b`; warns and returns one otherwise.
+    pub fn divu(a: Self, b: Self) -> Self {
+        if b.m <= 0 {
+            super::c2rust::alloc::warn_str(&format!("Attempted to divide by {}", b.m));
+            return Self::ONE;
+        }
+        let mut e = 0;
+        let mut am = (a.m as i32).abs();
+        while am >= b.m as i32 {
+            e += 1;
+            am >>= 1;
+        }
+        let num = vshr32(a.m as i32, e - 15);
+        Self {
+            m: div32_16(num, b.m),
+            e: (a.e as i32 - b.e as i32 - 15 + e) as i16,
+        }
+    }
+
+    /// `FLOAT_SQRT`
+    pub fn sqrt(self) -> Self {
+        let mut m = shl32(self.m as i32, 14);
+        let mut e = self.e as i32 - 14;
+        if e & 1 != 0 {
+            e -= 1;
+            m <<= 1;
+        }
+        Self { m: spx_sqrt(m), e: (e >> 1) as i16 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sqrt_matches_float() {
+        // Q14 in, Q7 out; the polynomial is good to about 1e-3 relative
+        for x in (1 << 10..1 << 28).step_by(1 << 16) {
+            let expected = ((x as f64) / 16384.0).sqrt();
+            let got = spx_sqrt(x) as f64 / 128.0;
+            assert!(
+                (got - expected).abs() <= expected * 2e-3 + 1.0 / 128.0,
+                "sqrt({x}): {got} vs {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn cos_matches_float() {
+        for x in (0..25736).step_by(7) {
+            let expected = (x as f64 / 8192.0).cos();
+            let got = spx_cos(x as i16) as f64 / 8192.0;
+            assert!((got - expected).abs() < 2e-3, "cos({x}): {got} vs {expected}");
+        }
+        for x in (0..1 << 17).step_by(13) {
+            let expected = (x as f64 * std::f64::consts::TAU / (1 << 17) as f64).cos();
+            let got = spx_cos_norm(x) as f64 / 32768.0;
+            assert!((got - expected).abs() < 1e-3, "cos_norm({x}): {got} vs {expected}");
+        }
+    }
+
+    #[test]
+    fn exp_matches_float() {
+        // the output is Q16 in an i32, so it saturates above e^10.4
+        for x in (-16384..21290).step_by(11) {
+            let expected = (x as f64 / 2048.0).exp();
+            let got = spx_exp(x as i16) as f64 / 65536.0;
+            assert!(
+                (got - expected).abs() <= expected * 2e-3 + 2.0 / 65536.0,
+                "exp({x}): {got} vs {expected}"
+            );
+        }
+        assert_eq!(spx_exp(i16::MAX), 0x7fffffff);
+        assert_eq!(spx_exp(i16::MIN), 0);
+    }
+
+    #[test]
+    fn spx_float_matches_f32() {
+        let values = [1e-6f32, 3.7e-3, 0.5, 1.0, 7.25, 1234.5, 6.0e6];
+        let close = |got: f32, expected: f32| (got - expected).abs() <= expected.abs() * 2e-4;
+        for &a in &values {
+            let fa = SpxFloat::from_f32(a);
+            assert!(close(fa.to_f32(), a), "{a} -> {}", fa.to_f32());
+            // FLOAT_SQRT goes through spx_sqrt's polynomial, so it's only good to 2e-3
+            let root = fa.sqrt().to_f32();
+            assert!((root - a.sqrt()).abs() <= a.sqrt() * 2e-3, "sqrt {a}: {root}");
+            for &b in &values {
+                let fb = SpxFloat::from_f32(b);
+                assert!(close(fa.mult(fb).to_f32(), a * b), "{a} * {b}");
+                assert!(close(SpxFloat::divu(fa, fb).to_f32(), a / b), "{a} / {b}");
+                // the sum only keeps the larger operand's 15 bits of precision
+                let max = a.max(b);
+                assert!((fa.add(fb).to_f32() - (a + b)).abs() <= max * 2e-4, "{a} + {b}");
+                assert_eq!(fa.lt(fb), a < b, "{a} < {b}");
+            }
+        }
+        assert_eq!(SpxFloat::from_int(0), SpxFloat::ZERO);
+        assert!(close(SpxFloat::from_int(-40000).to_f32(), -40000.0));
+    }
+}
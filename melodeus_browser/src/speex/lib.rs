@@ -20,11 +20,24 @@
 )]
 
 pub mod c2rust;
+pub mod fft;
+#[cfg(feature = "fixed-point")]
+pub mod fft_fixed;
+#[cfg(feature = "fixed-point")]
+pub mod fixed;
+#[cfg(not(feature = "fixed-point"))]
+pub mod mdf;
+#[cfg(feature = "fixed-point")]
+#[path = "mdf_fixed.rs"]
+pub mod mdf;
 
+use rustfft::{Fft, FftDirection, FftPlanner};
+use std::cell::RefCell;
 use std::convert::TryFrom;
 use std::ffi::{c_int, CStr};
 use std::fmt;
 use std::ptr::NonNull;
+use std::sync::Arc;
 
 pub use c2rust::speex_echo_h::{
     SPEEX_ECHO_GET_FRAME_SIZE, SPEEX_ECHO_GET_IMPULSE_RESPONSE,
@@ -36,6 +49,20 @@ pub use c2rust::mdf::{
     speex_echo_get_residual, speex_echo_playback, speex_echo_state_destroy, speex_echo_state_init,
     speex_echo_state_init_mc, speex_echo_state_reset, SpeexEchoState,
 };
+pub use c2rust::preprocess::{
+    speex_preprocess_ctl, speex_preprocess_estimate_update, speex_preprocess_run,
+    speex_preprocess_state_destroy, speex_preprocess_state_init, SpeexPreprocessState,
+};
+pub use c2rust::speex_preprocess_h::{
+    SPEEX_PREPROCESS_GET_AGC_GAIN, SPEEX_PREPROCESS_GET_PROB, SPEEX_PREPROCESS_SET_AGC,
+    SPEEX_PREPROCESS_SET_AGC_DECREMENT, SPEEX_PREPROCESS_SET_AGC_INCREMENT,
+    SPEEX_PREPROCESS_SET_AGC_LEVEL, SPEEX_PREPROCESS_SET_AGC_MAX_GAIN,
+    SPEEX_PREPROCESS_SET_DENOISE, SPEEX_PREPROCESS_SET_DEREVERB,
+    SPEEX_PREPROCESS_SET_DEREVERB_DECAY, SPEEX_PREPROCESS_SET_DEREVERB_LEVEL,
+    SPEEX_PREPROCESS_SET_ECHO_STATE, SPEEX_PREPROCESS_SET_ECHO_SUPPRESS,
+    SPEEX_PREPROCESS_SET_ECHO_SUPPRESS_ACTIVE, SPEEX_PREPROCESS_SET_NOISE_SUPPRESS,
+    SPEEX_PREPROCESS_SET_RESIDUAL_ECHO, SPEEX_PREPROCESS_SET_VAD,
+};
 pub use c2rust::resample::{
     speex_resampler_destroy, speex_resampler_get_input_latency, speex_resampler_get_output_latency,
     speex_resampler_get_quality, speex_resampler_get_rate, speex_resampler_init,
@@ -48,64 +75,64 @@ pub use c2rust::resample::{
     RESAMPLER_ERR_PTR_OVERLAP, RESAMPLER_ERR_SUCCESS,
 };
 
-/// Safe wrapper around the translated Speex echo canceller.
+thread_local! {
+    static FFT_PLANNER: RefCell<FftPlanner<f32>> = RefCell::new(FftPlanner::new());
+}
+
+/// Complex FFT of the given length, planned once per thread and shared with `fftwrap`.
+pub fn plan_fft(len: usize, direction: FftDirection) -> Arc<dyn Fft<f32>> {
+    FFT_PLANNER.with(|planner| planner.borrow_mut().plan_fft(len, direction))
+}
+
+/// Echo canceller built on the safe port of the Speex MDF algorithm ([`mdf::EchoState`]).
 pub struct EchoCanceller {
-    state: *mut SpeexEchoState,
+    state: mdf::EchoState,
     frame_size: usize,
     mic_channels: usize,
     speaker_channels: usize,
+    mic_buf: Vec<i16>,
+    speaker_buf: Vec<i16>,
+    out_buf: Vec<i16>,
+}
+
+fn f32_to_i16(sample: f32) -> i16 {
+    (sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16
+}
+
+fn i16_to_f32(sample: i16) -> f32 {
+    sample as f32 / i16::MAX as f32
 }
 
 impl EchoCanceller {
     /// Create a new echo canceller for the given configuration.
     pub fn new(frame_size: usize, filter_length: usize) -> Option<Self> {
-        unsafe {
-            let state = speex_echo_state_init(frame_size as c_int, filter_length as c_int);
-            if state.is_null() {
-                None
-            } else {
-                Some(Self {
-                    state,
-                    frame_size,
-                    mic_channels: 1,
-                    speaker_channels: 1,
-                })
-            }
-        }
+        Self::new_multichannel(frame_size, filter_length, 1, 1)
     }
 
-    /// Create a multi-channel echo canceller. Returns `None` on allocation failure.
+    /// Create a multi-channel echo canceller. Returns `None` for a zero frame size or channel count.
     pub fn new_multichannel(
         frame_size: usize,
         filter_length: usize,
         mics: usize,
         speakers: usize,
     ) -> Option<Self> {
-        unsafe {
-            let state = speex_echo_state_init_mc(
-                frame_size as c_int,
-                filter_length as c_int,
-                mics as c_int,
-                speakers as c_int,
-            );
-            if state.is_null() {
-                None
-            } else {
-                Some(Self {
-                    state,
-                    frame_size,
-                    mic_channels: mics,
-                    speaker_channels: speakers,
-                })
-            }
+        if frame_size == 0 || filter_length == 0 || mics == 0 || speakers == 0 {
+            return None;
         }
+        Some(Self {
+            state: mdf::EchoState::new(frame_size, filter_length, mics, speakers),
+            frame_size,
+            mic_channels: mics,
+            speaker_channels: speakers,
+            mic_buf: vec![0; frame_size * mics],
+            speaker_buf: vec![0; frame_size * speakers],
+            out_buf: vec![0; frame_size * mics],
+        })
     }
 
     /// Reset the internal filter and statistics.
     pub fn reset(&mut self) {
-        unsafe {
-            speex_echo_state_reset(self.state);
-        }
+        self.state.reset();
     }
 
     /// Process a frame of near-end (`mic`) and far-end (`speaker`) audio.
@@ -117,69 +144,447 @@ impl EchoCanceller {
         assert_eq!(mic.len(), self.frame_size * self.mic_channels);
         assert_eq!(speaker.len(), self.frame_size * self.speaker_channels);
         assert_eq!(out.len(), mic.len());
-        unsafe {
-            speex_echo_cancellation(self.state, mic.as_ptr(), speaker.as_ptr(), out.as_mut_ptr());
-        }
+        self.state.cancellation(mic, speaker, out);
     }
 
     /// Capture microphone audio using the internal playback delay buffer.
     pub fn capture(&mut self, mic: &[i16], out: &mut [i16]) {
         assert_eq!(mic.len(), self.frame_size * self.mic_channels);
         assert_eq!(out.len(), mic.len());
-        unsafe {
-            speex_echo_capture(self.state, mic.as_ptr(), out.as_mut_ptr());
-        }
+        self.state.capture(mic, out);
     }
 
     /// Provide the next loudspeaker frame for use with [`capture`].
     pub fn playback(&mut self, speaker: &[i16]) {
         assert_eq!(speaker.len(), self.frame_size * self.speaker_channels);
-        unsafe {
-            speex_echo_playback(self.state, speaker.as_ptr());
+        self.state.playback(speaker);
+    }
+
+    /// Same as [`cancel_frame`] for f32 samples in `[-1.0, 1.0]`.
+    pub fn cancel_frame_f32(&mut self, mic: &[f32], speaker: &[f32], out: &mut [f32]) {
+        assert_eq!(mic.len(), self.frame_size * self.mic_channels);
+        assert_eq!(speaker.len(), self.frame_size * self.speaker_channels);
+        assert_eq!(out.len(), mic.len());
+        for (dst, src) in self.mic_buf.iter_mut().zip(mic) {
+            *dst = f32_to_i16(*src);
+        }
+        for (dst, src) in self.speaker_buf.iter_mut().zip(speaker) {
+            *dst = f32_to_i16(*src);
+        }
+        self.state.cancellation(&self.mic_buf, &self.speaker_buf, &mut self.out_buf);
+        for (dst, src) in out.iter_mut().zip(&self.out_buf) {
+            *dst = i16_to_f32(*src);
         }
     }
 
+    /// Same as [`capture`] for f32 samples in `[-1.0, 1.0]`.
+    pub fn capture_f32(&mut self, mic: &[f32], out: &mut [f32]) {
+        assert_eq!(mic.len(), self.frame_size * self.mic_channels);
+        assert_eq!(out.len(), mic.len());
+        for (dst, src) in self.mic_buf.iter_mut().zip(mic) {
+            *dst = f32_to_i16(*src);
+        }
+        self.state.capture(&self.mic_buf, &mut self.out_buf);
+        for (dst, src) in out.iter_mut().zip(&self.out_buf) {
+            *dst = i16_to_f32(*src);
+        }
+    }
+
+    /// Same as [`playback`] for f32 samples in `[-1.0, 1.0]`.
+    pub fn playback_f32(&mut self, speaker: &[f32]) {
+        assert_eq!(speaker.len(), self.frame_size * self.speaker_channels);
+        for (dst, src) in self.speaker_buf.iter_mut().zip(speaker) {
+            *dst = f32_to_i16(*src);
+        }
+        self.state.playback(&self.speaker_buf);
+    }
+
     /// Update the sampling rate so Speex can tune its internal filters correctly.
     pub fn set_sampling_rate(&mut self, hz: u32) {
-        let mut val = hz as c_int;
-        unsafe {
-            speex_echo_ctl(
-                self.state,
-                SPEEX_ECHO_SET_SAMPLING_RATE,
-                &mut val as *mut _ as *mut _,
-            );
-        }
+        self.state.set_sampling_rate(hz);
     }
 
     /// Read back the sampling rate currently configured inside Speex.
     pub fn sampling_rate(&self) -> u32 {
+        self.state.sampling_rate()
+    }
+
+    /// Number of taps in each response returned by [`impulse_response`].
+    pub fn impulse_response_len(&self) -> usize {
+        self.state.impulse_response_len()
+    }
+
+    /// Learned echo path for every mic/speaker pair, indexed `[mic * speaker_channels + speaker]`.
+    ///
+    /// Taps are linear gains, read straight from the adaptive filter.
+    pub fn impulse_response(&self) -> Vec<Vec<f32>> {
+        self.state.impulse_response()
+    }
+
+    /// Copy out the converged adaptive filter so it can be restored in a later session.
+    pub fn snapshot(&self) -> EchoCancellerSnapshot {
+        self.state.snapshot()
+    }
+
+    /// Load a filter previously taken with [`snapshot`]. The layout must match this canceller.
+    pub fn restore(&mut self, snapshot: &EchoCancellerSnapshot) -> Result<(), SnapshotError> {
+        self.state.restore(snapshot)
+    }
+
+    /// Number of microphone channels this canceller was built for.
+    pub fn mic_channels(&self) -> usize {
+        self.mic_channels
+    }
+
+    /// Number of loudspeaker channels this canceller was built for.
+    pub fn speaker_channels(&self) -> usize {
+        self.speaker_channels
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotError {
+    Truncated,
+    BadMagic,
+    UnsupportedVersion(u32),
+    LayoutMismatch,
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Truncated => write!(f, "echo canceller snapshot is truncated"),
+            Self::BadMagic => write!(f, "not a speex echo canceller snapshot"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported echo canceller snapshot version {version}")
+            }
+            Self::LayoutMismatch => {
+                write!(f, "echo canceller snapshot was taken with a different frame size, filter length, sampling rate or channel count")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+const SNAPSHOT_MAGIC: &[u8; 4] = b"SPXE";
+const SNAPSHOT_VERSION: u32 = 1;
+
+/// Converged state of an [`EchoCanceller`] (adaptive filters and their step-size statistics).
+#[derive(Debug, Clone, PartialEq)]
+pub struct EchoCancellerSnapshot {
+    pub frame_size: usize,
+    pub filter_blocks: usize,
+    pub mic_channels: usize,
+    pub speaker_channels: usize,
+    pub sampling_rate: u32,
+    adapted: bool,
+    sum_adapt: f32,
+    leak_estimate: f32,
+    pey: f32,
+    pyy: f32,
+    davg: [f32; 2],
+    dvar: [f32; 2],
+    background: Vec<f32>,
+    foreground: Vec<f32>,
+    prop: Vec<f32>,
+    power: Vec<f32>,
+    power_1: Vec<f32>,
+    eh: Vec<f32>,
+    yh: Vec<f32>,
+}
+
+struct SnapshotReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl SnapshotReader<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8], SnapshotError> {
+        if self.bytes.len() < len {
+            return Err(SnapshotError::Truncated);
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> Result<u32, SnapshotError> {
+        let mut raw = [0u8; 4];
+        raw.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(raw))
+    }
+
+    fn f32(&mut self) -> Result<f32, SnapshotError> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+
+    fn f32_vec(&mut self) -> Result<Vec<f32>, SnapshotError> {
+        let len = self.u32()? as usize;
+        if self.bytes.len() / 4 < len {
+            return Err(SnapshotError::Truncated);
+        }
+        (0..len).map(|_| self.f32()).collect()
+    }
+}
+
+impl EchoCancellerSnapshot {
+    /// Serialize into a compact little-endian byte blob.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(
+            64 + 4 * (self.background.len() + self.foreground.len() + 5 * self.power.len()),
+        );
+        let put_u32 = |out: &mut Vec<u8>, v: u32| out.extend_from_slice(&v.to_le_bytes());
+        out.extend_from_slice(SNAPSHOT_MAGIC);
+        put_u32(&mut out, SNAPSHOT_VERSION);
+        put_u32(&mut out, self.frame_size as u32);
+        put_u32(&mut out, self.filter_blocks as u32);
+        put_u32(&mut out, self.mic_channels as u32);
+        put_u32(&mut out, self.speaker_channels as u32);
+        put_u32(&mut out, self.sampling_rate);
+        put_u32(&mut out, self.adapted as u32);
+        for v in [
+            self.sum_adapt,
+            self.leak_estimate,
+            self.pey,
+            self.pyy,
+            self.davg[0],
+            self.davg[1],
+            self.dvar[0],
+            self.dvar[1],
+        ] {
+            put_u32(&mut out, v.to_bits());
+        }
+        for values in [
+            &self.background,
+            &self.foreground,
+            &self.prop,
+            &self.power,
+            &self.power_1,
+            &self.eh,
+            &self.yh,
+        ] {
+            put_u32(&mut out, values.len() as u32);
+            for v in values.iter() {
+                put_u32(&mut out, v.to_bits());
+            }
+        }
+        out
+    }
+
+    /// Parse a blob produced by [`to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        let mut reader = SnapshotReader { bytes };
+        if reader.take(4)? != SNAPSHOT_MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+        let version = reader.u32()?;
+        if version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        Ok(Self {
+            frame_size: reader.u32()? as usize,
+            filter_blocks: reader.u32()? as usize,
+            mic_channels: reader.u32()? as usize,
+            speaker_channels: reader.u32()? as usize,
+            sampling_rate: reader.u32()?,
+            adapted: reader.u32()? != 0,
+            sum_adapt: reader.f32()?,
+            leak_estimate: reader.f32()?,
+            pey: reader.f32()?,
+            pyy: reader.f32()?,
+            davg: [reader.f32()?, reader.f32()?],
+            dvar: [reader.f32()?, reader.f32()?],
+            background: reader.f32_vec()?,
+            foreground: reader.f32_vec()?,
+            prop: reader.f32_vec()?,
+            power: reader.f32_vec()?,
+            power_1: reader.f32_vec()?,
+            eh: reader.f32_vec()?,
+            yh: reader.f32_vec()?,
+        })
+    }
+}
+
+/// Safe wrapper around the translated Speex preprocessor (noise suppression, AGC, dereverb).
+pub struct Preprocessor {
+    state: *mut SpeexPreprocessState,
+    frame_size: usize,
+    buf: Vec<i16>,
+    residual_echo: Vec<f32>,
+}
+
+impl Preprocessor {
+    /// Create a preprocessor for mono frames of `frame_size` samples.
+    pub fn new(frame_size: usize, sampling_rate: u32) -> Option<Self> {
+        unsafe {
+            let state = speex_preprocess_state_init(frame_size as c_int, sampling_rate as c_int);
+            if state.is_null() {
+                None
+            } else {
+                Some(Self {
+                    state,
+                    frame_size,
+                    buf: vec![0; frame_size],
+                    residual_echo: vec![0.0; frame_size + 1],
+                })
+            }
+        }
+    }
+
+    fn set_int(&mut self, request: c_int, value: i32) {
+        let mut val = value as c_int;
+        unsafe {
+            speex_preprocess_ctl(self.state, request, &mut val as *mut _ as *mut _);
+        }
+    }
+
+    fn set_float(&mut self, request: c_int, value: f32) {
+        let mut val = value;
+        unsafe {
+            speex_preprocess_ctl(self.state, request, &mut val as *mut _ as *mut _);
+        }
+    }
+
+    fn get_int(&self, request: c_int) -> i32 {
         let mut val: c_int = 0;
         unsafe {
-            speex_echo_ctl(
+            speex_preprocess_ctl(self.state, request, &mut val as *mut _ as *mut _);
+        }
+        val as i32
+    }
+
+    /// Enable or disable noise suppression (on by default).
+    pub fn set_denoise(&mut self, enabled: bool) {
+        self.set_int(SPEEX_PREPROCESS_SET_DENOISE, enabled as i32);
+    }
+
+    /// Maximum attenuation of the noise in dB (negative, default -15).
+    pub fn set_noise_suppress(&mut self, db: i32) {
+        self.set_int(SPEEX_PREPROCESS_SET_NOISE_SUPPRESS, db);
+    }
+
+    /// Enable or disable automatic gain control (off by default).
+    pub fn set_agc(&mut self, enabled: bool) {
+        self.set_int(SPEEX_PREPROCESS_SET_AGC, enabled as i32);
+    }
+
+    /// AGC target level in i16 sample units (default 8000).
+    pub fn set_agc_level(&mut self, level: f32) {
+        self.set_float(SPEEX_PREPROCESS_SET_AGC_LEVEL, level);
+    }
+
+    /// Maximum AGC gain in dB (default 30).
+    pub fn set_agc_max_gain(&mut self, db: i32) {
+        self.set_int(SPEEX_PREPROCESS_SET_AGC_MAX_GAIN, db);
+    }
+
+    /// Maximum AGC gain increase in dB per second (default 12).
+    pub fn set_agc_increment(&mut self, db_per_sec: i32) {
+        self.set_int(SPEEX_PREPROCESS_SET_AGC_INCREMENT, db_per_sec);
+    }
+
+    /// Maximum AGC gain decrease in dB per second (negative, default -40).
+    pub fn set_agc_decrement(&mut self, db_per_sec: i32) {
+        self.set_int(SPEEX_PREPROCESS_SET_AGC_DECREMENT, db_per_sec);
+    }
+
+    /// Current AGC gain in dB.
+    pub fn agc_gain(&self) -> i32 {
+        self.get_int(SPEEX_PREPROCESS_GET_AGC_GAIN)
+    }
+
+    /// Enable or disable dereverberation (off by default).
+    pub fn set_dereverb(&mut self, enabled: bool) {
+        self.set_int(SPEEX_PREPROCESS_SET_DEREVERB, enabled as i32);
+    }
+
+    /// Dereverberation level.
+    pub fn set_dereverb_level(&mut self, level: f32) {
+        self.set_float(SPEEX_PREPROCESS_SET_DEREVERB_LEVEL, level);
+    }
+
+    /// Dereverberation decay.
+    pub fn set_dereverb_decay(&mut self, decay: f32) {
+        self.set_float(SPEEX_PREPROCESS_SET_DEREVERB_DECAY, decay);
+    }
+
+    /// Enable or disable voice activity detection (the return value of [`run`]).
+    pub fn set_vad(&mut self, enabled: bool) {
+        self.set_int(SPEEX_PREPROCESS_SET_VAD, enabled as i32);
+    }
+
+    /// Maximum attenuation of the residual echo in dB (negative, default -40).
+    pub fn set_echo_suppress(&mut self, db: i32) {
+        self.set_int(SPEEX_PREPROCESS_SET_ECHO_SUPPRESS, db);
+    }
+
+    /// Maximum attenuation of the residual echo in dB while near-end speech is active (default -15).
+    pub fn set_echo_suppress_active(&mut self, db: i32) {
+        self.set_int(SPEEX_PREPROCESS_SET_ECHO_SUPPRESS_ACTIVE, db);
+    }
+
+    /// Probability that the last frame was speech, in `[0.0, 1.0]`.
+    pub fn speech_probability(&self) -> f32 {
+        self.get_int(SPEEX_PREPROCESS_GET_PROB) as f32 / 100.0
+    }
+
+    /// Process one frame in place. Returns the VAD decision (always `true` when VAD is off).
+    pub fn run(&mut self, frame: &mut [i16]) -> bool {
+        assert_eq!(frame.len(), self.frame_size);
+        unsafe { speex_preprocess_run(self.state, frame.as_mut_ptr()) != 0 }
+    }
+
+    /// Same as [`run`] for f32 samples in `[-1.0, 1.0]`.
+    pub fn run_f32(&mut self, frame: &mut [f32]) -> bool {
+        assert_eq!(frame.len(), self.frame_size);
+        for (dst, src) in self.buf.iter_mut().zip(frame.iter()) {
+            *dst = f32_to_i16(*src);
+        }
+        let speech = unsafe { speex_preprocess_run(self.state, self.buf.as_mut_ptr()) != 0 };
+        for (dst, src) in frame.iter_mut().zip(&self.buf) {
+            *dst = i16_to_f32(*src);
+        }
+        speech
+    }
+
+    /// Same as [`run_f32`], also suppressing the residual echo estimated by `echo`.
+    ///
+    /// `echo` must have just processed the frame being passed in and use the same frame size.
+    pub fn run_with_echo(&mut self, frame: &mut [f32], echo: &mut EchoCanceller) -> bool {
+        assert_eq!(echo.frame_size, self.frame_size);
+        echo.state.residual_echo(&mut self.residual_echo);
+        unsafe {
+            speex_preprocess_ctl(
                 self.state,
-                SPEEX_ECHO_GET_SAMPLING_RATE,
-                &mut val as *mut _ as *mut _,
+                SPEEX_PREPROCESS_SET_RESIDUAL_ECHO,
+                self.residual_echo.as_mut_ptr() as *mut _,
             );
         }
-        val as u32
+        self.run_f32(frame)
     }
 
-    /// Raw pointer to the underlying Speex echo state (for advanced use).
-    pub fn as_ptr(&self) -> *mut SpeexEchoState {
-        self.state
+    /// Update the noise estimate without modifying the frame (e.g. while the output is muted).
+    pub fn estimate_update(&mut self, frame: &[f32]) {
+        assert_eq!(frame.len(), self.frame_size);
+        for (dst, src) in self.buf.iter_mut().zip(frame) {
+            *dst = f32_to_i16(*src);
+        }
+        unsafe {
+            speex_preprocess_estimate_update(self.state, self.buf.as_mut_ptr());
+        }
     }
 }
 
-unsafe impl Send for EchoCanceller {}
-
-impl Drop for EchoCanceller {
+impl Drop for Preprocessor {
     fn drop(&mut self) {
         unsafe {
-            speex_echo_state_destroy(self.state);
+            speex_preprocess_state_destroy(self.state);
         }
     }
 }
 
+unsafe impl Send for Preprocessor {}
+
 unsafe impl Send for Resampler {}
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -241,6 +646,10 @@ impl std::error::Error for ResamplerError {}
 pub struct Resampler {
     state: NonNull<SpeexResamplerState>,
     channels: usize,
+    #[cfg(feature = "fixed-point")]
+    in_buf: Vec<i16>,
+    #[cfg(feature = "fixed-point")]
+    out_buf: Vec<i16>,
 }
 
 impl Resampler {
@@ -263,7 +672,14 @@ impl Resampler {
             }
             return Err(err);
         }
-        Ok(Self { state, channels })
+        Ok(Self {
+            state,
+            channels,
+            #[cfg(feature = "fixed-point")]
+            in_buf: Vec::new(),
+            #[cfg(feature = "fixed-point")]
+            out_buf: Vec::new(),
+        })
     }
 
     /// Create a resampler with integer in/out sample rates.
@@ -340,6 +756,7 @@ impl Resampler {
     }
 
     /// Resample interleaved 32-bit float audio. Returns (consumed_samples, produced_samples).
+    #[cfg(not(feature = "fixed-point"))]
     pub fn process_interleaved_f32(
         &mut self,
         input: &[f32],
@@ -363,6 +780,32 @@ impl Resampler {
         ))
     }
 
+    /// Resample interleaved 32-bit float audio. Returns (consumed_samples, produced_samples).
+    ///
+    /// The fixed-point kernels run on 16-bit samples, so this goes through the integer path.
+    #[cfg(feature = "fixed-point")]
+    pub fn process_interleaved_f32(
+        &mut self,
+        input: &[f32],
+        output: &mut [f32],
+    ) -> Result<(usize, usize), ResamplerError> {
+        let mut in_buf = std::mem::take(&mut self.in_buf);
+        let mut out_buf = std::mem::take(&mut self.out_buf);
+        in_buf.clear();
+        in_buf.extend(input.iter().map(|&sample| f32_to_i16(sample)));
+        out_buf.clear();
+        out_buf.resize(output.len(), 0);
+        let result = self.process_interleaved_i16(&in_buf, &mut out_buf);
+        if let Ok((_, produced)) = result {
+            for (dst, &src) in output[..produced].iter_mut().zip(&out_buf) {
+                *dst = i16_to_f32(src);
+            }
+        }
+        self.in_buf = in_buf;
+        self.out_buf = out_buf;
+        result
+    }
+
     /// Update the input/output sample rates.
     pub fn set_rate(&mut self, in_rate: u32, out_rate: u32) -> Result<(), ResamplerError> {
         let code = unsafe { speex_resampler_set_rate(self.state.as_ptr(), in_rate, out_rate) };
@@ -444,3 +887,248 @@ impl Drop for Resampler {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME_SIZE: usize = 64;
+    const FILTER_LENGTH: usize = 256;
+
+    // deterministic noise in i16 range, scaled by `gain`
+    fn noise(seed: &mut u32, len: usize, gain: f32) -> Vec<i16> {
+        (0..len)
+            .map(|_| {
+                *seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                ((*seed >> 16) as i16 as f32 * gain) as i16
+            })
+            .collect()
+    }
+
+    // speaker noise through a short two-tap echo path, plus a little near-end noise
+    fn run_frames(aec: &mut EchoCanceller, seed: &mut u32, frames: usize) -> Vec<i16> {
+        let mut history = vec![0i16; FRAME_SIZE];
+        let mut out_all = Vec::with_capacity(frames * FRAME_SIZE);
+        for _ in 0..frames {
+            let speaker = noise(seed, FRAME_SIZE, 0.3);
+            let near = noise(seed, FRAME_SIZE, 0.01);
+            let mic: Vec<i16> = (0..FRAME_SIZE)
+                .map(|i| {
+                    let delayed = |d: usize| {
+                        if i >= d { speaker[i - d] } else { history[FRAME_SIZE + i - d] }
+                    };
+                    (delayed(5) as f32 * 0.5 + delayed(12) as f32 * 0.2 + near[i] as f32) as i16
+                })
+                .collect();
+            let mut out = vec![0i16; FRAME_SIZE];
+            aec.cancel_frame(&mic, &speaker, &mut out);
+            out_all.extend_from_slice(&out);
+            history = speaker;
+        }
+        out_all
+    }
+
+    #[test]
+    fn snapshot_round_trips_through_bytes() {
+        let mut aec = EchoCanceller::new(FRAME_SIZE, FILTER_LENGTH).unwrap();
+        aec.set_sampling_rate(16_000);
+        let mut seed = 1;
+        run_frames(&mut aec, &mut seed, 200);
+
+        let snapshot = aec.snapshot();
+        let parsed = EchoCancellerSnapshot::from_bytes(&snapshot.to_bytes()).unwrap();
+        assert_eq!(parsed, snapshot);
+
+        // the snapshot holds the filter, not the signal history, so a restored canceller
+        // cancels from the first frames while a fresh one is still converging
+        let mut restored = EchoCanceller::new(FRAME_SIZE, FILTER_LENGTH).unwrap();
+        restored.set_sampling_rate(16_000);
+        restored.restore(&parsed).unwrap();
+        assert_eq!(restored.snapshot(), snapshot);
+        let mut fresh = EchoCanceller::new(FRAME_SIZE, FILTER_LENGTH).unwrap();
+        fresh.set_sampling_rate(16_000);
+        let energy = |out: Vec<i16>| out.iter().map(|&s| (s as f64).powi(2)).sum::<f64>();
+        let (mut restored_seed, mut fresh_seed) = (seed, seed);
+        let restored_energy = energy(run_frames(&mut restored, &mut restored_seed, 20));
+        let fresh_energy = energy(run_frames(&mut fresh, &mut fresh_seed, 20));
+        assert!(restored_energy * 10.0 < fresh_energy, "restored {restored_energy}, fresh {fresh_energy}");
+    }
+
+    #[test]
+    fn snapshot_rejects_bad_input() {
+        let mut aec = EchoCanceller::new(FRAME_SIZE, FILTER_LENGTH).unwrap();
+        aec.set_sampling_rate(16_000);
+        let bytes = aec.snapshot().to_bytes();
+
+        assert_eq!(EchoCancellerSnapshot::from_bytes(&bytes[..bytes.len() - 1]), Err(SnapshotError::Truncated));
+        assert_eq!(EchoCancellerSnapshot::from_bytes(&bytes[..2]), Err(SnapshotError::Truncated));
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] ^= 0xff;
+        assert_eq!(EchoCancellerSnapshot::from_bytes(&bad_magic), Err(SnapshotError::BadMagic));
+        let mut bad_version = bytes.clone();
+        bad_version[4] = 99;
+        assert_eq!(EchoCancellerSnapshot::from_bytes(&bad_version), Err(SnapshotError::UnsupportedVersion(99)));
+
+        let snapshot = EchoCancellerSnapshot::from_bytes(&bytes).unwrap();
+        let mut other_rate = EchoCanceller::new(FRAME_SIZE, FILTER_LENGTH).unwrap();
+        other_rate.set_sampling_rate(48_000);
+        assert_eq!(other_rate.restore(&snapshot), Err(SnapshotError::LayoutMismatch));
+        let mut other_layout = EchoCanceller::new_multichannel(FRAME_SIZE, FILTER_LENGTH, 1, 2).unwrap();
+        other_layout.set_sampling_rate(16_000);
+        assert_eq!(other_layout.restore(&snapshot), Err(SnapshotError::LayoutMismatch));
+    }
+
+    // least-squares fit of a sine at `w` radians per sample, returns the energy of the fit and of what's left
+    fn fit_sine(output: &[f32], w: f64) -> (f64, f64) {
+        let (mut ss, mut sc, mut cc, mut ys, mut yc) = (0.0, 0.0, 0.0, 0.0, 0.0);
+        for (i, &y) in output.iter().enumerate() {
+            let (s, c) = (w * i as f64).sin_cos();
+            ss += s * s;
+            sc += s * c;
+            cc += c * c;
+            ys += y as f64 * s;
+            yc += y as f64 * c;
+        }
+        let det = ss * cc - sc * sc;
+        let (a, b) = ((ys * cc - yc * sc) / det, (yc * ss - ys * sc) / det);
+        let (mut signal, mut noise) = (0.0, 0.0);
+        for (i, &y) in output.iter().enumerate() {
+            let (s, c) = (w * i as f64).sin_cos();
+            let fit = a * s + b * c;
+            signal += fit * fit;
+            noise += (y as f64 - fit).powi(2);
+        }
+        (signal, noise)
+    }
+
+    // resample a sine and fit it back; the residual is everything the resampler got wrong
+    fn resampled_sine_snr_db(in_rate: u32, out_rate: u32, hz: f64) -> f64 {
+        let mut resampler = Resampler::new(1, in_rate, out_rate, 5).unwrap();
+        let input: Vec<f32> =
+            (0..in_rate as usize).map(|i| 0.5 * (std::f64::consts::TAU * hz * i as f64 / in_rate as f64).sin() as f32).collect();
+        let mut output = vec![0.0f32; out_rate as usize + 64];
+        let (_, produced) = resampler.process_interleaved_f32(&input, &mut output).unwrap();
+        // skip the filter's startup and tail
+        let output = &output[out_rate as usize / 10..produced - out_rate as usize / 10];
+        let (signal, noise) = fit_sine(output, std::f64::consts::TAU * hz / out_rate as f64);
+        10.0 * (signal / noise).log10()
+    }
+
+    #[test]
+    fn resampler_keeps_sine_clean() {
+        // the fixed-point build resamples 16-bit samples with 16-bit filter taps, which costs
+        // about 30 dB against float at quality 5, but stays well under 16-bit quantization noise
+        let floor = if cfg!(feature = "fixed-point") { 70.0 } else { 100.0 };
+        for (in_rate, out_rate) in [(16_000, 48_000), (44_100, 48_000), (48_000, 16_000)] {
+            for hz in [440.0, 3_000.0] {
+                let snr = resampled_sine_snr_db(in_rate, out_rate, hz);
+                assert!(snr > floor, "{in_rate} -> {out_rate} at {hz} Hz: {snr} dB");
+            }
+        }
+    }
+
+    const PREPROCESS_RATE: u32 = 16_000;
+    const PREPROCESS_FRAME: usize = 160;
+
+    fn to_f32(samples: Vec<i16>) -> Vec<f32> {
+        samples.into_iter().map(i16_to_f32).collect()
+    }
+
+    // half a second on, half a second off, like speech and unlike the stationary noise the preprocessor learns
+    const BURST_LEN: usize = PREPROCESS_RATE as usize / 2;
+
+    fn tone_burst(i: usize, w: f64, amplitude: f32) -> f32 {
+        if (i / BURST_LEN) % 2 == 0 { amplitude * (w * i as f64).sin() as f32 } else { 0.0 }
+    }
+
+    #[test]
+    fn preprocessor_suppresses_noise_and_keeps_tone() {
+        let mut seed = 7;
+        let len = 8 * BURST_LEN;
+        let w = std::f64::consts::TAU * 1_000.0 / PREPROCESS_RATE as f64;
+        let noise = to_f32(noise(&mut seed, len, 0.05));
+        let input: Vec<f32> = (0..len).map(|i| tone_burst(i, w, 0.2) + noise[i]).collect();
+        let mut output = input.clone();
+        let mut preprocessor = Preprocessor::new(PREPROCESS_FRAME, PREPROCESS_RATE).unwrap();
+        for frame in output.chunks_exact_mut(PREPROCESS_FRAME) {
+            preprocessor.run_f32(frame);
+        }
+        // the last burst and the gap after it, once the noise estimate has settled
+        let burst = len - 2 * BURST_LEN + BURST_LEN / 4..len - BURST_LEN;
+        let gap = len - BURST_LEN + BURST_LEN / 4..len;
+        let energy = |x: &[f32]| x.iter().map(|&s| (s as f64).powi(2)).sum::<f64>();
+        let attenuation_db = 10.0 * (energy(&input[gap.clone()]) / energy(&output[gap])).log10();
+        let (tone_in, _) = fit_sine(&input[burst.clone()], w);
+        let (tone_out, _) = fit_sine(&output[burst], w);
+        let tone_db = 10.0 * (tone_out / tone_in).log10();
+        assert!(attenuation_db > 8.0, "noise only down {attenuation_db} dB");
+        assert!(tone_db.abs() < 1.0, "tone changed by {tone_db} dB");
+    }
+
+    #[test]
+    fn preprocessor_agc_raises_a_quiet_tone() {
+        let len = 12 * BURST_LEN;
+        let w = std::f64::consts::TAU * 500.0 / PREPROCESS_RATE as f64;
+        let input: Vec<f32> = (0..len).map(|i| tone_burst(i, w, 0.01)).collect();
+        let mut output = input.clone();
+        let mut preprocessor = Preprocessor::new(PREPROCESS_FRAME, PREPROCESS_RATE).unwrap();
+        preprocessor.set_denoise(false);
+        preprocessor.set_agc(true);
+        for frame in output.chunks_exact_mut(PREPROCESS_FRAME) {
+            preprocessor.run_f32(frame);
+        }
+        let tail = len - 2 * BURST_LEN + BURST_LEN / 4..len - BURST_LEN;
+        let (tone_in, _) = fit_sine(&input[tail.clone()], w);
+        let (tone_out, _) = fit_sine(&output[tail], w);
+        let gain_db = 10.0 * (tone_out / tone_in).log10();
+        assert!(gain_db > 12.0, "agc gain {gain_db} dB, reports {}", preprocessor.agc_gain());
+    }
+
+    // speaker noise that stops and starts, so the noise estimate can't learn the echo away, through an echo
+    // path the canceller only partly removes. Returns the residual energy with and without the echo link
+    fn residual_echo_energy(linked: bool) -> f64 {
+        let mut seed = 11;
+        let frames = 500;
+        let len = frames * PREPROCESS_FRAME;
+        let speaker: Vec<f32> = to_f32(noise(&mut seed, len, 0.3))
+            .into_iter()
+            .enumerate()
+            .map(|(i, s)| if (i / PREPROCESS_FRAME / 25) % 2 == 0 { s } else { 0.0 })
+            .collect();
+        let near = to_f32(noise(&mut seed, len, 0.003));
+        let mic: Vec<f32> = (0..len)
+            .map(|i| {
+                let delayed = |d: usize| if i >= d { speaker[i - d] } else { 0.0 };
+                let echo = 0.5 * delayed(40) + 0.2 * delayed(100);
+                // the loudspeaker distorts, which no linear filter can take out
+                echo + 2.0 * echo.powi(3) + near[i]
+            })
+            .collect();
+        let mut aec = EchoCanceller::new(PREPROCESS_FRAME, 1600).unwrap();
+        aec.set_sampling_rate(PREPROCESS_RATE);
+        let mut preprocessor = Preprocessor::new(PREPROCESS_FRAME, PREPROCESS_RATE).unwrap();
+        let mut out = vec![0.0; PREPROCESS_FRAME];
+        let mut energy = 0.0;
+        for (frame, (mic, speaker)) in mic.chunks_exact(PREPROCESS_FRAME).zip(speaker.chunks_exact(PREPROCESS_FRAME)).enumerate() {
+            aec.cancel_frame_f32(mic, speaker, &mut out);
+            if linked {
+                preprocessor.run_with_echo(&mut out, &mut aec);
+            } else {
+                preprocessor.run_f32(&mut out);
+            }
+            // once the canceller has converged
+            if frame >= 100 {
+                energy += out.iter().map(|&s| (s as f64).powi(2)).sum::<f64>();
+            }
+        }
+        energy
+    }
+
+    #[test]
+    fn preprocessor_suppresses_residual_echo_when_linked() {
+        let linked = residual_echo_energy(true);
+        let unlinked = residual_echo_energy(false);
+        let extra_db = 10.0 * (unlinked / linked).log10();
+        assert!(extra_db > 8.0, "linking only took another {extra_db} dB off");
+    }
+}
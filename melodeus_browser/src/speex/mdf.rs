@@ -0,0 +1,1038 @@
+//! Safe port of the speex MDF echo canceller (float build), replacing the c2rust translation
+//! in `c2rust/mdf.rs` for [`EchoCanceller`](super::EchoCanceller).
+//!
+//! This is the same algorithm with the same floating point operation order, including
+//! upstream's quirks (e.g. `reset` only clears the first channel's filter). With the
+//! `rustfft-fftwrap` feature (the default) both versions share one FFT and produce bit-identical
+//! output, which is what keeps the two interchangeable.
+
+use super::c2rust::alloc;
+use super::fft::RealFft;
+use super::{EchoCancellerSnapshot, SnapshotError};
+
+const MIN_LEAK: f32 = 0.005;
+const VAR1_SMOOTH: f32 = 0.36;
+const VAR2_SMOOTH: f32 = 0.7225;
+const VAR1_UPDATE: f32 = 0.5;
+const VAR2_UPDATE: f32 = 0.25;
+const VAR_BACKTRACK: f32 = 4.0;
+const PLAYBACK_DELAY: usize = 2;
+
+/// MDF (multidelay block frequency domain) adaptive filter state.
+///
+/// Time-domain buffers are lower case (`x`, `y`, `e`), their packed spectra end in `_freq`.
+/// Filters are laid out `[mic][block][speaker][bin]`.
+pub struct EchoState {
+    frame_size: usize,
+    window_size: usize,
+    // number of filter blocks (filter length / frame size, rounded up)
+    m: usize,
+    mics: usize,
+    speakers: usize,
+    cancel_count: i32,
+    adapted: bool,
+    saturated: i32,
+    screwed_up: i32,
+    sampling_rate: i32,
+    spec_average: f32,
+    beta0: f32,
+    beta_max: f32,
+    sum_adapt: f32,
+    leak_estimate: f32,
+
+    e: Vec<f32>,
+    x: Vec<f32>,
+    x_freq: Vec<f32>,
+    input: Vec<f32>,
+    y: Vec<f32>,
+    last_y: Vec<f32>,
+    y_freq: Vec<f32>,
+    e_freq: Vec<f32>,
+    phi: Vec<f32>,
+    // background (adaptive) filter
+    w: Vec<f32>,
+    foreground: Vec<f32>,
+    davg1: f32,
+    davg2: f32,
+    dvar1: f32,
+    dvar2: f32,
+    power: Vec<f32>,
+    power_1: Vec<f32>,
+    wtmp: Vec<f32>,
+    rf: Vec<f32>,
+    yf: Vec<f32>,
+    xf: Vec<f32>,
+    eh: Vec<f32>,
+    yh: Vec<f32>,
+    pey: f32,
+    pyy: f32,
+    window: Vec<f32>,
+    prop: Vec<f32>,
+    fft: RealFft,
+    mem_x: Vec<f32>,
+    mem_d: Vec<f32>,
+    mem_e: Vec<f32>,
+    preemph: f32,
+    notch_radius: f32,
+    notch_mem: Vec<f32>,
+    play_buf: Vec<i16>,
+    play_buf_pos: usize,
+    play_buf_started: bool,
+}
+
+fn notch_radius(sampling_rate: i32) -> f32 {
+    if sampling_rate < 12000 {
+        0.9
+    } else if sampling_rate < 24000 {
+        0.982
+    } else {
+        0.992
+    }
+}
+
+// DC rejection on every `stride`-th sample of `input`
+fn filter_dc_notch16(input: &[i16], stride: usize, radius: f32, out: &mut [f32], mem: &mut [f32]) {
+    let den2 = ((radius * radius) as f64 + 0.7 * (1.0 - radius) as f64 * (1.0 - radius) as f64) as f32;
+    for (i, out) in out.iter_mut().enumerate() {
+        let vin = input[i * stride] as f32;
+        let vout = mem[0] + vin;
+        mem[0] = mem[1] + 2.0 * (-vin + radius * vout);
+        mem[1] = vin - den2 * vout;
+        *out = radius * vout;
+    }
+}
+
+fn mdf_inner_prod(x: &[f32], y: &[f32]) -> f32 {
+    let mut sum = 0.0f32;
+    for (x, y) in x.chunks_exact(2).zip(y.chunks_exact(2)) {
+        let part = 0.0 + x[0] * y[0] + x[1] * y[1];
+        sum += part;
+    }
+    sum
+}
+
+fn power_spectrum(x: &[f32], ps: &mut [f32]) {
+    let n = x.len();
+    ps[0] = x[0] * x[0];
+    for j in 1..n / 2 {
+        ps[j] = x[2 * j - 1] * x[2 * j - 1] + x[2 * j] * x[2 * j];
+    }
+    ps[n / 2] = x[n - 1] * x[n - 1];
+}
+
+fn power_spectrum_accum(x: &[f32], ps: &mut [f32]) {
+    let n = x.len();
+    ps[0] += x[0] * x[0];
+    for j in 1..n / 2 {
+        ps[j] += x[2 * j - 1] * x[2 * j - 1] + x[2 * j] * x[2 * j];
+    }
+    ps[n / 2] += x[n - 1] * x[n - 1];
+}
+
+// acc = sum over blocks of x * y (complex, packed)
+fn spectral_mul_accum(x: &[f32], y: &[f32], acc: &mut [f32]) {
+    let n = acc.len();
+    acc.fill(0.0);
+    for (x, y) in x.chunks_exact(n).zip(y.chunks_exact(n)) {
+        acc[0] += x[0] * y[0];
+        for i in (1..n - 1).step_by(2) {
+            acc[i] += x[i] * y[i] - x[i + 1] * y[i + 1];
+            acc[i + 1] += x[i + 1] * y[i] + x[i] * y[i + 1];
+        }
+        acc[n - 1] += x[n - 1] * y[n - 1];
+    }
+}
+
+// prod = p * w * conj(x) * y (complex, packed, w per bin)
+fn weighted_spectral_mul_conj(w: &[f32], p: f32, x: &[f32], y: &[f32], prod: &mut [f32]) {
+    let n = prod.len();
+    let weight = p * w[0];
+    prod[0] = weight * (x[0] * y[0]);
+    for i in (1..n - 1).step_by(2) {
+        let weight = p * w[(i + 1) / 2];
+        prod[i] = weight * (x[i] * y[i] + x[i + 1] * y[i + 1]);
+        prod[i + 1] = weight * (-x[i + 1] * y[i] + x[i] * y[i + 1]);
+    }
+    let weight = p * w[n / 2];
+    prod[n - 1] = weight * (x[n - 1] * y[n - 1]);
+}
+
+// proportionate step size per block; indexes the filter as [filter][block][bin] like upstream
+fn mdf_adjust_prop(w: &[f32], n: usize, m: usize, filters: usize, prop: &mut [f32]) {
+    let mut max_sum = 1.0f32;
+    let mut prop_sum = 1.0f32;
+    for i in 0..m {
+        let mut tmp = 1.0f32;
+        for p in 0..filters {
+            for v in &w[p * n * m + i * n..p * n * m + i * n + n] {
+                tmp += v * v;
+            }
+        }
+        prop[i] = (tmp as f64).sqrt() as f32;
+        if prop[i] > max_sum {
+            max_sum = prop[i];
+        }
+    }
+    for p in prop.iter_mut() {
+        *p += 0.1 * max_sum;
+        prop_sum += *p;
+    }
+    for p in prop.iter_mut() {
+        *p = 0.99 * *p / prop_sum;
+    }
+}
+
+impl EchoState {
+    /// Echo canceller for `mics` interleaved microphone and `speakers` interleaved loudspeaker
+    /// channels. `filter_length` is the echo tail in samples.
+    pub fn new(frame_size: usize, filter_length: usize, mics: usize, speakers: usize) -> Self {
+        let n = 2 * frame_size;
+        let m = (filter_length + frame_size - 1) / frame_size;
+        let sampling_rate = 8000;
+        let bins = frame_size + 1;
+
+        let window = (0..n)
+            .map(|i| (0.5 - 0.5 * (2.0 * std::f64::consts::PI * i as f64 / n as f64).cos()) as f32)
+            .collect();
+
+        let decay = ((-(2.4f32 / m as f32)) as f64).exp() as f32;
+        let mut prop = vec![0.0f32; m];
+        prop[0] = 0.7;
+        let mut sum = prop[0];
+        for i in 1..m {
+            prop[i] = prop[i - 1] * decay;
+            sum += prop[i];
+        }
+        for p in prop.iter_mut().rev() {
+            *p = 0.8 * *p / sum;
+        }
+
+        Self {
+            frame_size,
+            window_size: n,
+            m,
+            mics,
+            speakers,
+            cancel_count: 0,
+            adapted: false,
+            saturated: 0,
+            screwed_up: 0,
+            sampling_rate,
+            spec_average: frame_size as f32 / sampling_rate as f32,
+            beta0: 2.0 * frame_size as f32 / sampling_rate as f32,
+            beta_max: 0.5 * frame_size as f32 / sampling_rate as f32,
+            sum_adapt: 0.0,
+            leak_estimate: 0.0,
+            e: vec![0.0; mics * n],
+            x: vec![0.0; speakers * n],
+            x_freq: vec![0.0; speakers * (m + 1) * n],
+            input: vec![0.0; mics * frame_size],
+            y: vec![0.0; mics * n],
+            last_y: vec![0.0; mics * n],
+            y_freq: vec![0.0; mics * n],
+            e_freq: vec![0.0; mics * n],
+            phi: vec![0.0; n],
+            w: vec![0.0; mics * speakers * m * n],
+            foreground: vec![0.0; mics * speakers * m * n],
+            davg1: 0.0,
+            davg2: 0.0,
+            dvar1: 0.0,
+            dvar2: 0.0,
+            power: vec![0.0; bins],
+            power_1: vec![1.0; bins],
+            wtmp: vec![0.0; n],
+            rf: vec![0.0; bins],
+            yf: vec![0.0; bins],
+            xf: vec![0.0; bins],
+            eh: vec![0.0; bins],
+            yh: vec![0.0; bins],
+            pey: 1.0,
+            pyy: 1.0,
+            window,
+            prop,
+            fft: RealFft::new(n),
+            mem_x: vec![0.0; speakers],
+            mem_d: vec![0.0; mics],
+            mem_e: vec![0.0; mics],
+            preemph: 0.9,
+            notch_radius: notch_radius(sampling_rate),
+            notch_mem: vec![0.0; 2 * mics],
+            play_buf: vec![0; speakers * (PLAYBACK_DELAY + 1) * frame_size],
+            play_buf_pos: PLAYBACK_DELAY * frame_size,
+            play_buf_started: false,
+        }
+    }
+
+    /// Reset the filters and statistics.
+    pub fn reset(&mut self) {
+        let n = self.window_size;
+        let m = self.m;
+        self.cancel_count = 0;
+        self.screwed_up = 0;
+        // upstream only clears the first mic/speaker pair here
+        self.w[..n * m].fill(0.0);
+        self.foreground[..n * m].fill(0.0);
+        self.x_freq[..n * (m + 1)].fill(0.0);
+        self.power.fill(0.0);
+        self.power_1.fill(1.0);
+        self.eh.fill(0.0);
+        self.yh.fill(0.0);
+        self.last_y[..self.frame_size].fill(0.0);
+        self.e_freq.fill(0.0);
+        self.x.fill(0.0);
+        self.notch_mem.fill(0.0);
+        self.mem_d.fill(0.0);
+        self.mem_e.fill(0.0);
+        self.mem_x.fill(0.0);
+        self.saturated = 0;
+        self.adapted = false;
+        self.sum_adapt = 0.0;
+        self.pey = 1.0;
+        self.pyy = 1.0;
+        self.davg1 = 0.0;
+        self.davg2 = 0.0;
+        self.dvar1 = 0.0;
+        self.dvar2 = 0.0;
+        self.play_buf[..3 * self.frame_size].fill(0);
+        self.play_buf_pos = PLAYBACK_DELAY * self.frame_size;
+        self.play_buf_started = false;
+    }
+
+    pub fn frame_size(&self) -> usize {
+        self.frame_size
+    }
+
+    pub fn mics(&self) -> usize {
+        self.mics
+    }
+
+    pub fn speakers(&self) -> usize {
+        self.speakers
+    }
+
+    pub fn sampling_rate(&self) -> u32 {
+        self.sampling_rate as u32
+    }
+
+    /// Retune the step sizes and DC notch for `hz`.
+    pub fn set_sampling_rate(&mut self, hz: u32) {
+        self.sampling_rate = hz as i32;
+        self.spec_average = self.frame_size as f32 / self.sampling_rate as f32;
+        self.beta0 = 2.0 * self.frame_size as f32 / self.sampling_rate as f32;
+        self.beta_max = 0.5 * self.frame_size as f32 / self.sampling_rate as f32;
+        self.notch_radius = notch_radius(self.sampling_rate);
+    }
+
+    /// Cancel the echo of `far_end` from `rec`, both interleaved, writing `frame_size` frames to `out`.
+    pub fn cancellation(&mut self, rec: &[i16], far_end: &[i16], out: &mut [i16]) {
+        let n = self.window_size;
+        let m = self.m;
+        let c = self.mics;
+        let k = self.speakers;
+        let fs = self.frame_size;
+        let rec = &rec[..fs * c];
+        let far_end = &far_end[..fs * k];
+        let out = &mut out[..fs * c];
+
+        self.cancel_count += 1;
+        let ss = (0.35f64 / m as f64) as f32;
+        let ss_1 = 1.0 - ss;
+
+        for chan in 0..c {
+            let input = &mut self.input[chan * fs..(chan + 1) * fs];
+            filter_dc_notch16(
+                &rec[chan..],
+                c,
+                self.notch_radius,
+                input,
+                &mut self.notch_mem[2 * chan..2 * chan + 2],
+            );
+            // pre-emphasis
+            for v in input.iter_mut() {
+                let tmp32 = *v - self.preemph * self.mem_d[chan];
+                self.mem_d[chan] = *v;
+                *v = tmp32;
+            }
+        }
+
+        for speak in 0..k {
+            let x = &mut self.x[speak * n..(speak + 1) * n];
+            for i in 0..fs {
+                x[i] = x[i + fs];
+                let sample = far_end[i * k + speak] as f32;
+                x[i + fs] = sample - self.preemph * self.mem_x[speak];
+                self.mem_x[speak] = sample;
+            }
+        }
+
+        // shift the far end spectrum history and add the new block
+        for speak in 0..k {
+            for j in (0..m).rev() {
+                let from = j * n * k + speak * n;
+                self.x_freq.copy_within(from..from + n, from + n * k);
+            }
+            self.fft.forward(
+                &self.x[speak * n..(speak + 1) * n],
+                &mut self.x_freq[speak * n..(speak + 1) * n],
+            );
+        }
+
+        let mut sxx = 0.0f32;
+        for speak in 0..k {
+            let x = &self.x[speak * n + fs..speak * n + n];
+            sxx += mdf_inner_prod(x, x);
+            power_spectrum_accum(&self.x_freq[speak * n..(speak + 1) * n], &mut self.xf);
+        }
+
+        // foreground filter output
+        let filter_len = n * k * m;
+        let mut sff = 0.0f32;
+        for chan in 0..c {
+            spectral_mul_accum(
+                &self.x_freq[..filter_len],
+                &self.foreground[chan * filter_len..(chan + 1) * filter_len],
+                &mut self.y_freq[chan * n..(chan + 1) * n],
+            );
+            self.fft
+                .inverse(&self.y_freq[chan * n..(chan + 1) * n], &mut self.e[chan * n..(chan + 1) * n]);
+            let e = &mut self.e[chan * n..(chan + 1) * n];
+            let input = &self.input[chan * fs..(chan + 1) * fs];
+            for i in 0..fs {
+                e[i] = input[i] - e[i + fs];
+            }
+            sff += mdf_inner_prod(&e[..fs], &e[..fs]);
+        }
+
+        if self.adapted {
+            mdf_adjust_prop(&self.w, n, m, c * k, &mut self.prop);
+        }
+
+        // background filter update
+        if self.saturated == 0 {
+            for chan in 0..c {
+                for speak in 0..k {
+                    for j in (0..m).rev() {
+                        let x_block = (j + 1) * n * k + speak * n;
+                        weighted_spectral_mul_conj(
+                            &self.power_1,
+                            self.prop[j],
+                            &self.x_freq[x_block..x_block + n],
+                            &self.e_freq[chan * n..(chan + 1) * n],
+                            &mut self.phi,
+                        );
+                        let w_block = chan * filter_len + j * n * k + speak * n;
+                        for (w, phi) in self.w[w_block..w_block + n].iter_mut().zip(&self.phi) {
+                            *w += *phi;
+                        }
+                    }
+                }
+            }
+        } else {
+            self.saturated -= 1;
+        }
+
+        // constrain one block per frame (plus the first) back to a linear convolution
+        for chan in 0..c {
+            for speak in 0..k {
+                for j in 0..m {
+                    if j == 0 || self.cancel_count % (m as i32 - 1) == j as i32 - 1 {
+                        let w_block = chan * filter_len + j * n * k + speak * n;
+                        self.fft.inverse(&self.w[w_block..w_block + n], &mut self.wtmp);
+                        self.wtmp[fs..].fill(0.0);
+                        self.fft.forward(&self.wtmp, &mut self.w[w_block..w_block + n]);
+                    }
+                }
+            }
+        }
+
+        self.xf.fill(0.0);
+        self.yf.fill(0.0);
+        self.rf.fill(0.0);
+
+        // background filter output
+        let mut dbf = 0.0f32;
+        let mut see = 0.0f32;
+        for chan in 0..c {
+            spectral_mul_accum(
+                &self.x_freq[..filter_len],
+                &self.w[chan * filter_len..(chan + 1) * filter_len],
+                &mut self.y_freq[chan * n..(chan + 1) * n],
+            );
+            self.fft
+                .inverse(&self.y_freq[chan * n..(chan + 1) * n], &mut self.y[chan * n..(chan + 1) * n]);
+            let e = &mut self.e[chan * n..(chan + 1) * n];
+            let y = &self.y[chan * n..(chan + 1) * n];
+            let input = &self.input[chan * fs..(chan + 1) * fs];
+            // difference between the two filters
+            for i in 0..fs {
+                e[i] = e[i + fs] - y[i + fs];
+            }
+            dbf += 10.0 + mdf_inner_prod(&e[..fs], &e[..fs]);
+            for i in 0..fs {
+                e[i] = input[i] - y[i + fs];
+            }
+            see += mdf_inner_prod(&e[..fs], &e[..fs]);
+        }
+
+        // decide between the foreground and background filter
+        let diff = sff - see;
+        self.davg1 = 0.6 * self.davg1 + 0.4 * diff;
+        self.davg2 = 0.85 * self.davg2 + 0.15 * diff;
+        self.dvar1 = VAR1_SMOOTH * self.dvar1 + 0.4 * sff * (0.4 * dbf);
+        self.dvar2 = VAR2_SMOOTH * self.dvar2 + 0.15 * sff * (0.15 * dbf);
+
+        let update_foreground = diff * diff.abs() > sff * dbf
+            || self.davg1 * self.davg1.abs() > VAR1_UPDATE * self.dvar1
+            || self.davg2 * self.davg2.abs() > VAR2_UPDATE * self.dvar2;
+
+        if update_foreground {
+            self.davg1 = 0.0;
+            self.davg2 = 0.0;
+            self.dvar1 = 0.0;
+            self.dvar2 = 0.0;
+            self.foreground.copy_from_slice(&self.w);
+            // smooth the transition between the two filter outputs
+            for chan in 0..c {
+                let e = &mut self.e[chan * n..(chan + 1) * n];
+                let y = &self.y[chan * n..(chan + 1) * n];
+                for i in 0..fs {
+                    e[i + fs] = self.window[i + fs] * e[i + fs] + self.window[i] * y[i + fs];
+                }
+            }
+        } else {
+            let reset_background = -diff * diff.abs() > VAR_BACKTRACK * (sff * dbf)
+                || -self.davg1 * self.davg1.abs() > VAR_BACKTRACK * self.dvar1
+                || -self.davg2 * self.davg2.abs() > VAR_BACKTRACK * self.dvar2;
+            if reset_background {
+                // the background filter diverged, go back to the foreground one
+                self.w.copy_from_slice(&self.foreground);
+                for chan in 0..c {
+                    let e = &mut self.e[chan * n..(chan + 1) * n];
+                    let y = &mut self.y[chan * n..(chan + 1) * n];
+                    let input = &self.input[chan * fs..(chan + 1) * fs];
+                    y[fs..].copy_from_slice(&e[fs..]);
+                    for i in 0..fs {
+                        e[i] = input[i] - y[i + fs];
+                    }
+                }
+                see = sff;
+                self.davg1 = 0.0;
+                self.davg2 = 0.0;
+                self.dvar1 = 0.0;
+                self.dvar2 = 0.0;
+            }
+        }
+
+        let mut sey = 0.0f32;
+        let mut syy = 0.0f32;
+        let mut sdd = 0.0f32;
+        for chan in 0..c {
+            let e = &mut self.e[chan * n..(chan + 1) * n];
+            let y = &mut self.y[chan * n..(chan + 1) * n];
+            let input = &self.input[chan * fs..(chan + 1) * fs];
+            for i in 0..fs {
+                // de-emphasis
+                let mut tmp_out = input[i] - e[i + fs];
+                tmp_out += self.preemph * self.mem_e[chan];
+                let sample = rec[i * c + chan];
+                if (sample <= -32000 || sample >= 32000) && self.saturated == 0 {
+                    self.saturated = 1;
+                }
+                out[i * c + chan] = if tmp_out < -32767.5 {
+                    -32768
+                } else if tmp_out > 32766.5 {
+                    32767
+                } else {
+                    (0.5 + tmp_out as f64).floor() as i16
+                };
+                self.mem_e[chan] = tmp_out;
+            }
+            for i in 0..fs {
+                e[i + fs] = e[i];
+                e[i] = 0.0;
+            }
+            sey += mdf_inner_prod(&e[fs..], &y[fs..]);
+            syy += mdf_inner_prod(&y[fs..], &y[fs..]);
+            sdd += mdf_inner_prod(input, input);
+
+            let e_freq = &mut self.e_freq[chan * n..(chan + 1) * n];
+            let y_freq = &mut self.y_freq[chan * n..(chan + 1) * n];
+            self.fft.forward(e, e_freq);
+            y[..fs].fill(0.0);
+            self.fft.forward(y, y_freq);
+            power_spectrum_accum(e_freq, &mut self.rf);
+            power_spectrum_accum(y_freq, &mut self.yf);
+        }
+
+        // sanity checks, reset if the filter blew up
+        let limit = n as f64 * 1e9;
+        if !(syy >= 0.0 && sxx >= 0.0 && see >= 0.0)
+            || !((sff as f64) < limit && (syy as f64) < limit && (sxx as f64) < limit)
+        {
+            self.screwed_up += 50;
+            out.fill(0);
+        } else if sff > sdd + n as f32 * 10000.0 {
+            self.screwed_up += 1;
+        } else {
+            self.screwed_up = 0;
+        }
+        if self.screwed_up >= 50 {
+            alloc::warn_str(
+                "The echo canceller started acting funny and got slapped (reset). It swears it will behave now.",
+            );
+            self.reset();
+            return;
+        }
+
+        let see_floor = n as f32 * 100.0;
+        see = if see > see_floor { see } else { see_floor };
+
+        for speak in 0..k {
+            let x = &self.x[speak * n + fs..speak * n + n];
+            sxx += mdf_inner_prod(x, x);
+            power_spectrum_accum(&self.x_freq[speak * n..(speak + 1) * n], &mut self.xf);
+        }
+
+        // smoothed far end power per bin
+        for (power, xf) in self.power.iter_mut().zip(&self.xf) {
+            *power = ss_1 * *power + 1.0 + ss * *xf;
+        }
+
+        // leak estimate: correlation between the error and filter output spectra changes
+        let mut pey = 1.0f32;
+        let mut pyy = 1.0f32;
+        for j in (0..=fs).rev() {
+            let eh = self.rf[j] - self.eh[j];
+            let yh = self.yf[j] - self.yh[j];
+            pey += eh * yh;
+            pyy += yh * yh;
+            self.eh[j] = (1.0 - self.spec_average) * self.eh[j] + self.spec_average * self.rf[j];
+            self.yh[j] = (1.0 - self.spec_average) * self.yh[j] + self.spec_average * self.yf[j];
+        }
+        pyy = (pyy as f64).sqrt() as f32;
+        pey /= pyy;
+
+        let mut tmp32 = self.beta0 * syy;
+        if tmp32 > self.beta_max * see {
+            tmp32 = self.beta_max * see;
+        }
+        let alpha = tmp32 / see;
+        let alpha_1 = 1.0 - alpha;
+        self.pey = alpha_1 * self.pey + alpha * pey;
+        self.pyy = alpha_1 * self.pyy + alpha * pyy;
+        if self.pyy < 1.0 {
+            self.pyy = 1.0;
+        }
+        if self.pey < MIN_LEAK * self.pyy {
+            self.pey = MIN_LEAK * self.pyy;
+        }
+        if self.pey > self.pyy {
+            self.pey = self.pyy;
+        }
+        self.leak_estimate = self.pey / self.pyy;
+        if self.leak_estimate > 16383.0 {
+            self.leak_estimate = 32767.0;
+        }
+
+        // residual to error ratio
+        let mut rer =
+            ((0.0001 * sxx as f64 + 3.0 * (self.leak_estimate * syy) as f64) / see as f64) as f32;
+        if rer < sey * sey / (1.0 + see * syy) {
+            rer = sey * sey / (1.0 + see * syy);
+        }
+        if rer as f64 > 0.5 {
+            rer = 0.5;
+        }
+
+        if !self.adapted && self.sum_adapt > m as f32 && self.leak_estimate * syy > 0.03 * syy {
+            self.adapted = true;
+        }
+
+        // step size per bin for the next frame
+        if self.adapted {
+            for i in 0..=fs {
+                let mut r = self.leak_estimate * self.yf[i];
+                let e = self.rf[i] + 1.0;
+                if r as f64 > 0.5 * e as f64 {
+                    r = (0.5 * e as f64) as f32;
+                }
+                r = (0.7 * r as f64 + 0.3 * (rer * e) as f64) as f32;
+                self.power_1[i] = r / (e * (self.power[i] + 10.0));
+            }
+        } else {
+            let mut adapt_rate = 0.0f32;
+            if sxx > n as f32 * 1000.0 {
+                tmp32 = 0.25 * sxx;
+                if tmp32 as f64 > 0.25 * see as f64 {
+                    tmp32 = (0.25 * see as f64) as f32;
+                }
+                adapt_rate = tmp32 / see;
+            }
+            for (power_1, power) in self.power_1.iter_mut().zip(&self.power) {
+                *power_1 = adapt_rate / (*power + 10.0);
+            }
+            self.sum_adapt += adapt_rate;
+        }
+
+        // keep the echo estimate for speex_echo_get_residual (first mic only, like upstream)
+        self.last_y.copy_within(fs..2 * fs, 0);
+        if self.adapted {
+            for i in 0..fs {
+                self.last_y[fs + i] = (rec[i] as i32 - out[i] as i32) as f32;
+            }
+        }
+    }
+
+    /// Like [`cancellation`](Self::cancellation), using far end frames queued with
+    /// [`playback`](Self::playback).
+    pub fn capture(&mut self, rec: &[i16], out: &mut [i16]) {
+        let fs = self.frame_size;
+        self.play_buf_started = true;
+        if self.play_buf_pos >= fs {
+            let play_buf = std::mem::take(&mut self.play_buf);
+            self.cancellation(rec, &play_buf, out);
+            self.play_buf = play_buf;
+            self.play_buf_pos -= fs;
+            let pos = self.play_buf_pos;
+            self.play_buf.copy_within(fs..fs + pos, 0);
+        } else {
+            alloc::warn_str("No playback frame available (your application is buggy and/or got xruns)");
+            if self.play_buf_pos != 0 {
+                alloc::warn_str("internal playback buffer corruption?");
+                self.play_buf_pos = 0;
+            }
+            out[..fs].copy_from_slice(&rec[..fs]);
+        }
+    }
+
+    /// Queue a far end frame for [`capture`](Self::capture).
+    pub fn playback(&mut self, play: &[i16]) {
+        let fs = self.frame_size;
+        if !self.play_buf_started {
+            alloc::warn_str("discarded first playback frame");
+            return;
+        }
+        if self.play_buf_pos <= PLAYBACK_DELAY * fs {
+            let pos = self.play_buf_pos;
+            self.play_buf[pos..pos + fs].copy_from_slice(&play[..fs]);
+            self.play_buf_pos += fs;
+            if self.play_buf_pos <= (PLAYBACK_DELAY - 1) * fs {
+                alloc::warn_str("Auto-filling the buffer (your application is buggy and/or got xruns)");
+                let pos = self.play_buf_pos;
+                self.play_buf[pos..pos + fs].copy_from_slice(&play[..fs]);
+                self.play_buf_pos += fs;
+            }
+        } else {
+            alloc::warn_str("Had to discard a playback frame (your application is buggy and/or got xruns)");
+        }
+    }
+
+    /// Power spectrum of the echo left after the last frame (`frame_size + 1` bins), for the
+    /// preprocessor's residual echo suppression.
+    pub fn residual_echo(&mut self, residual: &mut [f32]) {
+        let n = self.window_size;
+        for ((y, w), last_y) in self.y[..n].iter_mut().zip(&self.window).zip(&self.last_y) {
+            *y = w * last_y;
+        }
+        self.fft.forward(&self.y[..n], &mut self.y_freq[..n]);
+        power_spectrum(&self.y_freq[..n], residual);
+        let leak2 = if self.leak_estimate as f64 > 0.5 {
+            1.0
+        } else {
+            2.0 * self.leak_estimate
+        };
+        for r in residual[..=self.frame_size].iter_mut() {
+            *r = (leak2 * *r) as i32 as f32;
+        }
+    }
+
+    /// Taps per impulse response, `filter blocks * frame_size`.
+    pub fn impulse_response_len(&self) -> usize {
+        self.m * self.frame_size
+    }
+
+    /// Learned echo path as linear gains for every mic/speaker pair, indexed
+    /// `[mic * speakers + speaker]`.
+    pub fn impulse_response(&self) -> Vec<Vec<f32>> {
+        let n = self.window_size;
+        let fs = self.frame_size;
+        let mut fft = RealFft::new(n);
+        let mut wtmp = vec![0.0f32; n];
+        let mut responses = Vec::with_capacity(self.mics * self.speakers);
+        for mic in 0..self.mics {
+            for speaker in 0..self.speakers {
+                let mut response = vec![0.0f32; self.impulse_response_len()];
+                for j in 0..self.m {
+                    let offset = mic * n * self.speakers * self.m + j * n * self.speakers + speaker * n;
+                    fft.inverse(&self.w[offset..offset + n], &mut wtmp);
+                    // the inverse FFT is unscaled, so the filter comes back multiplied by the window size
+                    for (dst, src) in response[j * fs..(j + 1) * fs].iter_mut().zip(&wtmp[..fs]) {
+                        *dst = *src / n as f32;
+                    }
+                }
+                responses.push(response);
+            }
+        }
+        responses
+    }
+
+    pub(super) fn snapshot(&self) -> EchoCancellerSnapshot {
+        EchoCancellerSnapshot {
+            frame_size: self.frame_size,
+            filter_blocks: self.m,
+            mic_channels: self.mics,
+            speaker_channels: self.speakers,
+            sampling_rate: self.sampling_rate as u32,
+            adapted: self.adapted,
+            sum_adapt: self.sum_adapt,
+            leak_estimate: self.leak_estimate,
+            pey: self.pey,
+            pyy: self.pyy,
+            davg: [self.davg1, self.davg2],
+            dvar: [self.dvar1, self.dvar2],
+            background: self.w.clone(),
+            foreground: self.foreground.clone(),
+            prop: self.prop.clone(),
+            power: self.power.clone(),
+            power_1: self.power_1.clone(),
+            eh: self.eh.clone(),
+            yh: self.yh.clone(),
+        }
+    }
+
+    pub(super) fn restore(&mut self, snapshot: &EchoCancellerSnapshot) -> Result<(), SnapshotError> {
+        let bins = self.frame_size + 1;
+        if snapshot.frame_size != self.frame_size
+            || snapshot.filter_blocks != self.m
+            || snapshot.sampling_rate != self.sampling_rate as u32
+            || snapshot.mic_channels != self.mics
+            || snapshot.speaker_channels != self.speakers
+            || snapshot.background.len() != self.w.len()
+            || snapshot.foreground.len() != self.foreground.len()
+            || snapshot.prop.len() != self.m
+            || snapshot.power.len() != bins
+            || snapshot.power_1.len() != bins
+            || snapshot.eh.len() != bins
+            || snapshot.yh.len() != bins
+        {
+            return Err(SnapshotError::LayoutMismatch);
+        }
+        self.w.copy_from_slice(&snapshot.background);
+        self.foreground.copy_from_slice(&snapshot.foreground);
+        self.prop.copy_from_slice(&snapshot.prop);
+        self.power.copy_from_slice(&snapshot.power);
+        self.power_1.copy_from_slice(&snapshot.power_1);
+        self.eh.copy_from_slice(&snapshot.eh);
+        self.yh.copy_from_slice(&snapshot.yh);
+        self.adapted = snapshot.adapted;
+        self.sum_adapt = snapshot.sum_adapt;
+        self.leak_estimate = snapshot.leak_estimate;
+        self.pey = snapshot.pey;
+        self.pyy = snapshot.pyy;
+        self.davg1 = snapshot.davg[0];
+        self.davg2 = snapshot.davg[1];
+        self.dvar1 = snapshot.dvar[0];
+        self.dvar2 = snapshot.dvar[1];
+        Ok(())
+    }
+}
+
+// The safe port has to stay bit-identical to the c2rust translation, which needs the shared FFT.
+#[cfg(all(test, feature = "rustfft-fftwrap"))]
+mod tests {
+    use std::ffi::{c_int, c_void};
+
+    use super::*;
+    use crate::speex::{
+        speex_echo_cancellation, speex_echo_capture, speex_echo_ctl, speex_echo_get_residual,
+        speex_echo_playback, speex_echo_state_destroy, speex_echo_state_init_mc, speex_echo_state_reset,
+        SpeexEchoState, SPEEX_ECHO_GET_FRAME_SIZE, SPEEX_ECHO_GET_IMPULSE_RESPONSE,
+        SPEEX_ECHO_GET_IMPULSE_RESPONSE_SIZE, SPEEX_ECHO_GET_SAMPLING_RATE, SPEEX_ECHO_SET_SAMPLING_RATE,
+    };
+
+    struct Lcg(u32);
+
+    impl Lcg {
+        fn next(&mut self) -> u32 {
+            self.0 = self.0.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            self.0
+        }
+
+        fn sample(&mut self, gain: f32) -> i16 {
+            ((self.next() >> 16) as i16 as f32 * gain) as i16
+        }
+    }
+
+    // Owns a c2rust echo state and exposes the ctl getters.
+    struct Reference(*mut SpeexEchoState);
+
+    impl Reference {
+        fn new(frame_size: usize, filter_length: usize, mics: usize, speakers: usize) -> Self {
+            Self(unsafe {
+                speex_echo_state_init_mc(frame_size as c_int, filter_length as c_int, mics as c_int, speakers as c_int)
+            })
+        }
+
+        fn ctl_int(&self, request: c_int) -> c_int {
+            let mut value: c_int = 0;
+            assert_eq!(unsafe { speex_echo_ctl(self.0, request, &mut value as *mut c_int as *mut c_void) }, 0);
+            value
+        }
+
+        fn set_sampling_rate(&mut self, hz: u32) {
+            let mut hz = hz as c_int;
+            unsafe { speex_echo_ctl(self.0, SPEEX_ECHO_SET_SAMPLING_RATE, &mut hz as *mut c_int as *mut c_void) };
+        }
+
+        fn impulse_response(&self) -> Vec<i32> {
+            let mut filt = vec![0i32; self.ctl_int(SPEEX_ECHO_GET_IMPULSE_RESPONSE_SIZE) as usize];
+            unsafe { speex_echo_ctl(self.0, SPEEX_ECHO_GET_IMPULSE_RESPONSE, filt.as_mut_ptr() as *mut c_void) };
+            filt
+        }
+
+        fn slice<T>(&self, ptr: *mut T, len: usize) -> &[T] {
+            unsafe { std::slice::from_raw_parts(ptr, len) }
+        }
+    }
+
+    impl Drop for Reference {
+        fn drop(&mut self) {
+            unsafe { speex_echo_state_destroy(self.0) };
+        }
+    }
+
+    fn assert_bits_eq(what: &str, actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len(), "{what} length");
+        for (i, (a, e)) in actual.iter().zip(expected).enumerate() {
+            assert_eq!(a.to_bits(), e.to_bits(), "{what}[{i}]: {a} vs {e}");
+        }
+    }
+
+    // upstream's GET_IMPULSE_RESPONSE, which only reads blocks at j * N of the filter
+    fn expected_impulse_response(state: &EchoState) -> Vec<i32> {
+        let (n, fs) = (state.window_size, state.frame_size);
+        let mut fft = RealFft::new(n);
+        let mut wtmp = vec![0.0f32; n];
+        let mut filt = vec![0i32; state.m * fs];
+        for j in 0..state.m {
+            fft.inverse(&state.w[j * n..(j + 1) * n], &mut wtmp);
+            for i in 0..fs {
+                filt[j * fs + i] = (32767.0 * wtmp[i]) as i32;
+            }
+        }
+        filt
+    }
+
+    fn assert_same_state(state: &EchoState, reference: &Reference) {
+        let st = unsafe { &*reference.0 };
+        let filters = state.mics * state.speakers * state.m * state.window_size;
+        assert_eq!(state.cancel_count, st.cancel_count);
+        assert_eq!(state.adapted, st.adapted != 0);
+        assert_eq!(state.saturated, st.saturated);
+        assert_eq!(state.screwed_up, st.screwed_up);
+        assert_bits_eq("sum_adapt", &[state.sum_adapt], &[st.sum_adapt]);
+        assert_bits_eq("leak_estimate", &[state.leak_estimate], &[st.leak_estimate]);
+        assert_bits_eq("Pey/Pyy", &[state.pey, state.pyy], &[st.Pey, st.Pyy]);
+        assert_bits_eq("W", &state.w, reference.slice(st.W, filters));
+        assert_bits_eq("foreground", &state.foreground, reference.slice(st.foreground, filters));
+        assert_bits_eq("power_1", &state.power_1, reference.slice(st.power_1, state.frame_size + 1));
+
+        assert_eq!(reference.ctl_int(SPEEX_ECHO_GET_FRAME_SIZE) as usize, state.frame_size());
+        assert_eq!(reference.ctl_int(SPEEX_ECHO_GET_SAMPLING_RATE) as u32, state.sampling_rate());
+        assert_eq!(reference.ctl_int(SPEEX_ECHO_GET_IMPULSE_RESPONSE_SIZE) as usize, state.impulse_response_len());
+        assert_eq!(reference.impulse_response(), expected_impulse_response(state));
+    }
+
+    // Random far end through a short echo path, with bursts of near-end talk, a few clipped
+    // samples and a reset halfway so every adaptation branch runs.
+    fn run_differential(frame_size: usize, filter_length: usize, mics: usize, speakers: usize, sampling_rate: u32) {
+        let frames = 120;
+        let mut state = EchoState::new(frame_size, filter_length, mics, speakers);
+        let mut reference = Reference::new(frame_size, filter_length, mics, speakers);
+        state.set_sampling_rate(sampling_rate);
+        reference.set_sampling_rate(sampling_rate);
+
+        let mut rng = Lcg(frame_size as u32 * 31 + filter_length as u32 * 7 + (mics * 3 + speakers) as u32);
+        let mut far_history = vec![0i16; 16 * speakers];
+        let mut out = vec![0i16; frame_size * mics];
+        let mut ref_out = vec![0i16; frame_size * mics];
+        let mut residual = vec![0.0f32; frame_size + 1];
+        let mut ref_residual = vec![0.0f32; frame_size + 1];
+        for frame in 0..frames {
+            let far: Vec<i16> = (0..frame_size * speakers).map(|_| rng.sample(0.4)).collect();
+            let double_talk = (frame / 15) % 3 == 2;
+            let mut rec = vec![0i16; frame_size * mics];
+            for i in 0..frame_size {
+                for mic in 0..mics {
+                    let mut echo = 0.0f32;
+                    for speaker in 0..speakers {
+                        let gain = 0.3 / (1 + mic + speaker) as f32;
+                        // taps 8 and 3 samples back, reaching into the previous frame
+                        let tap = |d: usize| {
+                            let idx = (i * speakers + speaker) as isize - (d * speakers) as isize;
+                            if idx >= 0 { far[idx as usize] } else { far_history[(far_history.len() as isize + idx) as usize] }
+                        };
+                        echo += gain * tap(8) as f32 + 0.5 * gain * tap(3) as f32;
+                    }
+                    let near = if double_talk { rng.sample(0.3) as f32 } else { rng.sample(0.002) as f32 };
+                    let clipped = rng.next() % 997 == 0;
+                    rec[i * mics + mic] = if clipped { i16::MAX } else { (echo + near) as i16 };
+                }
+            }
+            let history_len = far_history.len();
+            far_history.copy_from_slice(&far[far.len() - history_len..]);
+
+            state.cancellation(&rec, &far, &mut out);
+            unsafe { speex_echo_cancellation(reference.0, rec.as_ptr(), far.as_ptr(), ref_out.as_mut_ptr()) };
+            assert_eq!(out, ref_out, "frame {frame}");
+
+            state.residual_echo(&mut residual);
+            unsafe { speex_echo_get_residual(reference.0, ref_residual.as_mut_ptr(), (frame_size + 1) as c_int) };
+            assert_bits_eq("residual", &residual, &ref_residual);
+
+            if frame == frames / 2 {
+                assert_same_state(&state, &reference);
+                state.reset();
+                unsafe { speex_echo_state_reset(reference.0) };
+            }
+        }
+        assert_same_state(&state, &reference);
+    }
+
+    #[test]
+    fn matches_c2rust_across_layouts() {
+        for (frame_size, filter_length) in [(64, 256), (80, 800), (160, 1600), (128, 300)] {
+            for (mics, speakers) in [(1, 1), (2, 1), (1, 2), (2, 2)] {
+                run_differential(frame_size, filter_length, mics, speakers, 16_000);
+            }
+        }
+        // the step sizes and notch radius depend on the rate
+        run_differential(80, 640, 1, 1, 8_000);
+        run_differential(480, 4800, 1, 1, 48_000);
+    }
+
+    #[test]
+    fn capture_playback_matches_c2rust() {
+        let frame_size = 64;
+        let mut state = EchoState::new(frame_size, 512, 1, 1);
+        let mut reference = Reference::new(frame_size, 512, 1, 1);
+        state.set_sampling_rate(16_000);
+        reference.set_sampling_rate(16_000);
+        let mut rng = Lcg(9);
+        let mut out = vec![0i16; frame_size];
+        let mut ref_out = vec![0i16; frame_size];
+        for frame in 0..80 {
+            let play: Vec<i16> = (0..frame_size).map(|_| rng.sample(0.4)).collect();
+            let rec: Vec<i16> = (0..frame_size).map(|i| play[i] / 3 + rng.sample(0.01)).collect();
+            // skip a playback now and then to go through the underrun paths
+            if frame % 17 != 5 {
+                state.playback(&play);
+                unsafe { speex_echo_playback(reference.0, play.as_ptr()) };
+            }
+            state.capture(&rec, &mut out);
+            unsafe { speex_echo_capture(reference.0, rec.as_ptr(), ref_out.as_mut_ptr()) };
+            assert_eq!(out, ref_out, "frame {frame}");
+        }
+        assert_same_state(&state, &reference);
+    }
+}
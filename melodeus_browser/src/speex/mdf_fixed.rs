@@ -0,0 +1,1088 @@
+//! Fixed-point port of the speex MDF echo canceller, used instead of [`mdf.rs`](super::mdf)
+//! by the `fixed-point` feature.
+//!
+//! This follows upstream's `FIXED_POINT` code paths: 16-bit signals and spectra, 32-bit
+//! filters (Q`WEIGHT_SHIFT + 16`) and pseudo-floats for the adaptation statistics. Everything is
+//! integer arithmetic, so the output is the same on every platform. The public API matches the
+//! float build; [`residual_echo`](EchoState::residual_echo) and
+//! [`impulse_response`](EchoState::impulse_response) convert to the float build's units, and
+//! snapshots are stored in float units so they can be restored by either build.
+
+use super::c2rust::alloc;
+use super::fft::RealFft;
+use super::fft_fixed::FixedRealFft;
+use super::fixed::{
+    div32, div32_16, mac16_16, mac16_32_q15, mult16_16, mult16_16_p15, mult16_16_q15,
+    mult16_32_q15, pshr32, qconst16, shl32, shr32, spx_cos, spx_exp, spx_sqrt, word2int, SpxFloat,
+};
+use super::{EchoCancellerSnapshot, SnapshotError};
+
+const WEIGHT_SHIFT: i32 = 11;
+const NORMALIZE_SCALEDOWN: i32 = 5;
+const NORMALIZE_SCALEUP: i32 = 3;
+const MIN_LEAK: SpxFloat = SpxFloat { m: 20972, e: -22 };
+const VAR1_SMOOTH: SpxFloat = SpxFloat { m: 23593, e: -16 };
+const VAR2_SMOOTH: SpxFloat = SpxFloat { m: 23675, e: -15 };
+const VAR1_UPDATE: SpxFloat = SpxFloat { m: 16384, e: -15 };
+const VAR2_UPDATE: SpxFloat = SpxFloat { m: 16384, e: -16 };
+const VAR_BACKTRACK: SpxFloat = SpxFloat { m: 16384, e: -12 };
+const PLAYBACK_DELAY: usize = 2;
+
+// Scale of the fixed-point values relative to the float build, for snapshots. Filters are
+// Q27 (foreground Q11), and the time-domain energies come out of mdf_inner_prod divided by 64.
+const FILTER_SCALE: f64 = (1u32 << (WEIGHT_SHIFT + 16)) as f64;
+const FOREGROUND_SCALE: f64 = (1u32 << WEIGHT_SHIFT) as f64;
+const Q15: f64 = 32768.0;
+const ENERGY_SCALE: f64 = 64.0;
+
+/// MDF (multidelay block frequency domain) adaptive filter state.
+///
+/// Time-domain buffers are lower case (`x`, `y`, `e`), their packed spectra end in `_freq`.
+/// Filters are laid out `[mic][block][speaker][bin]`.
+pub struct EchoState {
+    frame_size: usize,
+    window_size: usize,
+    // number of filter blocks (filter length / frame size, rounded up)
+    m: usize,
+    mics: usize,
+    speakers: usize,
+    cancel_count: i32,
+    adapted: bool,
+    saturated: i32,
+    screwed_up: i32,
+    sampling_rate: i32,
+    spec_average: i16,
+    beta0: i16,
+    beta_max: i16,
+    sum_adapt: i32,
+    leak_estimate: i16,
+
+    e: Vec<i16>,
+    x: Vec<i16>,
+    x_freq: Vec<i16>,
+    input: Vec<i16>,
+    y: Vec<i16>,
+    last_y: Vec<i16>,
+    y_freq: Vec<i16>,
+    e_freq: Vec<i16>,
+    phi: Vec<i32>,
+    // background (adaptive) filter
+    w: Vec<i32>,
+    foreground: Vec<i16>,
+    davg1: i32,
+    davg2: i32,
+    dvar1: SpxFloat,
+    dvar2: SpxFloat,
+    power: Vec<i32>,
+    power_1: Vec<SpxFloat>,
+    wtmp: Vec<i16>,
+    wtmp2: Vec<i16>,
+    rf: Vec<i32>,
+    yf: Vec<i32>,
+    xf: Vec<i32>,
+    eh: Vec<i32>,
+    yh: Vec<i32>,
+    residual: Vec<i32>,
+    pey: SpxFloat,
+    pyy: SpxFloat,
+    window: Vec<i16>,
+    prop: Vec<i16>,
+    fft: FixedRealFft,
+    mem_x: Vec<i16>,
+    mem_d: Vec<i16>,
+    mem_e: Vec<i16>,
+    preemph: i16,
+    notch_radius: i16,
+    notch_mem: Vec<i32>,
+    play_buf: Vec<i16>,
+    play_buf_pos: usize,
+    play_buf_started: bool,
+}
+
+fn notch_radius(sampling_rate: i32) -> i16 {
+    if sampling_rate < 12000 {
+        qconst16(0.9, 15)
+    } else if sampling_rate < 24000 {
+        qconst16(0.982, 15)
+    } else {
+        qconst16(0.992, 15)
+    }
+}
+
+// frame_size / sampling_rate in Q`shift`. Upstream uses DIV32_16 here, which truncates the
+// rate to 16 bits and breaks at 44.1/48 kHz, so divide in 32 bits instead.
+fn rate_ratio(frame_size: usize, sampling_rate: i32, shift: i32) -> i16 {
+    div32(shl32(frame_size as i32, shift), sampling_rate) as i16
+}
+
+// DC rejection on every `stride`-th sample of `input`
+fn filter_dc_notch16(input: &[i16], stride: usize, radius: i16, out: &mut [i16], mem: &mut [i32]) {
+    let den2 = (mult16_16_q15(radius, radius)
+        + mult16_16_q15(
+            qconst16(0.7, 15),
+            mult16_16_q15(32767 - radius, 32767 - radius) as i16,
+        )) as i16;
+    for (i, out) in out.iter_mut().enumerate() {
+        let vin = input[i * stride];
+        let vout = mem[0].wrapping_add(shl32(vin as i32, 15));
+        mem[0] = mem[1].wrapping_add(shl32(
+            shl32(-(vin as i32), 15).wrapping_add(mult16_32_q15(radius, vout)),
+            1,
+        ));
+        mem[1] = shl32(vin as i32, 15).wrapping_sub(mult16_32_q15(den2, vout));
+        *out = pshr32(mult16_32_q15(radius, vout), 15).clamp(-32767, 32767) as i16;
+    }
+}
+
+fn mdf_inner_prod(x: &[i16], y: &[i16]) -> i32 {
+    let mut sum = 0i32;
+    for (x, y) in x.chunks_exact(2).zip(y.chunks_exact(2)) {
+        let part = mac16_16(mac16_16(0, x[0], y[0]), x[1], y[1]);
+        sum = sum.wrapping_add(shr32(part, 6));
+    }
+    sum
+}
+
+fn power_spectrum(x: &[i16], ps: &mut [i32]) {
+    let n = x.len();
+    ps[0] = mult16_16(x[0], x[0]);
+    for j in 1..n / 2 {
+        ps[j] = mult16_16(x[2 * j - 1], x[2 * j - 1]).wrapping_add(mult16_16(x[2 * j], x[2 * j]));
+    }
+    ps[n / 2] = mult16_16(x[n - 1], x[n - 1]);
+}
+
+fn power_spectrum_accum(x: &[i16], ps: &mut [i32]) {
+    let n = x.len();
+    ps[0] = ps[0].wrapping_add(mult16_16(x[0], x[0]));
+    for j in 1..n / 2 {
+        let p = mult16_16(x[2 * j - 1], x[2 * j - 1]).wrapping_add(mult16_16(x[2 * j], x[2 * j]));
+        ps[j] = ps[j].wrapping_add(p);
+    }
+    ps[n / 2] = ps[n / 2].wrapping_add(mult16_16(x[n - 1], x[n - 1]));
+}
+
+// acc = sum over blocks of x * y (complex, packed), y being a Q27 or Q11 filter through `top`
+fn spectral_mul_accum<T: Copy>(x: &[i16], y: &[T], acc: &mut [i16], top: impl Fn(T) -> i16) {
+    let n = acc.len();
+    let blocks = x.len().min(y.len()) / n;
+    let mut tmp1 = 0i32;
+    for j in 0..blocks {
+        tmp1 = mac16_16(tmp1, x[j * n], top(y[j * n]));
+    }
+    acc[0] = pshr32(tmp1, WEIGHT_SHIFT) as i16;
+    for i in (1..n - 1).step_by(2) {
+        let mut tmp1 = 0i32;
+        let mut tmp2 = 0i32;
+        for j in 0..blocks {
+            let (xr, xi) = (x[j * n + i], x[j * n + i + 1]);
+            let (yr, yi) = (top(y[j * n + i]), top(y[j * n + i + 1]));
+            tmp1 = mac16_16(tmp1, xr, yr).wrapping_sub(mult16_16(xi, yi));
+            tmp2 = mac16_16(mac16_16(tmp2, xi, yr), xr, yi);
+        }
+        acc[i] = pshr32(tmp1, WEIGHT_SHIFT) as i16;
+        acc[i + 1] = pshr32(tmp2, WEIGHT_SHIFT) as i16;
+    }
+    let mut tmp1 = 0i32;
+    for j in 0..blocks {
+        tmp1 = mac16_16(tmp1, x[(j + 1) * n - 1], top(y[(j + 1) * n - 1]));
+    }
+    acc[n - 1] = pshr32(tmp1, WEIGHT_SHIFT) as i16;
+}
+
+// prod = p * w * conj(x) * y (complex, packed, w per bin)
+fn weighted_spectral_mul_conj(w: &[SpxFloat], p: SpxFloat, x: &[i16], y: &[i16], prod: &mut [i32]) {
+    let n = prod.len();
+    let weight = p.amult(w[0]);
+    prod[0] = weight.mul32(mult16_16(x[0], y[0]));
+    for i in (1..n - 1).step_by(2) {
+        let weight = p.amult(w[(i + 1) / 2]);
+        prod[i] = weight.mul32(mac16_16(mult16_16(x[i], y[i]), x[i + 1], y[i + 1]));
+        prod[i + 1] = weight.mul32(mac16_16(mult16_16(x[i + 1].wrapping_neg(), y[i]), x[i], y[i + 1]));
+    }
+    let weight = p.amult(w[n / 2]);
+    prod[n - 1] = weight.mul32(mult16_16(x[n - 1], y[n - 1]));
+}
+
+// proportionate step size per block; indexes the filter as [filter][block][bin] like upstream
+fn mdf_adjust_prop(w: &[i32], n: usize, m: usize, filters: usize, prop: &mut [i16]) {
+    let mut max_sum: i16 = 1;
+    let mut prop_sum: i32 = 1;
+    for i in 0..m {
+        let mut tmp: i32 = 1;
+        for p in 0..filters {
+            for v in &w[p * n * m + i * n..p * n * m + i * n + n] {
+                let v = shr32(*v, 18) as i16;
+                tmp = tmp.wrapping_add(mult16_16(v, v));
+            }
+        }
+        // prevent overflows in the square root
+        let tmp = tmp.wrapping_abs().min(536870912);
+        prop[i] = spx_sqrt(tmp);
+        if prop[i] > max_sum {
+            max_sum = prop[i];
+        }
+    }
+    for p in prop.iter_mut() {
+        *p = p.wrapping_add(mult16_16_q15(qconst16(0.1, 15), max_sum) as i16);
+        prop_sum += *p as i32;
+    }
+    for p in prop.iter_mut() {
+        *p = div32(mult16_16(qconst16(0.99, 15), *p), prop_sum) as i16;
+    }
+}
+
+impl EchoState {
+    /// Echo canceller for `mics` interleaved microphone and `speakers` interleaved loudspeaker
+    /// channels. `filter_length` is the echo tail in samples.
+    pub fn new(frame_size: usize, filter_length: usize, mics: usize, speakers: usize) -> Self {
+        let n = 2 * frame_size;
+        let m = (filter_length + frame_size - 1) / frame_size;
+        let sampling_rate = 8000;
+        let bins = frame_size + 1;
+
+        let mut window = vec![0i16; n];
+        for i in 0..n / 2 {
+            let angle = div32_16(mult16_16(25736, (i << 1) as i16), n as i16);
+            window[i] = 16383 - (spx_cos(angle) << 1);
+            window[n - i - 1] = window[i];
+        }
+
+        // ratio of ~10 between the adaptation rate of the first and last block
+        let decay = shr32(spx_exp(-div32_16(qconst16(2.4, 11) as i32, m as i16)), 1) as i16;
+        let mut prop = vec![0i16; m];
+        prop[0] = qconst16(0.7, 15);
+        let mut sum = prop[0] as i32;
+        for i in 1..m {
+            prop[i] = mult16_16_q15(prop[i - 1], decay) as i16;
+            sum += prop[i] as i32;
+        }
+        for p in prop.iter_mut().rev() {
+            *p = div32(mult16_16(qconst16(0.8, 15), *p), sum) as i16;
+        }
+
+        Self {
+            frame_size,
+            window_size: n,
+            m,
+            mics,
+            speakers,
+            cancel_count: 0,
+            adapted: false,
+            saturated: 0,
+            screwed_up: 0,
+            sampling_rate,
+            spec_average: rate_ratio(frame_size, sampling_rate, 15),
+            beta0: rate_ratio(frame_size, sampling_rate, 16),
+            beta_max: rate_ratio(frame_size, sampling_rate, 14),
+            sum_adapt: 0,
+            leak_estimate: 0,
+            e: vec![0; mics * n],
+            x: vec![0; speakers * n],
+            x_freq: vec![0; speakers * (m + 1) * n],
+            input: vec![0; mics * frame_size],
+            y: vec![0; mics * n],
+            last_y: vec![0; mics * n],
+            y_freq: vec![0; mics * n],
+            e_freq: vec![0; mics * n],
+            phi: vec![0; n],
+            w: vec![0; mics * speakers * m * n],
+            foreground: vec![0; mics * speakers * m * n],
+            davg1: 0,
+            davg2: 0,
+            dvar1: SpxFloat::ZERO,
+            dvar2: SpxFloat::ZERO,
+            power: vec![0; bins],
+            power_1: vec![SpxFloat::ONE; bins],
+            wtmp: vec![0; n],
+            wtmp2: vec![0; n],
+            rf: vec![0; bins],
+            yf: vec![0; bins],
+            xf: vec![0; bins],
+            eh: vec![0; bins],
+            yh: vec![0; bins],
+            residual: vec![0; bins],
+            pey: SpxFloat::ONE,
+            pyy: SpxFloat::ONE,
+            window,
+            prop,
+            fft: FixedRealFft::new(n),
+            mem_x: vec![0; speakers],
+            mem_d: vec![0; mics],
+            mem_e: vec![0; mics],
+            preemph: qconst16(0.9, 15),
+            notch_radius: notch_radius(sampling_rate),
+            notch_mem: vec![0; 2 * mics],
+            play_buf: vec![0; speakers * (PLAYBACK_DELAY + 1) * frame_size],
+            play_buf_pos: PLAYBACK_DELAY * frame_size,
+            play_buf_started: false,
+        }
+    }
+
+    /// Reset the filters and statistics.
+    pub fn reset(&mut self) {
+        let n = self.window_size;
+        let m = self.m;
+        self.cancel_count = 0;
+        self.screwed_up = 0;
+        // upstream only clears the first mic/speaker pair here
+        self.w[..n * m].fill(0);
+        self.foreground[..n * m].fill(0);
+        self.x_freq[..n * (m + 1)].fill(0);
+        self.power.fill(0);
+        self.power_1.fill(SpxFloat::ONE);
+        self.eh.fill(0);
+        self.yh.fill(0);
+        self.last_y[..self.frame_size].fill(0);
+        self.e_freq.fill(0);
+        self.x.fill(0);
+        self.notch_mem.fill(0);
+        self.mem_d.fill(0);
+        self.mem_e.fill(0);
+        self.mem_x.fill(0);
+        self.saturated = 0;
+        self.adapted = false;
+        self.sum_adapt = 0;
+        self.pey = SpxFloat::ONE;
+        self.pyy = SpxFloat::ONE;
+        self.davg1 = 0;
+        self.davg2 = 0;
+        self.dvar1 = SpxFloat::ZERO;
+        self.dvar2 = SpxFloat::ZERO;
+        self.play_buf[..3 * self.frame_size].fill(0);
+        self.play_buf_pos = PLAYBACK_DELAY * self.frame_size;
+        self.play_buf_started = false;
+    }
+
+    pub fn frame_size(&self) -> usize {
+        self.frame_size
+    }
+
+    pub fn mics(&self) -> usize {
+        self.mics
+    }
+
+    pub fn speakers(&self) -> usize {
+        self.speakers
+    }
+
+    pub fn sampling_rate(&self) -> u32 {
+        self.sampling_rate as u32
+    }
+
+    /// Retune the step sizes and DC notch for `hz`.
+    pub fn set_sampling_rate(&mut self, hz: u32) {
+        self.sampling_rate = hz as i32;
+        self.spec_average = rate_ratio(self.frame_size, self.sampling_rate, 15);
+        self.beta0 = rate_ratio(self.frame_size, self.sampling_rate, 16);
+        self.beta_max = rate_ratio(self.frame_size, self.sampling_rate, 14);
+        self.notch_radius = notch_radius(self.sampling_rate);
+    }
+
+    /// Cancel the echo of `far_end` from `rec`, both interleaved, writing `frame_size` frames to `out`.
+    pub fn cancellation(&mut self, rec: &[i16], far_end: &[i16], out: &mut [i16]) {
+        let n = self.window_size;
+        let m = self.m;
+        let c = self.mics;
+        let k = self.speakers;
+        let fs = self.frame_size;
+        let rec = &rec[..fs * c];
+        let far_end = &far_end[..fs * k];
+        let out = &mut out[..fs * c];
+
+        self.cancel_count += 1;
+        let ss = div32_16(11469, m as i16);
+        let ss_1 = 32767 - ss;
+
+        for chan in 0..c {
+            let input = &mut self.input[chan * fs..(chan + 1) * fs];
+            filter_dc_notch16(
+                &rec[chan..],
+                c,
+                self.notch_radius,
+                input,
+                &mut self.notch_mem[2 * chan..2 * chan + 2],
+            );
+            // pre-emphasis
+            for v in input.iter_mut() {
+                let mut tmp32 = *v as i32 - mult16_16_p15(self.preemph, self.mem_d[chan]);
+                if tmp32 > 32767 || tmp32 < -32767 {
+                    tmp32 = tmp32.clamp(-32767, 32767);
+                    if self.saturated == 0 {
+                        self.saturated = 1;
+                    }
+                }
+                self.mem_d[chan] = *v;
+                *v = tmp32 as i16;
+            }
+        }
+
+        for speak in 0..k {
+            let x = &mut self.x[speak * n..(speak + 1) * n];
+            for i in 0..fs {
+                x[i] = x[i + fs];
+                let sample = far_end[i * k + speak];
+                let mut tmp32 = sample as i32 - mult16_16_p15(self.preemph, self.mem_x[speak]);
+                if tmp32 > 32767 || tmp32 < -32767 {
+                    tmp32 = tmp32.clamp(-32767, 32767);
+                    self.saturated = m as i32 + 1;
+                }
+                x[i + fs] = tmp32 as i16;
+                self.mem_x[speak] = sample;
+            }
+        }
+
+        // shift the far end spectrum history and add the new block
+        for speak in 0..k {
+            for j in (0..m).rev() {
+                let from = j * n * k + speak * n;
+                self.x_freq.copy_within(from..from + n, from + n * k);
+            }
+            self.fft.forward(
+                &self.x[speak * n..(speak + 1) * n],
+                &mut self.x_freq[speak * n..(speak + 1) * n],
+            );
+        }
+
+        let mut sxx = 0i32;
+        for speak in 0..k {
+            let x = &self.x[speak * n + fs..speak * n + n];
+            sxx = sxx.wrapping_add(mdf_inner_prod(x, x));
+            power_spectrum_accum(&self.x_freq[speak * n..(speak + 1) * n], &mut self.xf);
+        }
+
+        // foreground filter output
+        let filter_len = n * k * m;
+        let mut sff = 0i32;
+        for chan in 0..c {
+            spectral_mul_accum(
+                &self.x_freq[..filter_len],
+                &self.foreground[chan * filter_len..(chan + 1) * filter_len],
+                &mut self.y_freq[chan * n..(chan + 1) * n],
+                |v| v,
+            );
+            self.fft
+                .inverse(&self.y_freq[chan * n..(chan + 1) * n], &mut self.e[chan * n..(chan + 1) * n]);
+            let e = &mut self.e[chan * n..(chan + 1) * n];
+            let input = &self.input[chan * fs..(chan + 1) * fs];
+            for i in 0..fs {
+                e[i] = input[i].wrapping_sub(e[i + fs]);
+            }
+            sff = sff.wrapping_add(mdf_inner_prod(&e[..fs], &e[..fs]));
+        }
+
+        if self.adapted {
+            mdf_adjust_prop(&self.w, n, m, c * k, &mut self.prop);
+        }
+
+        // background filter update
+        if self.saturated == 0 {
+            for chan in 0..c {
+                for speak in 0..k {
+                    for j in (0..m).rev() {
+                        let x_block = (j + 1) * n * k + speak * n;
+                        weighted_spectral_mul_conj(
+                            &self.power_1,
+                            SpxFloat::from_int(self.prop[j] as i32).shl(-15),
+                            &self.x_freq[x_block..x_block + n],
+                            &self.e_freq[chan * n..(chan + 1) * n],
+                            &mut self.phi,
+                        );
+                        let w_block = chan * filter_len + j * n * k + speak * n;
+                        for (w, phi) in self.w[w_block..w_block + n].iter_mut().zip(&self.phi) {
+                            *w = w.wrapping_add(*phi);
+                        }
+                    }
+                }
+            }
+        } else {
+            self.saturated -= 1;
+        }
+
+        // constrain one block per frame (plus the first) back to a linear convolution
+        for chan in 0..c {
+            for speak in 0..k {
+                for j in 0..m {
+                    if j == 0 || self.cancel_count % (m as i32 - 1) == j as i32 - 1 {
+                        let w_block = chan * filter_len + j * n * k + speak * n;
+                        let w = &mut self.w[w_block..w_block + n];
+                        for (dst, src) in self.wtmp2.iter_mut().zip(w.iter()) {
+                            *dst = pshr32(*src, NORMALIZE_SCALEDOWN + 16) as i16;
+                        }
+                        self.fft.inverse(&self.wtmp2, &mut self.wtmp);
+                        self.wtmp[..fs].fill(0);
+                        for v in self.wtmp[fs..].iter_mut() {
+                            *v = v.wrapping_shl(NORMALIZE_SCALEUP as u32);
+                        }
+                        self.fft.forward(&self.wtmp, &mut self.wtmp2);
+                        // the "-1" in the shift trades update speed for less noise
+                        for (dst, src) in w.iter_mut().zip(&self.wtmp2) {
+                            *dst = dst.wrapping_sub(shl32(
+                                *src as i32,
+                                16 + NORMALIZE_SCALEDOWN - NORMALIZE_SCALEUP - 1,
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+
+        self.xf.fill(0);
+        self.yf.fill(0);
+        self.rf.fill(0);
+
+        // background filter output
+        let mut dbf = 0i32;
+        let mut see = 0i32;
+        for chan in 0..c {
+            spectral_mul_accum(
+                &self.x_freq[..filter_len],
+                &self.w[chan * filter_len..(chan + 1) * filter_len],
+                &mut self.y_freq[chan * n..(chan + 1) * n],
+                |v| (v >> 16) as i16,
+            );
+            self.fft
+                .inverse(&self.y_freq[chan * n..(chan + 1) * n], &mut self.y[chan * n..(chan + 1) * n]);
+            let e = &mut self.e[chan * n..(chan + 1) * n];
+            let y = &self.y[chan * n..(chan + 1) * n];
+            let input = &self.input[chan * fs..(chan + 1) * fs];
+            // difference between the two filters
+            for i in 0..fs {
+                e[i] = e[i + fs].wrapping_sub(y[i + fs]);
+            }
+            dbf = dbf.wrapping_add(10).wrapping_add(mdf_inner_prod(&e[..fs], &e[..fs]));
+            for i in 0..fs {
+                e[i] = input[i].wrapping_sub(y[i + fs]);
+            }
+            see = see.wrapping_add(mdf_inner_prod(&e[..fs], &e[..fs]));
+        }
+
+        // decide between the foreground and background filter
+        let diff = sff.wrapping_sub(see);
+        self.davg1 = mult16_32_q15(qconst16(0.6, 15), self.davg1)
+            .wrapping_add(mult16_32_q15(qconst16(0.4, 15), diff));
+        self.davg2 = mult16_32_q15(qconst16(0.85, 15), self.davg2)
+            .wrapping_add(mult16_32_q15(qconst16(0.15, 15), diff));
+        self.dvar1 = VAR1_SMOOTH.mult(self.dvar1).add(SpxFloat::mul32u(
+            mult16_32_q15(qconst16(0.4, 15), sff),
+            mult16_32_q15(qconst16(0.4, 15), dbf),
+        ));
+        self.dvar2 = VAR2_SMOOTH.mult(self.dvar2).add(SpxFloat::mul32u(
+            mult16_32_q15(qconst16(0.15, 15), sff),
+            mult16_32_q15(qconst16(0.15, 15), dbf),
+        ));
+
+        let update_foreground = SpxFloat::mul32u(diff, diff.wrapping_abs()).gt(SpxFloat::mul32u(sff, dbf))
+            || SpxFloat::mul32u(self.davg1, self.davg1.wrapping_abs()).gt(VAR1_UPDATE.mult(self.dvar1))
+            || SpxFloat::mul32u(self.davg2, self.davg2.wrapping_abs()).gt(VAR2_UPDATE.mult(self.dvar2));
+
+        if update_foreground {
+            self.davg1 = 0;
+            self.davg2 = 0;
+            self.dvar1 = SpxFloat::ZERO;
+            self.dvar2 = SpxFloat::ZERO;
+            for (fg, w) in self.foreground.iter_mut().zip(&self.w) {
+                *fg = pshr32(*w, 16) as i16;
+            }
+            // smooth the transition between the two filter outputs
+            for chan in 0..c {
+                let e = &mut self.e[chan * n..(chan + 1) * n];
+                let y = &self.y[chan * n..(chan + 1) * n];
+                for i in 0..fs {
+                    e[i + fs] = (mult16_16_q15(self.window[i + fs], e[i + fs])
+                        + mult16_16_q15(self.window[i], y[i + fs])) as i16;
+                }
+            }
+        } else {
+            let reset_background = SpxFloat::mul32u(diff.wrapping_neg(), diff.wrapping_abs())
+                .gt(VAR_BACKTRACK.mult(SpxFloat::mul32u(sff, dbf)))
+                || SpxFloat::mul32u(self.davg1.wrapping_neg(), self.davg1.wrapping_abs())
+                    .gt(VAR_BACKTRACK.mult(self.dvar1))
+                || SpxFloat::mul32u(self.davg2.wrapping_neg(), self.davg2.wrapping_abs())
+                    .gt(VAR_BACKTRACK.mult(self.dvar2));
+            if reset_background {
+                // the background filter diverged, go back to the foreground one
+                for (w, fg) in self.w.iter_mut().zip(&self.foreground) {
+                    *w = shl32(*fg as i32, 16);
+                }
+                for chan in 0..c {
+                    let e = &mut self.e[chan * n..(chan + 1) * n];
+                    let y = &mut self.y[chan * n..(chan + 1) * n];
+                    let input = &self.input[chan * fs..(chan + 1) * fs];
+                    y[fs..].copy_from_slice(&e[fs..]);
+                    for i in 0..fs {
+                        e[i] = input[i].wrapping_sub(y[i + fs]);
+                    }
+                }
+                see = sff;
+                self.davg1 = 0;
+                self.davg2 = 0;
+                self.dvar1 = SpxFloat::ZERO;
+                self.dvar2 = SpxFloat::ZERO;
+            }
+        }
+
+        let mut sey = 0i32;
+        let mut syy = 0i32;
+        let mut sdd = 0i32;
+        for chan in 0..c {
+            let e = &mut self.e[chan * n..(chan + 1) * n];
+            let y = &mut self.y[chan * n..(chan + 1) * n];
+            let input = &self.input[chan * fs..(chan + 1) * fs];
+            for i in 0..fs {
+                // de-emphasis
+                let mut tmp_out = input[i] as i32 - e[i + fs] as i32;
+                tmp_out += mult16_16_p15(self.preemph, self.mem_e[chan]);
+                let sample = rec[i * c + chan];
+                if (sample <= -32000 || sample >= 32000) && self.saturated == 0 {
+                    self.saturated = 1;
+                }
+                out[i * c + chan] = word2int(tmp_out);
+                self.mem_e[chan] = tmp_out as i16;
+            }
+            for i in 0..fs {
+                e[i + fs] = e[i];
+                e[i] = 0;
+            }
+            sey = sey.wrapping_add(mdf_inner_prod(&e[fs..], &y[fs..]));
+            syy = syy.wrapping_add(mdf_inner_prod(&y[fs..], &y[fs..]));
+            sdd = sdd.wrapping_add(mdf_inner_prod(input, input));
+
+            let e_freq = &mut self.e_freq[chan * n..(chan + 1) * n];
+            let y_freq = &mut self.y_freq[chan * n..(chan + 1) * n];
+            self.fft.forward(e, e_freq);
+            y[..fs].fill(0);
+            self.fft.forward(y, y_freq);
+            power_spectrum_accum(e_freq, &mut self.rf);
+            power_spectrum_accum(y_freq, &mut self.yf);
+        }
+
+        // sanity checks, reset if the filter blew up
+        if !(syy >= 0 && sxx >= 0 && see >= 0) {
+            self.screwed_up += 50;
+            out.fill(0);
+        } else if shr32(sff, 2) > sdd.wrapping_add(shr32(mult16_16(n as i16, 10000), 6)) {
+            self.screwed_up += 1;
+        } else {
+            self.screwed_up = 0;
+        }
+        if self.screwed_up >= 50 {
+            alloc::warn_str(
+                "The echo canceller started acting funny and got slapped (reset). It swears it will behave now.",
+            );
+            self.reset();
+            return;
+        }
+
+        see = see.max(shr32(mult16_16(n as i16, 100), 6));
+
+        for speak in 0..k {
+            let x = &self.x[speak * n + fs..speak * n + n];
+            sxx = sxx.wrapping_add(mdf_inner_prod(x, x));
+            power_spectrum_accum(&self.x_freq[speak * n..(speak + 1) * n], &mut self.xf);
+        }
+
+        // smoothed far end power per bin
+        for (power, xf) in self.power.iter_mut().zip(&self.xf) {
+            *power = mult16_32_q15(ss_1, *power)
+                .wrapping_add(1)
+                .wrapping_add(mult16_32_q15(ss, *xf));
+        }
+
+        // leak estimate: correlation between the error and filter output spectra changes
+        let mut pey = SpxFloat::ONE;
+        let mut pyy = SpxFloat::ONE;
+        for j in (0..=fs).rev() {
+            let eh = SpxFloat::from_int(self.rf[j].wrapping_sub(self.eh[j]));
+            let yh = SpxFloat::from_int(self.yf[j].wrapping_sub(self.yh[j]));
+            pey = pey.add(eh.mult(yh));
+            pyy = pyy.add(yh.mult(yh));
+            self.eh[j] = mac16_32_q15(
+                mult16_32_q15(32767 - self.spec_average, self.eh[j]),
+                self.spec_average,
+                self.rf[j],
+            );
+            self.yh[j] = mac16_32_q15(
+                mult16_32_q15(32767 - self.spec_average, self.yh[j]),
+                self.spec_average,
+                self.yf[j],
+            );
+        }
+        let pyy = pyy.sqrt();
+        let pey = SpxFloat::divu(pey, pyy);
+
+        let mut tmp32 = mult16_32_q15(self.beta0, syy);
+        if tmp32 > mult16_32_q15(self.beta_max, see) {
+            tmp32 = mult16_32_q15(self.beta_max, see);
+        }
+        let alpha = SpxFloat::div32(tmp32, see);
+        let alpha_1 = SpxFloat::ONE.sub(alpha);
+        self.pey = alpha_1.mult(self.pey).add(alpha.mult(pey));
+        self.pyy = alpha_1.mult(self.pyy).add(alpha.mult(pyy));
+        if self.pyy.lt(SpxFloat::ONE) {
+            self.pyy = SpxFloat::ONE;
+        }
+        if self.pey.lt(MIN_LEAK.mult(self.pyy)) {
+            self.pey = MIN_LEAK.mult(self.pyy);
+        }
+        if self.pey.gt(self.pyy) {
+            self.pey = self.pyy;
+        }
+        self.leak_estimate = SpxFloat::divu(self.pey, self.pyy).shl(14).extract16();
+        self.leak_estimate = if self.leak_estimate > 16383 {
+            32767
+        } else {
+            self.leak_estimate << 1
+        };
+
+        // residual to error ratio
+        let tmp32 = mult16_32_q15(self.leak_estimate, syy);
+        let mut tmp32 = shr32(sxx, 13).wrapping_add(tmp32.wrapping_add(shl32(tmp32, 1)));
+        // y in e is a lower bound on the RER
+        let bound = SpxFloat::from_int(sey);
+        let bound = SpxFloat::divu(bound.mult(bound), SpxFloat::from_int(syy.wrapping_add(1)));
+        if bound.gt(SpxFloat::from_int(see)) {
+            tmp32 = see;
+        } else if tmp32 < bound.extract32() {
+            tmp32 = bound.extract32();
+        }
+        if tmp32 > shr32(see, 1) {
+            tmp32 = shr32(see, 1);
+        }
+        let rer = SpxFloat::div32(tmp32, see).shl(15).extract16();
+
+        if !self.adapted
+            && self.sum_adapt > shl32(m as i32, 15)
+            && mult16_32_q15(self.leak_estimate, syy) > mult16_32_q15(qconst16(0.03, 15), syy)
+        {
+            self.adapted = true;
+        }
+
+        // step size per bin for the next frame
+        if self.adapted {
+            for i in 0..=fs {
+                let mut r = mult16_32_q15(self.leak_estimate, shl32(self.yf[i], 3));
+                let e = shl32(self.rf[i], 3).wrapping_add(1);
+                if r > shr32(e, 1) {
+                    r = shr32(e, 1);
+                }
+                r = mult16_32_q15(qconst16(0.7, 15), r)
+                    .wrapping_add(mult16_32_q15(qconst16(0.3, 15), mult16_32_q15(rer, e)));
+                self.power_1[i] = SpxFloat::div32_float(
+                    r,
+                    SpxFloat::mul32u(e, self.power[i].wrapping_add(10)),
+                )
+                .shl(WEIGHT_SHIFT + 16);
+            }
+        } else {
+            let mut adapt_rate: i16 = 0;
+            if sxx > shr32(mult16_16(n as i16, 1000), 6) {
+                let mut tmp32 = mult16_32_q15(qconst16(0.25, 15), sxx);
+                if tmp32 > shr32(see, 2) {
+                    tmp32 = shr32(see, 2);
+                }
+                adapt_rate = SpxFloat::div32(tmp32, see).shl(15).extract16();
+            }
+            for (power_1, power) in self.power_1.iter_mut().zip(&self.power) {
+                *power_1 = SpxFloat::div32(adapt_rate as i32, power.wrapping_add(10))
+                    .shl(WEIGHT_SHIFT + 1);
+            }
+            self.sum_adapt = self.sum_adapt.wrapping_add(adapt_rate as i32);
+        }
+
+        // keep the echo estimate for speex_echo_get_residual (first mic only, like upstream)
+        self.last_y.copy_within(fs..2 * fs, 0);
+        if self.adapted {
+            for i in 0..fs {
+                self.last_y[fs + i] = rec[i].wrapping_sub(out[i]);
+            }
+        }
+    }
+
+    /// Like [`cancellation`](Self::cancellation), using far end frames queued with
+    /// [`playback`](Self::playback).
+    pub fn capture(&mut self, rec: &[i16], out: &mut [i16]) {
+        let fs = self.frame_size;
+        self.play_buf_started = true;
+        if self.play_buf_pos >= fs {
+            let play_buf = std::mem::take(&mut self.play_buf);
+            self.cancellation(rec, &play_buf, out);
+            self.play_buf = play_buf;
+            self.play_buf_pos -= fs;
+            let pos = self.play_buf_pos;
+            self.play_buf.copy_within(fs..fs + pos, 0);
+        } else {
+            alloc::warn_str("No playback frame available (your application is buggy and/or got xruns)");
+            if self.play_buf_pos != 0 {
+                alloc::warn_str("internal playback buffer corruption?");
+                self.play_buf_pos = 0;
+            }
+            out[..fs].copy_from_slice(&rec[..fs]);
+        }
+    }
+
+    /// Queue a far end frame for [`capture`](Self::capture).
+    pub fn playback(&mut self, play: &[i16]) {
+        let fs = self.frame_size;
+        if !self.play_buf_started {
+            alloc::warn_str("discarded first playback frame");
+            return;
+        }
+        if self.play_buf_pos <= PLAYBACK_DELAY * fs {
+            let pos = self.play_buf_pos;
+            self.play_buf[pos..pos + fs].copy_from_slice(&play[..fs]);
+            self.play_buf_pos += fs;
+            if self.play_buf_pos <= (PLAYBACK_DELAY - 1) * fs {
+                alloc::warn_str("Auto-filling the buffer (your application is buggy and/or got xruns)");
+                let pos = self.play_buf_pos;
+                self.play_buf[pos..pos + fs].copy_from_slice(&play[..fs]);
+                self.play_buf_pos += fs;
+            }
+        } else {
+            alloc::warn_str("Had to discard a playback frame (your application is buggy and/or got xruns)");
+        }
+    }
+
+    /// Power spectrum of the echo left after the last frame (`frame_size + 1` bins), for the
+    /// preprocessor's residual echo suppression.
+    pub fn residual_echo(&mut self, residual: &mut [f32]) {
+        let n = self.window_size;
+        for ((y, w), last_y) in self.y[..n].iter_mut().zip(&self.window).zip(&self.last_y) {
+            *y = mult16_16_q15(*w, *last_y) as i16;
+        }
+        self.fft.forward(&self.y[..n], &mut self.y_freq[..n]);
+        power_spectrum(&self.y_freq[..n], &mut self.residual);
+        let leak2 = if self.leak_estimate > 16383 {
+            32767
+        } else {
+            self.leak_estimate << 1
+        };
+        for (r, p) in residual[..=self.frame_size].iter_mut().zip(&self.residual) {
+            *r = mult16_32_q15(leak2, *p) as f32;
+        }
+    }
+
+    /// Taps per impulse response, `filter blocks * frame_size`.
+    pub fn impulse_response_len(&self) -> usize {
+        self.m * self.frame_size
+    }
+
+    /// Learned echo path as linear gains for every mic/speaker pair, indexed
+    /// `[mic * speakers + speaker]`.
+    pub fn impulse_response(&self) -> Vec<Vec<f32>> {
+        let n = self.window_size;
+        let fs = self.frame_size;
+        // transform in float for the extra precision, the result isn't on the audio path
+        let mut fft = RealFft::new(n);
+        let mut w = vec![0.0f32; n];
+        let mut wtmp = vec![0.0f32; n];
+        let mut responses = Vec::with_capacity(self.mics * self.speakers);
+        for mic in 0..self.mics {
+            for speaker in 0..self.speakers {
+                let mut response = vec![0.0f32; self.impulse_response_len()];
+                for j in 0..self.m {
+                    let offset = mic * n * self.speakers * self.m + j * n * self.speakers + speaker * n;
+                    for (dst, src) in w.iter_mut().zip(&self.w[offset..offset + n]) {
+                        *dst = (*src as f64 / FILTER_SCALE) as f32;
+                    }
+                    fft.inverse(&w, &mut wtmp);
+                    // the inverse FFT is unscaled, so the filter comes back multiplied by the window size
+                    for (dst, src) in response[j * fs..(j + 1) * fs].iter_mut().zip(&wtmp[..fs]) {
+                        *dst = *src / n as f32;
+                    }
+                }
+                responses.push(response);
+            }
+        }
+        responses
+    }
+
+    pub(super) fn snapshot(&self) -> EchoCancellerSnapshot {
+        let scaled = |values: &[i32], scale: f64| -> Vec<f32> {
+            values.iter().map(|v| (*v as f64 / scale) as f32).collect()
+        };
+        EchoCancellerSnapshot {
+            frame_size: self.frame_size,
+            filter_blocks: self.m,
+            mic_channels: self.mics,
+            speaker_channels: self.speakers,
+            sampling_rate: self.sampling_rate as u32,
+            adapted: self.adapted,
+            sum_adapt: (self.sum_adapt as f64 / Q15) as f32,
+            leak_estimate: (self.leak_estimate as f64 / Q15) as f32,
+            pey: self.pey.to_f32(),
+            pyy: self.pyy.to_f32(),
+            davg: [
+                (self.davg1 as f64 * ENERGY_SCALE) as f32,
+                (self.davg2 as f64 * ENERGY_SCALE) as f32,
+            ],
+            dvar: [
+                (self.dvar1.to_f32() as f64 * ENERGY_SCALE * ENERGY_SCALE) as f32,
+                (self.dvar2.to_f32() as f64 * ENERGY_SCALE * ENERGY_SCALE) as f32,
+            ],
+            background: scaled(&self.w, FILTER_SCALE),
+            foreground: self
+                .foreground
+                .iter()
+                .map(|v| (*v as f64 / FOREGROUND_SCALE) as f32)
+                .collect(),
+            prop: self.prop.iter().map(|v| (*v as f64 / Q15) as f32).collect(),
+            power: scaled(&self.power, 1.0),
+            power_1: self
+                .power_1
+                .iter()
+                .map(|v| (v.to_f32() as f64 / FILTER_SCALE) as f32)
+                .collect(),
+            eh: scaled(&self.eh, 1.0),
+            yh: scaled(&self.yh, 1.0),
+        }
+    }
+
+    pub(super) fn restore(&mut self, snapshot: &EchoCancellerSnapshot) -> Result<(), SnapshotError> {
+        let bins = self.frame_size + 1;
+        if snapshot.frame_size != self.frame_size
+            || snapshot.filter_blocks != self.m
+            || snapshot.sampling_rate != self.sampling_rate as u32
+            || snapshot.mic_channels != self.mics
+            || snapshot.speaker_channels != self.speakers
+            || snapshot.background.len() != self.w.len()
+            || snapshot.foreground.len() != self.foreground.len()
+            || snapshot.prop.len() != self.m
+            || snapshot.power.len() != bins
+            || snapshot.power_1.len() != bins
+            || snapshot.eh.len() != bins
+            || snapshot.yh.len() != bins
+        {
+            return Err(SnapshotError::LayoutMismatch);
+        }
+        // `as` saturates, which is what we want for values outside the fixed-point range
+        let fixed = |v: f32, scale: f64| -> i32 { (v as f64 * scale).round() as i32 };
+        for (dst, src) in self.w.iter_mut().zip(&snapshot.background) {
+            *dst = fixed(*src, FILTER_SCALE);
+        }
+        for (dst, src) in self.foreground.iter_mut().zip(&snapshot.foreground) {
+            *dst = (*src as f64 * FOREGROUND_SCALE).round() as i16;
+        }
+        for (dst, src) in self.prop.iter_mut().zip(&snapshot.prop) {
+            *dst = (*src as f64 * Q15).round() as i16;
+        }
+        for (dst, src) in self.power.iter_mut().zip(&snapshot.power) {
+            *dst = fixed(*src, 1.0);
+        }
+        for (dst, src) in self.power_1.iter_mut().zip(&snapshot.power_1) {
+            *dst = SpxFloat::from_f32((*src as f64 * FILTER_SCALE) as f32);
+        }
+        for (dst, src) in self.eh.iter_mut().zip(&snapshot.eh) {
+            *dst = fixed(*src, 1.0);
+        }
+        for (dst, src) in self.yh.iter_mut().zip(&snapshot.yh) {
+            *dst = fixed(*src, 1.0);
+        }
+        self.adapted = snapshot.adapted;
+        self.sum_adapt = fixed(snapshot.sum_adapt, Q15);
+        self.leak_estimate = (snapshot.leak_estimate as f64 * Q15).round() as i16;
+        self.pey = SpxFloat::from_f32(snapshot.pey);
+        self.pyy = SpxFloat::from_f32(snapshot.pyy);
+        self.davg1 = fixed(snapshot.davg[0], 1.0 / ENERGY_SCALE);
+        self.davg2 = fixed(snapshot.davg[1], 1.0 / ENERGY_SCALE);
+        self.dvar1 = SpxFloat::from_f32((snapshot.dvar[0] as f64 / (ENERGY_SCALE * ENERGY_SCALE)) as f32);
+        self.dvar2 = SpxFloat::from_f32((snapshot.dvar[1] as f64 / (ENERGY_SCALE * ENERGY_SCALE)) as f32);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::c_int;
+
+    use super::*;
+    use crate::speex::{
+        speex_echo_cancellation, speex_echo_ctl, speex_echo_state_destroy, speex_echo_state_init_mc,
+        SPEEX_ECHO_SET_SAMPLING_RATE,
+    };
+
+    // far end noise through a short echo path with a little near-end noise, as (mic, far) frames
+    fn echo_frames(frame_size: usize, frames: usize) -> Vec<(Vec<i16>, Vec<i16>)> {
+        let mut seed = 7u32;
+        let mut noise = |gain: f32| {
+            seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            ((seed >> 16) as i16 as f32 * gain) as i16
+        };
+        let far: Vec<i16> = (0..frame_size * frames).map(|_| noise(0.3)).collect();
+        let taps = [(3, 0.6f32), (17, -0.25), (40, 0.1)];
+        (0..frames)
+            .map(|f| {
+                let mic = (f * frame_size..(f + 1) * frame_size)
+                    .map(|i| {
+                        let echo: f32 = taps.iter().filter(|(d, _)| i >= *d).map(|(d, g)| far[i - d] as f32 * g).sum();
+                        (echo + noise(0.002) as f32) as i16
+                    })
+                    .collect();
+                (mic, far[f * frame_size..(f + 1) * frame_size].to_vec())
+            })
+            .collect()
+    }
+
+    // echo return loss enhancement over the second half, in dB
+    fn erle_db(frames: &[(Vec<i16>, Vec<i16>)], outputs: &[Vec<i16>]) -> f64 {
+        let energy = |v: &[i16]| v.iter().map(|&s| (s as f64).powi(2)).sum::<f64>();
+        let half = frames.len() / 2;
+        let mic: f64 = frames[half..].iter().map(|(mic, _)| energy(mic)).sum();
+        let out: f64 = outputs[half..].iter().map(|out| energy(out)).sum();
+        10.0 * (mic / out.max(1.0)).log10()
+    }
+
+    fn run_fixed(frame_size: usize, filter_length: usize, frames: &[(Vec<i16>, Vec<i16>)]) -> Vec<Vec<i16>> {
+        let mut state = EchoState::new(frame_size, filter_length, 1, 1);
+        state.set_sampling_rate(16_000);
+        frames
+            .iter()
+            .map(|(mic, far)| {
+                let mut out = vec![0i16; frame_size];
+                state.cancellation(mic, far, &mut out);
+                out
+            })
+            .collect()
+    }
+
+    // the c2rust translation is always built in float, so it's the reference here
+    fn run_float(frame_size: usize, filter_length: usize, frames: &[(Vec<i16>, Vec<i16>)]) -> Vec<Vec<i16>> {
+        let st = unsafe { speex_echo_state_init_mc(frame_size as c_int, filter_length as c_int, 1, 1) };
+        let mut rate: c_int = 16_000;
+        unsafe { speex_echo_ctl(st, SPEEX_ECHO_SET_SAMPLING_RATE, &mut rate as *mut c_int as *mut _) };
+        let outputs = frames
+            .iter()
+            .map(|(mic, far)| {
+                let mut out = vec![0i16; frame_size];
+                unsafe { speex_echo_cancellation(st, mic.as_ptr(), far.as_ptr(), out.as_mut_ptr()) };
+                out
+            })
+            .collect();
+        unsafe { speex_echo_state_destroy(st) };
+        outputs
+    }
+
+    #[test]
+    fn converges_like_float() {
+        // the fixed-point build rounds every stage to 16 bits, so only the cancellation depth is
+        // compared: both must cancel and land within 6 dB of each other
+        for (frame_size, filter_length) in [(64, 256), (160, 1600)] {
+            let frames = echo_frames(frame_size, 400);
+            let fixed = erle_db(&frames, &run_fixed(frame_size, filter_length, &frames));
+            let float = erle_db(&frames, &run_float(frame_size, filter_length, &frames));
+            assert!(fixed > 15.0 && float > 15.0, "{frame_size}/{filter_length}: fixed {fixed} dB, float {float} dB");
+            assert!((fixed - float).abs() < 6.0, "{frame_size}/{filter_length}: fixed {fixed} dB, float {float} dB");
+        }
+    }
+
+    #[test]
+    fn output_is_deterministic() {
+        // integer arithmetic only, so the output is the same on every platform; pinned to a hash
+        // so any change to the arithmetic shows up here
+        let frames = echo_frames(64, 200);
+        let fnv = run_fixed(64, 256, &frames).iter().flatten().fold(0xcbf2_9ce4_8422_2325u64, |h, &s| {
+            (h ^ s as u16 as u64).wrapping_mul(0x0000_0100_0000_01b3)
+        });
+        assert_eq!(fnv, 0x3061_ade2_fbf8_5d6e);
+    }
+}